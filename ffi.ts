@@ -15,10 +15,187 @@ const lib = Deno.dlopen(libPath, {
   gamepad_api_new: { parameters: [], result: "pointer" },
   get_gamepads: {
     parameters: ["pointer"],
-    result: { struct: ["usize", "pointer"] },
+    result: { struct: ["usize", "pointer", "usize"] },
   },
+  set_rumble: {
+    parameters: ["pointer", "usize", "f32", "f32"],
+    result: "bool",
+  },
+  get_gamepad_motion: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_stick_drift: {
+    parameters: ["pointer", "usize", "u8", "pointer"],
+    result: "bool",
+  },
+  set_drift_compensation: {
+    parameters: ["pointer", "usize", "bool"],
+    result: "bool",
+  },
+  set_adaptive_range: {
+    parameters: ["pointer", "usize", "bool"],
+    result: "bool",
+  },
+  get_adaptive_range: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  set_stick_gate: {
+    parameters: ["pointer", "usize", "u8", "pointer"],
+    result: "bool",
+  },
+  set_stick_response: {
+    parameters: ["pointer", "usize", "u8", "pointer"],
+    result: "bool",
+  },
+  start_stick_calibration: {
+    parameters: ["pointer", "usize", "u8"],
+    result: "bool",
+  },
+  next_stick_calibration_step: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  get_stick_calibration_status: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  apply_stick_calibration: {
+    parameters: ["pointer", "usize", "bool"],
+    result: "bool",
+  },
+  cancel_stick_calibration: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  erase_user_stick_calibration: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  start_spi_dump: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  start_spi_restore: {
+    parameters: ["pointer", "usize", "pointer", "usize"],
+    result: "bool",
+  },
+  get_spi_progress: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_spi_dump: {
+    parameters: ["pointer", "usize", "pointer", "usize"],
+    result: "usize",
+  },
+  cancel_spi_transfer: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  summarize_spi_dump: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_calibration_sources: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_device_info: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_init_failure: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_gamepad_colors: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  get_gamepad_touches: {
+    parameters: ["pointer", "usize", "pointer", "usize"],
+    result: "usize",
+  },
+  trigger_haptic_pulse: {
+    parameters: ["pointer", "usize", "u8", "u16", "u16", "u16"],
+    result: "bool",
+  },
+  get_wheel_state: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  set_wheel_range: {
+    parameters: ["pointer", "usize", "u16"],
+    result: "bool",
+  },
+  set_wheel_autocenter: {
+    parameters: ["pointer", "usize", "f32"],
+    result: "bool",
+  },
+  upload_wheel_effect: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "i32",
+  },
+  update_wheel_effect: {
+    parameters: ["pointer", "usize", "usize", "pointer"],
+    result: "bool",
+  },
+  stop_wheel_effect: {
+    parameters: ["pointer", "usize", "usize"],
+    result: "bool",
+  },
+  set_wheel_gain: {
+    parameters: ["pointer", "usize", "f32"],
+    result: "bool",
+  },
+  read_nfc_tag: {
+    parameters: ["pointer", "usize", "function", "pointer"],
+    result: "bool",
+  },
+  cancel_nfc_read: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  start_ir_camera: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  stop_ir_camera: {
+    parameters: ["pointer", "usize"],
+    result: "bool",
+  },
+  get_ir_image: {
+    parameters: ["pointer", "usize", "pointer", "pointer", "usize"],
+    result: "bool",
+  },
+  get_ir_moments: {
+    parameters: ["pointer", "usize", "pointer", "usize"],
+    result: "usize",
+  },
+  get_ir_clusters: {
+    parameters: ["pointer", "usize", "pointer", "usize"],
+    result: "usize",
+  },
+  add_gamepad_mappings: {
+    parameters: ["pointer", "pointer"],
+    result: "usize",
+  },
+  load_profiles: {
+    parameters: ["pointer", "pointer"],
+    result: "usize",
+  },
+  set_profile: {
+    parameters: ["pointer", "usize", "pointer"],
+    result: "bool",
+  },
+  enable_calibration_cache: {
+    parameters: ["pointer", "pointer"],
+    result: "bool",
+  },
+  free_gamepad_api: { parameters: ["pointer"], result: "void" },
   free_gamepad_array: {
-    parameters: [{ struct: ["usize", "pointer"] }],
+    parameters: [{ struct: ["usize", "pointer", "usize"] }],
     result: "void",
   },
 });
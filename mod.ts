@@ -5,10 +5,320 @@ interface Gamepad {
   index: number;
   axes: number[];
   buttons: Array<{ pressed: boolean; value: number }>;
+  // Hat switches as bits: 1 up, 2 right, 4 down, 8 left, 0 centered.
+  hats: number[];
+  mapping: "standard" | "";
 }
 
+interface Motion {
+  // Acceleration in G.
+  accel: [number, number, number];
+  // Angular velocity in degrees per second, null without a gyroscope.
+  gyro: [number, number, number] | null;
+}
+
+interface DeviceInfo {
+  firmwareVersion: string;
+  controllerType:
+    | "joyConLeft"
+    | "joyConRight"
+    | "proController"
+    | "nesLeft"
+    | "nesRight"
+    | "snes"
+    | "n64"
+    | "genesis"
+    | "unknown";
+  // Bluetooth address, e.g. "98:b6:e9:01:02:03".
+  macAddress: string;
+}
+
+interface InitFailure {
+  // Subcommand ID or, over USB, proprietary command that went unanswered.
+  commandId: number;
+  proprietary: boolean;
+  // Whether the controller answered with a NACK rather than not at all.
+  nacked: boolean;
+  // Address of an SPI flash read, null for other requests.
+  spiAddress: number | null;
+}
+
+interface StickDrift {
+  // Where the stick rests in calibrated axes, x then y.
+  rest: [number, number];
+  // Distance of the rest position from the center, a fraction of a full
+  // deflection.
+  drift: number;
+  noise: number;
+  // Reports the stick was seen resting in.
+  samples: number;
+}
+
+interface AdaptiveRange {
+  // Raw 12-bit positions of left x, left y, right x and right y, 0 where a side
+  // wasn't reached yet.
+  min: [number, number, number, number];
+  max: [number, number, number, number];
+  // Whether the learned bounds replace the calibrated ones.
+  enabled: boolean;
+}
+
+interface StickGate {
+  shape: "circle" | "octagon" | "learned";
+  // Maps the corrected circle onto a square, for games that expect diagonals
+  // at (1, 1).
+  squareOutput?: boolean;
+  // Distance of the diagonal corners of an octagon, cardinal ones are at 1.
+  diagonal?: number;
+}
+
+// Distances are fractions of a full deflection.
+interface StickResponse {
+  deadzoneShape?: "radial" | "axial" | "scaledRadial" | "hybrid";
+  innerDeadzone?: number;
+  // Distance counted as a full deflection.
+  outerDeadzone?: number;
+  // Smallest output past the inner deadzone.
+  antiDeadzone?: number;
+  curve?: "linear" | "power" | "sCurve" | "lookup";
+  // Of the power and S-curve.
+  exponent?: number;
+  // Up to 16 outputs for evenly spaced distances from 0 to 1.
+  lookup?: number[];
+}
+
+interface ControllerColors {
+  // CSS hex colors, e.g. "#ff3c28".
+  body: string;
+  buttons: string;
+  // Only Pro Controllers have grips of their own.
+  leftGrip: string | null;
+  rightGrip: string | null;
+}
+
+type CalibrationSource = "default" | "factory" | "user";
+
+interface CalibrationSources {
+  leftStick: CalibrationSource;
+  rightStick: CalibrationSource;
+  imu: CalibrationSource;
+}
+
+interface StickCalibrationStatus {
+  phase:
+    | "samplingCenter"
+    | "samplingRange"
+    | "sampled"
+    | "writing"
+    | "done"
+    | "failed";
+  stick: "left" | "right" | "both";
+  // Reports sampled in the current phase.
+  sampleCount: number;
+  // Raw 12-bit positions.
+  center: [number, number];
+  min: [number, number];
+  max: [number, number];
+}
+
+interface SpiProgress {
+  // "mismatch" when a restored dump is of another controller, nothing is
+  // written then.
+  state: "running" | "done" | "failed" | "mismatch";
+  // Bytes read for dumps, chunks written for restores.
+  done: number;
+  total: number;
+}
+
+interface StickCalibration {
+  // Raw 12-bit positions.
+  center: [number, number];
+  min: [number, number];
+  max: [number, number];
+}
+
+interface SpiSummary {
+  serial: string;
+  colors: ControllerColors;
+  // Bluetooth address of the paired host, null if unpaired.
+  hostAddress: string | null;
+  sources: CalibrationSources;
+  leftStick: StickCalibration;
+  rightStick: StickCalibration;
+  accelerometerOrigin: [number, number, number];
+  accelerometerSensitivity: [number, number, number];
+  gyroOrigin: [number, number, number];
+  gyroSensitivity: [number, number, number];
+}
+
+interface GamepadTouch {
+  touchId: number;
+  surfaceId: number;
+  // Normalized to -1..1, -1 is the left and top edge.
+  position: [number, number];
+}
+
+interface WheelState {
+  // -1..1 over the rotation range, -1 is full left.
+  steering: number;
+  // Pedal positions, 0..1.
+  throttle: number;
+  brake: number;
+  clutch: number;
+  // Rotation range in degrees, lock to lock.
+  range: number;
+  // H-shifter gear, 0 is neutral and -1 reverse.
+  gear: number;
+}
+
+interface NfcTag {
+  uid: Uint8Array;
+  // Raw pages starting at page 0, 540 bytes for an amiibo.
+  data: Uint8Array;
+}
+
+interface IrConfig {
+  mode: "moment" | "clustering" | "image";
+  // Image size, 320x240 by default.
+  resolution?: "320x240" | "160x120" | "80x60" | "40x30";
+  farLeds?: boolean;
+  nearLeds?: boolean;
+  // LED intensity, 0..15.
+  intensity?: number;
+  // Exposure in microseconds, up to 600.
+  exposureUs?: number;
+}
+
+interface IrImage {
+  frameId: number;
+  width: number;
+  height: number;
+  // 8-bit grayscale, row by row.
+  pixels: Uint8Array;
+}
+
+// One block of the 8x6 grid in moment mode.
+interface IrMoment {
+  averageIntensity: number;
+  centroid: [number, number];
+}
+
+// A bright object in clustering mode, in pixels of the 320x240 sensor.
+interface IrCluster {
+  averageIntensity: number;
+  pixelCount: number;
+  centroid: [number, number];
+  bounds: [number, number, number, number];
+}
+
+type WheelEffect =
+  | { type: "constant"; level: number }
+  | {
+    type: "spring";
+    center: number;
+    deadband: number;
+    leftCoefficient: number;
+    rightCoefficient: number;
+    saturation: number;
+  }
+  | {
+    type: "damper";
+    leftCoefficient: number;
+    rightCoefficient: number;
+    saturation: number;
+  };
+
+// size_of::<Motion>() on the Rust side
+const MOTION_SIZE = 28;
+// size_of::<ControllerInfo>() on the Rust side
+const DEVICE_INFO_SIZE = 9;
+const CONTROLLER_TYPES: Record<number, DeviceInfo["controllerType"]> = {
+  1: "joyConLeft",
+  2: "joyConRight",
+  3: "proController",
+  9: "nesLeft",
+  10: "nesRight",
+  11: "snes",
+  12: "n64",
+  13: "genesis",
+};
+// size_of::<InitFailure>() on the Rust side
+const INIT_FAILURE_SIZE = 8;
+// size_of::<ControllerColors>() on the Rust side
+const CONTROLLER_COLORS_SIZE = 13;
+// size_of::<CalibrationSources>() on the Rust side
+const CALIBRATION_SOURCES_SIZE = 3;
+const CALIBRATION_SOURCES: CalibrationSource[] = ["default", "factory", "user"];
+// size_of::<StickCalibrationStatus>() on the Rust side
+const STICK_CALIBRATION_STATUS_SIZE = 20;
+const CALIBRATION_PHASES: StickCalibrationStatus["phase"][] = [
+  "samplingCenter",
+  "samplingRange",
+  "sampled",
+  "writing",
+  "done",
+  "failed",
+];
+const STICKS: StickCalibrationStatus["stick"][] = ["left", "right", "both"];
+const DEADZONE_SHAPES: StickResponse["deadzoneShape"][] = [
+  "radial",
+  "axial",
+  "scaledRadial",
+  "hybrid",
+];
+const RESPONSE_CURVES: StickResponse["curve"][] = [
+  "linear",
+  "power",
+  "sCurve",
+  "lookup",
+];
+// size_of::<StickDrift>() on the Rust side
+const STICK_DRIFT_SIZE = 20;
+// size_of::<AdaptiveRange>() on the Rust side
+const ADAPTIVE_RANGE_SIZE = 18;
+const GATE_SHAPES: StickGate["shape"][] = ["circle", "octagon", "learned"];
+// size_of::<StickGate>() on the Rust side
+const STICK_GATE_SIZE = 8;
+// size_of::<StickResponse>() on the Rust side
+const STICK_RESPONSE_SIZE = 84;
+// size_of::<SpiProgress>() and size_of::<SpiSummary>() on the Rust side
+const SPI_PROGRESS_SIZE = 12;
+const SPI_SUMMARY_SIZE = 88;
+const SPI_FLASH_SIZE = 0x80000;
+const SPI_PROGRESS_STATES: SpiProgress["state"][] = [
+  "running",
+  "done",
+  "failed",
+  "mismatch",
+];
+// size_of::<Touch>() on the Rust side
+const TOUCH_SIZE = 16;
+const MAX_TOUCHES = 4;
+// size_of::<WheelState>() on the Rust side
+const WHEEL_STATE_SIZE = 20;
+// size_of::<WheelEffect>() on the Rust side
+const WHEEL_EFFECT_SIZE = 28;
+const WHEEL_EFFECT_TYPES = ["constant", "spring", "damper"];
+// size_of::<NfcTag>() on the Rust side
+const NFC_TAG_SIZE = 554;
+// size_of::<IrConfig>() and size_of::<IrImage>() on the Rust side
+const IR_CONFIG_SIZE = 8;
+const IR_IMAGE_SIZE = 8;
+const IR_MODES = { moment: 3, clustering: 6, image: 7 };
+const IR_RESOLUTIONS = ["320x240", "160x120", "80x60", "40x30"];
+const MAX_IR_IMAGE_SIZE = 320 * 240;
+// size_of::<IrMoment>() and size_of::<IrCluster>() on the Rust side
+const IR_MOMENT_SIZE = 6;
+const IR_MOMENT_COUNT = 48;
+const IR_CLUSTER_SIZE = 16;
+const MAX_IR_CLUSTERS = 16;
+
 const __ptr_gamepad_api__ = ffi.symbols.gamepad_api_new();
 
+globalThis.addEventListener("unload", () => {
+  ffi.symbols.free_gamepad_api(__ptr_gamepad_api__);
+});
+
 function getGamepads(): Gamepad[] {
   const bufPtr = ffi.symbols.get_gamepads(__ptr_gamepad_api__);
 
@@ -20,25 +330,35 @@ function getGamepads(): Gamepad[] {
 
   const gamepads: Gamepad[] = [];
 
+  // Each gamepad is a GamepadHeader followed by its axes, buttons and hats.
+  let offset = 0;
   for (let i = 0; i < len; i++) {
-    let offset = 0;
+    const size = view2.getUint32(offset);
+    const mapping = view2.getUint8(offset + 4) === 1 ? "standard" : "";
+    const index = view2.getBigUint64(offset + 8);
+    const axisCount = view2.getUint32(offset + 16);
+    const axesOffset = offset + view2.getUint32(offset + 20);
+    const buttonCount = view2.getUint32(offset + 24);
+    const buttonsOffset = offset + view2.getUint32(offset + 28);
+    const hatCount = view2.getUint32(offset + 32);
+    const hatsOffset = offset + view2.getUint32(offset + 36);
 
-    const index = view2.getBigUint64(offset);
-    offset += 8;
     const axes = [];
-    for (let i = 0; i < 4; i++) {
-      axes.push(view2.getFloat32(offset));
-      offset += 4;
+    for (let i = 0; i < axisCount; i++) {
+      axes.push(view2.getFloat32(axesOffset + i * 4));
     }
     const buttons: Gamepad["buttons"] = [];
-    for (let i = 0; i < 24; i++) {
-      const pressed = view2.getUint8(offset) === 1;
-      offset += 4;
-      const value = view2.getFloat32(offset);
-      offset += 4;
+    for (let i = 0; i < buttonCount; i++) {
+      const pressed = view2.getUint8(buttonsOffset + i * 8) === 1;
+      const value = view2.getFloat32(buttonsOffset + i * 8 + 4);
       buttons.push({ pressed, value });
     }
-    gamepads.push({ index: Number(index), axes, buttons });
+    const hats = [];
+    for (let i = 0; i < hatCount; i++) {
+      hats.push(view2.getUint8(hatsOffset + i));
+    }
+    gamepads.push({ index: Number(index), axes, buttons, hats, mapping });
+    offset += size;
   }
 
   ffi.symbols.free_gamepad_array(bufPtr!);
@@ -46,4 +366,776 @@ function getGamepads(): Gamepad[] {
   return gamepads;
 }
 
-export { getGamepads };
+function setRumble(
+  index: number,
+  strongMagnitude: number,
+  weakMagnitude: number,
+): boolean {
+  return ffi.symbols.set_rumble(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    strongMagnitude,
+    weakMagnitude,
+  );
+}
+
+// The MAC address identifies a controller across connections, unlike its index.
+function getDeviceInfo(index: number): DeviceInfo | null {
+  const buf = new Uint8Array(DEVICE_INFO_SIZE);
+  const ok = ffi.symbols.get_device_info(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  return {
+    firmwareVersion: `${buf[0]}.${buf[1]}`,
+    controllerType: CONTROLLER_TYPES[buf[2]] ?? "unknown",
+    macAddress: Array.from(buf.subarray(3, 9))
+      .map((b) => b.toString(16).padStart(2, "0"))
+      .join(":"),
+  };
+}
+
+// A controller that stopped initializing still reports buttons and sticks,
+// without calibration or motion.
+function getInitFailure(index: number): InitFailure | null {
+  const buf = new Uint8Array(INIT_FAILURE_SIZE);
+  const ok = ffi.symbols.get_init_failure(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const spiAddress = new DataView(buf.buffer).getUint32(4, true);
+  return {
+    commandId: buf[0],
+    proprietary: buf[1] !== 0,
+    nacked: buf[2] !== 0,
+    spiAddress: spiAddress === 0 ? null : spiAddress,
+  };
+}
+
+function getColors(index: number): ControllerColors | null {
+  const buf = new Uint8Array(CONTROLLER_COLORS_SIZE);
+  const ok = ffi.symbols.get_gamepad_colors(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  return ok ? decodeColors(buf) : null;
+}
+
+function decodeColors(buf: Uint8Array): ControllerColors {
+  const color = (offset: number) =>
+    "#" + Array.from(buf.subarray(offset, offset + 3))
+      .map((c) => c.toString(16).padStart(2, "0"))
+      .join("");
+  const hasGrips = buf[12] === 1;
+  return {
+    body: color(0),
+    buttons: color(3),
+    leftGrip: hasGrips ? color(6) : null,
+    rightGrip: hasGrips ? color(9) : null,
+  };
+}
+
+// Starts reading the whole SPI flash while getGamepads is polled, which takes a few minutes.
+function startSpiDump(index: number): boolean {
+  return ffi.symbols.start_spi_dump(__ptr_gamepad_api__, BigInt(index));
+}
+
+// Writes the user calibration of a dump back, nothing else is written. Dumps
+// of another controller are refused, see SpiProgress.
+function startSpiRestore(index: number, dump: Uint8Array): boolean {
+  return ffi.symbols.start_spi_restore(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(dump),
+    BigInt(dump.length),
+  );
+}
+
+function getSpiProgress(index: number): SpiProgress | null {
+  const buf = new Uint8Array(SPI_PROGRESS_SIZE);
+  const ok = ffi.symbols.get_spi_progress(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  return {
+    state: SPI_PROGRESS_STATES[buf[0]],
+    done: view.getUint32(4, true),
+    total: view.getUint32(8, true),
+  };
+}
+
+// The finished dump of startSpiDump.
+function getSpiDump(index: number): Uint8Array | null {
+  const buf = new Uint8Array(SPI_FLASH_SIZE);
+  const len = ffi.symbols.get_spi_dump(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+    BigInt(buf.length),
+  );
+  return len === BigInt(SPI_FLASH_SIZE) ? buf : null;
+}
+
+function cancelSpiTransfer(index: number): boolean {
+  return ffi.symbols.cancel_spi_transfer(__ptr_gamepad_api__, BigInt(index));
+}
+
+function summarizeSpiDump(dump: Uint8Array): SpiSummary | null {
+  const buf = new Uint8Array(SPI_SUMMARY_SIZE);
+  const ok = ffi.symbols.summarize_spi_dump(
+    Deno.UnsafePointer.of(dump),
+    BigInt(dump.length),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  const u16 = (offset: number) => view.getUint16(offset, true);
+  const pair = (offset: number): [number, number] => [
+    u16(offset),
+    u16(offset + 2),
+  ];
+  const triple = (offset: number): [number, number, number] => [
+    u16(offset),
+    u16(offset + 2),
+    u16(offset + 4),
+  ];
+  const stick = (offset: number): StickCalibration => ({
+    center: pair(offset),
+    min: pair(offset + 4),
+    max: pair(offset + 8),
+  });
+  const hasPairing = buf[29] === 1;
+  return {
+    serial: new TextDecoder().decode(buf.subarray(0, 16)).replace(/\0+$/, ""),
+    colors: decodeColors(buf.subarray(16, 29)),
+    hostAddress: hasPairing
+      ? Array.from(buf.subarray(30, 36))
+        .map((b) => b.toString(16).padStart(2, "0"))
+        .join(":")
+      : null,
+    sources: {
+      leftStick: CALIBRATION_SOURCES[buf[36]],
+      rightStick: CALIBRATION_SOURCES[buf[37]],
+      imu: CALIBRATION_SOURCES[buf[38]],
+    },
+    leftStick: stick(40),
+    rightStick: stick(52),
+    accelerometerOrigin: triple(64),
+    accelerometerSensitivity: triple(70),
+    gyroOrigin: triple(76),
+    gyroSensitivity: triple(82),
+  };
+}
+
+// Starts calibrating a stick: sample the resting center, call nextStickCalibrationStep, sample
+// the range while rotating the stick, call it again, then apply the result.
+function startStickCalibration(index: number, stick: "left" | "right"): boolean {
+  return ffi.symbols.start_stick_calibration(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    STICKS.indexOf(stick),
+  );
+}
+
+// Measured on Nintendo controllers while no button is pressed and the stick
+// holds still near the center.
+function getStickDrift(
+  index: number,
+  stick: "left" | "right",
+): StickDrift | null {
+  const buf = new Uint8Array(STICK_DRIFT_SIZE);
+  const ok = ffi.symbols.get_stick_drift(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    STICKS.indexOf(stick),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  const f32 = (offset: number) => view.getFloat32(offset, true);
+  return {
+    rest: [f32(0), f32(4)],
+    drift: f32(8),
+    noise: f32(12),
+    samples: view.getUint32(16, true),
+  };
+}
+
+// Recenters the sticks on where they rest, nothing is written to the
+// controller.
+function setDriftCompensation(index: number, enabled: boolean): boolean {
+  return ffi.symbols.set_drift_compensation(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    enabled,
+  );
+}
+
+// Learns how far the sticks of a Nintendo controller reach and uses that
+// instead of their calibrated range.
+function setAdaptiveRange(index: number, enabled: boolean): boolean {
+  return ffi.symbols.set_adaptive_range(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    enabled,
+  );
+}
+
+function getAdaptiveRange(index: number): AdaptiveRange | null {
+  const buf = new Uint8Array(ADAPTIVE_RANGE_SIZE);
+  const ok = ffi.symbols.get_adaptive_range(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  const u16s = (offset: number): AdaptiveRange["min"] => [
+    view.getUint16(offset, true),
+    view.getUint16(offset + 2, true),
+    view.getUint16(offset + 4, true),
+    view.getUint16(offset + 6, true),
+  ];
+  return { min: u16s(0), max: u16s(8), enabled: buf[16] !== 0 };
+}
+
+// Brings the diagonals of a stick to a full deflection before its deadzones
+// apply, null turns it off. Returns false if the settings are out of range.
+function setStickGate(
+  index: number,
+  stick: "left" | "right",
+  gate: StickGate | null,
+): boolean {
+  let buf: Uint8Array | null = null;
+  if (gate) {
+    buf = new Uint8Array(STICK_GATE_SIZE);
+    buf[0] = GATE_SHAPES.indexOf(gate.shape);
+    buf[1] = gate.squareOutput ? 1 : 0;
+    new DataView(buf.buffer).setFloat32(4, gate.diagonal ?? 1, true);
+  }
+  return ffi.symbols.set_stick_gate(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    STICKS.indexOf(stick),
+    buf ? Deno.UnsafePointer.of(buf) : null,
+  );
+}
+
+// Works for every gamepad with sticks in the standard layout, null goes back to
+// the default. Returns false if the settings are out of range.
+function setStickResponse(
+  index: number,
+  stick: "left" | "right",
+  response: StickResponse | null,
+): boolean {
+  let buf: Uint8Array | null = null;
+  if (response) {
+    const lookup = response.lookup ?? [];
+    buf = new Uint8Array(STICK_RESPONSE_SIZE);
+    const view = new DataView(buf.buffer);
+    buf[0] = DEADZONE_SHAPES.indexOf(response.deadzoneShape ?? "scaledRadial");
+    buf[1] = RESPONSE_CURVES.indexOf(response.curve ?? "linear");
+    buf[2] = lookup.length;
+    view.setFloat32(4, response.innerDeadzone ?? 0.1, true);
+    view.setFloat32(8, response.outerDeadzone ?? 1, true);
+    view.setFloat32(12, response.antiDeadzone ?? 0, true);
+    view.setFloat32(16, response.exponent ?? 1, true);
+    lookup.slice(0, 16).forEach((value, i) => {
+      view.setFloat32(20 + i * 4, value, true);
+    });
+  }
+  return ffi.symbols.set_stick_response(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    STICKS.indexOf(stick),
+    buf ? Deno.UnsafePointer.of(buf) : null,
+  );
+}
+
+// Returns false while there aren't enough samples for the current phase.
+function nextStickCalibrationStep(index: number): boolean {
+  return ffi.symbols.next_stick_calibration_step(
+    __ptr_gamepad_api__,
+    BigInt(index),
+  );
+}
+
+function getStickCalibrationStatus(
+  index: number,
+): StickCalibrationStatus | null {
+  const buf = new Uint8Array(STICK_CALIBRATION_STATUS_SIZE);
+  const ok = ffi.symbols.get_stick_calibration_status(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  const pair = (offset: number): [number, number] => [
+    view.getUint16(offset, true),
+    view.getUint16(offset + 2, true),
+  ];
+  return {
+    phase: CALIBRATION_PHASES[buf[0]],
+    stick: STICKS[buf[1]],
+    sampleCount: view.getUint32(4, true),
+    center: pair(8),
+    min: pair(12),
+    max: pair(16),
+  };
+}
+
+// With write, also stores the calibration on the controller as user calibration.
+function applyStickCalibration(index: number, write: boolean): boolean {
+  return ffi.symbols.apply_stick_calibration(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    write,
+  );
+}
+
+function cancelStickCalibration(index: number): boolean {
+  return ffi.symbols.cancel_stick_calibration(
+    __ptr_gamepad_api__,
+    BigInt(index),
+  );
+}
+
+// Restores the factory calibration of both sticks.
+function eraseUserStickCalibration(index: number): boolean {
+  return ffi.symbols.erase_user_stick_calibration(
+    __ptr_gamepad_api__,
+    BigInt(index),
+  );
+}
+
+// Which calibration a Nintendo controller uses, "user" after recalibrating on a Switch.
+function getCalibrationSources(index: number): CalibrationSources | null {
+  const buf = new Uint8Array(CALIBRATION_SOURCES_SIZE);
+  const ok = ffi.symbols.get_calibration_sources(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  return {
+    leftStick: CALIBRATION_SOURCES[buf[0]],
+    rightStick: CALIBRATION_SOURCES[buf[1]],
+    imu: CALIBRATION_SOURCES[buf[2]],
+  };
+}
+
+function getMotion(index: number): Motion | null {
+  const buf = new Uint8Array(MOTION_SIZE);
+  const ok = ffi.symbols.get_gamepad_motion(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  const vector = (offset: number): [number, number, number] => [
+    view.getFloat32(offset, true),
+    view.getFloat32(offset + 4, true),
+    view.getFloat32(offset + 8, true),
+  ];
+  const hasAccel = view.getUint8(24) === 1;
+  const hasGyro = view.getUint8(25) === 1;
+  if (!hasAccel) {
+    return null;
+  }
+  return { accel: vector(0), gyro: hasGyro ? vector(12) : null };
+}
+
+function getTouches(index: number): GamepadTouch[] {
+  const buf = new Uint8Array(TOUCH_SIZE * MAX_TOUCHES);
+  const len = Number(
+    ffi.symbols.get_gamepad_touches(
+      __ptr_gamepad_api__,
+      BigInt(index),
+      Deno.UnsafePointer.of(buf),
+      BigInt(MAX_TOUCHES),
+    ),
+  );
+  const view = new DataView(buf.buffer);
+  const touches: GamepadTouch[] = [];
+  for (let i = 0; i < len; i++) {
+    const offset = i * TOUCH_SIZE;
+    touches.push({
+      touchId: view.getUint32(offset, true),
+      surfaceId: view.getUint8(offset + 4),
+      position: [
+        view.getFloat32(offset + 8, true),
+        view.getFloat32(offset + 12, true),
+      ],
+    });
+  }
+  return touches;
+}
+
+// Pulses the haptics under a trackpad, pad 0 is the left and 1 the right one.
+function triggerHapticPulse(
+  index: number,
+  pad: number,
+  durationUs: number,
+  intervalUs = 0,
+  count = 1,
+): boolean {
+  return ffi.symbols.trigger_haptic_pulse(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    pad,
+    durationUs,
+    intervalUs,
+    count,
+  );
+}
+
+function getWheelState(index: number): WheelState | null {
+  const buf = new Uint8Array(WHEEL_STATE_SIZE);
+  const ok = ffi.symbols.get_wheel_state(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(buf.buffer);
+  return {
+    steering: view.getFloat32(0, true),
+    throttle: view.getFloat32(4, true),
+    brake: view.getFloat32(8, true),
+    clutch: view.getFloat32(12, true),
+    range: view.getUint16(16, true),
+    gear: view.getInt8(18),
+  };
+}
+
+function setWheelRange(index: number, degrees: number): boolean {
+  return ffi.symbols.set_wheel_range(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    degrees,
+  );
+}
+
+// Strength of the built-in centering spring, 0 turns it off.
+function setWheelAutocenter(index: number, strength: number): boolean {
+  return ffi.symbols.set_wheel_autocenter(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    strength,
+  );
+}
+
+function encodeWheelEffect(effect: WheelEffect): Uint8Array {
+  const buf = new Uint8Array(WHEEL_EFFECT_SIZE);
+  const view = new DataView(buf.buffer);
+  view.setUint8(0, WHEEL_EFFECT_TYPES.indexOf(effect.type));
+  if (effect.type === "constant") {
+    view.setFloat32(4, effect.level, true);
+  } else {
+    if (effect.type === "spring") {
+      view.setFloat32(8, effect.center, true);
+      view.setFloat32(12, effect.deadband, true);
+    }
+    view.setFloat32(16, effect.leftCoefficient, true);
+    view.setFloat32(20, effect.rightCoefficient, true);
+    view.setFloat32(24, effect.saturation, true);
+  }
+  return buf;
+}
+
+// Starts a force feedback effect, returns its slot or null if all slots are in use.
+function uploadWheelEffect(index: number, effect: WheelEffect): number | null {
+  const slot = ffi.symbols.upload_wheel_effect(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(encodeWheelEffect(effect)),
+  );
+  return slot < 0 ? null : slot;
+}
+
+function updateWheelEffect(
+  index: number,
+  slot: number,
+  effect: WheelEffect,
+): boolean {
+  return ffi.symbols.update_wheel_effect(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    BigInt(slot),
+    Deno.UnsafePointer.of(encodeWheelEffect(effect)),
+  );
+}
+
+function stopWheelEffect(index: number, slot: number): boolean {
+  return ffi.symbols.stop_wheel_effect(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    BigInt(slot),
+  );
+}
+
+// Scales the force of all effects, 0..1.
+function setWheelGain(index: number, gain: number): boolean {
+  return ffi.symbols.set_wheel_gain(__ptr_gamepad_api__, BigInt(index), gain);
+}
+
+// Resolves once a tag was held against the right Joy-Con or Pro Controller, with null if the
+// read failed or was cancelled. Only progresses while getGamepads is being called.
+function readNfcTag(index: number): Promise<NfcTag | null> {
+  return new Promise((resolve) => {
+    const callback = new Deno.UnsafeCallback(
+      { parameters: ["usize", "pointer", "pointer"], result: "void" } as const,
+      (_index, tagPtr, _userData) => {
+        queueMicrotask(() => callback.close());
+        if (tagPtr === null) {
+          resolve(null);
+          return;
+        }
+        const view = new Deno.UnsafePointerView(tagPtr);
+        const tag = new Uint8Array(view.getArrayBuffer(NFC_TAG_SIZE).slice(0));
+        const uidLen = tag[0];
+        const dataLen = tag[12] | (tag[13] << 8);
+        resolve({
+          uid: tag.slice(1, 1 + uidLen),
+          data: tag.slice(14, 14 + dataLen),
+        });
+      },
+    );
+    const ok = ffi.symbols.read_nfc_tag(
+      __ptr_gamepad_api__,
+      BigInt(index),
+      callback.pointer,
+      null,
+    );
+    if (!ok) {
+      callback.close();
+      resolve(null);
+    }
+  });
+}
+
+function cancelNfcRead(index: number): boolean {
+  return ffi.symbols.cancel_nfc_read(__ptr_gamepad_api__, BigInt(index));
+}
+
+// Starts the IR camera of a right Joy-Con, its data is collected while getGamepads is polled.
+function startIrCamera(index: number, config: IrConfig): boolean {
+  const buf = new Uint8Array(IR_CONFIG_SIZE);
+  const view = new DataView(buf.buffer);
+  view.setUint8(0, IR_MODES[config.mode]);
+  const resolution = IR_RESOLUTIONS.indexOf(config.resolution ?? "320x240");
+  view.setUint8(1, resolution);
+  view.setUint8(
+    2,
+    (config.farLeds ?? true ? 1 : 0) | (config.nearLeds ?? true ? 2 : 0),
+  );
+  view.setUint8(3, config.intensity ?? 15);
+  view.setUint8(4, config.intensity ?? 15);
+  view.setUint16(6, config.exposureUs ?? 200, true);
+  return ffi.symbols.start_ir_camera(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(buf),
+  );
+}
+
+function stopIrCamera(index: number): boolean {
+  return ffi.symbols.stop_ir_camera(__ptr_gamepad_api__, BigInt(index));
+}
+
+// The last complete image in image mode, null before the first one.
+function getIrImage(index: number): IrImage | null {
+  const header = new Uint8Array(IR_IMAGE_SIZE);
+  const pixels = new Uint8Array(MAX_IR_IMAGE_SIZE);
+  const ok = ffi.symbols.get_ir_image(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    Deno.UnsafePointer.of(header),
+    Deno.UnsafePointer.of(pixels),
+    BigInt(pixels.length),
+  );
+  if (!ok) {
+    return null;
+  }
+  const view = new DataView(header.buffer);
+  const width = view.getUint16(4, true);
+  const height = view.getUint16(6, true);
+  return {
+    frameId: view.getUint32(0, true),
+    width,
+    height,
+    pixels: pixels.slice(0, width * height),
+  };
+}
+
+function getIrMoments(index: number): IrMoment[] {
+  const buf = new Uint8Array(IR_MOMENT_SIZE * IR_MOMENT_COUNT);
+  const len = Number(
+    ffi.symbols.get_ir_moments(
+      __ptr_gamepad_api__,
+      BigInt(index),
+      Deno.UnsafePointer.of(buf),
+      BigInt(IR_MOMENT_COUNT),
+    ),
+  );
+  const view = new DataView(buf.buffer);
+  const moments: IrMoment[] = [];
+  for (let i = 0; i < len; i++) {
+    const offset = i * IR_MOMENT_SIZE;
+    moments.push({
+      averageIntensity: view.getUint16(offset, true),
+      centroid: [
+        view.getUint16(offset + 2, true),
+        view.getUint16(offset + 4, true),
+      ],
+    });
+  }
+  return moments;
+}
+
+function getIrClusters(index: number): IrCluster[] {
+  const buf = new Uint8Array(IR_CLUSTER_SIZE * MAX_IR_CLUSTERS);
+  const len = Number(
+    ffi.symbols.get_ir_clusters(
+      __ptr_gamepad_api__,
+      BigInt(index),
+      Deno.UnsafePointer.of(buf),
+      BigInt(MAX_IR_CLUSTERS),
+    ),
+  );
+  const view = new DataView(buf.buffer);
+  const clusters: IrCluster[] = [];
+  for (let i = 0; i < len; i++) {
+    const offset = i * IR_CLUSTER_SIZE;
+    const u16 = (n: number) => view.getUint16(offset + n * 2, true);
+    clusters.push({
+      averageIntensity: u16(0),
+      pixelCount: u16(1),
+      centroid: [u16(2), u16(3)],
+      bounds: [u16(4), u16(5), u16(6), u16(7)],
+    });
+  }
+  return clusters;
+}
+
+function addMappings(mappings: string): number {
+  const buf = new TextEncoder().encode(mappings + "\0");
+  return Number(
+    ffi.symbols.add_gamepad_mappings(
+      __ptr_gamepad_api__,
+      Deno.UnsafePointer.of(buf),
+    ),
+  );
+}
+
+// Replaces the input profiles with the ones in a TOML file. Returns 0, else
+// the number of the first line that isn't understood, keeping the current
+// profiles.
+function loadProfiles(profiles: string): number {
+  const buf = new TextEncoder().encode(profiles + "\0");
+  return Number(
+    ffi.symbols.load_profiles(
+      __ptr_gamepad_api__,
+      Deno.UnsafePointer.of(buf),
+    ),
+  );
+}
+
+// Switches a gamepad to another loaded profile, null goes back to the one
+// assigned in the file.
+function setProfile(index: number, name: string | null): boolean {
+  const buf = name === null ? null : new TextEncoder().encode(name + "\0");
+  return ffi.symbols.set_profile(
+    __ptr_gamepad_api__,
+    BigInt(index),
+    buf && Deno.UnsafePointer.of(buf),
+  );
+}
+
+// Returning Nintendo controllers use the calibration cached in `path` until
+// their SPI flash was read again.
+function enableCalibrationCache(path: string): boolean {
+  const buf = new TextEncoder().encode(path + "\0");
+  return ffi.symbols.enable_calibration_cache(
+    __ptr_gamepad_api__,
+    Deno.UnsafePointer.of(buf),
+  );
+}
+
+export {
+  addMappings,
+  applyStickCalibration,
+  cancelNfcRead,
+  cancelSpiTransfer,
+  cancelStickCalibration,
+  enableCalibrationCache,
+  eraseUserStickCalibration,
+  getAdaptiveRange,
+  getCalibrationSources,
+  getColors,
+  getDeviceInfo,
+  getGamepads,
+  getInitFailure,
+  getIrClusters,
+  getIrImage,
+  getIrMoments,
+  getMotion,
+  getSpiDump,
+  getSpiProgress,
+  getStickCalibrationStatus,
+  getStickDrift,
+  getTouches,
+  getWheelState,
+  loadProfiles,
+  nextStickCalibrationStep,
+  readNfcTag,
+  setAdaptiveRange,
+  setDriftCompensation,
+  setProfile,
+  setRumble,
+  setStickGate,
+  setStickResponse,
+  setWheelAutocenter,
+  setWheelGain,
+  setWheelRange,
+  startIrCamera,
+  startSpiDump,
+  startSpiRestore,
+  startStickCalibration,
+  stopIrCamera,
+  stopWheelEffect,
+  summarizeSpiDump,
+  triggerHapticPulse,
+  updateWheelEffect,
+  uploadWheelEffect,
+};
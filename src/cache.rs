@@ -0,0 +1,367 @@
+/**
+ * Calibration cache: keeps the calibration and colors read from the SPI flash of Nintendo
+ * controllers in a text file, keyed by Bluetooth address, so a returning controller is usable
+ * before its SPI flash was read again. One controller per line:
+ *
+ *  <address> <firmware major>.<minor> <type> <29 calibration values> <3 sources> <colors>
+ *  [<8 learned stick bounds>]
+ *
+ * Calibration values and sources are comma separated, colors are `body,buttons[,left,right]`
+ * in hex RGB, or `-` when unknown. Learned stick bounds are the minimums then the maximums of
+ * left x, left y, right x and right y, 0 when unknown.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+ */
+use crate::{
+    AdaptiveRange, CalibrationData, CalibrationSource, CalibrationSources, ControllerColors,
+    STICK_POSITION_MAX,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub firmware_version: [u8; 2],
+    pub controller_type: u8,
+    pub cal_data: CalibrationData,
+    pub colors: Option<ControllerColors>,
+    // Only the bounds are kept, not whether they're used.
+    pub range: AdaptiveRange,
+}
+
+pub struct CalibrationCache {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl CalibrationCache {
+    /// Loads the cache at `path`, a missing file is an empty cache. Lines that don't parse or
+    /// hold impossible stick calibration are left out, their controllers read it again.
+    pub fn open(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .map(|text| text.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+        CalibrationCache { path, entries }
+    }
+
+    /// The entry of a controller, if it was cached with the same firmware and type.
+    pub fn get(
+        &self,
+        key: &str,
+        firmware_version: [u8; 2],
+        controller_type: u8,
+    ) -> Option<&CacheEntry> {
+        self.entries.get(key).filter(|entry| {
+            entry.firmware_version == firmware_version && entry.controller_type == controller_type
+        })
+    }
+
+    /// Stores an entry and writes the file. Write errors only cost the next connection time.
+    pub fn insert(&mut self, key: String, entry: CacheEntry) {
+        self.entries.insert(key, entry);
+        self.write();
+    }
+
+    fn write(&self) {
+        let mut lines: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| format_line(key, entry))
+            .collect();
+        lines.sort();
+        let _ = std::fs::write(&self.path, lines.join("\n") + "\n");
+    }
+}
+
+fn calibration_values(cal: &CalibrationData) -> [u16; 29] {
+    [
+        cal.lx_center,
+        cal.lx_min,
+        cal.lx_max,
+        cal.ly_center,
+        cal.ly_min,
+        cal.ly_max,
+        cal.rx_center,
+        cal.rx_min,
+        cal.rx_max,
+        cal.ry_center,
+        cal.ry_min,
+        cal.ry_max,
+        cal.dead_zone,
+        cal.range_ratio,
+        cal.accelerometer_origin_x,
+        cal.accelerometer_origin_y,
+        cal.accelerometer_origin_z,
+        cal.accelerometer_sensitivity_x,
+        cal.accelerometer_sensitivity_y,
+        cal.accelerometer_sensitivity_z,
+        cal.gyro_origin_x,
+        cal.gyro_origin_y,
+        cal.gyro_origin_z,
+        cal.gyro_sensitivity_x,
+        cal.gyro_sensitivity_y,
+        cal.gyro_sensitivity_z,
+        cal.horizontal_offset_x,
+        cal.horizontal_offset_y,
+        cal.horizontal_offset_z,
+    ]
+}
+
+// Fields in the order of `calibration_values`.
+fn calibration_from_values(values: [u16; 29], sources: CalibrationSources) -> CalibrationData {
+    let mut values = values.into_iter();
+    let mut next = || values.next().unwrap();
+    CalibrationData {
+        lx_center: next(),
+        lx_min: next(),
+        lx_max: next(),
+        ly_center: next(),
+        ly_min: next(),
+        ly_max: next(),
+        rx_center: next(),
+        rx_min: next(),
+        rx_max: next(),
+        ry_center: next(),
+        ry_min: next(),
+        ry_max: next(),
+        dead_zone: next(),
+        range_ratio: next(),
+        accelerometer_origin_x: next(),
+        accelerometer_origin_y: next(),
+        accelerometer_origin_z: next(),
+        accelerometer_sensitivity_x: next(),
+        accelerometer_sensitivity_y: next(),
+        accelerometer_sensitivity_z: next(),
+        gyro_origin_x: next(),
+        gyro_origin_y: next(),
+        gyro_origin_z: next(),
+        gyro_sensitivity_x: next(),
+        gyro_sensitivity_y: next(),
+        gyro_sensitivity_z: next(),
+        horizontal_offset_x: next(),
+        horizontal_offset_y: next(),
+        horizontal_offset_z: next(),
+        sources,
+    }
+}
+
+// Whether every stick axis has min < center < max in the 12 bit range, like calibration read
+// from the SPI flash after validation.
+fn has_valid_sticks(cal: &CalibrationData) -> bool {
+    [
+        (cal.lx_center, cal.lx_min, cal.lx_max),
+        (cal.ly_center, cal.ly_min, cal.ly_max),
+        (cal.rx_center, cal.rx_min, cal.rx_max),
+        (cal.ry_center, cal.ry_min, cal.ry_max),
+    ]
+    .iter()
+    .all(|&(center, min, max)| min < center && center < max && max <= STICK_POSITION_MAX)
+}
+
+fn format_rgb(rgb: [u8; 3]) -> String {
+    format!("{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+fn parse_rgb(text: &str) -> Option<[u8; 3]> {
+    let value = u32::from_str_radix(text, 16).ok()?;
+    (text.len() == 6).then_some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+fn format_line(key: &str, entry: &CacheEntry) -> String {
+    let values = calibration_values(&entry.cal_data).map(|value| value.to_string());
+    let sources = &entry.cal_data.sources;
+    let colors = match &entry.colors {
+        Some(colors) if colors.has_grips => [
+            colors.body,
+            colors.buttons,
+            colors.left_grip,
+            colors.right_grip,
+        ]
+        .map(format_rgb)
+        .join(","),
+        Some(colors) => [colors.body, colors.buttons].map(format_rgb).join(","),
+        None => "-".to_string(),
+    };
+    let range = [entry.range.min, entry.range.max]
+        .concat()
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>();
+    format!(
+        "{} {}.{} {} {} {},{},{} {} {}",
+        key,
+        entry.firmware_version[0],
+        entry.firmware_version[1],
+        entry.controller_type,
+        values.join(","),
+        sources.left_stick as u8,
+        sources.right_stick as u8,
+        sources.imu as u8,
+        colors,
+        range.join(","),
+    )
+}
+
+fn parse_line(line: &str) -> Option<(String, CacheEntry)> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    // Lines written before stick ranges were learned end with the colors.
+    let (fields, range) = match &fields[..] {
+        [fields @ .., range] if fields.len() == 6 => (fields, Some(*range)),
+        fields => (fields, None),
+    };
+    let &[key, firmware, controller_type, values, sources, colors] = fields else {
+        return None;
+    };
+    let (major, minor) = firmware.split_once('.')?;
+    let values: Vec<u16> = values
+        .split(',')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let sources: Vec<CalibrationSource> = sources
+        .split(',')
+        .map(|v| CalibrationSource::try_from(v.parse::<u8>().ok()?).ok())
+        .collect::<Option<_>>()?;
+    let &[left_stick, right_stick, imu] = &sources[..] else {
+        return None;
+    };
+    let colors = match colors {
+        "-" => None,
+        colors => {
+            let rgbs: Vec<_> = colors.split(',').map(parse_rgb).collect::<Option<_>>()?;
+            match rgbs[..] {
+                [body, buttons] => Some(ControllerColors {
+                    body,
+                    buttons,
+                    ..Default::default()
+                }),
+                [body, buttons, left_grip, right_grip] => Some(ControllerColors {
+                    body,
+                    buttons,
+                    left_grip,
+                    right_grip,
+                    has_grips: true,
+                }),
+                _ => return None,
+            }
+        }
+    };
+    let range = match range {
+        Some(range) => {
+            let bounds: Vec<u16> = range
+                .split(',')
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?;
+            if bounds.len() != 8 {
+                return None;
+            }
+            AdaptiveRange {
+                min: bounds[..4].try_into().ok()?,
+                max: bounds[4..].try_into().ok()?,
+                ..Default::default()
+            }
+        }
+        None => AdaptiveRange::default(),
+    };
+    let entry = CacheEntry {
+        firmware_version: [major.parse().ok()?, minor.parse().ok()?],
+        controller_type: controller_type.parse().ok()?,
+        cal_data: calibration_from_values(
+            values.try_into().ok()?,
+            CalibrationSources {
+                left_stick,
+                right_stick,
+                imu,
+            },
+        ),
+        colors,
+        range,
+    };
+    has_valid_sticks(&entry.cal_data).then(|| (key.to_string(), entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "98:b6:e9:12:34:56";
+    // Written before stick ranges were learned.
+    const OLD_LINE: &str = "98:b6:e9:12:34:56 4.33 3 \
+        2078,603,3508,1922,421,3299,2088,586,3550,2053,517,3441,174,3686,\
+        65486,65504,15,16384,16384,16384,65533,2,65532,13371,13371,13371,80,0,4081 \
+        1,2,1 323232,ffffff,1edc00,1edc00";
+
+    fn sample_entry() -> CacheEntry {
+        let (_, entry) = parse_line(OLD_LINE).unwrap();
+        CacheEntry {
+            range: AdaptiveRange {
+                min: [410, 0, 395, 402],
+                max: [3705, 3690, 0, 3712],
+                ..Default::default()
+            },
+            ..entry
+        }
+    }
+
+    #[test]
+    fn parses_lines_without_ranges() {
+        let (key, entry) = parse_line(OLD_LINE).unwrap();
+        assert_eq!(key, KEY);
+        assert_eq!(entry.firmware_version, [4, 33]);
+        assert_eq!(entry.controller_type, 3);
+        let cal = &entry.cal_data;
+        assert_eq!((cal.lx_center, cal.lx_min, cal.lx_max), (2078, 603, 3508));
+        assert_eq!((cal.ry_center, cal.ry_min, cal.ry_max), (2053, 517, 3441));
+        assert_eq!(cal.accelerometer_origin_x, 65486);
+        assert_eq!(cal.horizontal_offset_z, 4081);
+        assert_eq!(cal.sources.left_stick, CalibrationSource::Factory);
+        assert_eq!(cal.sources.right_stick, CalibrationSource::User);
+        let colors = entry.colors.unwrap();
+        assert!(colors.has_grips);
+        assert_eq!(colors.body, [0x32, 0x32, 0x32]);
+        assert_eq!(colors.right_grip, [0x1e, 0xdc, 0x00]);
+        assert_eq!(entry.range, AdaptiveRange::default());
+    }
+
+    #[test]
+    fn round_trips_entries() {
+        let entry = sample_entry();
+        let line = format_line(KEY, &entry);
+        let (key, parsed) = parse_line(&line).unwrap();
+        assert_eq!(key, KEY);
+        assert_eq!(parsed.range, entry.range);
+        assert_eq!(format_line(&key, &parsed), line);
+
+        let without_colors = CacheEntry {
+            colors: None,
+            ..entry
+        };
+        let line = format_line(KEY, &without_colors);
+        let (_, parsed) = parse_line(&line).unwrap();
+        assert!(parsed.colors.is_none());
+        assert_eq!(format_line(KEY, &parsed), line);
+    }
+
+    #[test]
+    fn rejects_impossible_stick_calibration() {
+        let mut entry = sample_entry();
+        entry.cal_data.lx_min = entry.cal_data.lx_center;
+        assert!(parse_line(&format_line(KEY, &entry)).is_none());
+
+        let mut entry = sample_entry();
+        entry.cal_data.ry_max = 0x1000;
+        assert!(parse_line(&format_line(KEY, &entry)).is_none());
+
+        let mut entry = sample_entry();
+        entry.cal_data.ly_center = 0;
+        assert!(parse_line(&format_line(KEY, &entry)).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line(&OLD_LINE.replace(" 1,2,1 ", " 1,2 ")).is_none());
+        assert!(parse_line(&OLD_LINE.replace("323232", "3232")).is_none());
+        assert!(parse_line(&format!("{OLD_LINE} 1,2,3")).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+/**
+ * Stick calibration wizard: samples the resting center of a stick, then its range while it's
+ * rotated along the edge of its gate, and optionally stores the result as user calibration in
+ * the SPI flash, where a Switch would put it.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+ */
+use crate::spi::SpiWrite;
+use crate::{
+    util, CalibrationPhase, SPIAddress, StickCalibrationStatus, SubcommandID,
+    USER_CALIBRATION_MAGIC,
+};
+use num_enum::TryFromPrimitive;
+
+// Per stick user calibration: the magic and 9 bytes of calibration.
+const USER_STICK_CALIBRATION_SIZE: usize = 11;
+
+// Enough reports to average out the noise of a resting stick.
+const MIN_CENTER_SAMPLES: u32 = 30;
+// Smallest distance from the center in every direction, worn sticks reach about 1300.
+const MIN_RANGE: u16 = 600;
+
+// `StickCalibrationStatus::stick` while erasing the calibration of both sticks.
+const BOTH_STICKS: u8 = 2;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum Stick {
+    Left = 0,
+    Right = 1,
+}
+
+/// Calibration of one stick in the absolute form of `CalibrationData`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StickCalibration {
+    pub center: [u16; 2],
+    pub min: [u16; 2],
+    pub max: [u16; 2],
+}
+
+impl StickCalibration {
+    /// The SPI flash layout: distances from the center, in the order of the stick.
+    pub fn to_spi(self, stick: Stick) -> [u8; 9] {
+        let center = util::pack_shorts(self.center[0], self.center[1]);
+        let min = util::pack_shorts(self.center[0] - self.min[0], self.center[1] - self.min[1]);
+        let max = util::pack_shorts(self.max[0] - self.center[0], self.max[1] - self.center[1]);
+        let parts = match stick {
+            Stick::Left => [max, center, min],
+            Stick::Right => [center, min, max],
+        };
+        let mut bytes = [0; 9];
+        for (chunk, part) in bytes.chunks_exact_mut(3).zip(parts) {
+            chunk.copy_from_slice(&part);
+        }
+        bytes
+    }
+}
+
+pub struct CalibrationSession {
+    // `None` when erasing the user calibration of both sticks.
+    stick: Option<Stick>,
+    phase: CalibrationPhase,
+    center_sum: [u32; 2],
+    sample_count: u32,
+    min: [u16; 2],
+    max: [u16; 2],
+    result: Option<StickCalibration>,
+    write: Option<SpiWrite>,
+    erased: bool,
+}
+
+impl CalibrationSession {
+    pub fn new(stick: Stick) -> Self {
+        CalibrationSession {
+            stick: Some(stick),
+            phase: CalibrationPhase::SamplingCenter,
+            center_sum: [0; 2],
+            sample_count: 0,
+            min: [u16::MAX; 2],
+            max: [0; 2],
+            result: None,
+            write: None,
+            erased: false,
+        }
+    }
+
+    /// Overwrites the user calibration of both sticks, which restores the factory calibration.
+    pub fn erase() -> Self {
+        let mut session = Self::new(Stick::Left);
+        session.stick = None;
+        session.phase = CalibrationPhase::Writing;
+        session.write = Some(SpiWrite::new(
+            SPIAddress::UserStickCalibration as u32,
+            vec![0xff; USER_STICK_CALIBRATION_SIZE * 2],
+        ));
+        session
+    }
+
+    pub fn stick(&self) -> Option<Stick> {
+        self.stick
+    }
+
+    pub fn phase(&self) -> CalibrationPhase {
+        self.phase
+    }
+
+    pub fn result(&self) -> Option<StickCalibration> {
+        self.result
+    }
+
+    /// True once after the erase finished, when the calibration needs reading again.
+    pub fn take_erased(&mut self) -> bool {
+        std::mem::take(&mut self.erased)
+    }
+
+    pub fn status(&self) -> StickCalibrationStatus {
+        let center = match self.sample_count {
+            0 => [0; 2],
+            count => self.center_sum.map(|sum| (sum / count) as u16),
+        };
+        StickCalibrationStatus {
+            phase: self.phase as u8,
+            stick: self.stick.map_or(BOTH_STICKS, |stick| stick as u8),
+            sample_count: self.sample_count,
+            center: self.result.map_or(center, |result| result.center),
+            min: self.min,
+            max: self.max,
+            ..Default::default()
+        }
+    }
+
+    /// Handles the raw stick positions of a full report.
+    pub fn on_sticks(&mut self, left: &[u8; 3], right: &[u8; 3]) {
+        let raw = match self.stick {
+            Some(Stick::Left) => left,
+            Some(Stick::Right) => right,
+            None => return,
+        };
+        let mut position = [0; 2];
+        let [x, y] = &mut position;
+        util::unpack_shorts(raw, x, y);
+        match self.phase {
+            CalibrationPhase::SamplingCenter => {
+                for (sum, value) in self.center_sum.iter_mut().zip(position) {
+                    *sum += value as u32;
+                }
+                self.sample_count += 1;
+            }
+            CalibrationPhase::SamplingRange => {
+                for (axis, value) in position.into_iter().enumerate() {
+                    self.min[axis] = self.min[axis].min(value);
+                    self.max[axis] = self.max[axis].max(value);
+                }
+                self.sample_count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves from center to range sampling, then computes the calibration. Returns false if
+    /// there aren't enough samples yet, or the session is past sampling.
+    pub fn next(&mut self) -> bool {
+        match self.phase {
+            CalibrationPhase::SamplingCenter if self.sample_count >= MIN_CENTER_SAMPLES => {
+                let center = self.center_sum.map(|sum| (sum / self.sample_count) as u16);
+                self.result = Some(StickCalibration {
+                    center,
+                    min: center,
+                    max: center,
+                });
+                self.min = center;
+                self.max = center;
+                self.sample_count = 0;
+                self.phase = CalibrationPhase::SamplingRange;
+                true
+            }
+            CalibrationPhase::SamplingRange => {
+                let Some(result) = &mut self.result else {
+                    return false;
+                };
+                let reaches = (0..2).all(|axis| {
+                    result.center[axis] - self.min[axis] >= MIN_RANGE
+                        && self.max[axis] - result.center[axis] >= MIN_RANGE
+                });
+                if !reaches {
+                    return false;
+                }
+                result.min = self.min;
+                result.max = self.max;
+                self.phase = CalibrationPhase::Sampled;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes the sampled calibration as user calibration.
+    pub fn write(&mut self) -> bool {
+        let (CalibrationPhase::Sampled, Some(stick), Some(result)) =
+            (self.phase, self.stick, self.result)
+        else {
+            return false;
+        };
+        let address = match stick {
+            Stick::Left => SPIAddress::UserStickCalibration as u32,
+            Stick::Right => {
+                SPIAddress::UserStickCalibration as u32 + USER_STICK_CALIBRATION_SIZE as u32
+            }
+        };
+        let mut data = USER_CALIBRATION_MAGIC.to_vec();
+        data.extend_from_slice(&result.to_spi(stick));
+        self.write = Some(SpiWrite::new(address, data));
+        self.phase = CalibrationPhase::Writing;
+        true
+    }
+
+    /// Marks a sampled calibration as applied without writing it.
+    pub fn finish(&mut self) -> bool {
+        if self.phase != CalibrationPhase::Sampled {
+            return false;
+        }
+        self.phase = CalibrationPhase::Done;
+        true
+    }
+
+    /// Returns the subcommand to send next, if any.
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        let write = self.write.as_mut()?;
+        let request = write.update();
+        if let Some(ok) = write.result() {
+            self.write = None;
+            self.phase = if ok {
+                CalibrationPhase::Done
+            } else {
+                CalibrationPhase::Failed
+            };
+            self.erased = ok && self.stick.is_none();
+        }
+        request
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        if let Some(write) = &mut self.write {
+            write.on_subcommand_reply(id, ack, data);
+        }
+    }
+}
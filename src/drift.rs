@@ -0,0 +1,143 @@
+/**
+ * Stick drift monitor: worn sticks come to rest away from the center in the SPI flash. While
+ * no button is pressed and a stick holds still near the center, its position slowly updates
+ * an estimate of where it rests. A controller lying still, as told by its gyroscope, makes
+ * that estimate trustworthy sooner. Optionally the axes are recentered on the estimate and
+ * the deadzone grows to cover its noise, nothing is written to the controller.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+ */
+use crate::{Motion, StickDrift};
+
+// Reports a stick has to hold still before it counts as resting, fewer when the controller
+// lies still as well.
+const REST_REPORTS: u32 = 120;
+const STILL_REST_REPORTS: u32 = 30;
+// Angular velocity of a controller lying on a table, in degrees per second.
+const STILL_GYRO: f32 = 5.0;
+
+// Movement between reports that still counts as holding still, in calibrated axes.
+const STABLE_TOLERANCE: f32 = 0.03;
+// Farther from the center it's held by a thumb, not drifting.
+const MAX_DRIFT: f32 = 0.4;
+// How fast the rest position and its noise follow resting positions.
+const REST_SMOOTHING: f32 = 0.05;
+// Resting positions before the estimate is used to recenter.
+const MIN_REST_SAMPLES: u32 = 60;
+// Deadzone around the rest position, in multiples of its noise.
+const NOISE_MARGIN: f32 = 3.0;
+
+#[derive(Debug, Default, Clone)]
+struct StickMonitor {
+    // Position in the last report, to tell whether the stick holds still.
+    recent: [f32; 2],
+    still_reports: u32,
+    rest: [f32; 2],
+    noise: f32,
+    samples: u32,
+}
+
+impl StickMonitor {
+    fn update(&mut self, position: [f32; 2], required_reports: u32) {
+        let moved = (position[0] - self.recent[0]).hypot(position[1] - self.recent[1]);
+        self.recent = position;
+        if moved > STABLE_TOLERANCE || position[0].hypot(position[1]) > MAX_DRIFT {
+            self.still_reports = 0;
+            return;
+        }
+        self.still_reports += 1;
+        if self.still_reports < required_reports {
+            return;
+        }
+        if self.samples == 0 {
+            self.rest = position;
+        }
+        let deviation = (position[0] - self.rest[0]).hypot(position[1] - self.rest[1]);
+        for (rest, value) in self.rest.iter_mut().zip(position) {
+            *rest += (value - *rest) * REST_SMOOTHING;
+        }
+        self.noise += (deviation - self.noise) * REST_SMOOTHING;
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    fn is_known(&self) -> bool {
+        self.samples >= MIN_REST_SAMPLES
+    }
+}
+
+// Moves `rest` to 0 and keeps -1 and 1 where they are.
+fn recenter(value: f32, rest: f32) -> f32 {
+    if value >= rest {
+        (value - rest) / (1.0 - rest)
+    } else {
+        (value - rest) / (1.0 + rest)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DriftMonitor {
+    sticks: [StickMonitor; 2],
+    compensate: bool,
+}
+
+impl DriftMonitor {
+    pub fn set_compensation(&mut self, enabled: bool) {
+        self.compensate = enabled;
+    }
+
+    /// Handles the calibrated axes of a report, before any deadzone.
+    pub fn update(&mut self, axes: &[f32], any_pressed: bool, motion: Option<&Motion>) {
+        if any_pressed {
+            for stick in &mut self.sticks {
+                stick.still_reports = 0;
+            }
+            return;
+        }
+        let still = motion.is_some_and(|motion| {
+            motion.has_gyro && motion.gyro.iter().all(|value| value.abs() < STILL_GYRO)
+        });
+        let required_reports = if still {
+            STILL_REST_REPORTS
+        } else {
+            REST_REPORTS
+        };
+        for (stick, position) in self.sticks.iter_mut().zip(axes.chunks_exact(2)) {
+            stick.update([position[0], position[1]], required_reports);
+        }
+    }
+
+    pub fn status(&self, stick: usize) -> StickDrift {
+        let monitor = &self.sticks[stick];
+        StickDrift {
+            rest: monitor.rest,
+            drift: monitor.rest[0].hypot(monitor.rest[1]),
+            noise: monitor.noise,
+            samples: monitor.samples,
+        }
+    }
+
+    /// Recenters the sticks on their rest positions, if enabled and known.
+    pub fn compensate(&self, axes: &mut [f32]) {
+        if !self.compensate {
+            return;
+        }
+        for (stick, position) in self.sticks.iter().zip(axes.chunks_exact_mut(2)) {
+            if stick.is_known() {
+                for (value, rest) in position.iter_mut().zip(stick.rest) {
+                    *value = recenter(*value, rest).clamp(-1.0, 1.0);
+                }
+            }
+        }
+    }
+
+    /// The deadzone of a stick, grown to cover the noise of its rest position if compensating.
+    pub fn deadzone(&self, stick: usize, deadzone: f32) -> f32 {
+        let monitor = &self.sticks[stick];
+        if self.compensate && monitor.is_known() {
+            deadzone.max(monitor.noise * NOISE_MARGIN)
+        } else {
+            deadzone
+        }
+    }
+}
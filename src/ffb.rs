@@ -0,0 +1,379 @@
+/**
+ * Force feedback effects for Logitech wheels, in the classic Logitech command encoding.
+ *
+ * ref:
+ *  https://github.com/torvalds/linux/blob/master/drivers/hid/hid-lg4ff.c
+ *  https://github.com/berarma/new-lg4ff
+ *  Logitech Force Feedback Protocol V1.6
+ */
+// One 7 byte output report.
+pub type Command = [u8; 7];
+
+pub const SLOT_COUNT: usize = 4;
+
+// Low nibble of the first byte, the high nibble selects the slots.
+const CMD_DOWNLOAD_AND_PLAY: u8 = 0x01;
+const CMD_STOP: u8 = 0x03;
+const CMD_DEFAULT_SPRING_ON: u8 = 0x04;
+const CMD_DEFAULT_SPRING_OFF: u8 = 0x05;
+const CMD_REFRESH: u8 = 0x0c;
+const CMD_EXTENDED: u8 = 0xf8;
+const CMD_SET_DEFAULT_SPRING: u8 = 0xfe;
+
+const EXT_CMD_SET_RANGE: u8 = 0x81;
+
+const FORCE_TYPE_VARIABLE: u8 = 0x08;
+const FORCE_TYPE_HIGH_RES_SPRING: u8 = 0x0b;
+const FORCE_TYPE_HIGH_RES_DAMPER: u8 = 0x0c;
+const FORCE_TYPE_DEFAULT_SPRING: u8 = 0x0d;
+
+// No force for the variable force type.
+const LEVEL_CENTER: u8 = 0x80;
+const POSITION_MAX: f32 = 2047.0;
+const COEFFICIENT_MAX: f32 = 15.0;
+
+pub const RANGE_MIN: u16 = 40;
+pub const RANGE_MAX: u16 = 900;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// `level` in `-1.0..=1.0`, positive pushes the wheel to the right.
+    Constant { level: f32 },
+    /// Pulls the wheel towards `center` outside of `center ± deadband`, all positions in
+    /// `-1.0..=1.0`. Coefficients are in `-1.0..=1.0` for each side, negative pushes away,
+    /// and `saturation` in `0.0..=1.0` caps the force.
+    Spring {
+        center: f32,
+        deadband: f32,
+        left_coefficient: f32,
+        right_coefficient: f32,
+        saturation: f32,
+    },
+    /// Resists wheel movement, coefficients and `saturation` as for `Effect::Spring`.
+    Damper {
+        left_coefficient: f32,
+        right_coefficient: f32,
+        saturation: f32,
+    },
+}
+
+fn slot_mask(slot: usize) -> u8 {
+    0x10 << slot
+}
+
+fn level_byte(level: f32) -> u8 {
+    (LEVEL_CENTER as f32 + (level.clamp(-1.0, 1.0) * 127.0).round()) as u8
+}
+
+fn position(value: f32) -> u16 {
+    ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * POSITION_MAX).round() as u16
+}
+
+// 4 bit magnitude and the sign bit, set for negative coefficients.
+fn coefficient(value: f32) -> (u8, u8) {
+    let value = value.clamp(-1.0, 1.0);
+    (
+        (value.abs() * COEFFICIENT_MAX).round() as u8,
+        (value < 0.0) as u8,
+    )
+}
+
+fn saturation_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Encodes `effect` for `slot`, with `gain` in `0.0..=1.0` applied to its force.
+/// `command` is `CMD_DOWNLOAD_AND_PLAY` or `CMD_REFRESH`.
+fn encode_effect(command: u8, slot: usize, effect: &Effect, gain: f32) -> Command {
+    let header = slot_mask(slot) | command;
+    match *effect {
+        Effect::Constant { level } => [
+            header,
+            FORCE_TYPE_VARIABLE,
+            level_byte(level * gain),
+            LEVEL_CENTER,
+            0,
+            0,
+            0,
+        ],
+        Effect::Spring {
+            center,
+            deadband,
+            left_coefficient,
+            right_coefficient,
+            saturation,
+        } => {
+            let deadband = deadband.abs();
+            let d1 = position(center - deadband);
+            let d2 = position(center + deadband);
+            let (k1, s1) = coefficient(left_coefficient * gain);
+            let (k2, s2) = coefficient(right_coefficient * gain);
+            [
+                header,
+                FORCE_TYPE_HIGH_RES_SPRING,
+                (d1 >> 3) as u8,
+                (d2 >> 3) as u8,
+                k2 << 4 | k1,
+                ((d2 & 0x7) as u8) << 5 | s2 << 4 | ((d1 & 0x7) as u8) << 1 | s1,
+                saturation_byte(saturation),
+            ]
+        }
+        Effect::Damper {
+            left_coefficient,
+            right_coefficient,
+            saturation,
+        } => {
+            let (k1, s1) = coefficient(left_coefficient * gain);
+            let (k2, s2) = coefficient(right_coefficient * gain);
+            [
+                header,
+                FORCE_TYPE_HIGH_RES_DAMPER,
+                k1,
+                s1,
+                k2,
+                s2,
+                saturation_byte(saturation),
+            ]
+        }
+    }
+}
+
+pub fn encode_play(slot: usize, effect: &Effect, gain: f32) -> Command {
+    encode_effect(CMD_DOWNLOAD_AND_PLAY, slot, effect, gain)
+}
+
+/// Updates the parameters of an effect already playing in `slot`.
+pub fn encode_refresh(slot: usize, effect: &Effect, gain: f32) -> Command {
+    encode_effect(CMD_REFRESH, slot, effect, gain)
+}
+
+pub fn encode_stop(slot: usize) -> Command {
+    [slot_mask(slot) | CMD_STOP, 0, 0, 0, 0, 0, 0]
+}
+
+pub fn encode_stop_all() -> Command {
+    [0xf0 | CMD_STOP, 0, 0, 0, 0, 0, 0]
+}
+
+/// The built-in centering spring, `strength` in `0.0..=1.0` and 0.0 turns it off.
+pub fn encode_autocenter(strength: f32) -> Vec<Command> {
+    let magnitude = (strength.clamp(0.0, 1.0) * 0xffff as f32) as u32;
+    if magnitude == 0 {
+        return vec![[0xf0 | CMD_DEFAULT_SPRING_OFF, 0, 0, 0, 0, 0, 0]];
+    }
+
+    // Same curve as hid-lg4ff for all wheels but the MOMO.
+    let (expand_a, expand_b) = if magnitude <= 0xaaaa {
+        (0x0c * magnitude, 0x80 * magnitude)
+    } else {
+        (
+            0x0c * 0xaaaa + 0x06 * (magnitude - 0xaaaa),
+            0x80 * 0xaaaa + 0xff * (magnitude - 0xaaaa),
+        )
+    };
+    let expand_a = expand_a >> 1;
+    vec![
+        [
+            CMD_SET_DEFAULT_SPRING,
+            FORCE_TYPE_DEFAULT_SPRING,
+            (expand_a / 0xaaaa) as u8,
+            (expand_a / 0xaaaa) as u8,
+            (expand_b / 0xaaaa) as u8,
+            0,
+            0,
+        ],
+        [0x10 | CMD_DEFAULT_SPRING_ON, 0, 0, 0, 0, 0, 0],
+    ]
+}
+
+/// Sets the rotation range in degrees, lock to lock.
+pub fn encode_range(degrees: u16) -> Command {
+    let [low, high] = degrees.clamp(RANGE_MIN, RANGE_MAX).to_le_bytes();
+    [CMD_EXTENDED, EXT_CMD_SET_RANGE, low, high, 0, 0, 0]
+}
+
+/// Effects playing on a wheel, one per hardware slot.
+#[derive(Debug)]
+pub struct EffectSlots {
+    slots: [Option<Effect>; SLOT_COUNT],
+    gain: f32,
+}
+
+impl Default for EffectSlots {
+    fn default() -> Self {
+        EffectSlots {
+            slots: [None; SLOT_COUNT],
+            gain: 1.0,
+        }
+    }
+}
+
+impl EffectSlots {
+    /// Plays `effect` in a free slot, returns the slot and the command to send.
+    pub fn upload(&mut self, effect: Effect) -> Option<(usize, Command)> {
+        let slot = self.slots.iter().position(Option::is_none)?;
+        self.slots[slot] = Some(effect);
+        Some((slot, encode_play(slot, &effect, self.gain)))
+    }
+
+    /// Changes the effect playing in `slot`, returns `None` for an empty slot.
+    pub fn update(&mut self, slot: usize, effect: Effect) -> Option<Command> {
+        let current = self.slots.get_mut(slot)?.as_mut()?;
+        // A refresh can't change the force type.
+        let same_type = std::mem::discriminant(current) == std::mem::discriminant(&effect);
+        *current = effect;
+        if same_type {
+            Some(encode_refresh(slot, &effect, self.gain))
+        } else {
+            Some(encode_play(slot, &effect, self.gain))
+        }
+    }
+
+    /// Stops and frees `slot`, returns `None` for an empty slot.
+    pub fn stop(&mut self, slot: usize) -> Option<Command> {
+        self.slots.get_mut(slot)?.take()?;
+        Some(encode_stop(slot))
+    }
+
+    /// Sets the overall strength in `0.0..=1.0`, returns the commands updating the
+    /// effects already playing.
+    pub fn set_gain(&mut self, gain: f32) -> Vec<Command> {
+        self.gain = gain.clamp(0.0, 1.0);
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, effect)| Some(encode_refresh(slot, effect.as_ref()?, self.gain)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPRING: Effect = Effect::Spring {
+        center: 0.0,
+        deadband: 0.5,
+        left_coefficient: -0.5,
+        right_coefficient: 1.0,
+        saturation: 1.0,
+    };
+
+    #[test]
+    fn encodes_constant_force() {
+        // Slot 1 download and play, variable force on the first force of the pair.
+        let effect = Effect::Constant { level: 0.5 };
+        assert_eq!(
+            encode_play(0, &effect, 1.0),
+            [0x11, 0x08, 0xc0, 0x80, 0, 0, 0]
+        );
+        assert_eq!(
+            encode_play(0, &effect, 0.0),
+            [0x11, 0x08, 0x80, 0x80, 0, 0, 0]
+        );
+        let effect = Effect::Constant { level: -2.0 };
+        assert_eq!(
+            encode_play(3, &effect, 1.0),
+            [0x81, 0x08, 0x01, 0x80, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn encodes_high_res_spring() {
+        // Deadband 512..1535 of 0..2047, k1 8 pushing away, k2 15, full clip.
+        assert_eq!(
+            encode_play(0, &SPRING, 1.0),
+            [0x11, 0x0b, 0x40, 0xbf, 0xf8, 0xe1, 0xff]
+        );
+        assert_eq!(
+            encode_refresh(1, &SPRING, 1.0),
+            [0x2c, 0x0b, 0x40, 0xbf, 0xf8, 0xe1, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_high_res_damper() {
+        let effect = Effect::Damper {
+            left_coefficient: 1.0,
+            right_coefficient: -1.0,
+            saturation: 0.5,
+        };
+        assert_eq!(
+            encode_play(2, &effect, 1.0),
+            [0x41, 0x0c, 0x0f, 0x00, 0x0f, 0x01, 0x80]
+        );
+    }
+
+    #[test]
+    fn encodes_stop() {
+        assert_eq!(encode_stop(0), [0x13, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode_stop(2), [0x43, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode_stop_all(), [0xf3, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn encodes_autocenter() {
+        assert_eq!(
+            encode_autocenter(1.0),
+            [
+                [0xfe, 0x0d, 0x07, 0x07, 0xff, 0, 0],
+                [0x14, 0, 0, 0, 0, 0, 0]
+            ]
+        );
+        assert_eq!(
+            encode_autocenter(0.5),
+            [
+                [0xfe, 0x0d, 0x04, 0x04, 0x5f, 0, 0],
+                [0x14, 0, 0, 0, 0, 0, 0]
+            ]
+        );
+        assert_eq!(encode_autocenter(0.0), [[0xf5, 0, 0, 0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn encodes_range() {
+        assert_eq!(encode_range(900), [0xf8, 0x81, 0x84, 0x03, 0, 0, 0]);
+        assert_eq!(encode_range(10), [0xf8, 0x81, 0x28, 0x00, 0, 0, 0]);
+        assert_eq!(encode_range(1080), [0xf8, 0x81, 0x84, 0x03, 0, 0, 0]);
+    }
+
+    #[test]
+    fn allocates_and_frees_slots() {
+        let mut slots = EffectSlots::default();
+        let effect = Effect::Constant { level: 0.0 };
+        for slot in 0..SLOT_COUNT {
+            assert_eq!(slots.upload(effect).unwrap().0, slot);
+        }
+        assert!(slots.upload(effect).is_none());
+        assert_eq!(slots.stop(1), Some(encode_stop(1)));
+        assert_eq!(slots.stop(1), None);
+        assert_eq!(slots.upload(effect).unwrap().0, 1);
+        assert_eq!(slots.stop(SLOT_COUNT), None);
+    }
+
+    #[test]
+    fn refreshes_only_same_force_type() {
+        let mut slots = EffectSlots::default();
+        let (slot, _) = slots.upload(Effect::Constant { level: 0.0 }).unwrap();
+        let effect = Effect::Constant { level: 1.0 };
+        assert_eq!(
+            slots.update(slot, effect),
+            Some(encode_refresh(0, &effect, 1.0))
+        );
+        assert_eq!(
+            slots.update(slot, SPRING),
+            Some(encode_play(0, &SPRING, 1.0))
+        );
+        assert_eq!(slots.update(1, SPRING), None);
+    }
+
+    #[test]
+    fn applies_gain_to_playing_effects() {
+        let mut slots = EffectSlots::default();
+        slots.upload(Effect::Constant { level: 1.0 }).unwrap();
+        assert_eq!(slots.set_gain(0.5), [[0x1c, 0x08, 0xc0, 0x80, 0, 0, 0]]);
+        // Effects started later get the gain as well.
+        let (_, command) = slots.upload(Effect::Constant { level: -1.0 }).unwrap();
+        assert_eq!(command, [0x21, 0x08, 0x40, 0x80, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,216 @@
+/**
+ * Nintendo GameCube controller adapter, four controller ports behind one HID device.
+ *
+ * port form:
+ *  https://github.com/libsdl-org/SDL/blob/main/src/joystick/hidapi/SDL_hidapi_gamecube.c
+ *  https://github.com/dolphin-emu/dolphin/blob/master/Source/Core/InputCommon/GCAdapter.cpp
+ */
+use crate::{util, Button, Gamepad, GamepadMapping};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use zerocopy::*;
+
+const VENDOR_ID_NINTENDO: u16 = 0x057e;
+const PRODUCT_ID_NINTENDO_GAMECUBE_ADAPTER: u16 = 0x0337;
+
+const PORT_COUNT: usize = 4;
+
+#[repr(u8)]
+enum OutputReportID {
+    Rumble = 0x11,
+    Init = 0x13,
+}
+
+const INPUT_REPORT_ID: u8 = 0x21;
+const INPUT_REPORT_SIZE: usize = 37;
+
+const PORT_STATUS_WIRED: u8 = 0x10;
+const PORT_STATUS_WIRELESS: u8 = 0x20;
+// Set when the adapter's second (power) USB cable is plugged in.
+const PORT_STATUS_RUMBLE_POWER: u8 = 0x04;
+
+// Usable stick and trigger travel from the origin, in raw units.
+const STICK_RANGE: f32 = 100.0;
+const TRIGGER_MAX: u8 = 230;
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug, Clone, Copy)]
+struct PortPacket {
+    status: u8,
+    buttons: [u8; 2],
+    stick_x: u8,
+    stick_y: u8,
+    c_stick_x: u8,
+    c_stick_y: u8,
+    trigger_l: u8,
+    trigger_r: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct AdapterInputPacket {
+    ports: [PortPacket; PORT_COUNT],
+}
+
+// (byte, mask, standard gamepad button), laid out by position.
+const BUTTON_LAYOUT: [(usize, u8, usize); 11] = [
+    (0, 0x01, 0),  // A
+    (0, 0x04, 1),  // X
+    (0, 0x02, 2),  // B
+    (0, 0x08, 3),  // Y
+    (1, 0x02, 5),  // Z
+    (1, 0x01, 9),  // Start
+    (0, 0x80, 12), // D-pad up
+    (0, 0x40, 13), // D-pad down
+    (0, 0x10, 14), // D-pad left
+    (0, 0x20, 15), // D-pad right
+    (1, 0x08, 6),  // L (digital click)
+];
+const BUTTON_R_CLICK: (usize, u8) = (1, 0x04);
+
+struct Port {
+    index: usize,
+    // First report after the controller was plugged in, the controller's neutral position.
+    origin: PortPacket,
+    state: PortPacket,
+    rumble: bool,
+}
+
+impl Port {
+    fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        let state = &self.state;
+        let origin = &self.origin;
+
+        for (byte, mask, index) in BUTTON_LAYOUT {
+            gamepad.buttons[index] = Button::digital(state.buttons[byte] & mask != 0);
+        }
+
+        let trigger = |value: u8, origin: u8| {
+            let range = TRIGGER_MAX.saturating_sub(origin).max(1) as f32;
+            value.saturating_sub(origin) as f32 / range
+        };
+        let l_click = gamepad.buttons[6].pressed;
+        let (byte, mask) = BUTTON_R_CLICK;
+        let r_click = state.buttons[byte] & mask != 0;
+        gamepad.buttons[6] = Button::analog(trigger(state.trigger_l, origin.trigger_l));
+        gamepad.buttons[6].pressed |= l_click;
+        gamepad.buttons[7] = Button::analog(trigger(state.trigger_r, origin.trigger_r));
+        gamepad.buttons[7].pressed |= r_click;
+
+        let axis =
+            |value: u8, origin: u8| ((value as f32 - origin as f32) / STICK_RANGE).clamp(-1.0, 1.0);
+        // Standard layout, up is -1.0.
+        gamepad.axes[0] = axis(state.stick_x, origin.stick_x);
+        gamepad.axes[1] = -axis(state.stick_y, origin.stick_y);
+        gamepad.axes[2] = axis(state.c_stick_x, origin.c_stick_x);
+        gamepad.axes[3] = -axis(state.c_stick_y, origin.c_stick_y);
+
+        gamepad.mapping = GamepadMapping::Standard;
+    }
+}
+
+pub struct GameCubeAdapter {
+    device: HidDevice,
+    // Shared by the controllers in its ports.
+    serial: Option<String>,
+    ports: [Option<Port>; PORT_COUNT],
+    rumble_allowed: [bool; PORT_COUNT],
+}
+
+impl GameCubeAdapter {
+    pub fn is_supported(device_info: &DeviceInfo) -> bool {
+        device_info.vendor_id() == VENDOR_ID_NINTENDO
+            && device_info.product_id() == PRODUCT_ID_NINTENDO_GAMECUBE_ADAPTER
+    }
+
+    pub fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Option<Self> {
+        let device = device_info.open_device(hidapi).ok()?;
+        // Without this the adapter doesn't send any input reports.
+        device.write(&[OutputReportID::Init as u8]).ok()?;
+        Some(GameCubeAdapter {
+            device,
+            serial: util::serial_number(device_info),
+            ports: Default::default(),
+            rumble_allowed: [false; PORT_COUNT],
+        })
+    }
+
+    /// Applies all pending input reports, returns false once the adapter is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; INPUT_REPORT_SIZE];
+        loop {
+            match self.device.read_timeout(&mut buf, 0) {
+                Ok(0) => return true,
+                Ok(len) => {
+                    if len == INPUT_REPORT_SIZE && buf[0] == INPUT_REPORT_ID {
+                        let packet = AdapterInputPacket::read_from_prefix(&buf[1..]).unwrap();
+                        self.update_ports(&packet);
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn update_ports(&mut self, packet: &AdapterInputPacket) {
+        for (i, state) in packet.ports.iter().enumerate() {
+            let wireless = state.status & PORT_STATUS_WIRELESS != 0;
+            let connected = state.status & (PORT_STATUS_WIRED | PORT_STATUS_WIRELESS) != 0;
+            self.rumble_allowed[i] = state.status & PORT_STATUS_RUMBLE_POWER != 0 && !wireless;
+
+            if !connected {
+                self.ports[i] = None;
+                continue;
+            }
+
+            let port = self.ports[i].get_or_insert_with(|| Port {
+                index: util::generate_id(),
+                origin: *state,
+                state: *state,
+                rumble: false,
+            });
+            port.state = *state;
+        }
+    }
+
+    pub fn fill_gamepads(&self, gamepads: &mut Vec<Gamepad>) {
+        for port in self.ports.iter().flatten() {
+            let mut gamepad = Gamepad {
+                index: port.index,
+                ..Default::default()
+            };
+            port.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+        }
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    pub fn has_gamepad(&self, index: usize) -> bool {
+        self.ports.iter().flatten().any(|port| port.index == index)
+    }
+
+    /// The adapter only supports switching the motor on and off.
+    pub fn set_rumble(&mut self, index: usize, strong: f32, weak: f32) -> bool {
+        let Some(i) = self
+            .ports
+            .iter()
+            .position(|port| port.as_ref().is_some_and(|port| port.index == index))
+        else {
+            return false;
+        };
+        if !self.rumble_allowed[i] {
+            return false;
+        }
+
+        self.ports[i].as_mut().unwrap().rumble = strong > 0.0 || weak > 0.0;
+
+        let mut packet = [0; 1 + PORT_COUNT];
+        packet[0] = OutputReportID::Rumble as u8;
+        for (value, port) in packet[1..].iter_mut().zip(&self.ports) {
+            *value = port.as_ref().is_some_and(|port| port.rumble) as u8;
+        }
+        self.device.write(&packet).is_ok()
+    }
+}
@@ -0,0 +1,122 @@
+/**
+ * Stick gate correction: the gate of a stick limits how far it reaches in each direction,
+ * octagonal gates keep diagonals short of a full deflection after the axes were calibrated
+ * one at a time. Dividing by the reach of the gate in the direction of the stick brings the
+ * whole edge to a distance of 1, cardinal directions stay as they are. The reach comes from a
+ * fixed profile or is learned from the farthest positions seen.
+ *
+ * ref:
+ *  https://github.com/Minimuino/thumbstick-deadzones
+ *  http://squircular.blogspot.com/2015/09/mapping-circle-to-square.html
+ */
+use crate::{GateShape, StickGate};
+use std::f32::consts::{FRAC_PI_4, PI, SQRT_2};
+
+// Directions the learned gate keeps its reach for, the first one points right.
+const LEARNED_SECTORS: usize = 32;
+// Closer positions don't teach anything, they're not at the edge.
+const MIN_LEARNED_REACH: f32 = 0.5;
+
+#[derive(Debug, Clone)]
+enum Profile {
+    Circle,
+    // Reach of the diagonal corners, cardinal corners reach 1.
+    Octagon(f32),
+    // Farthest position seen per sector, 0 before the first.
+    Learned([f32; LEARNED_SECTORS]),
+}
+
+#[derive(Debug, Clone)]
+pub struct GateCorrection {
+    profile: Profile,
+    square_output: bool,
+}
+
+impl GateCorrection {
+    /// `None` if the settings are out of range.
+    pub fn new(settings: &StickGate) -> Option<Self> {
+        let profile = match GateShape::try_from(settings.shape).ok()? {
+            GateShape::Circle => Profile::Circle,
+            GateShape::Octagon if (0.5..=SQRT_2).contains(&settings.diagonal) => {
+                Profile::Octagon(settings.diagonal)
+            }
+            GateShape::Octagon => return None,
+            GateShape::Learned => Profile::Learned([0.0; LEARNED_SECTORS]),
+        };
+        Some(GateCorrection {
+            profile,
+            square_output: settings.square_output,
+        })
+    }
+
+    // Where the edge of the gate is in the direction of `angle`.
+    fn reach(&self, angle: f32) -> f32 {
+        match &self.profile {
+            Profile::Circle => 1.0,
+            Profile::Octagon(diagonal) => {
+                // Intersection of the ray with the edge between the two closest corners.
+                let k = (angle / FRAC_PI_4).floor();
+                let corner = |k: f32| {
+                    let reach = if k.rem_euclid(2.0) == 0.0 {
+                        1.0
+                    } else {
+                        *diagonal
+                    };
+                    let (sin, cos) = (k * FRAC_PI_4).sin_cos();
+                    (cos * reach, sin * reach)
+                };
+                let (ax, ay) = corner(k);
+                let (bx, by) = corner(k + 1.0);
+                let (ex, ey) = (bx - ax, by - ay);
+                let (sin, cos) = angle.sin_cos();
+                (ax * ey - ay * ex) / (cos * ey - sin * ex)
+            }
+            Profile::Learned(reaches) => {
+                // Linear between the centers of the two closest sectors, unseen ones reach 1.
+                let position = angle.rem_euclid(2.0 * PI) / (2.0 * PI) * LEARNED_SECTORS as f32;
+                let position = position - 0.5;
+                let i = position.floor().rem_euclid(LEARNED_SECTORS as f32) as usize;
+                let t = position - position.floor();
+                let reach = |i: usize| {
+                    let reach = reaches[i % LEARNED_SECTORS];
+                    if reach > 0.0 {
+                        reach
+                    } else {
+                        1.0
+                    }
+                };
+                reach(i) + (reach(i + 1) - reach(i)) * t
+            }
+        }
+    }
+
+    fn learn(&mut self, angle: f32, magnitude: f32) {
+        let Profile::Learned(reaches) = &mut self.profile else {
+            return;
+        };
+        if magnitude < MIN_LEARNED_REACH {
+            return;
+        }
+        let sector = angle.rem_euclid(2.0 * PI) / (2.0 * PI) * LEARNED_SECTORS as f32;
+        let reach = &mut reaches[sector as usize % LEARNED_SECTORS];
+        *reach = reach.max(magnitude.min(SQRT_2));
+    }
+
+    /// Corrects calibrated axes in `-1.0..=1.0`.
+    pub fn apply(&mut self, x: f32, y: f32) -> (f32, f32) {
+        let magnitude = x.hypot(y);
+        if magnitude == 0.0 {
+            return (0.0, 0.0);
+        }
+        let angle = y.atan2(x);
+        self.learn(angle, magnitude);
+        let corrected = (magnitude / self.reach(angle)).min(1.0);
+        let (mut x, mut y) = (x / magnitude * corrected, y / magnitude * corrected);
+        if self.square_output {
+            // Radial stretch of the unit circle onto the square, the edge reaches the corners.
+            let factor = corrected / x.abs().max(y.abs());
+            (x, y) = (x * factor, y * factor);
+        }
+        (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0))
+    }
+}
@@ -0,0 +1,112 @@
+/**
+ * Driver for HID controllers without a dedicated driver, driven by their report descriptor.
+ */
+use crate::report_descriptor::{
+    GenericState, ReportDecoder, MAX_REPORT_SIZE, USAGE_GAMEPAD, USAGE_JOYSTICK,
+    USAGE_MULTI_AXIS_CONTROLLER, USAGE_PAGE_GENERIC_DESKTOP,
+};
+use crate::sdl_mapping::{self, Mapping};
+use crate::{Gamepad, GamepadMapping, DEFAULT_BUTTON_COUNT};
+use hidapi::{DeviceInfo, HidApi, HidDevice, MAX_REPORT_DESCRIPTOR_SIZE};
+
+// One more for the report ID.
+const GENERIC_REPORT_BUF_SIZE: usize = MAX_REPORT_SIZE + 1;
+
+const VENDOR_ID_LOGITECH: u16 = 0x046d;
+const VENDOR_ID_MADCATZ: u16 = 0x0738;
+const VENDOR_ID_SAITEK: u16 = 0x06a3;
+const VENDOR_ID_THRUSTMASTER: u16 = 0x044f;
+
+// Flight sticks and throttles. Game controller mappings squeeze them onto the standard
+// layout and drop most of their controls, so they always use the raw layout.
+const FLIGHT_DEVICES: [(u16, u16); 11] = [
+    (VENDOR_ID_LOGITECH, 0xc215),     // Extreme 3D Pro
+    (VENDOR_ID_LOGITECH, 0xc287),     // Flight System G940
+    (VENDOR_ID_MADCATZ, 0x2221),      // X56 stick
+    (VENDOR_ID_MADCATZ, 0xa221),      // X56 throttle
+    (VENDOR_ID_SAITEK, 0x075c),       // X52
+    (VENDOR_ID_SAITEK, 0x0762),       // X52 Pro
+    (VENDOR_ID_THRUSTMASTER, 0x0402), // HOTAS Warthog stick
+    (VENDOR_ID_THRUSTMASTER, 0x0404), // HOTAS Warthog throttle
+    (VENDOR_ID_THRUSTMASTER, 0xb108), // T.Flight HOTAS X
+    (VENDOR_ID_THRUSTMASTER, 0xb10a), // T.16000M
+    (VENDOR_ID_THRUSTMASTER, 0xb687), // TWCS throttle
+];
+
+pub struct GenericDevice {
+    device: HidDevice,
+    decoder: ReportDecoder,
+    state: GenericState,
+    guid: String,
+    mapping: Option<Mapping>,
+    flight: bool,
+}
+
+impl GenericDevice {
+    /// Whether the top-level collection of `device_info` looks like a game controller.
+    pub fn is_supported(device_info: &DeviceInfo) -> bool {
+        device_info.usage_page() == USAGE_PAGE_GENERIC_DESKTOP
+            && [USAGE_JOYSTICK, USAGE_GAMEPAD, USAGE_MULTI_AXIS_CONTROLLER]
+                .contains(&device_info.usage())
+    }
+
+    pub fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Option<Self> {
+        let device = device_info.open_device(hidapi).ok()?;
+        let mut descriptor = [0; MAX_REPORT_DESCRIPTOR_SIZE];
+        let len = device.get_report_descriptor(&mut descriptor).ok()?;
+        let decoder = ReportDecoder::parse(&descriptor[..len])?;
+        let state = decoder.new_state();
+        // Unknown joysticks with more hats or buttons than a gamepad are likely flight
+        // controls too.
+        let flight = FLIGHT_DEVICES.contains(&(device_info.vendor_id(), device_info.product_id()))
+            || (device_info.usage() == USAGE_JOYSTICK
+                && (decoder.hat_count > 1 || decoder.button_count > DEFAULT_BUTTON_COUNT));
+        let guid = sdl_mapping::create_guid(
+            device_info.bus_type(),
+            device_info.vendor_id(),
+            device_info.product_id(),
+            device_info.release_number(),
+        );
+        Some(GenericDevice {
+            device,
+            decoder,
+            state,
+            guid,
+            mapping: None,
+            flight,
+        })
+    }
+
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    pub fn set_mapping(&mut self, mapping: Option<Mapping>) {
+        if !self.flight {
+            self.mapping = mapping;
+        }
+    }
+
+    /// Applies all pending input reports, returns false once the device is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; GENERIC_REPORT_BUF_SIZE];
+        loop {
+            match self.device.read_timeout(&mut buf, 0) {
+                Ok(0) => return true,
+                Ok(len) => self.decoder.decode(&buf[..len], &mut self.state),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        match &self.mapping {
+            Some(mapping) => {
+                mapping.apply(&self.state, gamepad);
+                gamepad.mapping = GamepadMapping::Standard;
+            }
+            // Flight controls keep their hats separate, a hat on a stick is no d-pad.
+            None => self.state.fill_gamepad(gamepad, !self.flight),
+        }
+    }
+}
@@ -0,0 +1,598 @@
+/**
+ * Xbox GIP (Gaming Input Protocol) driver for wired Xbox One / Series controllers.
+ *
+ * port form:
+ *  https://github.com/torvalds/linux/blob/master/drivers/input/joystick/xpad.c
+ *  https://github.com/libsdl-org/SDL/blob/main/src/joystick/hidapi/SDL_hidapi_xboxone.c
+ */
+use crate::{Button, Gamepad, GamepadMapping};
+use num_enum::TryFromPrimitive;
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType};
+use std::time::Duration;
+use zerocopy::*;
+
+const VENDOR_ID_MICROSOFT: u16 = 0x045e;
+
+const PRODUCT_ID_XBOX_ONE_S: u16 = 0x02ea;
+const PRODUCT_ID_XBOX_ONE_ELITE_SERIES_1: u16 = 0x02e3;
+const PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2: u16 = 0x0b00;
+const PRODUCT_ID_XBOX_SERIES_X: u16 = 0x0b12;
+
+// GIP controllers expose a vendor specific interface instead of a HID one.
+const GIP_INTERFACE_CLASS: u8 = 0xff;
+const GIP_INTERFACE_SUBCLASS: u8 = 0x47;
+const GIP_INTERFACE_PROTOCOL: u8 = 0xd0;
+
+const GIP_OPT_ACK: u8 = 0x10;
+const GIP_OPT_INTERNAL: u8 = 0x20;
+
+const GIP_PWR_ON: u8 = 0x00;
+const GIP_LED_ON: u8 = 0x01;
+
+const GIP_MOTOR_ALL: u8 = 0x0f;
+
+const GIP_HEADER_SIZE: usize = 4;
+const GIP_PACKET_BUF_SIZE: usize = 64;
+
+const TRIGGER_MAX: f32 = 1023.0;
+
+const USB_TIMEOUT: Duration = Duration::from_millis(1);
+const USB_WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq)]
+pub enum GipCommand {
+    Ack = 0x01,
+    Announce = 0x02,
+    Status = 0x03,
+    Identify = 0x04,
+    Power = 0x05,
+    Authenticate = 0x06,
+    VirtualKey = 0x07,
+    Rumble = 0x09,
+    Led = 0x0a,
+    Firmware = 0x0c,
+    Input = 0x20,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, AsBytes, Default, Debug, Clone, Copy)]
+pub struct GipHeader {
+    pub command: u8,
+    pub options: u8,
+    pub sequence: u8,
+    pub length: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct GipInputPacket {
+    header: GipHeader,
+    buttons: [u8; 2],
+    left_trigger: u16,
+    right_trigger: u16,
+    left_stick_x: i16,
+    left_stick_y: i16,
+    right_stick_x: i16,
+    right_stick_y: i16,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, AsBytes, Default)]
+struct GipRumblePacket {
+    header: GipHeader,
+    padding: u8,
+    motors: u8,
+    left_trigger: u8,
+    right_trigger: u8,
+    left: u8,
+    right: u8,
+    on_period: u8,
+    off_period: u8,
+    repeat_count: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, AsBytes, Default)]
+struct GipAckPacket {
+    header: GipHeader,
+    padding: u8,
+    command: u8,
+    options: u8,
+    received: [u8; 2],
+    padding2: [u8; 4],
+}
+
+/// Where (and whether) the back paddles of an Elite controller are reported.
+/// This moved around between controller revisions and firmware versions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddleLayout {
+    None,
+    EliteSeries1,
+    EliteSeries2Firmware4,
+    EliteSeries2Firmware5Early,
+    EliteSeries2,
+}
+
+impl PaddleLayout {
+    pub fn from_device(vendor_id: u16, product_id: u16, bcd_device: u16) -> Self {
+        match (vendor_id, product_id) {
+            (VENDOR_ID_MICROSOFT, PRODUCT_ID_XBOX_ONE_ELITE_SERIES_1) => PaddleLayout::EliteSeries1,
+            (VENDOR_ID_MICROSOFT, PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2) => {
+                if bcd_device < 0x0500 {
+                    PaddleLayout::EliteSeries2Firmware4
+                } else if bcd_device < 0x050b {
+                    PaddleLayout::EliteSeries2Firmware5Early
+                } else {
+                    PaddleLayout::EliteSeries2
+                }
+            }
+            _ => PaddleLayout::None,
+        }
+    }
+
+    /// Returns the paddle bits (P1..P4 in bits 0..3) from a full input packet.
+    fn extract(&self, packet: &[u8]) -> u8 {
+        // The paddles are muted when a custom mapping profile is active, which is
+        // detected by comparing against the factory mapping bytes.
+        match self {
+            PaddleLayout::None => 0,
+            PaddleLayout::EliteSeries1 => {
+                if packet.len() <= 32 || packet[4..6] != packet[18..20] {
+                    return 0;
+                }
+                let bits = packet[32];
+                // P1..P4 are reported as bits 1, 3, 0, 2.
+                ((bits >> 1) & 1)
+                    | (((bits >> 3) & 1) << 1)
+                    | ((bits & 1) << 2)
+                    | (((bits >> 2) & 1) << 3)
+            }
+            PaddleLayout::EliteSeries2Firmware4 | PaddleLayout::EliteSeries2 => {
+                if packet.len() <= 19 || packet[19] != 0 {
+                    return 0;
+                }
+                packet[18] & 0x0f
+            }
+            PaddleLayout::EliteSeries2Firmware5Early => {
+                if packet.len() <= 23 || packet[23] != 0 {
+                    return 0;
+                }
+                packet[22] & 0x0f
+            }
+        }
+    }
+}
+
+/// Splits a raw GIP packet into its header and payload.
+pub fn parse_packet(buf: &[u8]) -> Option<(GipHeader, &[u8])> {
+    let header = GipHeader::read_from_prefix(buf)?;
+    let end = GIP_HEADER_SIZE + header.length as usize;
+    if buf.len() < end {
+        return None;
+    }
+    Some((header, &buf[GIP_HEADER_SIZE..end]))
+}
+
+pub fn power_on_packet(sequence: u8) -> [u8; 5] {
+    [
+        GipCommand::Power as u8,
+        GIP_OPT_INTERNAL,
+        sequence,
+        1,
+        GIP_PWR_ON,
+    ]
+}
+
+/// Required by the Xbox One S and Elite Series 2 to start reporting input.
+pub fn s_init_packet(sequence: u8) -> [u8; 5] {
+    [
+        GipCommand::Power as u8,
+        GIP_OPT_INTERNAL,
+        sequence,
+        0x0f,
+        0x06,
+    ]
+}
+
+pub fn led_on_packet(sequence: u8) -> [u8; 7] {
+    [
+        GipCommand::Led as u8,
+        GIP_OPT_INTERNAL,
+        sequence,
+        3,
+        0x00,
+        GIP_LED_ON,
+        0x14,
+    ]
+}
+
+/// `strong` drives the left (low frequency) motor, `weak` the right one. Both are
+/// clamped to `0.0..=1.0`.
+pub fn rumble_packet(sequence: u8, strong: f32, weak: f32) -> [u8; 13] {
+    let packet = GipRumblePacket {
+        header: GipHeader {
+            command: GipCommand::Rumble as u8,
+            options: 0x00,
+            sequence,
+            length: 9,
+        },
+        motors: GIP_MOTOR_ALL,
+        left: (strong.clamp(0.0, 1.0) * 100.0) as u8,
+        right: (weak.clamp(0.0, 1.0) * 100.0) as u8,
+        on_period: 0xff,
+        off_period: 0x00,
+        repeat_count: 0xff,
+        ..Default::default()
+    };
+    let mut buf = [0; 13];
+    buf.copy_from_slice(packet.as_bytes());
+    buf
+}
+
+/// Acknowledges a packet that was sent with `GIP_OPT_ACK`. The sequence number
+/// must match the one of the packet being acknowledged.
+pub fn ack_packet(header: &GipHeader) -> [u8; 13] {
+    let packet = GipAckPacket {
+        header: GipHeader {
+            command: GipCommand::Ack as u8,
+            options: GIP_OPT_INTERNAL,
+            sequence: header.sequence,
+            length: 9,
+        },
+        command: header.command,
+        options: header.options & GIP_OPT_INTERNAL,
+        received: (header.length as u16).to_le_bytes(),
+        ..Default::default()
+    };
+    let mut buf = [0; 13];
+    buf.copy_from_slice(packet.as_bytes());
+    buf
+}
+
+/// Decoded state of a GIP controller, assembled from input and guide button packets.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GipState {
+    pub buttons: u16,
+    pub left_trigger: u16,
+    pub right_trigger: u16,
+    pub left_stick: (i16, i16),
+    pub right_stick: (i16, i16),
+    pub guide: bool,
+    pub share: bool,
+    pub paddles: u8,
+}
+
+impl GipState {
+    /// Applies one packet to the state. Returns true if the packet must be acknowledged.
+    pub fn handle_packet(&mut self, buf: &[u8], product_id: u16, layout: PaddleLayout) -> bool {
+        let Some((header, payload)) = parse_packet(buf) else {
+            return false;
+        };
+        let packet = &buf[..GIP_HEADER_SIZE + payload.len()];
+
+        match GipCommand::try_from(header.command) {
+            Ok(GipCommand::Input) => {
+                if let Some(input) = GipInputPacket::read_from_prefix(packet) {
+                    self.buttons = u16::from_le_bytes(input.buttons);
+                    self.left_trigger = input.left_trigger;
+                    self.right_trigger = input.right_trigger;
+                    self.left_stick = (input.left_stick_x, input.left_stick_y);
+                    self.right_stick = (input.right_stick_x, input.right_stick_y);
+                }
+                if product_id == PRODUCT_ID_XBOX_SERIES_X && packet.len() >= 18 {
+                    self.share = packet[packet.len() - 18] & 0x01 != 0;
+                }
+                self.paddles = layout.extract(packet);
+            }
+            Ok(GipCommand::VirtualKey) => {
+                self.guide = payload.first().is_some_and(|v| v & 0x03 != 0);
+            }
+            _ => {}
+        }
+
+        header.options & GIP_OPT_ACK != 0
+    }
+
+    /// Fills `gamepad` using the W3C standard gamepad layout. The back paddles
+    /// P1..P4 follow at 17..20 and the share button at 21.
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        // (standard button index, bit in the input packet button field)
+        const BUTTON_BITS: [(usize, u16); 14] = [
+            (0, 1 << 4),   // A
+            (1, 1 << 5),   // B
+            (2, 1 << 6),   // X
+            (3, 1 << 7),   // Y
+            (4, 1 << 12),  // LB
+            (5, 1 << 13),  // RB
+            (8, 1 << 3),   // View
+            (9, 1 << 2),   // Menu
+            (10, 1 << 14), // Left stick
+            (11, 1 << 15), // Right stick
+            (12, 1 << 8),  // D-pad up
+            (13, 1 << 9),  // D-pad down
+            (14, 1 << 10), // D-pad left
+            (15, 1 << 11), // D-pad right
+        ];
+
+        for (index, mask) in BUTTON_BITS {
+            gamepad.buttons[index] = Button::digital(self.buttons & mask != 0);
+        }
+
+        gamepad.buttons[6] = Button::analog(self.left_trigger as f32 / TRIGGER_MAX);
+        gamepad.buttons[7] = Button::analog(self.right_trigger as f32 / TRIGGER_MAX);
+        gamepad.buttons[16] = Button::digital(self.guide);
+        for i in 0..4 {
+            gamepad.buttons[17 + i] = Button::digital(self.paddles & (1 << i) != 0);
+        }
+        gamepad.buttons[21] = Button::digital(self.share);
+
+        // GIP reports up as positive, the standard layout wants up as -1.0.
+        gamepad.axes[0] = normalize_stick(self.left_stick.0);
+        gamepad.axes[1] = -normalize_stick(self.left_stick.1);
+        gamepad.axes[2] = normalize_stick(self.right_stick.0);
+        gamepad.axes[3] = -normalize_stick(self.right_stick.1);
+        gamepad.mapping = GamepadMapping::Standard;
+    }
+}
+
+fn normalize_stick(value: i16) -> f32 {
+    (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0)
+}
+
+pub struct GipDevice {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    product_id: u16,
+    // From the USB string descriptor, `None` if the device has none.
+    serial: Option<String>,
+    paddle_layout: PaddleLayout,
+    sequence: u8,
+    state: GipState,
+}
+
+impl GipDevice {
+    /// Opens `device` if it exposes a GIP interface and runs the power-on handshake.
+    pub fn open(device: &Device<Context>) -> Option<Self> {
+        let descriptor = device.device_descriptor().ok()?;
+        let config = device.active_config_descriptor().ok()?;
+
+        let (interface, endpoint_in, endpoint_out) = config.interfaces().find_map(|interface| {
+            let alt = interface.descriptors().find(|alt| {
+                alt.class_code() == GIP_INTERFACE_CLASS
+                    && alt.sub_class_code() == GIP_INTERFACE_SUBCLASS
+                    && alt.protocol_code() == GIP_INTERFACE_PROTOCOL
+            })?;
+            let endpoint = |direction| {
+                alt.endpoint_descriptors()
+                    .find(|ep| {
+                        ep.direction() == direction && ep.transfer_type() == TransferType::Interrupt
+                    })
+                    .map(|ep| ep.address())
+            };
+            Some((
+                alt.interface_number(),
+                endpoint(Direction::In)?,
+                endpoint(Direction::Out)?,
+            ))
+        })?;
+
+        let handle = device.open().ok()?;
+        // Not supported on every platform, claiming will fail if it matters.
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface).ok()?;
+        let serial = handle
+            .read_serial_number_string_ascii(&descriptor)
+            .ok()
+            .filter(|serial| !serial.is_empty());
+
+        // Re-encode the firmware version as the raw bcdDevice value.
+        let version = descriptor.device_version();
+        let bcd_device = ((version.major() as u16 / 10) << 12)
+            | ((version.major() as u16 % 10) << 8)
+            | ((version.minor() as u16) << 4)
+            | version.sub_minor() as u16;
+
+        let mut gip = GipDevice {
+            handle,
+            interface,
+            endpoint_in,
+            endpoint_out,
+            product_id: descriptor.product_id(),
+            serial,
+            paddle_layout: PaddleLayout::from_device(
+                descriptor.vendor_id(),
+                descriptor.product_id(),
+                bcd_device,
+            ),
+            sequence: 0,
+            state: GipState::default(),
+        };
+        gip.start();
+        Some(gip)
+    }
+
+    fn next_sequence(&mut self) -> u8 {
+        // Sequence 0 is reserved for acks of unsequenced packets.
+        self.sequence = self.sequence.wrapping_add(1).max(1);
+        self.sequence
+    }
+
+    fn write(&self, packet: &[u8]) {
+        let _ = self
+            .handle
+            .write_interrupt(self.endpoint_out, packet, USB_WRITE_TIMEOUT);
+    }
+
+    fn start(&mut self) {
+        let seq = self.next_sequence();
+        self.write(&power_on_packet(seq));
+
+        if matches!(
+            self.product_id,
+            PRODUCT_ID_XBOX_ONE_S | PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2
+        ) {
+            let seq = self.next_sequence();
+            self.write(&s_init_packet(seq));
+        }
+
+        let seq = self.next_sequence();
+        self.write(&led_on_packet(seq));
+
+        // Make sure the motors are stopped.
+        let seq = self.next_sequence();
+        self.write(&rumble_packet(seq, 0.0, 0.0));
+    }
+
+    /// Drains all pending packets, returns false once the device is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; GIP_PACKET_BUF_SIZE];
+        loop {
+            match self
+                .handle
+                .read_interrupt(self.endpoint_in, &mut buf, USB_TIMEOUT)
+            {
+                Ok(len) => {
+                    let packet = &buf[..len];
+                    if self
+                        .state
+                        .handle_packet(packet, self.product_id, self.paddle_layout)
+                    {
+                        if let Some((header, _)) = parse_packet(packet) {
+                            self.write(&ack_packet(&header));
+                        }
+                    }
+                }
+                Err(rusb::Error::Timeout) => return true,
+                Err(rusb::Error::NoDevice) => return false,
+                Err(_) => return true,
+            }
+        }
+    }
+
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        self.state.fill_gamepad(gamepad);
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    pub fn set_rumble(&mut self, strong: f32, weak: f32) {
+        let seq = self.next_sequence();
+        self.write(&rumble_packet(seq, strong, weak));
+    }
+}
+
+impl Drop for GipDevice {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Xbox One S input report: A pressed, left trigger fully pulled, left stick up and right.
+    const INPUT_PACKET: [u8; 18] = [
+        0x20, 0x00, 0x0e, 0x0e, 0x10, 0x00, 0xff, 0x03, 0x00, 0x00, 0xff, 0x7f, 0xff, 0x7f, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+    // Guide button pressed, sent with the ack option.
+    const GUIDE_PACKET: [u8; 6] = [0x07, 0x30, 0x02, 0x02, 0x01, 0x5b];
+
+    #[test]
+    fn parses_header_and_payload() {
+        let (header, payload) = parse_packet(&GUIDE_PACKET).unwrap();
+        assert_eq!(header.command, GipCommand::VirtualKey as u8);
+        assert_eq!(header.options, 0x30);
+        assert_eq!(header.sequence, 0x02);
+        assert_eq!(payload, &[0x01, 0x5b]);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        assert!(parse_packet(&INPUT_PACKET[..10]).is_none());
+        assert!(parse_packet(&INPUT_PACKET[..3]).is_none());
+        let mut state = GipState::default();
+        assert!(!state.handle_packet(&INPUT_PACKET[..10], 0, PaddleLayout::None));
+        assert_eq!(state, GipState::default());
+    }
+
+    #[test]
+    fn encodes_power_on() {
+        assert_eq!(power_on_packet(0x01), [0x05, 0x20, 0x01, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn encodes_rumble() {
+        assert_eq!(
+            rumble_packet(0x03, 1.0, 0.5),
+            [0x09, 0x00, 0x03, 0x09, 0x00, 0x0f, 0x00, 0x00, 100, 50, 0xff, 0x00, 0xff]
+        );
+        // Out of range magnitudes are clamped.
+        assert_eq!(rumble_packet(0x03, 2.0, -1.0)[8..10], [100, 0]);
+    }
+
+    #[test]
+    fn encodes_ack() {
+        let (header, _) = parse_packet(&GUIDE_PACKET).unwrap();
+        assert_eq!(
+            ack_packet(&header),
+            [0x01, 0x20, 0x02, 0x09, 0x00, 0x07, 0x20, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn handles_input() {
+        let mut state = GipState::default();
+        assert!(!state.handle_packet(&INPUT_PACKET, PRODUCT_ID_XBOX_ONE_S, PaddleLayout::None));
+        assert_eq!(state.buttons, 0x0010);
+        assert_eq!(state.left_trigger, 0x03ff);
+        assert_eq!(state.left_stick, (i16::MAX, i16::MAX));
+
+        let mut gamepad = Gamepad::default();
+        state.fill_gamepad(&mut gamepad);
+        assert!(gamepad.buttons[0].pressed);
+        assert!(!gamepad.buttons[1].pressed);
+        assert_eq!(gamepad.buttons[6].value, 1.0);
+        assert_eq!(gamepad.axes[0], 1.0);
+        // Up is -1.0 in the standard layout.
+        assert_eq!(gamepad.axes[1], -1.0);
+        assert_eq!(gamepad.mapping, GamepadMapping::Standard);
+    }
+
+    #[test]
+    fn handles_guide_and_asks_for_ack() {
+        let mut state = GipState::default();
+        assert!(state.handle_packet(&GUIDE_PACKET, PRODUCT_ID_XBOX_ONE_S, PaddleLayout::None));
+        assert!(state.guide);
+        let released = [0x07, 0x30, 0x03, 0x02, 0x00, 0x5b];
+        state.handle_packet(&released, PRODUCT_ID_XBOX_ONE_S, PaddleLayout::None);
+        assert!(!state.guide);
+    }
+
+    #[test]
+    fn reads_elite_series_2_paddles() {
+        let mut packet = INPUT_PACKET.to_vec();
+        packet[3] = 0x10;
+        packet.extend([0x05, 0x00]);
+        let mut state = GipState::default();
+        let layout = PaddleLayout::from_device(
+            VENDOR_ID_MICROSOFT,
+            PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2,
+            0x0510,
+        );
+        assert_eq!(layout, PaddleLayout::EliteSeries2);
+        state.handle_packet(&packet, PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2, layout);
+        assert_eq!(state.paddles, 0x05);
+
+        // A custom mapping profile mutes the paddles.
+        packet[19] = 0x01;
+        state.handle_packet(&packet, PRODUCT_ID_XBOX_ONE_ELITE_SERIES_2, layout);
+        assert_eq!(state.paddles, 0);
+    }
+}
@@ -0,0 +1,326 @@
+/**
+ * Force feedback of Logitech wheels speaking HID++ 2.0 (G920) through its force feedback
+ * feature. Features are called by an index the wheel looks up from their ID, and effects
+ * live in slots the wheel hands out when they're downloaded.
+ *
+ * ref:
+ *  https://github.com/torvalds/linux/blob/master/drivers/hid/hid-logitech-hidpp.c
+ */
+use crate::ffb::{self, Effect};
+
+pub const USAGE_PAGE_HIDPP: u16 = 0xff43;
+pub const USAGE_HIDPP_LONG: u16 = 0x0602;
+pub const USAGE_HIDPP_VERY_LONG: u16 = 0x0604;
+
+const REPORT_ID_LONG: u8 = 0x11;
+const REPORT_ID_VERY_LONG: u8 = 0x12;
+const LONG_REPORT_SIZE: usize = 20;
+const VERY_LONG_REPORT_SIZE: usize = 64;
+// Report ID, device index, feature index, function and software ID.
+const HEADER_SIZE: usize = 4;
+
+// Devices plugged in directly, not through a receiver.
+const DEVICE_INDEX_CORDED: u8 = 0xff;
+// Low nibble of the function byte, tells replies to us apart from other software's.
+const SOFTWARE_ID: u8 = 0x01;
+// Feature index of error replies, followed by the index and function of the failed call.
+const ERROR_FEATURE_INDEX: u8 = 0xff;
+
+const ROOT_FEATURE_INDEX: u8 = 0x00;
+const ROOT_GET_FEATURE: u8 = 0x0;
+
+pub const FEATURE_FORCE_FEEDBACK: u16 = 0x8123;
+
+// Functions of the force feedback feature.
+const FF_RESET_ALL: u8 = 0x1;
+const FF_DOWNLOAD_EFFECT: u8 = 0x2;
+const FF_DESTROY_EFFECT: u8 = 0x4;
+const FF_SET_APERTURE: u8 = 0x6;
+const FF_SET_GLOBAL_GAINS: u8 = 0x8;
+
+const EFFECT_CONSTANT: u8 = 0x00;
+const EFFECT_SPRING: u8 = 0x06;
+const EFFECT_DAMPER: u8 = 0x07;
+// Starts the effect once it's downloaded.
+const EFFECT_AUTOSTART: u8 = 0x80;
+
+// Slot byte of a download creating a new effect.
+pub const NEW_SLOT: u8 = 0;
+
+/// A call of `function` of the feature at `feature_index`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub feature_index: u8,
+    pub function: u8,
+    pub params: Vec<u8>,
+}
+
+impl Call {
+    fn function_byte(&self) -> u8 {
+        self.function << 4 | SOFTWARE_ID
+    }
+
+    /// Whether the parameters need a very long report.
+    pub fn is_very_long(&self) -> bool {
+        self.params.len() > LONG_REPORT_SIZE - HEADER_SIZE
+    }
+
+    pub fn report(&self) -> Vec<u8> {
+        let (report_id, size) = if self.is_very_long() {
+            (REPORT_ID_VERY_LONG, VERY_LONG_REPORT_SIZE)
+        } else {
+            (REPORT_ID_LONG, LONG_REPORT_SIZE)
+        };
+        let mut report = vec![0; size];
+        report[..HEADER_SIZE].copy_from_slice(&[
+            report_id,
+            DEVICE_INDEX_CORDED,
+            self.feature_index,
+            self.function_byte(),
+        ]);
+        report[HEADER_SIZE..HEADER_SIZE + self.params.len()].copy_from_slice(&self.params);
+        report
+    }
+
+    /// Parameters of the reply to this call, `Some(Err(code))` for an error reply and `None`
+    /// for any other report.
+    pub fn parse_reply<'a>(&self, report: &'a [u8]) -> Option<Result<&'a [u8], u8>> {
+        let [report_id, _, header @ ..] = report else {
+            return None;
+        };
+        if ![REPORT_ID_LONG, REPORT_ID_VERY_LONG].contains(report_id) {
+            return None;
+        }
+        let call = [self.feature_index, self.function_byte()];
+        match header {
+            [ERROR_FEATURE_INDEX, index, function, code, ..] if [*index, *function] == call => {
+                Some(Err(*code))
+            }
+            [index, function, params @ ..] if [*index, *function] == call => Some(Ok(params)),
+            _ => None,
+        }
+    }
+}
+
+/// Looks up the index of `feature`, the reply's first parameter. Index 0 means unsupported.
+pub fn get_feature(feature: u16) -> Call {
+    Call {
+        feature_index: ROOT_FEATURE_INDEX,
+        function: ROOT_GET_FEATURE,
+        params: feature.to_be_bytes().to_vec(),
+    }
+}
+
+fn signed(value: f32) -> [u8; 2] {
+    ((value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16).to_be_bytes()
+}
+
+// 15 bits, split after the high 7 like the wheel expects.
+fn unsigned(value: f32) -> [u8; 2] {
+    let value = (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+    [(value >> 9) as u8, (value >> 1) as u8]
+}
+
+/// The force feedback feature, at the index `get_feature` returned.
+#[derive(Debug, Clone, Copy)]
+pub struct ForceFeedback {
+    pub feature_index: u8,
+}
+
+impl ForceFeedback {
+    fn call(&self, function: u8, params: &[u8]) -> Call {
+        Call {
+            feature_index: self.feature_index,
+            function,
+            params: params.to_vec(),
+        }
+    }
+
+    /// Stops and destroys all effects.
+    pub fn reset_all(&self) -> Call {
+        self.call(FF_RESET_ALL, &[])
+    }
+
+    /// Downloads and starts `effect` in `slot`, `NEW_SLOT` for a new one. The reply's first
+    /// parameter is the slot it ended up in.
+    pub fn download(&self, slot: u8, effect: &Effect) -> Call {
+        // Slot, type, then duration and delay, 0 plays until it's destroyed.
+        let mut params = vec![slot, 0, 0, 0, 0, 0];
+        match *effect {
+            Effect::Constant { level } => {
+                params[1] = EFFECT_CONSTANT | EFFECT_AUTOSTART;
+                params.extend(signed(level));
+                // No envelope.
+                params.extend([0; 6]);
+            }
+            Effect::Spring {
+                center,
+                deadband,
+                left_coefficient,
+                right_coefficient,
+                saturation,
+            } => {
+                params[1] = EFFECT_SPRING | EFFECT_AUTOSTART;
+                // A width over the whole range, the same fraction as a distance from the
+                // center over our range of 2.0.
+                let deadband = deadband.abs();
+                params.extend(unsigned(saturation));
+                params.extend(signed(left_coefficient));
+                params.extend(unsigned(deadband));
+                params.extend(signed(center));
+                params.extend(signed(right_coefficient));
+                params.extend(unsigned(saturation));
+            }
+            Effect::Damper {
+                left_coefficient,
+                right_coefficient,
+                saturation,
+            } => {
+                params[1] = EFFECT_DAMPER | EFFECT_AUTOSTART;
+                params.extend(unsigned(saturation));
+                params.extend(signed(left_coefficient));
+                params.extend([0; 4]);
+                params.extend(signed(right_coefficient));
+                params.extend(unsigned(saturation));
+            }
+        }
+        self.call(FF_DOWNLOAD_EFFECT, &params)
+    }
+
+    /// Stops the effect in `slot` and frees the slot.
+    pub fn destroy(&self, slot: u8) -> Call {
+        self.call(FF_DESTROY_EFFECT, &[slot])
+    }
+
+    /// The built-in centering spring, a spring with a coefficient of a quarter of its
+    /// saturation. `strength` in `0.0..=1.0`, 0.0 has to destroy it instead.
+    pub fn autocenter(&self, slot: u8, strength: f32) -> Call {
+        let magnitude = (strength.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+        let coefficient = (magnitude >> 3).to_be_bytes();
+        let saturation = [(magnitude >> 9) as u8, (magnitude >> 1) as u8];
+        let mut params = vec![slot, EFFECT_SPRING | EFFECT_AUTOSTART, 0, 0, 0, 0];
+        params.extend(saturation);
+        params.extend(coefficient);
+        // No deadband, centered.
+        params.extend([0; 4]);
+        params.extend(coefficient);
+        params.extend(saturation);
+        self.call(FF_DOWNLOAD_EFFECT, &params)
+    }
+
+    /// Sets the rotation range in degrees, lock to lock.
+    pub fn set_aperture(&self, degrees: u16) -> Call {
+        let degrees = degrees.clamp(ffb::RANGE_MIN, ffb::RANGE_MAX);
+        self.call(FF_SET_APERTURE, &degrees.to_be_bytes())
+    }
+
+    /// Scales the force of all effects, `gain` in `0.0..=1.0`.
+    pub fn set_gain(&self, gain: f32) -> Call {
+        let gain = (gain.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+        let [high, low] = gain.to_be_bytes();
+        // No boost.
+        self.call(FF_SET_GLOBAL_GAINS, &[high, low, 0, 0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEATURE: ForceFeedback = ForceFeedback {
+        feature_index: 0x0b,
+    };
+
+    #[test]
+    fn encodes_long_report() {
+        let report = get_feature(FEATURE_FORCE_FEEDBACK).report();
+        assert_eq!(report.len(), LONG_REPORT_SIZE);
+        assert_eq!(report[..6], [0x11, 0xff, 0x00, 0x01, 0x81, 0x23]);
+        assert!(report[6..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn parses_replies() {
+        let call = get_feature(FEATURE_FORCE_FEEDBACK);
+        let mut reply = [0; LONG_REPORT_SIZE];
+        reply[..5].copy_from_slice(&[0x11, 0xff, 0x00, 0x01, 0x0b]);
+        assert_eq!(call.parse_reply(&reply).unwrap().unwrap()[0], 0x0b);
+
+        let error = [0x11, 0xff, 0xff, 0x00, 0x01, 0x02, 0, 0];
+        assert_eq!(call.parse_reply(&error), Some(Err(0x02)));
+
+        // Another software ID, an input report and a short one.
+        assert_eq!(call.parse_reply(&[0x11, 0xff, 0x00, 0x02, 0x0b]), None);
+        assert_eq!(call.parse_reply(&[0x01, 0xff, 0x00, 0x01, 0x0b]), None);
+        assert_eq!(call.parse_reply(&[0x11, 0xff]), None);
+    }
+
+    #[test]
+    fn encodes_constant_force() {
+        let call = FEATURE.download(NEW_SLOT, &Effect::Constant { level: -1.0 });
+        assert!(!call.is_very_long());
+        let report = call.report();
+        assert_eq!(report[..4], [0x11, 0xff, 0x0b, 0x21]);
+        assert_eq!(
+            report[4..18],
+            [0x00, 0x80, 0, 0, 0, 0, 0x80, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn encodes_conditions_in_very_long_reports() {
+        let spring = Effect::Spring {
+            center: 0.5,
+            deadband: 0.25,
+            left_coefficient: 1.0,
+            right_coefficient: -0.5,
+            saturation: 1.0,
+        };
+        let call = FEATURE.download(3, &spring);
+        assert!(call.is_very_long());
+        let report = call.report();
+        assert_eq!(report.len(), VERY_LONG_REPORT_SIZE);
+        assert_eq!(report[..4], [0x12, 0xff, 0x0b, 0x21]);
+        assert_eq!(
+            report[4..22],
+            [
+                0x03, 0x86, 0, 0, 0, 0, 0x7f, 0xff, 0x7f, 0xff, 0x20, 0x00, 0x40, 0x00, 0xc0, 0x00,
+                0x7f, 0xff
+            ]
+        );
+
+        let damper = Effect::Damper {
+            left_coefficient: 0.5,
+            right_coefficient: 0.5,
+            saturation: 0.0,
+        };
+        assert_eq!(
+            FEATURE.download(NEW_SLOT, &damper).params,
+            [0x00, 0x87, 0, 0, 0, 0, 0, 0, 0x40, 0x00, 0, 0, 0, 0, 0x40, 0x00, 0, 0]
+        );
+    }
+
+    #[test]
+    fn encodes_autocenter() {
+        assert_eq!(
+            FEATURE.autocenter(NEW_SLOT, 1.0).params,
+            [0x00, 0x86, 0, 0, 0, 0, 0x7f, 0xff, 0x1f, 0xff, 0, 0, 0, 0, 0x1f, 0xff, 0x7f, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_settings() {
+        assert_eq!(
+            FEATURE.reset_all().report()[..5],
+            [0x11, 0xff, 0x0b, 0x11, 0]
+        );
+        assert_eq!(
+            FEATURE.destroy(2).report()[..5],
+            [0x11, 0xff, 0x0b, 0x41, 2]
+        );
+        assert_eq!(FEATURE.set_aperture(900).params, [0x03, 0x84]);
+        assert_eq!(FEATURE.set_aperture(2000).params, [0x03, 0x84]);
+        assert_eq!(FEATURE.set_gain(1.0).params, [0xff, 0xff, 0, 0]);
+        assert_eq!(FEATURE.set_gain(0.5).params, [0x80, 0x00, 0, 0]);
+    }
+}
@@ -3,59 +3,232 @@
  *  https://source.chromium.org/chromium/chromium/src/+/main:device/gamepad/nintendo_controller.cc;bpv=0;bpt=1
  *  https://github.com/libsdl-org/SDL/blob/efefc4a1f35812007663f4afccd7bae68496238f/src/joystick/hidapi/SDL_hidapi_switch.c#L87
  */
-use hidapi::{HidApi, HidDevice};
+use hidapi::{BusType, DeviceInfo, HidApi, HidDevice};
 use num_enum::TryFromPrimitive;
-use std::collections::{HashMap, HashSet};
+use rusb::UsbContext;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use transport::{Request, Transport};
 use zerocopy::*;
 
+mod cache;
+mod calibration;
+mod drift;
+mod ffb;
+mod gamecube;
+mod gate;
+mod generic;
+mod gip;
+mod hidpp;
+mod ir;
+mod mcu;
+mod nfc;
+mod profile;
+mod range;
+mod report_descriptor;
+mod response;
+mod ringcon;
+mod sdl_mapping;
+mod spi;
+mod steam;
+mod transport;
 mod util;
+mod wheel;
+mod wiimote;
 
 const VENDOR_ID_NINTENDO: u16 = 0x057e;
 
 const PRODUCT_ID_NINTENDO_JOYCONL: u16 = 0x2006;
 const PRODUCT_ID_NINTENDO_JOYCONR: u16 = 0x2007;
 const PRODUCT_IDNINTENDO_PROCON: u16 = 0x2009;
+// Each Joy-Con in the charging grip shows up as its own HID interface.
+const PRODUCT_ID_NINTENDO_CHARGING_GRIP: u16 = 0x200e;
+// Nintendo Switch Online controllers. The NES controllers use the Joy-Con product IDs.
+const PRODUCT_ID_NINTENDO_SNES: u16 = 0x2017;
+const PRODUCT_ID_NINTENDO_N64: u16 = 0x2019;
+const PRODUCT_ID_NINTENDO_GENESIS: u16 = 0x201e;
 
-const DEVICE_TUPLES: [(u16, u16); 3] = [
+const DEVICE_TUPLES: [(u16, u16); 7] = [
     (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_JOYCONL),
     (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_JOYCONR),
     (VENDOR_ID_NINTENDO, PRODUCT_IDNINTENDO_PROCON),
+    (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_CHARGING_GRIP),
+    (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_SNES),
+    (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_N64),
+    (VENDOR_ID_NINTENDO, PRODUCT_ID_NINTENDO_GENESIS),
+];
+
+// Bit positions in the 24 bit button status field.
+const BUTTON_Y: usize = 0;
+const BUTTON_X: usize = 1;
+const BUTTON_B: usize = 2;
+const BUTTON_A: usize = 3;
+const BUTTON_R: usize = 6;
+const BUTTON_ZR: usize = 7;
+const BUTTON_MINUS: usize = 8;
+const BUTTON_PLUS: usize = 9;
+const BUTTON_LSTICK: usize = 11;
+const BUTTON_HOME: usize = 12;
+const BUTTON_CAPTURE: usize = 13;
+const BUTTON_DOWN: usize = 16;
+const BUTTON_UP: usize = 17;
+const BUTTON_RIGHT: usize = 18;
+const BUTTON_LEFT: usize = 19;
+const BUTTON_L: usize = 22;
+const BUTTON_ZL: usize = 23;
+
+// (button status bit, standard gamepad button) for the Switch Online controllers,
+// laid out by position like the Switch does.
+const SNES_BUTTON_LAYOUT: [(usize, usize); 16] = [
+    (BUTTON_B, 0),
+    (BUTTON_A, 1),
+    (BUTTON_Y, 2),
+    (BUTTON_X, 3),
+    (BUTTON_L, 4),
+    (BUTTON_R, 5),
+    (BUTTON_ZL, 6),
+    (BUTTON_ZR, 7),
+    (BUTTON_MINUS, 8),
+    (BUTTON_PLUS, 9),
+    (BUTTON_UP, 12),
+    (BUTTON_DOWN, 13),
+    (BUTTON_LEFT, 14),
+    (BUTTON_RIGHT, 15),
+    (BUTTON_HOME, 16),
+    (BUTTON_CAPTURE, 21),
+];
+
+const NES_BUTTON_LAYOUT: [(usize, usize); 10] = [
+    (BUTTON_B, 0),
+    (BUTTON_A, 1),
+    (BUTTON_L, 4),
+    (BUTTON_R, 5),
+    (BUTTON_MINUS, 8),
+    (BUTTON_PLUS, 9),
+    (BUTTON_UP, 12),
+    (BUTTON_DOWN, 13),
+    (BUTTON_LEFT, 14),
+    (BUTTON_RIGHT, 15),
+];
+
+// Z is the left trigger, the extra ZR on the back the right one. The C buttons
+// are reported through Y, ZR, X and MINUS and are mapped to the right stick, so
+// the back ZR takes the left stick button bit.
+// ref: https://github.com/torvalds/linux/blob/master/drivers/hid/hid-nintendo.c
+const BUTTON_N64_ZR: usize = BUTTON_LSTICK;
+const N64_BUTTON_LAYOUT: [(usize, usize); 13] = [
+    (BUTTON_A, 0),
+    (BUTTON_B, 2),
+    (BUTTON_L, 4),
+    (BUTTON_R, 5),
+    (BUTTON_ZL, 6),
+    (BUTTON_N64_ZR, 7),
+    (BUTTON_PLUS, 9),
+    (BUTTON_UP, 12),
+    (BUTTON_DOWN, 13),
+    (BUTTON_LEFT, 14),
+    (BUTTON_RIGHT, 15),
+    (BUTTON_HOME, 16),
+    (BUTTON_CAPTURE, 21),
+];
+
+// (C up, C down, C left, C right)
+const N64_C_BUTTONS: (usize, usize, usize, usize) = (BUTTON_Y, BUTTON_ZR, BUTTON_X, BUTTON_MINUS);
+
+// The bottom row A, B, C is reported as A, B, R and the top row X, Y, Z as X, Y, L.
+const GENESIS_BUTTON_LAYOUT: [(usize, usize); 14] = [
+    (BUTTON_B, 0),
+    (BUTTON_R, 1),
+    (BUTTON_A, 2),
+    (BUTTON_Y, 3),
+    (BUTTON_X, 4),
+    (BUTTON_L, 5),
+    (BUTTON_ZR, 8),
+    (BUTTON_PLUS, 9),
+    (BUTTON_UP, 12),
+    (BUTTON_DOWN, 13),
+    (BUTTON_LEFT, 14),
+    (BUTTON_RIGHT, 15),
+    (BUTTON_HOME, 16),
+    (BUTTON_CAPTURE, 21),
 ];
 
 // Bogus calibration value that should be ignored.
 const CAL_BOGUS_VALUE: u16 = 0xfff;
+// Largest 12-bit stick position.
+const STICK_POSITION_MAX: u16 = 0xfff;
 // Default calibration values to use if the controller returns bogus values.
 const CAL_DEFAULT_DEADZONE: u16 = 160;
 const CAL_DEFAULT_MIN: u16 = 550;
 const CAL_DEFAULT_CENTER: u16 = 2050;
 const CAL_DEFAULT_MAX: u16 = 3550;
+// Nominal IMU sensitivities, for ±8G and ±2000dps.
+const CAL_DEFAULT_ACCEL_SENSITIVITY: u16 = 16384;
+const CAL_DEFAULT_GYRO_SENSITIVITY: u16 = 13371;
+// `SPIAddress::PairingInfo` starts with this when a host is paired, followed by its address.
+const PAIRING_MAGIC: u8 = 0x95;
+const PAIRING_HOST_ADDRESS_OFFSET: usize = 8;
+// `SPIAddress::ColorInfo` of controllers with grip colors.
+const COLOR_INFO_WITH_GRIPS: u8 = 0x02;
+// Marks valid user calibration in the SPI flash, written after recalibrating on a Switch.
+const USER_CALIBRATION_MAGIC: [u8; 2] = [0xb2, 0xa1];
+// Acceleration in G and angular velocity in degrees per second at the sensitivity value.
+const IMU_ACCEL_RANGE: f32 = 4.0;
+const IMU_GYRO_RANGE: f32 = 936.0;
 
 #[repr(u8)]
+#[allow(dead_code)]
 enum OutputReportID {
     RumbleAndSubcommand = 0x01,
     RumbleOnly = 0x10,
+    RequestMcuData = 0x11,
     Proprietary = 0x80,
 }
 
+// Commands sent with `OutputReportID::Proprietary`, only supported over USB.
 #[repr(u8)]
-#[derive(Debug, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, TryFromPrimitive)]
+enum ProprietaryCommandID {
+    Status = 0x01,
+    Handshake = 0x02,
+    HighSpeed = 0x03,
+    DisableUsbTimeout = 0x04,
+    EnableUsbTimeout = 0x05,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, TryFromPrimitive)]
 enum SubcommandID {
     BluetoothManualPair = 0x01,
     RequestDeviceInfo = 0x02,
     SetInputReportMode = 0x03,
     SetHCIState = 0x06,
     SPIFlashRead = 0x10,
+    SPIFlashWrite = 0x11,
+    SetMcuConfig = 0x21,
+    SetMcuState = 0x22,
     SetPlayerLights = 0x30,
     SetHomeLight = 0x38,
     EnableIMU = 0x40,
     SetIMUSensitivity = 0x41,
     EnableVibration = 0x48,
+    ReadExternalDevice = 0x58,
+    GetExternalDeviceInfo = 0x59,
+    EnableExternalPolling = 0x5a,
+    SetExternalDeviceConfig = 0x5c,
 }
 
 #[repr(u16)]
-#[derive(Debug, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, TryFromPrimitive)]
 enum SPIAddress {
     // SPI memory regions.
+    PairingInfo = 0x2000,
+    //const size_t kSpiPairingInfoSize = 0x26;
+    SerialNumber = 0x6000,
+    //const size_t kSpiSerialNumberSize = 16;
+    ColorInfo = 0x601b,
+    //const size_t kSpiColorInfoSize = 1;
     ImuCalibration = 0x6020,
     //const size_t kSpiImuCalibrationSize = 24;
     AnalogStickCalibration = 0x603d,
@@ -64,6 +237,110 @@ enum SPIAddress {
     //const size_t kSpiImuHorizontalOffsetsSize = 6;
     AnalogStickParameters = 0x6086,
     //const size_t kSpiAnalogStickParametersSize = 18;
+    Colors = 0x6050,
+    //const size_t kSpiColorsSize = 12;
+    UserStickCalibration = 0x8010,
+    //const size_t kSpiUserStickCalibrationSize = 22;
+    UserImuCalibration = 0x8026,
+    //const size_t kSpiUserImuCalibrationSize = 26;
+}
+
+// Controller type as reported by `SubcommandID::RequestDeviceInfo`.
+#[repr(u8)]
+#[derive(Debug, Default, TryFromPrimitive, Clone, Copy, PartialEq)]
+enum ControllerType {
+    #[default]
+    Unknown = 0x00,
+    JoyConLeft = 0x01,
+    JoyConRight = 0x02,
+    ProController = 0x03,
+    NesLeft = 0x09,
+    NesRight = 0x0a,
+    Snes = 0x0b,
+    N64 = 0x0c,
+    Genesis = 0x0d,
+}
+
+impl ControllerType {
+    fn from_product_id(product_id: u16) -> Self {
+        match product_id {
+            PRODUCT_ID_NINTENDO_JOYCONL => ControllerType::JoyConLeft,
+            PRODUCT_ID_NINTENDO_JOYCONR => ControllerType::JoyConRight,
+            PRODUCT_IDNINTENDO_PROCON => ControllerType::ProController,
+            PRODUCT_ID_NINTENDO_SNES => ControllerType::Snes,
+            PRODUCT_ID_NINTENDO_N64 => ControllerType::N64,
+            PRODUCT_ID_NINTENDO_GENESIS => ControllerType::Genesis,
+            _ => ControllerType::Unknown,
+        }
+    }
+
+    // The SNES, NES and Genesis controllers have no sticks and return garbage
+    // for the stick calibration reads.
+    // The N64 controller reports its C buttons in the axes of the right stick.
+    fn has_right_stick(&self) -> bool {
+        matches!(
+            self,
+            ControllerType::JoyConRight | ControllerType::ProController
+        )
+    }
+
+    fn has_sticks(&self) -> bool {
+        !matches!(
+            self,
+            ControllerType::NesLeft
+                | ControllerType::NesRight
+                | ControllerType::Snes
+                | ControllerType::Genesis
+        )
+    }
+
+    // Only the right Joy-Con and the Pro Controller have the NFC/IR MCU.
+    fn has_mcu(&self) -> bool {
+        matches!(
+            self,
+            ControllerType::JoyConRight | ControllerType::ProController
+        )
+    }
+
+    fn has_imu(&self) -> bool {
+        matches!(
+            self,
+            ControllerType::JoyConLeft
+                | ControllerType::JoyConRight
+                | ControllerType::ProController
+        )
+    }
+
+    // Name in profile files, see `GamepadAPI::load_profiles`.
+    fn profile_name(&self) -> &'static str {
+        match self {
+            ControllerType::Unknown => "nintendo",
+            ControllerType::JoyConLeft => "joycon-left",
+            ControllerType::JoyConRight => "joycon-right",
+            ControllerType::ProController => "pro-controller",
+            ControllerType::NesLeft => "nes-left",
+            ControllerType::NesRight => "nes-right",
+            ControllerType::Snes => "snes",
+            ControllerType::N64 => "n64",
+            ControllerType::Genesis => "genesis",
+        }
+    }
+
+    // A on the right of the face buttons, B at the bottom. The N64 and Genesis controllers
+    // have their own layouts.
+    fn has_nintendo_labels(&self) -> bool {
+        !matches!(self, ControllerType::N64 | ControllerType::Genesis)
+    }
+
+    fn button_layout(&self) -> Option<&'static [(usize, usize)]> {
+        match self {
+            ControllerType::NesLeft | ControllerType::NesRight => Some(&NES_BUTTON_LAYOUT),
+            ControllerType::Snes => Some(&SNES_BUTTON_LAYOUT),
+            ControllerType::N64 => Some(&N64_BUTTON_LAYOUT),
+            ControllerType::Genesis => Some(&GENESIS_BUTTON_LAYOUT),
+            _ => None,
+        }
+    }
 }
 
 #[repr(u8)]
@@ -78,6 +355,7 @@ enum InputReportID {
 
 #[repr(C)]
 #[derive(FromBytes, FromZeroes, Default)]
+#[allow(dead_code)]
 struct SubcmdReply {
     ack: u8,        /* MSB 1 for ACK, 0 for NACK */
     id: u8,         /* id of requested subcmd */
@@ -132,7 +410,49 @@ struct SubcommandInputPacket {
     subcommand_data: [u8; 18],
 }
 
-const REPORT_BUF_SIZE: usize = 48;
+// Large enough for the MCU data of `InputReportID::FullControllerAndMcuState`.
+const REPORT_BUF_SIZE: usize = mcu::MCU_DATA_OFFSET + mcu::MCU_DATA_SIZE;
+const MCU_OUTPUT_REPORT_SIZE: usize = 11 + mcu::MCU_REQUEST_SIZE;
+const USB_OUTPUT_REPORT_SIZE: usize = 64;
+
+// Keeps a silent controller (e.g. over USB before the handshake) from blocking `get_gamepads`.
+const READ_TIMEOUT_MS: i32 = 5;
+// IR image fragments each wait for an acknowledgment, so pending ones are answered in one go.
+const MAX_IR_REPORTS_PER_POLL: usize = 32;
+// A Joy-Con taken out of the charging grip just goes quiet, its slot starts over after this.
+const GRIP_SLOT_TIMEOUT: Duration = Duration::from_secs(1);
+// Empty charging grip slots are asked for a Joy-Con this often.
+const GRIP_SLOT_RETRY: Duration = Duration::from_secs(1);
+// SPI flash chunks are read one reply at a time, answered ones are followed up right away.
+const MAX_SPI_REPORTS_PER_POLL: usize = 8;
+// Learned stick ranges that moved are saved to the calibration cache this often.
+const RANGE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Offset of the subcommand reply data in a `SubcommandReply` input report.
+const SUBCOMMAND_REPLY_DATA_OFFSET: usize = 15;
+// Offset of the data of a `SubcommandID::SPIFlashRead` reply.
+const SPI_REPLY_DATA_OFFSET: usize = 20;
+// Offset of the first of the three IMU samples in a `FullControllerState` input report.
+const IMU_DATA_OFFSET: usize = 13;
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct CommandAckPacket {
+    command_id: u8,
+    status: u8,
+    // Only set for `ProprietaryCommandID::Status`, 0x00 for an empty charging grip slot.
+    controller_type: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct DeviceInfoPacket {
+    firmware_version: [u8; 2],
+    controller_type: u8,
+    unknown: u8,
+    // Big endian.
+    mac_address: [u8; 6],
+}
 
 #[repr(C)]
 #[derive(FromBytes, FromZeroes, Default, Debug)]
@@ -146,6 +466,46 @@ struct AnalogStickCalibrationPacket {
     r_xy_min: [u8; 3],
     r_xy_max: [u8; 3],
 }
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct ColorsPacket {
+    body: [u8; 3],
+    buttons: [u8; 3],
+    left_grip: [u8; 3],
+    right_grip: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct UserStickCalibrationPacket {
+    // Each stick is only valid with `USER_CALIBRATION_MAGIC`.
+    l_magic: [u8; 2],
+    l_xy_max: [u8; 3],
+    l_xy_center: [u8; 3],
+    l_xy_min: [u8; 3],
+
+    r_magic: [u8; 2],
+    r_xy_center: [u8; 3],
+    r_xy_min: [u8; 3],
+    r_xy_max: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct UserImuCalibrationPacket {
+    magic: [u8; 2],
+    cal: ImuCalibrationPacket,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct ImuCalibrationPacket {
+    accelerometer_origin: [u16; 3],
+    accelerometer_sensitivity: [u16; 3],
+    gyro_origin: [u16; 3],
+    gyro_sensitivity: [u16; 3],
+}
+
 #[repr(C)]
 #[derive(FromBytes, FromZeroes, Default, Debug)]
 struct AnalogStickParamsCalibrationPacket {
@@ -156,7 +516,8 @@ struct AnalogStickParamsCalibrationPacket {
     params: [u8; 3],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
 struct CalibrationData {
     // Analog stick calibration data.
     lx_center: u16,
@@ -191,6 +552,8 @@ struct CalibrationData {
     horizontal_offset_x: u16,
     horizontal_offset_y: u16,
     horizontal_offset_z: u16,
+
+    sources: CalibrationSources,
 }
 
 impl Default for CalibrationData {
@@ -216,427 +579,3916 @@ impl Default for CalibrationData {
             accelerometer_origin_x: 0,
             accelerometer_origin_y: 0,
             accelerometer_origin_z: 0,
-            accelerometer_sensitivity_x: 0,
-            accelerometer_sensitivity_y: 0,
-            accelerometer_sensitivity_z: 0,
+            accelerometer_sensitivity_x: CAL_DEFAULT_ACCEL_SENSITIVITY,
+            accelerometer_sensitivity_y: CAL_DEFAULT_ACCEL_SENSITIVITY,
+            accelerometer_sensitivity_z: CAL_DEFAULT_ACCEL_SENSITIVITY,
             gyro_origin_x: 0,
             gyro_origin_y: 0,
             gyro_origin_z: 0,
-            gyro_sensitivity_x: 0,
-            gyro_sensitivity_y: 0,
-            gyro_sensitivity_z: 0,
+            gyro_sensitivity_x: CAL_DEFAULT_GYRO_SENSITIVITY,
+            gyro_sensitivity_y: CAL_DEFAULT_GYRO_SENSITIVITY,
+            gyro_sensitivity_z: CAL_DEFAULT_GYRO_SENSITIVITY,
             horizontal_offset_x: 0,
             horizontal_offset_y: 0,
             horizontal_offset_z: 0,
+
+            sources: CalibrationSources::default(),
         }
     }
 }
 
 #[repr(C)]
-#[derive(FromBytes, FromZeroes, AsBytes, Default)]
+#[derive(FromBytes, FromZeroes, AsBytes)]
 struct SubcommandOutputPacket {
     report_id: u8,
     report_counter: u8,
     rumble_data: [u8; 8],
     subcommand_id: u8,
-    subcommand_data: [u8; 32], // 38
+    subcommand_data: [u8; 38],
 }
 
-#[repr(C)]
+// Arguments of `SubcommandID::SPIFlashRead`, also the start of its reply data.
+#[repr(C, packed)]
 #[derive(FromBytes, FromZeroes, AsBytes, Default)]
 struct ReadSpiPacket {
-    address: u16,
-    padding: u16,
-    length: u16,
+    address: u32,
+    length: u8,
 }
 
-#[repr(C)]
-#[derive(Default)]
+// Axes and buttons every gamepad has, drivers may add more.
+pub const DEFAULT_AXIS_COUNT: usize = 4;
+pub const DEFAULT_BUTTON_COUNT: usize = 24;
+
 pub struct Gamepad {
     pub index: usize,
-    pub axes: [f32; 4],
-    pub buttons: [Button; 24],
+    pub axes: Vec<f32>,
+    pub buttons: Vec<Button>,
+    /// Hat switches as `HAT_*` bits of `report_descriptor`.
+    pub hats: Vec<u8>,
+    pub mapping: GamepadMapping,
 }
 
-#[repr(C)]
-pub struct GamepadArray {
-    len: usize,
-    data: *const Gamepad,
+impl Default for Gamepad {
+    fn default() -> Self {
+        Gamepad {
+            index: 0,
+            axes: vec![0.0; DEFAULT_AXIS_COUNT],
+            buttons: vec![Button::default(); DEFAULT_BUTTON_COUNT],
+            hats: Vec::new(),
+            mapping: GamepadMapping::None,
+        }
+    }
+}
+
+impl Gamepad {
+    /// Appends the C ABI layout of the gamepad to `buf`, see `GamepadHeader`.
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        let header_size = std::mem::size_of::<GamepadHeader>();
+        let axes_offset = header_size;
+        let buttons_offset = axes_offset + self.axes.len() * std::mem::size_of::<f32>();
+        let hats_offset = buttons_offset + self.buttons.len() * std::mem::size_of::<Button>();
+        let size = (hats_offset + self.hats.len()).next_multiple_of(GAMEPAD_ALIGN);
+
+        let header = GamepadHeader {
+            size: size as u32,
+            mapping: self.mapping as u8,
+            index: self.index as u64,
+            axis_count: self.axes.len() as u32,
+            axes_offset: axes_offset as u32,
+            button_count: self.buttons.len() as u32,
+            buttons_offset: buttons_offset as u32,
+            hat_count: self.hats.len() as u32,
+            hats_offset: hats_offset as u32,
+            ..Default::default()
+        };
+        let start = buf.len();
+        buf.extend_from_slice(header.as_bytes());
+        buf.extend_from_slice(self.axes.as_bytes());
+        buf.extend_from_slice(self.buttons.as_bytes());
+        buf.extend_from_slice(&self.hats);
+        buf.resize(start + size, 0);
+    }
 }
 
+const GAMEPAD_ALIGN: usize = 8;
+
+/// Start of one gamepad in the buffer returned by `get_gamepads`. Its axes (`f32`),
+/// buttons (`Button`) and hats (`u8`) follow, at byte offsets from the start of the
+/// header, and the next gamepad starts `size` bytes after it.
 #[repr(C)]
-#[derive(Default)]
-pub struct Button {
-    pub pressed: bool,
+#[derive(AsBytes, Default)]
+pub struct GamepadHeader {
+    pub size: u32,
+    pub mapping: u8,
     _padding: [u8; 3],
-    pub value: f32,
+    pub index: u64,
+    pub axis_count: u32,
+    pub axes_offset: u32,
+    pub button_count: u32,
+    pub buttons_offset: u32,
+    pub hat_count: u32,
+    pub hats_offset: u32,
 }
 
-#[derive(Debug, Default)]
-struct GamepadContext {
-    cal_data: CalibrationData,
-    init_state: GamepadInitState,
+/// Button and axis layout of a `Gamepad`, mirrors the `mapping` attribute of the web API.
+#[repr(u8)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum GamepadMapping {
+    /// Device specific layout.
+    #[default]
+    None = 0,
+    /// The standard gamepad layout, see https://w3c.github.io/gamepad/#remapping
+    Standard = 1,
 }
 
-#[derive(Debug, Default, TryFromPrimitive, Clone, Copy)]
+/// Motion sensor readings of a gamepad, in the controller's own axes.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Motion {
+    /// Acceleration in G.
+    pub accel: [f32; 3],
+    /// Angular velocity in degrees per second.
+    pub gyro: [f32; 3],
+    pub has_accel: bool,
+    pub has_gyro: bool,
+}
+
+/// Where the calibration of a stick or the IMU came from.
 #[repr(u8)]
-enum GamepadInitState {
+#[derive(Debug, Default, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum CalibrationSource {
+    /// Nominal values, the SPI flash holds none.
     #[default]
-    Uninitialized = 0,
-    RequestedAnalogCalibration = 1,
-    RequestedAnalogCalibrationOk = 2,
-    RequestedAnalogParams = 3,
-    RequestedAnalogParamsOk = 4,
-    Initialized = 5,
+    Default = 0,
+    Factory = 1,
+    /// Written by recalibrating on a Switch.
+    User = 2,
 }
 
-pub struct GamepadAPI {
-    hidapi: HidApi,
-    device_map: HashMap<String, (usize, HidDevice, GamepadContext)>,
-    input_buf: [u8; REPORT_BUF_SIZE],
-    report_counter: u8,
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CalibrationSources {
+    pub left_stick: CalibrationSource,
+    pub right_stick: CalibrationSource,
+    pub imu: CalibrationSource,
 }
 
-impl GamepadAPI {
-    pub fn new() -> Self {
-        let hidapi = HidApi::new().unwrap();
-        GamepadAPI {
-            hidapi,
-            device_map: HashMap::new(),
-            input_buf: [0; REPORT_BUF_SIZE],
-            report_counter: 0,
-        }
-    }
+/// Progress of a stick calibration, see `GamepadAPI::start_stick_calibration`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationPhase {
+    /// The stick should rest in the center.
+    SamplingCenter = 0,
+    /// The stick should be rotated along the edge of its gate a few times.
+    SamplingRange = 1,
+    /// The calibration can be applied.
+    Sampled = 2,
+    /// Writing to and verifying the SPI flash.
+    Writing = 3,
+    Done = 4,
+    /// The SPI flash write failed, the calibration is still applied until disconnect.
+    Failed = 5,
+}
 
-    fn send_subcommand(
-        report_counter: &mut u8,
-        device: &HidDevice,
-        command_id: SubcommandID,
-        buf: &[u8],
-    ) {
-        let mut packet = SubcommandOutputPacket::default();
-        packet.report_id = OutputReportID::RumbleAndSubcommand as u8;
-        packet.subcommand_id = command_id as u8;
-        *report_counter = (*report_counter + 1) & 0xF;
-        packet.report_counter = *report_counter;
-        packet.subcommand_data[..buf.len()].copy_from_slice(buf);
-        device.write(&packet.as_bytes()).unwrap();
-    }
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StickCalibrationStatus {
+    /// `CalibrationPhase`.
+    pub phase: u8,
+    /// 0 left, 1 right, 2 both while erasing.
+    pub stick: u8,
+    _padding: [u8; 2],
+    /// Reports sampled in the current phase.
+    pub sample_count: u32,
+    /// Raw 12-bit positions, x then y.
+    pub center: [u16; 2],
+    pub min: [u16; 2],
+    pub max: [u16; 2],
+}
 
-    fn request_analog_calibration(report_counter: &mut u8, device: &HidDevice) {
-        let packet = ReadSpiPacket {
-            address: SPIAddress::AnalogStickCalibration as u16,
-            padding: 0_u16,
-            length: 18_u16,
-        };
-        Self::send_subcommand(
-            report_counter,
-            device,
-            SubcommandID::SPIFlashRead,
-            packet.as_bytes(),
-        );
-    }
+/// Shape of the inner deadzone of a stick, see `StickResponse`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum DeadzoneShape {
+    /// Zero inside a circle, unchanged outside of it.
+    Radial = 0,
+    /// Each axis on its own, rescaled to start at zero past the deadzone.
+    Axial = 1,
+    /// Zero inside a circle, rescaled to start at zero past it.
+    ScaledRadial = 2,
+    /// Scaled radial, plus snapping to an axis when close to it.
+    Hybrid = 3,
+}
 
-    fn request_analog_params(report_counter: &mut u8, device: &HidDevice) {
-        let packet = ReadSpiPacket {
-            address: SPIAddress::AnalogStickParameters as u16,
-            padding: 0_u16,
-            length: 18_u16,
-        };
-        Self::send_subcommand(
-            report_counter,
-            device,
-            SubcommandID::SPIFlashRead,
-            packet.as_bytes(),
-        );
-    }
+/// Response curve of a stick, applied to the distance from the center past the deadzone.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum ResponseCurve {
+    Linear = 0,
+    /// `distance ^ exponent`.
+    Power = 1,
+    /// Flat around the center and the edge for exponents above 1.
+    SCurve = 2,
+    /// Linear interpolation of `lookup`.
+    Lookup = 3,
+}
 
-    fn update_gamepad(
-        button_status: &[u8],
-        left_stick: &[u8],
-        right_stick: &[u8],
-        cal_data: &CalibrationData,
-        gamepad: &mut Gamepad,
-        is_simple: bool,
-    ) {
-        let button_values = util::extract_bits(button_status, 3);
-        for i in 0..24 {
-            gamepad.buttons[i].pressed = button_values[i] > 0;
-            gamepad.buttons[i].value = button_values[i] as f32;
+/// Where a stick rests, see `GamepadAPI::get_stick_drift`. Positions are in calibrated axes.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StickDrift {
+    /// x then y.
+    pub rest: [f32; 2],
+    /// Distance of the rest position from the center, a fraction of a full deflection.
+    pub drift: f32,
+    /// Average distance of resting positions from the rest position.
+    pub noise: f32,
+    /// Reports the stick was seen resting in.
+    pub samples: u32,
+}
+
+/// Learned reach of the stick axes of a Nintendo controller, see
+/// `GamepadAPI::get_adaptive_range`. Raw 12-bit positions of left x, left y, right x and
+/// right y, 0 where a side wasn't reached yet.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AdaptiveRange {
+    pub min: [u16; 4],
+    pub max: [u16; 4],
+    /// Whether the learned bounds replace the calibrated ones.
+    pub enabled: bool,
+    pub _padding: u8,
+}
+
+/// Gate of a stick, see `StickGate`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum GateShape {
+    /// Reaches 1 in every direction, only useful with `StickGate::square_output`.
+    Circle = 0,
+    /// Corners in the cardinal and diagonal directions, see `StickGate::diagonal`.
+    Octagon = 1,
+    /// Learned from the farthest positions seen in each direction.
+    Learned = 2,
+}
+
+/// Stick gate correction, see `GamepadAPI::set_stick_gate`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StickGate {
+    /// `GateShape`.
+    pub shape: u8,
+    /// Maps the corrected circle onto a square, for games that expect diagonals at (1, 1).
+    pub square_output: bool,
+    pub _padding: [u8; 2],
+    /// Distance of the diagonal corners of an octagon from the center, cardinal ones are at 1.
+    pub diagonal: f32,
+}
+
+pub const MAX_RESPONSE_LOOKUP_POINTS: usize = 16;
+
+/// Deadzones and response curve of a stick, see `GamepadAPI::set_stick_response`. Distances
+/// are fractions of a full deflection.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StickResponse {
+    /// `DeadzoneShape`.
+    pub deadzone_shape: u8,
+    /// `ResponseCurve`.
+    pub curve: u8,
+    /// Points used of `lookup`, at least 2 for `ResponseCurve::Lookup`.
+    pub lookup_len: u8,
+    pub _padding: u8,
+    pub inner_deadzone: f32,
+    /// Distance counted as a full deflection.
+    pub outer_deadzone: f32,
+    /// Smallest output past the inner deadzone, to skip the deadzone of a game.
+    pub anti_deadzone: f32,
+    /// Of `ResponseCurve::Power` and `ResponseCurve::SCurve`.
+    pub exponent: f32,
+    /// Outputs for evenly spaced distances from 0 to 1.
+    pub lookup: [f32; MAX_RESPONSE_LOOKUP_POINTS],
+}
+
+impl Default for StickResponse {
+    fn default() -> Self {
+        StickResponse {
+            deadzone_shape: DeadzoneShape::ScaledRadial as u8,
+            curve: ResponseCurve::Linear as u8,
+            lookup_len: 0,
+            _padding: 0,
+            inner_deadzone: 0.1,
+            outer_deadzone: 1.0,
+            anti_deadzone: 0.0,
+            exponent: 1.0,
+            lookup: [0.0; MAX_RESPONSE_LOOKUP_POINTS],
         }
+    }
+}
 
-        let mut lx: u16 = 0;
-        let mut ly: u16 = 0;
-        let mut rx: u16 = 0;
-        let mut ry: u16 = 0;
+/// Progress of `GamepadAPI::start_spi_dump` and `GamepadAPI::start_spi_restore`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpiProgress {
+    /// 0 running, 1 done, 2 failed, 3 refused restore of another controller's dump.
+    pub state: u8,
+    _padding: [u8; 3],
+    /// Bytes read for dumps, chunks written for restores.
+    pub done: u32,
+    pub total: u32,
+}
 
-        if is_simple {
-            lx = (left_stick[1] as u16) << 4;
-            ly = (left_stick[3] as u16) << 4;
-            rx = (right_stick[1] as u16) << 4;
-            ry = (right_stick[3] as u16) << 4;
-        } else {
-            util::unpack_shorts(left_stick, &mut lx, &mut ly);
-            util::unpack_shorts(right_stick, &mut rx, &mut ry);
+/// What a SPI flash dump holds, calibration as `CalibrationData` would use it.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpiSummary {
+    /// ASCII, zero padded, empty on controllers without one.
+    pub serial: [u8; 16],
+    pub colors: ControllerColors,
+    pub has_pairing: bool,
+    /// Bluetooth address of the paired host.
+    pub host_address: [u8; 6],
+    pub sources: CalibrationSources,
+    _padding: u8,
+    pub left_stick: calibration::StickCalibration,
+    pub right_stick: calibration::StickCalibration,
+    pub accelerometer_origin: [u16; 3],
+    pub accelerometer_sensitivity: [u16; 3],
+    pub gyro_origin: [u16; 3],
+    pub gyro_sensitivity: [u16; 3],
+}
+
+impl SpiSummary {
+    /// `None` unless `dump` is a complete dump.
+    pub fn from_dump(dump: &[u8]) -> Option<Self> {
+        if dump.len() != spi::FLASH_SIZE {
+            return None;
         }
+        let at = |address: SPIAddress| &dump[address as usize..];
 
-        let is_left_deadzone = util::is_dead_zone(
-            lx,
-            ly,
-            cal_data.lx_center,
-            cal_data.ly_center,
-            cal_data.dead_zone,
+        let mut cal_data = CalibrationData::default();
+        GamepadAPI::update_stick_calibration_data(
+            &AnalogStickCalibrationPacket::read_from_prefix(at(
+                SPIAddress::AnalogStickCalibration,
+            ))?,
+            &mut cal_data,
         );
+        GamepadAPI::update_user_stick_calibration_data(
+            &UserStickCalibrationPacket::read_from_prefix(at(SPIAddress::UserStickCalibration))?,
+            &mut cal_data,
+        );
+        GamepadAPI::update_imu_calibration_data(
+            &ImuCalibrationPacket::read_from_prefix(at(SPIAddress::ImuCalibration))?,
+            &mut cal_data,
+        );
+        GamepadAPI::update_user_imu_calibration_data(
+            &UserImuCalibrationPacket::read_from_prefix(at(SPIAddress::UserImuCalibration))?,
+            &mut cal_data,
+        );
+
+        let colors = ColorsPacket::read_from_prefix(at(SPIAddress::Colors))?;
+        let has_grips = at(SPIAddress::ColorInfo)[0] == COLOR_INFO_WITH_GRIPS;
 
-        let is_right_deadzone = util::is_dead_zone(
-            rx,
-            ry,
-            cal_data.rx_center,
-            cal_data.ry_center,
-            cal_data.dead_zone,
+        let mut serial = [0; 16];
+        serial.copy_from_slice(&at(SPIAddress::SerialNumber)[..16]);
+        // Erased flash, or no serial number at all.
+        if !serial.is_ascii() {
+            serial = [0; 16];
+        }
+
+        let pairing = at(SPIAddress::PairingInfo);
+        let mut host_address = [0; 6];
+        host_address.copy_from_slice(
+            &pairing[PAIRING_HOST_ADDRESS_OFFSET..PAIRING_HOST_ADDRESS_OFFSET + 6],
         );
 
-        gamepad.axes[0] = if is_left_deadzone {
-            0.0
-        } else {
-            util::clamp_axis(lx, cal_data.lx_min, cal_data.lx_max)
-        };
-        gamepad.axes[1] = if is_left_deadzone {
-            0.0
-        } else {
-            util::clamp_axis(ly, cal_data.ly_min, cal_data.ly_max)
-        };
-        gamepad.axes[2] = if is_right_deadzone {
-            0.0
-        } else {
-            util::clamp_axis(rx, cal_data.rx_min, cal_data.rx_max)
-        };
-        gamepad.axes[3] = if is_right_deadzone {
-            0.0
-        } else {
-            util::clamp_axis(ry, cal_data.ry_min, cal_data.ry_max)
-        };
+        Some(SpiSummary {
+            serial,
+            colors: GamepadAPI::read_colors(&colors, has_grips),
+            has_pairing: pairing[0] == PAIRING_MAGIC,
+            host_address,
+            sources: cal_data.sources,
+            left_stick: calibration::StickCalibration {
+                center: [cal_data.lx_center, cal_data.ly_center],
+                min: [cal_data.lx_min, cal_data.ly_min],
+                max: [cal_data.lx_max, cal_data.ly_max],
+            },
+            right_stick: calibration::StickCalibration {
+                center: [cal_data.rx_center, cal_data.ry_center],
+                min: [cal_data.rx_min, cal_data.ry_min],
+                max: [cal_data.rx_max, cal_data.ry_max],
+            },
+            accelerometer_origin: [
+                cal_data.accelerometer_origin_x,
+                cal_data.accelerometer_origin_y,
+                cal_data.accelerometer_origin_z,
+            ],
+            accelerometer_sensitivity: [
+                cal_data.accelerometer_sensitivity_x,
+                cal_data.accelerometer_sensitivity_y,
+                cal_data.accelerometer_sensitivity_z,
+            ],
+            gyro_origin: [
+                cal_data.gyro_origin_x,
+                cal_data.gyro_origin_y,
+                cal_data.gyro_origin_z,
+            ],
+            gyro_sensitivity: [
+                cal_data.gyro_sensitivity_x,
+                cal_data.gyro_sensitivity_y,
+                cal_data.gyro_sensitivity_z,
+            ],
+            ..Default::default()
+        })
     }
+}
 
-    fn update_stick_calibration_data(
-        cal: &AnalogStickCalibrationPacket,
-        cal_data: &mut CalibrationData,
-    ) {
-        util::unpack_shorts(
-            &cal.l_xy_center,
-            &mut cal_data.lx_center,
-            &mut cal_data.ly_center,
-        );
-        util::unpack_shorts(
-            &cal.r_xy_center,
-            &mut cal_data.rx_center,
-            &mut cal_data.ry_center,
-        );
-        util::unpack_shorts(&cal.l_xy_min, &mut cal_data.lx_min, &mut cal_data.ly_min);
-        util::unpack_shorts(&cal.r_xy_min, &mut cal_data.rx_min, &mut cal_data.ry_min);
-
-        util::unpack_shorts(&cal.l_xy_max, &mut cal_data.lx_max, &mut cal_data.ly_max);
-        util::unpack_shorts(&cal.r_xy_max, &mut cal_data.rx_max, &mut cal_data.ry_max);
-        if cal_data.lx_min == CAL_BOGUS_VALUE && cal_data.ly_max == CAL_BOGUS_VALUE {
-            cal_data.lx_min = CAL_DEFAULT_MIN;
-            cal_data.lx_max = CAL_DEFAULT_MAX;
-            cal_data.lx_center = CAL_DEFAULT_CENTER;
-            cal_data.ly_min = CAL_DEFAULT_MIN;
-            cal_data.ly_max = CAL_DEFAULT_MAX;
-            cal_data.ly_center = CAL_DEFAULT_CENTER;
-        } else {
-            cal_data.lx_min = cal_data.lx_center - cal_data.lx_min;
-            cal_data.lx_max = cal_data.lx_center + cal_data.lx_max;
-            cal_data.ly_min = cal_data.ly_center - cal_data.ly_min;
-            cal_data.ly_max = cal_data.ly_center + cal_data.ly_max;
-        }
-
-        if cal_data.rx_min == CAL_BOGUS_VALUE && cal_data.ry_max == CAL_BOGUS_VALUE {
-            cal_data.rx_min = CAL_DEFAULT_MIN;
-            cal_data.rx_max = CAL_DEFAULT_MAX;
-            cal_data.rx_center = CAL_DEFAULT_CENTER;
-            cal_data.ry_min = CAL_DEFAULT_MIN;
-            cal_data.ry_max = CAL_DEFAULT_MAX;
-            cal_data.ry_center = CAL_DEFAULT_CENTER;
-        } else {
-            cal_data.rx_min = cal_data.rx_center - cal_data.rx_min;
-            cal_data.rx_max = cal_data.rx_center + cal_data.rx_max;
-            cal_data.ry_min = cal_data.ry_center - cal_data.ry_min;
-            cal_data.ry_max = cal_data.ry_center + cal_data.ry_max;
-        }
+/// What a Nintendo controller reports about itself.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ControllerInfo {
+    /// Major, minor.
+    pub firmware_version: [u8; 2],
+    /// 1 left Joy-Con, 2 right Joy-Con, 3 Pro Controller, 9/10 NES, 11 SNES, 12 N64, 13 Genesis.
+    pub controller_type: u8,
+    /// Bluetooth address, also reported over USB.
+    pub mac_address: [u8; 6],
+}
+
+impl ControllerInfo {
+    /// Stays the same across connections, unlike the gamepad index.
+    pub fn identity_key(&self) -> String {
+        self.mac_address
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
     }
+}
 
-    fn update_stick_params_calibration_data(
-        cal: &AnalogStickParamsCalibrationPacket,
-        cal_data: &mut CalibrationData,
-    ) {
-        util::unpack_shorts(
-            &cal.params,
-            &mut cal_data.dead_zone,
-            &mut cal_data.range_ratio,
-        );
-        if cal_data.dead_zone == CAL_BOGUS_VALUE {
-            cal_data.dead_zone = CAL_DEFAULT_DEADZONE;
+/// The request a Nintendo controller never answered, which stopped its initialization.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InitFailure {
+    /// A subcommand ID or, over USB, a proprietary command.
+    pub command_id: u8,
+    pub is_proprietary: bool,
+    /// Whether the last answer was a NACK rather than nothing at all.
+    pub nacked: bool,
+    pub _padding: u8,
+    /// Address of an SPI flash read, 0 for other requests.
+    pub spi_address: u32,
+}
+
+/// Colors of a controller as stored in its SPI flash, RGB.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ControllerColors {
+    pub body: [u8; 3],
+    pub buttons: [u8; 3],
+    /// Only set for Pro Controllers, see `has_grips`.
+    pub left_grip: [u8; 3],
+    pub right_grip: [u8; 3],
+    pub has_grips: bool,
+}
+
+/// A finger on a touch surface of a gamepad, mirrors `GamepadTouch` of the web API.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Touch {
+    pub touch_id: u32,
+    pub surface_id: u8,
+    _padding: [u8; 3],
+    /// Normalized to `-1.0..=1.0`, -1.0 is the left and top edge.
+    pub position: [f32; 2],
+}
+
+impl Touch {
+    fn new(touch_id: u32, surface_id: u8, position: [f32; 2]) -> Self {
+        Touch {
+            touch_id,
+            surface_id,
+            position,
+            ..Default::default()
         }
     }
+}
 
-    fn read_data_and_fill(
-        device: &HidDevice,
+pub const NFC_TAG_UID_SIZE: usize = 10;
+// The 135 pages of an NTAG215.
+pub const NFC_TAG_DATA_SIZE: usize = 540;
+
+/// An NFC tag read by `GamepadAPI::read_nfc_tag`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NfcTag {
+    pub uid_len: u8,
+    pub uid: [u8; NFC_TAG_UID_SIZE],
+    _padding: u8,
+    pub data_len: u16,
+    /// Raw pages starting at page 0, an amiibo fills all of them.
+    pub data: [u8; NFC_TAG_DATA_SIZE],
+}
+
+impl Default for NfcTag {
+    fn default() -> Self {
+        NfcTag {
+            uid_len: 0,
+            uid: [0; NFC_TAG_UID_SIZE],
+            _padding: 0,
+            data_len: 0,
+            data: [0; NFC_TAG_DATA_SIZE],
+        }
+    }
+}
+
+impl NfcTag {
+    pub fn uid(&self) -> &[u8] {
+        &self.uid[..self.uid_len as usize]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.data_len as usize]
+    }
+}
+
+// `IrConfig::leds` bits.
+pub const IR_LED_FAR: u8 = 0x01;
+pub const IR_LED_NEAR: u8 = 0x02;
+
+/// Settings of `GamepadAPI::start_ir_camera`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IrConfig {
+    /// `ir::IrMode`: 3 moment, 6 clustering, 7 image transfer.
+    pub mode: u8,
+    /// `ir::IrResolution` of images: 0 320x240, 1 160x120, 2 80x60, 3 40x30.
+    pub resolution: u8,
+    /// `IR_LED_*` bits of the LED groups to switch on.
+    pub leds: u8,
+    pub intensity_far: u8,
+    pub intensity_near: u8,
+    _padding: u8,
+    /// Up to 600.
+    pub exposure_us: u16,
+}
+
+/// One block of the 8x6 grid reported in moment mode.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, FromBytes, FromZeroes)]
+pub struct IrMoment {
+    pub average_intensity: u16,
+    pub centroid_x: u16,
+    pub centroid_y: u16,
+}
+
+/// A bright object reported in clustering mode, positions in pixels of the 320x240 sensor.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, FromBytes, FromZeroes)]
+pub struct IrCluster {
+    pub average_intensity: u16,
+    pub pixel_count: u16,
+    pub centroid_x: u16,
+    pub centroid_y: u16,
+    pub bound_x: u16,
+    pub bound_y: u16,
+    pub bound_width: u16,
+    pub bound_height: u16,
+}
+
+/// Describes the pixels written by `get_ir_image`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IrImage {
+    /// Increments with every complete image.
+    pub frame_id: u32,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Called with the gamepad index and the tag, or `None` if the read failed or was cancelled.
+pub type NfcCallback = Box<dyn FnOnce(usize, Option<&NfcTag>)>;
+
+struct NfcSession {
+    reader: nfc::NfcReader,
+    callback: Option<NfcCallback>,
+}
+
+impl NfcSession {
+    fn complete(&mut self, index: usize, tag: Option<&NfcTag>) {
+        if let Some(callback) = self.callback.take() {
+            callback(index, tag);
+        }
+    }
+}
+
+/// Steering and pedal positions of a racing wheel.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WheelState {
+    /// `-1.0..=1.0` over the rotation range, left is -1.0.
+    pub steering: f32,
+    /// Pedal positions in `0.0..=1.0`.
+    pub throttle: f32,
+    pub brake: f32,
+    pub clutch: f32,
+    /// Rotation range in degrees, lock to lock.
+    pub range: u16,
+    /// H-shifter gear, 0 is neutral and -1 reverse.
+    pub gear: i8,
+}
+
+/// Force feedback effect types of `WheelEffect`.
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive)]
+pub enum WheelEffectType {
+    Constant = 0,
+    Spring = 1,
+    Damper = 2,
+}
+
+/// C ABI version of `ffb::Effect`, fields not used by `effect_type` are ignored.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WheelEffect {
+    pub effect_type: u8,
+    pub level: f32,
+    pub center: f32,
+    pub deadband: f32,
+    pub left_coefficient: f32,
+    pub right_coefficient: f32,
+    pub saturation: f32,
+}
+
+impl WheelEffect {
+    fn to_effect(self) -> Option<ffb::Effect> {
+        Some(match WheelEffectType::try_from(self.effect_type).ok()? {
+            WheelEffectType::Constant => ffb::Effect::Constant { level: self.level },
+            WheelEffectType::Spring => ffb::Effect::Spring {
+                center: self.center,
+                deadband: self.deadband,
+                left_coefficient: self.left_coefficient,
+                right_coefficient: self.right_coefficient,
+                saturation: self.saturation,
+            },
+            WheelEffectType::Damper => ffb::Effect::Damper {
+                left_coefficient: self.left_coefficient,
+                right_coefficient: self.right_coefficient,
+                saturation: self.saturation,
+            },
+        })
+    }
+}
+
+/// `len` gamepads, packed into `size` bytes starting at `data`.
+#[repr(C)]
+pub struct GamepadArray {
+    len: usize,
+    data: *const GamepadHeader,
+    size: usize,
+}
+
+#[repr(C)]
+#[derive(AsBytes, Default, Clone, Copy)]
+pub struct Button {
+    pub pressed: bool,
+    _padding: [u8; 3],
+    pub value: f32,
+}
+
+impl Button {
+    fn digital(pressed: bool) -> Self {
+        Button {
+            pressed,
+            value: if pressed { 1.0 } else { 0.0 },
+            ..Default::default()
+        }
+    }
+
+    fn analog(value: f32) -> Self {
+        let value = value.clamp(0.0, 1.0);
+        Button {
+            pressed: value > 0.0,
+            value,
+            ..Default::default()
+        }
+    }
+}
+
+// What profiles are assigned by, kept from when a device was opened, see
+// `GamepadAPI::active_profile`.
+struct DeviceIdentity {
+    // `profile::ProfileSet` name of the controller type.
+    controller_type: &'static str,
+    serial: Option<String>,
+    nintendo_labels: bool,
+}
+
+impl DeviceIdentity {
+    fn new(controller_type: &'static str, serial: Option<String>) -> Self {
+        DeviceIdentity {
+            controller_type,
+            serial,
+            nintendo_labels: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GamepadContext {
+    cal_data: CalibrationData,
+    init_state: GamepadInitState,
+    // The initialization request waiting for its reply.
+    transport: Transport,
+    cache_state: CacheState,
+    // Set when a required request stayed unanswered, the initialization stops there.
+    init_failure: Option<InitFailure>,
+    controller_type: ControllerType,
+    is_usb: bool,
+    motion: Option<Motion>,
+    device_info: Option<ControllerInfo>,
+    colors: Option<ControllerColors>,
+    // Running NFC read, see `GamepadAPI::read_nfc_tag`.
+    nfc: Option<NfcSession>,
+    // Running IR camera, see `GamepadAPI::start_ir_camera`. Only one MCU client at a time.
+    ir: Option<ir::IrCamera>,
+    // Probed once after initialization, kept while a Ring-Con is attached.
+    ring_con: Option<ringcon::RingCon>,
+    // Stick calibration wizard, see `GamepadAPI::start_stick_calibration`.
+    calibration: Option<calibration::CalibrationSession>,
+    // SPI flash dump or restore, kept after finishing until the next one.
+    spi: Option<spi::SpiTransfer>,
+    // Reads the calibration again while it's in use, see `CacheState::Refreshing`.
+    refresh: Option<spi::SpiRead>,
+    drift: drift::DriftMonitor,
+    range: range::RangeLearner,
+    // When the last input report arrived.
+    last_report: Option<Instant>,
+    // When the USB status was last asked for, see `GRIP_SLOT_RETRY`.
+    last_status_request: Option<Instant>,
+    // When the learned stick ranges were last saved, see `RANGE_SAVE_INTERVAL`.
+    range_saved_at: Option<Instant>,
+}
+
+// Whether the calibration of a controller came from `GamepadAPI::calibration_cache`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum CacheState {
+    #[default]
+    Lookup,
+    // Cached calibration in use, the SPI flash is read again once initialized.
+    Cached,
+    // Reading the SPI flash, during the initialization or in the background once
+    // initialized. The result is cached once both are done.
+    Refreshing,
+    Done,
+}
+
+impl GamepadContext {
+    // Whether something is writing or reading the SPI flash in chunks.
+    fn spi_busy(&self) -> bool {
+        self.calibration
+            .as_ref()
+            .is_some_and(|session| session.phase() == CalibrationPhase::Writing)
+            || self
+                .spi
+                .as_ref()
+                .is_some_and(|transfer| !transfer.is_done())
+    }
+}
+
+#[derive(Debug, Default, TryFromPrimitive, Clone, Copy)]
+#[repr(u8)]
+enum GamepadInitState {
+    #[default]
+    Uninitialized = 0,
+    // USB only handshake, the controller ignores everything else until it's done.
+    RequestedUsbStatus = 1,
+    RequestedUsbStatusOk = 2,
+    RequestedUsbHandshake = 3,
+    RequestedUsbHandshakeOk = 4,
+    RequestedUsbHighSpeed = 5,
+    RequestedUsbHighSpeedOk = 6,
+    RequestedUsbHighSpeedHandshake = 7,
+    RequestedUsbHighSpeedHandshakeOk = 8,
+    RequestedDeviceInfo = 9,
+    RequestedDeviceInfoOk = 10,
+    RequestedColors = 11,
+    RequestedColorsOk = 12,
+    RequestedAnalogCalibration = 13,
+    RequestedAnalogCalibrationOk = 14,
+    RequestedUserAnalogCalibration = 15,
+    RequestedUserAnalogCalibrationOk = 16,
+    RequestedAnalogParams = 17,
+    RequestedAnalogParamsOk = 18,
+    RequestedImuCalibration = 19,
+    RequestedImuCalibrationOk = 20,
+    RequestedUserImuCalibration = 21,
+    RequestedUserImuCalibrationOk = 22,
+    RequestedEnableImu = 23,
+    RequestedEnableImuOk = 24,
+    RequestedFullReportMode = 25,
+    RequestedFullReportModeOk = 26,
+    Initialized = 27,
+}
+
+impl GamepadInitState {
+    // Where the initialization goes on when a read with usable defaults stays unanswered.
+    fn fallback(self) -> Option<Self> {
+        match self {
+            GamepadInitState::RequestedColors => Some(GamepadInitState::RequestedColorsOk),
+            GamepadInitState::RequestedAnalogCalibration => {
+                Some(GamepadInitState::RequestedAnalogCalibrationOk)
+            }
+            GamepadInitState::RequestedUserAnalogCalibration => {
+                Some(GamepadInitState::RequestedUserAnalogCalibrationOk)
+            }
+            GamepadInitState::RequestedAnalogParams => {
+                Some(GamepadInitState::RequestedAnalogParamsOk)
+            }
+            GamepadInitState::RequestedImuCalibration => {
+                Some(GamepadInitState::RequestedImuCalibrationOk)
+            }
+            GamepadInitState::RequestedUserImuCalibration => {
+                Some(GamepadInitState::RequestedUserImuCalibrationOk)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct GamepadAPI {
+    hidapi: HidApi,
+    // `None` where libusb can't be used, e.g. without access to usbfs, only Xbox controllers
+    // go without then.
+    usb: Option<rusb::Context>,
+    device_map: HashMap<String, (usize, HidDevice, GamepadContext)>,
+    // Wired Xbox controllers, keyed by USB (bus number, address).
+    gip_map: HashMap<(u8, u8), (usize, gip::GipDevice)>,
+    // USB devices `gip::GipDevice::open` turned down, not tried again until they're replugged.
+    gip_rejected: HashSet<(u8, u8)>,
+    // Other HID game controllers, keyed by device path.
+    generic_map: HashMap<String, (usize, generic::GenericDevice)>,
+    // GameCube controller adapters, keyed by device path. Each port has its own index.
+    gamecube_map: HashMap<String, gamecube::GameCubeAdapter>,
+    // Wii Remotes, keyed by device path.
+    wiimote_map: HashMap<String, (usize, wiimote::WiimoteDevice)>,
+    // Steam Controllers and the Steam Deck, keyed by device path.
+    steam_map: HashMap<String, (usize, steam::SteamDevice)>,
+    // Racing wheels, keyed by device path.
+    wheel_map: HashMap<String, (usize, wheel::WheelDevice)>,
+    mappings: sdl_mapping::MappingDatabase,
+    // See `GamepadAPI::enable_calibration_cache`.
+    calibration_cache: Option<cache::CalibrationCache>,
+    // Left and right stick, see `GamepadAPI::set_stick_response`.
+    stick_responses: HashMap<usize, [Option<response::Response>; 2]>,
+    // Left and right stick, see `GamepadAPI::set_stick_gate`.
+    stick_gates: HashMap<usize, [Option<gate::GateCorrection>; 2]>,
+    // See `GamepadAPI::load_profiles`.
+    profiles: profile::ProfileSet,
+    // Profile names set with `GamepadAPI::set_profile`, by index.
+    profile_overrides: HashMap<usize, String>,
+    // By index, except for the ports of GameCube adapters, which go by their adapter.
+    identities: HashMap<usize, DeviceIdentity>,
+    input_buf: [u8; REPORT_BUF_SIZE],
+    report_counter: u8,
+}
+
+impl Drop for GamepadAPI {
+    fn drop(&mut self) {
+        // Give USB controllers their default timeout back so they can return to Bluetooth.
+        for (_, device, context) in self.device_map.values_mut() {
+            if let Some(cache) = &mut self.calibration_cache {
+                Self::save_range(cache, context);
+            }
+            // Unplugged controllers are fine to skip, panicking here would cross the C ABI.
+            if context.is_usb {
+                let packet = Self::proprietary_packet(ProprietaryCommandID::EnableUsbTimeout);
+                let _ = device.write(&packet);
+            }
+        }
+    }
+}
+
+impl Default for GamepadAPI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GamepadAPI {
+    pub fn new() -> Self {
+        let hidapi = HidApi::new().unwrap();
+        let usb = rusb::Context::new().ok();
+
+        // Same environment variables SDL reads its extra mappings from.
+        let mut mappings = sdl_mapping::MappingDatabase::default();
+        if let Ok(path) = std::env::var("SDL_GAMECONTROLLERCONFIG_FILE") {
+            if let Ok(text) = std::fs::read_to_string(path) {
+                mappings.add_mappings(&text);
+            }
+        }
+        if let Ok(text) = std::env::var("SDL_GAMECONTROLLERCONFIG") {
+            mappings.add_mappings(&text);
+        }
+
+        GamepadAPI {
+            hidapi,
+            usb,
+            device_map: HashMap::new(),
+            gip_map: HashMap::new(),
+            gip_rejected: HashSet::new(),
+            generic_map: HashMap::new(),
+            gamecube_map: HashMap::new(),
+            wiimote_map: HashMap::new(),
+            steam_map: HashMap::new(),
+            wheel_map: HashMap::new(),
+            mappings,
+            calibration_cache: None,
+            stick_responses: HashMap::new(),
+            stick_gates: HashMap::new(),
+            profiles: Default::default(),
+            profile_overrides: HashMap::new(),
+            identities: HashMap::new(),
+            input_buf: [0; REPORT_BUF_SIZE],
+            report_counter: 0,
+        }
+    }
+
+    fn send_subcommand(
+        report_counter: &mut u8,
+        device: &HidDevice,
+        command_id: SubcommandID,
+        buf: &[u8],
+    ) {
+        *report_counter = (*report_counter + 1) & 0xF;
+        let mut packet = SubcommandOutputPacket::new_zeroed();
+        packet.report_id = OutputReportID::RumbleAndSubcommand as u8;
+        packet.report_counter = *report_counter;
+        packet.subcommand_id = command_id as u8;
+        packet.subcommand_data[..buf.len()].copy_from_slice(buf);
+        // A disconnected controller is noticed by the next read.
+        let _ = device.write(packet.as_bytes());
+    }
+
+    fn send_mcu_request(report_counter: &mut u8, device: &HidDevice, request: mcu::McuRequest) {
+        match request {
+            mcu::McuRequest::Subcommand(command_id, data) => {
+                Self::send_subcommand(report_counter, device, command_id, &data);
+            }
+            mcu::McuRequest::Mcu(command, data) => {
+                *report_counter = (*report_counter + 1) & 0xF;
+                let mut packet = [0; MCU_OUTPUT_REPORT_SIZE];
+                packet[0] = OutputReportID::RequestMcuData as u8;
+                packet[1] = *report_counter;
+                packet[10] = command as u8;
+                packet[11..].copy_from_slice(&data);
+                let _ = device.write(&packet);
+            }
+        }
+    }
+
+    fn proprietary_packet(command_id: ProprietaryCommandID) -> [u8; USB_OUTPUT_REPORT_SIZE] {
+        let mut packet = [0; USB_OUTPUT_REPORT_SIZE];
+        packet[0] = OutputReportID::Proprietary as u8;
+        packet[1] = command_id as u8;
+        packet
+    }
+
+    fn send_proprietary_command(device: &HidDevice, command_id: ProprietaryCommandID) {
+        let _ = device.write(&Self::proprietary_packet(command_id));
+    }
+
+    fn send_request(report_counter: &mut u8, device: &HidDevice, request: &Request) {
+        match request {
+            Request::Subcommand(command_id, data) => {
+                Self::send_subcommand(report_counter, device, *command_id, data);
+            }
+            Request::Proprietary(command_id) => Self::send_proprietary_command(device, *command_id),
+        }
+    }
+
+    // Sends an initialization request, which `Transport` sends again until it's answered.
+    fn send_init_request(
+        report_counter: &mut u8,
+        device: &HidDevice,
+        context: &mut GamepadContext,
+        request: Request,
+        state: GamepadInitState,
+    ) {
+        Self::send_request(report_counter, device, context.transport.send(request));
+        context.init_state = state;
+    }
+
+    fn read_spi_request(address: SPIAddress, length: u8) -> Request {
+        let packet = ReadSpiPacket {
+            address: address as u32,
+            length,
+        };
+        Request::Subcommand(SubcommandID::SPIFlashRead, packet.as_bytes().to_vec())
+    }
+
+    fn device_info_request() -> Request {
+        Request::Subcommand(SubcommandID::RequestDeviceInfo, vec![])
+    }
+
+    fn analog_calibration_request() -> Request {
+        Self::read_spi_request(SPIAddress::AnalogStickCalibration, 18)
+    }
+
+    fn analog_params_request() -> Request {
+        Self::read_spi_request(SPIAddress::AnalogStickParameters, 18)
+    }
+
+    fn colors_request() -> Request {
+        Self::read_spi_request(SPIAddress::Colors, 12)
+    }
+
+    fn user_stick_calibration_request() -> Request {
+        Self::read_spi_request(SPIAddress::UserStickCalibration, 22)
+    }
+
+    fn user_imu_calibration_request() -> Request {
+        Self::read_spi_request(SPIAddress::UserImuCalibration, 26)
+    }
+
+    fn imu_calibration_request() -> Request {
+        Self::read_spi_request(SPIAddress::ImuCalibration, 24)
+    }
+
+    // The cache entry of a controller, with the calibration in use.
+    fn cache_entry(info: &ControllerInfo, context: &GamepadContext) -> cache::CacheEntry {
+        cache::CacheEntry {
+            firmware_version: info.firmware_version,
+            controller_type: info.controller_type,
+            cal_data: context.cal_data.clone(),
+            colors: context.colors,
+            range: context.range.profile(),
+        }
+    }
+
+    // Saves learned stick ranges that moved since the last time, along with the calibration in
+    // use if the controller wasn't cached yet.
+    fn save_range(cache: &mut cache::CalibrationCache, context: &mut GamepadContext) {
+        if let Some(info) = context.device_info {
+            if context.range.take_changed() {
+                cache.insert(info.identity_key(), Self::cache_entry(&info, context));
+            }
+        }
+    }
+
+    // The SPI flash reads of the initialization, in the same order, to read the calibration
+    // again without going through it.
+    fn calibration_refresh(controller_type: ControllerType) -> spi::SpiRead {
+        let mut regions = vec![(SPIAddress::Colors, 12)];
+        if controller_type.has_sticks() {
+            regions.extend([
+                (SPIAddress::AnalogStickCalibration, 18),
+                (SPIAddress::UserStickCalibration, 22),
+                (SPIAddress::AnalogStickParameters, 18),
+            ]);
+            if controller_type.has_imu() {
+                regions.extend([
+                    (SPIAddress::ImuCalibration, 24),
+                    (SPIAddress::UserImuCalibration, 26),
+                ]);
+            }
+        }
+        let regions: Vec<_> = regions
+            .into_iter()
+            .map(|(address, length)| (address as u32, length))
+            .collect();
+        spi::SpiRead::new(&regions)
+    }
+
+    fn enable_imu_request() -> Request {
+        Request::Subcommand(SubcommandID::EnableIMU, vec![1])
+    }
+
+    fn full_report_mode_request() -> Request {
+        Request::Subcommand(
+            SubcommandID::SetInputReportMode,
+            vec![InputReportID::FullControllerState as u8],
+        )
+    }
+
+    fn update_gamepad(
+        button_status: &[u8],
+        left_stick: &[u8],
+        right_stick: &[u8],
+        cal_data: &CalibrationData,
+        controller_type: ControllerType,
         gamepad: &mut Gamepad,
+        is_simple: bool,
+    ) {
+        let button_values = util::extract_bits(button_status, 3);
+        if let Some(layout) = controller_type.button_layout() {
+            for (bit, index) in layout {
+                gamepad.buttons[*index] = Button::digital(button_values[*bit] > 0);
+            }
+            gamepad.mapping = GamepadMapping::Standard;
+        } else {
+            for (button, value) in gamepad.buttons.iter_mut().zip(button_values.iter()) {
+                button.pressed = *value > 0;
+                button.value = *value as f32;
+            }
+        }
+
+        if !controller_type.has_sticks() {
+            return;
+        }
+
+        let mut lx: u16 = 0;
+        let mut ly: u16 = 0;
+        let mut rx: u16 = 0;
+        let mut ry: u16 = 0;
+
+        if is_simple {
+            lx = (left_stick[1] as u16) << 4;
+            ly = (left_stick[3] as u16) << 4;
+            rx = (right_stick[1] as u16) << 4;
+            ry = (right_stick[3] as u16) << 4;
+        } else {
+            util::unpack_shorts(left_stick, &mut lx, &mut ly);
+            util::unpack_shorts(right_stick, &mut rx, &mut ry);
+        }
+
+        // Deadzones are applied later, see `GamepadAPI::set_stick_response`.
+        gamepad.axes[0] = util::clamp_axis(lx, cal_data.lx_min, cal_data.lx_max);
+        gamepad.axes[1] = util::clamp_axis(ly, cal_data.ly_min, cal_data.ly_max);
+        gamepad.axes[2] = util::clamp_axis(rx, cal_data.rx_min, cal_data.rx_max);
+        gamepad.axes[3] = util::clamp_axis(ry, cal_data.ry_min, cal_data.ry_max);
+
+        if controller_type == ControllerType::N64 {
+            // Standard layout, up is -1.0.
+            gamepad.axes[1] = -gamepad.axes[1];
+            let (up, down, left, right) = N64_C_BUTTONS;
+            let c_button = |bit: usize| button_values[bit] as f32;
+            gamepad.axes[2] = c_button(right) - c_button(left);
+            gamepad.axes[3] = c_button(down) - c_button(up);
+        }
+    }
+
+    // Stores the data read from `address`, what isn't calibration or colors is ignored.
+    fn apply_spi_read(
+        address: SPIAddress,
+        read: &[u8],
+        controller_type: ControllerType,
+        cal_data: &mut CalibrationData,
+        colors: &mut Option<ControllerColors>,
+    ) {
+        match address {
+            SPIAddress::AnalogStickCalibration => {
+                if let Some(cal) = AnalogStickCalibrationPacket::read_from_prefix(read) {
+                    Self::update_stick_calibration_data(&cal, cal_data);
+                }
+            }
+            SPIAddress::AnalogStickParameters => {
+                if let Some(cal) = AnalogStickParamsCalibrationPacket::read_from_prefix(read) {
+                    Self::update_stick_params_calibration_data(&cal, cal_data);
+                }
+            }
+            SPIAddress::Colors => {
+                if let Some(packet) = ColorsPacket::read_from_prefix(read) {
+                    // Only Pro Controllers have separately colored grips.
+                    let has_grips = controller_type == ControllerType::ProController;
+                    *colors = Some(Self::read_colors(&packet, has_grips));
+                }
+            }
+            SPIAddress::UserStickCalibration => {
+                if let Some(cal) = UserStickCalibrationPacket::read_from_prefix(read) {
+                    Self::update_user_stick_calibration_data(&cal, cal_data);
+                }
+            }
+            SPIAddress::UserImuCalibration => {
+                if let Some(cal) = UserImuCalibrationPacket::read_from_prefix(read) {
+                    Self::update_user_imu_calibration_data(&cal, cal_data);
+                }
+            }
+            SPIAddress::ImuCalibration => {
+                if let Some(cal) = ImuCalibrationPacket::read_from_prefix(read) {
+                    Self::update_imu_calibration_data(&cal, cal_data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_stick_calibration_data(
+        cal: &AnalogStickCalibrationPacket,
         cal_data: &mut CalibrationData,
-        init_state: &mut GamepadInitState,
-        buf: &mut [u8],
     ) {
-        let len = device.read(buf).unwrap();
+        cal_data.sources.left_stick = Self::update_left_stick_calibration_data(
+            &cal.l_xy_max,
+            &cal.l_xy_center,
+            &cal.l_xy_min,
+            CalibrationSource::Factory,
+            cal_data,
+        );
+        cal_data.sources.right_stick = Self::update_right_stick_calibration_data(
+            &cal.r_xy_center,
+            &cal.r_xy_min,
+            &cal.r_xy_max,
+            CalibrationSource::Factory,
+            cal_data,
+        );
+    }
+
+    // User calibration replaces the factory calibration of each stick it's valid for.
+    fn update_user_stick_calibration_data(
+        cal: &UserStickCalibrationPacket,
+        cal_data: &mut CalibrationData,
+    ) {
+        if cal.l_magic == USER_CALIBRATION_MAGIC {
+            cal_data.sources.left_stick = Self::update_left_stick_calibration_data(
+                &cal.l_xy_max,
+                &cal.l_xy_center,
+                &cal.l_xy_min,
+                CalibrationSource::User,
+                cal_data,
+            );
+        }
+        if cal.r_magic == USER_CALIBRATION_MAGIC {
+            cal_data.sources.right_stick = Self::update_right_stick_calibration_data(
+                &cal.r_xy_center,
+                &cal.r_xy_min,
+                &cal.r_xy_max,
+                CalibrationSource::User,
+                cal_data,
+            );
+        }
+    }
+
+    fn update_left_stick_calibration_data(
+        xy_max: &[u8; 3],
+        xy_center: &[u8; 3],
+        xy_min: &[u8; 3],
+        source: CalibrationSource,
+        cal_data: &mut CalibrationData,
+    ) -> CalibrationSource {
+        let in_use = cal_data.sources.left_stick;
+        Self::update_stick_axes(
+            [
+                (
+                    &mut cal_data.lx_center,
+                    &mut cal_data.lx_min,
+                    &mut cal_data.lx_max,
+                ),
+                (
+                    &mut cal_data.ly_center,
+                    &mut cal_data.ly_min,
+                    &mut cal_data.ly_max,
+                ),
+            ],
+            Self::stick_calibration(xy_center, xy_min, xy_max),
+            source,
+            in_use,
+        )
+    }
+
+    fn update_right_stick_calibration_data(
+        xy_center: &[u8; 3],
+        xy_min: &[u8; 3],
+        xy_max: &[u8; 3],
+        source: CalibrationSource,
+        cal_data: &mut CalibrationData,
+    ) -> CalibrationSource {
+        let in_use = cal_data.sources.right_stick;
+        Self::update_stick_axes(
+            [
+                (
+                    &mut cal_data.rx_center,
+                    &mut cal_data.rx_min,
+                    &mut cal_data.rx_max,
+                ),
+                (
+                    &mut cal_data.ry_center,
+                    &mut cal_data.ry_min,
+                    &mut cal_data.ry_max,
+                ),
+            ],
+            Self::stick_calibration(xy_center, xy_min, xy_max),
+            source,
+            in_use,
+        )
+    }
+
+    // (center, min, max) of both axes of a stick, min and max are stored as distances from the
+    // center. `None` unless they're all 12-bit positions around the center, e.g. when erased.
+    fn stick_calibration(
+        xy_center: &[u8; 3],
+        xy_min: &[u8; 3],
+        xy_max: &[u8; 3],
+    ) -> Option<[(u16, u16, u16); 2]> {
+        let unpack = |xy: &[u8; 3]| {
+            let (mut x, mut y) = (0, 0);
+            util::unpack_shorts(xy, &mut x, &mut y);
+            [x, y]
+        };
+        let [centers, min_distances, max_distances] = [xy_center, xy_min, xy_max].map(unpack);
+        let axis = |i: usize| {
+            let center = centers[i];
+            let min = center.checked_sub(min_distances[i])?;
+            let max = center
+                .checked_add(max_distances[i])
+                .filter(|max| *max <= STICK_POSITION_MAX)?;
+            (min < center && center < max).then_some((center, min, max))
+        };
+        Some([axis(0)?, axis(1)?])
+    }
+
+    // Stores the (center, min, max) of both axes of a stick and returns `source`. Without a
+    // valid calibration, user calibration keeps the one `in_use` and factory calibration falls
+    // back to the defaults, the source returned is theirs.
+    fn update_stick_axes(
+        axes: [(&mut u16, &mut u16, &mut u16); 2],
+        calibration: Option<[(u16, u16, u16); 2]>,
+        source: CalibrationSource,
+        in_use: CalibrationSource,
+    ) -> CalibrationSource {
+        let (calibration, source) = match (calibration, source) {
+            (Some(calibration), source) => (calibration, source),
+            (None, CalibrationSource::User) => return in_use,
+            (None, _) => (
+                [(CAL_DEFAULT_CENTER, CAL_DEFAULT_MIN, CAL_DEFAULT_MAX); 2],
+                CalibrationSource::Default,
+            ),
+        };
+        for ((center, min, max), values) in axes.into_iter().zip(calibration) {
+            (*center, *min, *max) = values;
+        }
+        source
+    }
+
+    fn read_colors(packet: &ColorsPacket, has_grips: bool) -> ControllerColors {
+        let (left_grip, right_grip) = if has_grips {
+            (packet.left_grip, packet.right_grip)
+        } else {
+            Default::default()
+        };
+        ControllerColors {
+            body: packet.body,
+            buttons: packet.buttons,
+            left_grip,
+            right_grip,
+            has_grips,
+        }
+    }
+
+    fn update_user_imu_calibration_data(
+        cal: &UserImuCalibrationPacket,
+        cal_data: &mut CalibrationData,
+    ) {
+        if cal.magic == USER_CALIBRATION_MAGIC {
+            Self::update_imu_calibration_data(&cal.cal, cal_data);
+            cal_data.sources.imu = CalibrationSource::User;
+        }
+    }
+
+    fn update_imu_calibration_data(cal: &ImuCalibrationPacket, cal_data: &mut CalibrationData) {
+        cal_data.sources.imu = CalibrationSource::Factory;
+        [
+            cal_data.accelerometer_origin_x,
+            cal_data.accelerometer_origin_y,
+            cal_data.accelerometer_origin_z,
+        ] = cal.accelerometer_origin;
+        [
+            cal_data.gyro_origin_x,
+            cal_data.gyro_origin_y,
+            cal_data.gyro_origin_z,
+        ] = cal.gyro_origin;
+        // Keep the nominal sensitivities when the SPI flash is erased.
+        if !cal.accelerometer_sensitivity.contains(&0xffff) {
+            [
+                cal_data.accelerometer_sensitivity_x,
+                cal_data.accelerometer_sensitivity_y,
+                cal_data.accelerometer_sensitivity_z,
+            ] = cal.accelerometer_sensitivity;
+        }
+        if !cal.gyro_sensitivity.contains(&0xffff) {
+            [
+                cal_data.gyro_sensitivity_x,
+                cal_data.gyro_sensitivity_y,
+                cal_data.gyro_sensitivity_z,
+            ] = cal.gyro_sensitivity;
+        }
+    }
+
+    fn update_motion(imu: &IMUData, cal_data: &CalibrationData) -> Motion {
+        let accel = |raw: i16, origin: u16, sensitivity: u16| {
+            raw as f32 * IMU_ACCEL_RANGE / (sensitivity as i16 as f32 - origin as i16 as f32)
+        };
+        let gyro = |raw: i16, origin: u16, sensitivity: u16| {
+            let origin = origin as i16 as f32;
+            (raw as f32 - origin) * IMU_GYRO_RANGE / (sensitivity as i16 as f32 - origin)
+        };
+        Motion {
+            accel: [
+                accel(
+                    imu.accel_x,
+                    cal_data.accelerometer_origin_x,
+                    cal_data.accelerometer_sensitivity_x,
+                ),
+                accel(
+                    imu.accel_y,
+                    cal_data.accelerometer_origin_y,
+                    cal_data.accelerometer_sensitivity_y,
+                ),
+                accel(
+                    imu.accel_z,
+                    cal_data.accelerometer_origin_z,
+                    cal_data.accelerometer_sensitivity_z,
+                ),
+            ],
+            gyro: [
+                gyro(
+                    imu.gyro_x,
+                    cal_data.gyro_origin_x,
+                    cal_data.gyro_sensitivity_x,
+                ),
+                gyro(
+                    imu.gyro_y,
+                    cal_data.gyro_origin_y,
+                    cal_data.gyro_sensitivity_y,
+                ),
+                gyro(
+                    imu.gyro_z,
+                    cal_data.gyro_origin_z,
+                    cal_data.gyro_sensitivity_z,
+                ),
+            ],
+            has_accel: true,
+            has_gyro: true,
+        }
+    }
+
+    fn update_stick_params_calibration_data(
+        cal: &AnalogStickParamsCalibrationPacket,
+        cal_data: &mut CalibrationData,
+    ) {
+        util::unpack_shorts(
+            &cal.params,
+            &mut cal_data.dead_zone,
+            &mut cal_data.range_ratio,
+        );
+        if cal_data.dead_zone == CAL_BOGUS_VALUE {
+            cal_data.dead_zone = CAL_DEFAULT_DEADZONE;
+        }
+    }
+
+    fn read_data_and_fill(
+        device: &HidDevice,
+        gamepad: &mut Gamepad,
+        context: &mut GamepadContext,
+        buf: &mut [u8],
+        timeout_ms: i32,
+    ) -> Option<usize> {
+        // Fails once the controller is unplugged.
+        let len = device.read_timeout(buf, timeout_ms).ok()?;
+        if len > 0 {
+            context.last_report = Some(Instant::now());
+        }
+        let GamepadContext {
+            cal_data,
+            init_state,
+            transport,
+            controller_type,
+            motion,
+            device_info,
+            colors,
+            nfc,
+            ir,
+            ring_con,
+            calibration,
+            spi,
+            refresh,
+            range,
+            ..
+        } = context;
+
+        if len >= 2 {
+            if let Ok(InputReportID::CommandAck) = InputReportID::try_from(buf[0]) {
+                let ack = CommandAckPacket::read_from_prefix(&buf[1..]).unwrap();
+                if !transport.on_command_ack(ack.command_id) {
+                    return Some(len);
+                }
+                match (ProprietaryCommandID::try_from(ack.command_id), *init_state) {
+                    (Ok(ProprietaryCommandID::Status), GamepadInitState::RequestedUsbStatus) => {
+                        // The charging grip reports which Joy-Con sits in this slot.
+                        if let Ok(reported_type) = ControllerType::try_from(ack.controller_type) {
+                            *controller_type = reported_type;
+                        }
+                        *init_state = GamepadInitState::RequestedUsbStatusOk;
+                    }
+                    (
+                        Ok(ProprietaryCommandID::Handshake),
+                        GamepadInitState::RequestedUsbHandshake,
+                    ) => {
+                        *init_state = GamepadInitState::RequestedUsbHandshakeOk;
+                    }
+                    (
+                        Ok(ProprietaryCommandID::HighSpeed),
+                        GamepadInitState::RequestedUsbHighSpeed,
+                    ) => {
+                        *init_state = GamepadInitState::RequestedUsbHighSpeedOk;
+                    }
+                    (
+                        Ok(ProprietaryCommandID::Handshake),
+                        GamepadInitState::RequestedUsbHighSpeedHandshake,
+                    ) => {
+                        *init_state = GamepadInitState::RequestedUsbHighSpeedHandshakeOk;
+                    }
+                    _ => {}
+                }
+                return Some(len);
+            }
+        }
+
+        if len >= 12 {
+            match InputReportID::try_from(buf[0]) {
+                Ok(
+                    report_id @ (InputReportID::FullControllerState
+                    | InputReportID::FullControllerAndMcuState),
+                ) => {
+                    let info = ControllerStateInfoPacket::read_from_prefix(&buf[1..]).unwrap();
+                    range.on_sticks(
+                        &info.controller_state.left_stick,
+                        &info.controller_state.right_stick,
+                        cal_data,
+                    );
+                    Self::update_gamepad(
+                        &info.controller_state.button_status,
+                        &info.controller_state.left_stick,
+                        &info.controller_state.right_stick,
+                        &range.apply(cal_data),
+                        *controller_type,
+                        gamepad,
+                        false,
+                    );
+                    if let Some(ring_con) = ring_con {
+                        ring_con.on_input_report(&buf[..len]);
+                    }
+                    if let Some(session) = calibration {
+                        session.on_sticks(
+                            &info.controller_state.left_stick,
+                            &info.controller_state.right_stick,
+                        );
+                    }
+                    if controller_type.has_imu() && len >= IMU_DATA_OFFSET + 12 {
+                        let imu = IMUData::read_from_prefix(&buf[IMU_DATA_OFFSET..]).unwrap();
+                        *motion = Some(Self::update_motion(&imu, cal_data));
+                    }
+                    if matches!(report_id, InputReportID::FullControllerAndMcuState)
+                        && len > mcu::MCU_DATA_OFFSET
+                    {
+                        let data = &buf[mcu::MCU_DATA_OFFSET..len];
+                        if let Some(session) = nfc {
+                            session.reader.on_mcu_report(data);
+                        }
+                        if let Some(camera) = ir {
+                            camera.on_mcu_report(data);
+                        }
+                    }
+                }
+                Ok(InputReportID::SimpleControllerState) => {
+                    let state = SimpleControllerStatePacket::read_from_prefix(&buf[1..]).unwrap();
+                    Self::update_gamepad(
+                        &state.button_status,
+                        &state.left_stick,
+                        &state.right_stick,
+                        &range.apply(cal_data),
+                        *controller_type,
+                        gamepad,
+                        true,
+                    );
+                }
+                Ok(InputReportID::SubcommandReply) => {
+                    let pack = SubcommandInputPacket::read_from_prefix(&buf[1..]).unwrap();
+                    Self::update_gamepad(
+                        &pack.controller_state_info.controller_state.button_status,
+                        &pack.controller_state_info.controller_state.left_stick,
+                        &pack.controller_state_info.controller_state.right_stick,
+                        &range.apply(cal_data),
+                        *controller_type,
+                        gamepad,
+                        false,
+                    );
+                    let ack = pack.subcommand_ack & 0x80 != 0;
+                    let data = &buf[SUBCOMMAND_REPLY_DATA_OFFSET..len];
+                    // Only the reply to the pending request moves the initialization on.
+                    let init_reply = transport.on_subcommand_reply(pack.subcommand_id, ack, data);
+                    match SubcommandID::try_from(pack.subcommand_id) {
+                        _ if !init_reply => {}
+                        Ok(SubcommandID::RequestDeviceInfo) => {
+                            let info = DeviceInfoPacket::read_from_prefix(
+                                &buf[SUBCOMMAND_REPLY_DATA_OFFSET..],
+                            )
+                            .unwrap();
+                            // Only the reported type tells NES controllers and Joy-Cons apart.
+                            if let Ok(reported_type) =
+                                ControllerType::try_from(info.controller_type)
+                            {
+                                if reported_type != ControllerType::Unknown {
+                                    *controller_type = reported_type;
+                                }
+                            }
+                            *device_info = Some(ControllerInfo {
+                                firmware_version: info.firmware_version,
+                                controller_type: info.controller_type,
+                                mac_address: info.mac_address,
+                            });
+                            *init_state = GamepadInitState::RequestedDeviceInfoOk;
+                        }
+                        Ok(SubcommandID::SPIFlashRead) => {
+                            if let Ok(address) = SPIAddress::try_from(pack.address) {
+                                Self::apply_spi_read(
+                                    address,
+                                    &buf[SPI_REPLY_DATA_OFFSET..],
+                                    *controller_type,
+                                    cal_data,
+                                    colors,
+                                );
+                                *init_state = match address {
+                                    SPIAddress::AnalogStickCalibration => {
+                                        GamepadInitState::RequestedAnalogCalibrationOk
+                                    }
+                                    SPIAddress::AnalogStickParameters => {
+                                        GamepadInitState::RequestedAnalogParamsOk
+                                    }
+                                    SPIAddress::Colors => GamepadInitState::RequestedColorsOk,
+                                    SPIAddress::UserStickCalibration => {
+                                        GamepadInitState::RequestedUserAnalogCalibrationOk
+                                    }
+                                    SPIAddress::UserImuCalibration => {
+                                        GamepadInitState::RequestedUserImuCalibrationOk
+                                    }
+                                    SPIAddress::ImuCalibration => {
+                                        GamepadInitState::RequestedImuCalibrationOk
+                                    }
+                                    _ => *init_state,
+                                };
+                            }
+                        }
+                        Ok(SubcommandID::EnableIMU) => {
+                            *init_state = GamepadInitState::RequestedEnableImuOk;
+                        }
+                        Ok(SubcommandID::SetInputReportMode) => {
+                            *init_state = GamepadInitState::RequestedFullReportModeOk;
+                        }
+                        _ => {}
+                    }
+                    if let Some(session) = nfc {
+                        session.reader.on_subcommand_reply(pack.subcommand_id, ack);
+                    }
+                    if let Some(camera) = ir {
+                        camera.on_subcommand_reply(pack.subcommand_id, ack);
+                    }
+                    if let Some(ring_con) = ring_con {
+                        ring_con.on_subcommand_reply(pack.subcommand_id, ack, data);
+                    }
+                    if let Some(session) = calibration {
+                        session.on_subcommand_reply(pack.subcommand_id, ack, data);
+                    }
+                    if let Some(transfer) = spi {
+                        transfer.on_subcommand_reply(pack.subcommand_id, ack, data);
+                    }
+                    if let Some(refresh) = refresh {
+                        refresh.on_subcommand_reply(pack.subcommand_id, ack, data);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(len)
+    }
+}
+
+impl GamepadAPI {
+    pub fn get_gamepads(&mut self) -> Vec<Gamepad> {
+        let GamepadAPI {
+            input_buf,
+            device_map,
+            hidapi,
+            report_counter,
+            usb,
+            gip_map,
+            gip_rejected,
+            generic_map,
+            gamecube_map,
+            wiimote_map,
+            steam_map,
+            wheel_map,
+            mappings,
+            calibration_cache,
+            stick_responses,
+            stick_gates,
+            identities,
+            ..
+        } = self;
+
+        // Deadzones of Nintendo controllers from their SPI flash, by index.
+        let mut factory_deadzones = HashMap::new();
+        // Inner deadzones covering the noise of drifting sticks, see `drift::DriftMonitor`.
+        let mut drift_deadzones = HashMap::new();
+
+        // The previous device list is kept when it can't be refreshed.
+        let _ = hidapi.refresh_devices();
+
+        let mut gamepads = Vec::new();
+
+        let mut live_sns: HashSet<String> = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if !DEVICE_TUPLES.contains(&(device_info.vendor_id(), device_info.product_id())) {
+                continue;
+            }
+
+            // Some Bluetooth stacks report no serial number, the path is unique for the session.
+            let mut sn = match device_info.serial_number() {
+                Some(sn) if !sn.is_empty() => sn.to_string(),
+                _ => device_info.path().to_string_lossy().into_owned(),
+            };
+            // Both Joy-Cons in the charging grip share the grip's serial number.
+            let is_grip_slot = device_info.product_id() == PRODUCT_ID_NINTENDO_CHARGING_GRIP;
+            if is_grip_slot {
+                sn = format!("{}-{}", sn, device_info.interface_number());
+            }
+
+            let (index, device, context) = match device_map.entry(sn.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    // Tried again on the next poll, e.g. once another process let go of it.
+                    let Ok(device) = device_info.open_device(hidapi) else {
+                        continue;
+                    };
+                    let context = GamepadContext {
+                        controller_type: ControllerType::from_product_id(device_info.product_id()),
+                        is_usb: matches!(device_info.bus_type(), BusType::Usb),
+                        ..Default::default()
+                    };
+                    let index = util::generate_id();
+                    identities.insert(index, DeviceIdentity::new("", Some(sn.clone())));
+                    entry.insert((index, device, context))
+                }
+            };
+
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+
+            // Empty grip slots don't answer anything but the status request, no need to wait.
+            let timeout_ms = if is_grip_slot && context.controller_type == ControllerType::Unknown {
+                0
+            } else {
+                READ_TIMEOUT_MS
+            };
+            // Dropped from `device_map` below, like a controller missing from the device list.
+            if Self::read_data_and_fill(device, &mut gamepad, context, input_buf, timeout_ms)
+                .is_none()
+            {
+                continue;
+            }
+            live_sns.insert(sn.clone());
+
+            // Dropped like an unplugged controller, the slot is opened again on the next poll.
+            if is_grip_slot
+                && matches!(context.init_state, GamepadInitState::Initialized)
+                && context
+                    .last_report
+                    .is_some_and(|time| time.elapsed() > GRIP_SLOT_TIMEOUT)
+            {
+                live_sns.remove(&sn);
+                continue;
+            }
+
+            if context.init_failure.is_none() {
+                match context.transport.update() {
+                    Ok(Some(request)) => Self::send_request(report_counter, device, request),
+                    Ok(None) => {}
+                    // Colors and calibration have defaults, the rest is needed to talk to it.
+                    Err(failure) => match context.init_state.fallback() {
+                        Some(state) => context.init_state = state,
+                        None => context.init_failure = Some(failure),
+                    },
+                }
+            }
+
+            match context.init_state {
+                GamepadInitState::Uninitialized if context.is_usb => {
+                    let due = context
+                        .last_status_request
+                        .is_none_or(|time| time.elapsed() >= GRIP_SLOT_RETRY);
+                    if due {
+                        context.last_status_request = Some(Instant::now());
+                        Self::send_init_request(
+                            report_counter,
+                            device,
+                            context,
+                            Request::Proprietary(ProprietaryCommandID::Status),
+                            GamepadInitState::RequestedUsbStatus,
+                        );
+                    }
+                }
+                GamepadInitState::Uninitialized => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::device_info_request(),
+                        GamepadInitState::RequestedDeviceInfo,
+                    );
+                }
+                GamepadInitState::RequestedUsbStatusOk => {
+                    if context.controller_type == ControllerType::Unknown {
+                        // Empty charging grip slot, asked again once `GRIP_SLOT_RETRY` passed.
+                        context.init_state = GamepadInitState::Uninitialized;
+                    } else {
+                        Self::send_init_request(
+                            report_counter,
+                            device,
+                            context,
+                            Request::Proprietary(ProprietaryCommandID::Handshake),
+                            GamepadInitState::RequestedUsbHandshake,
+                        );
+                    }
+                }
+                GamepadInitState::RequestedUsbHandshakeOk => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Request::Proprietary(ProprietaryCommandID::HighSpeed),
+                        GamepadInitState::RequestedUsbHighSpeed,
+                    );
+                }
+                GamepadInitState::RequestedUsbHighSpeedOk => {
+                    // The baud rate change needs a new handshake.
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Request::Proprietary(ProprietaryCommandID::Handshake),
+                        GamepadInitState::RequestedUsbHighSpeedHandshake,
+                    );
+                }
+                GamepadInitState::RequestedUsbHighSpeedHandshakeOk => {
+                    // Keep talking HID over USB instead of timing out back to Bluetooth.
+                    Self::send_proprietary_command(device, ProprietaryCommandID::DisableUsbTimeout);
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::device_info_request(),
+                        GamepadInitState::RequestedDeviceInfo,
+                    );
+                }
+                GamepadInitState::RequestedDeviceInfoOk => {
+                    let cached =
+                        match (context.cache_state, &calibration_cache, context.device_info) {
+                            (CacheState::Lookup, Some(cache), Some(info)) => cache
+                                .get(
+                                    &info.identity_key(),
+                                    info.firmware_version,
+                                    info.controller_type,
+                                )
+                                .cloned(),
+                            _ => None,
+                        };
+                    if let Some(entry) = cached {
+                        context.cal_data = entry.cal_data;
+                        context.colors = entry.colors;
+                        context.range.load(&entry.range);
+                        context.cache_state = CacheState::Cached;
+                        // Straight to the full reports, skipping the SPI flash reads.
+                        context.init_state = if context.controller_type.has_imu() {
+                            GamepadInitState::RequestedUserImuCalibrationOk
+                        } else {
+                            GamepadInitState::Initialized
+                        };
+                    } else {
+                        context.cache_state = CacheState::Refreshing;
+                        Self::send_init_request(
+                            report_counter,
+                            device,
+                            context,
+                            Self::colors_request(),
+                            GamepadInitState::RequestedColors,
+                        );
+                    }
+                }
+                GamepadInitState::RequestedColorsOk => {
+                    if context.controller_type.has_sticks() {
+                        Self::send_init_request(
+                            report_counter,
+                            device,
+                            context,
+                            Self::analog_calibration_request(),
+                            GamepadInitState::RequestedAnalogCalibration,
+                        );
+                    } else {
+                        context.init_state = GamepadInitState::Initialized;
+                    }
+                }
+                GamepadInitState::RequestedAnalogCalibrationOk => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::user_stick_calibration_request(),
+                        GamepadInitState::RequestedUserAnalogCalibration,
+                    );
+                }
+                GamepadInitState::RequestedUserAnalogCalibrationOk => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::analog_params_request(),
+                        GamepadInitState::RequestedAnalogParams,
+                    );
+                }
+                GamepadInitState::RequestedAnalogParamsOk => {
+                    if context.controller_type.has_imu() {
+                        Self::send_init_request(
+                            report_counter,
+                            device,
+                            context,
+                            Self::imu_calibration_request(),
+                            GamepadInitState::RequestedImuCalibration,
+                        );
+                    } else {
+                        context.init_state = GamepadInitState::Initialized;
+                    }
+                }
+                GamepadInitState::RequestedImuCalibrationOk => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::user_imu_calibration_request(),
+                        GamepadInitState::RequestedUserImuCalibration,
+                    );
+                }
+                GamepadInitState::RequestedUserImuCalibrationOk => {
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::enable_imu_request(),
+                        GamepadInitState::RequestedEnableImu,
+                    );
+                }
+                GamepadInitState::RequestedEnableImuOk => {
+                    // The simple HID report has no IMU data.
+                    Self::send_init_request(
+                        report_counter,
+                        device,
+                        context,
+                        Self::full_report_mode_request(),
+                        GamepadInitState::RequestedFullReportMode,
+                    );
+                }
+                GamepadInitState::RequestedFullReportModeOk => {
+                    if context.controller_type == ControllerType::JoyConRight
+                        && context.ring_con.is_none()
+                    {
+                        context.ring_con = Some(Default::default());
+                    }
+                    context.init_state = GamepadInitState::Initialized;
+                }
+                GamepadInitState::Initialized => {
+                    match (
+                        context.cache_state,
+                        calibration_cache.as_mut(),
+                        context.device_info,
+                    ) {
+                        // The cached calibration stays in use until it's read again.
+                        (CacheState::Cached, _, _) => {
+                            context.cache_state = CacheState::Refreshing;
+                            context.refresh =
+                                Some(Self::calibration_refresh(context.controller_type));
+                        }
+                        (CacheState::Refreshing, cache, info) if context.refresh.is_none() => {
+                            if let (Some(cache), Some(info)) = (cache, info) {
+                                cache
+                                    .insert(info.identity_key(), Self::cache_entry(&info, context));
+                            }
+                            context.cache_state = CacheState::Done;
+                        }
+                        _ => {}
+                    }
+                    if let Some(cache) = calibration_cache.as_mut() {
+                        let due = context
+                            .range_saved_at
+                            .is_none_or(|time| time.elapsed() >= RANGE_SAVE_INTERVAL);
+                        if due {
+                            context.range_saved_at = Some(Instant::now());
+                            Self::save_range(cache, context);
+                        }
+                    }
+                    if let Some(session) = &mut context.nfc {
+                        if let Some(request) = session.reader.update() {
+                            Self::send_mcu_request(report_counter, device, request);
+                        }
+                        if let Some(tag) = session.reader.take_result() {
+                            session.complete(*index, tag.as_ref());
+                        }
+                        if session.reader.is_done() {
+                            context.nfc = None;
+                        }
+                    }
+                    for _ in 0..MAX_IR_REPORTS_PER_POLL {
+                        let Some(camera) = &mut context.ir else {
+                            break;
+                        };
+                        if let Some(request) = camera.update() {
+                            Self::send_mcu_request(report_counter, device, request);
+                        }
+                        if camera.is_done() {
+                            context.ir = None;
+                            break;
+                        }
+                        if Self::read_data_and_fill(device, &mut gamepad, context, input_buf, 0)
+                            .unwrap_or(0)
+                            == 0
+                        {
+                            break;
+                        }
+                    }
+                    if let Some(session) = &mut context.calibration {
+                        if let Some((command_id, data)) = session.update() {
+                            Self::send_subcommand(report_counter, device, command_id, &data);
+                        }
+                        // Falls back to the factory calibration.
+                        if session.take_erased() {
+                            context.cache_state = CacheState::Refreshing;
+                            context.refresh =
+                                Some(Self::calibration_refresh(context.controller_type));
+                        }
+                    }
+                    for _ in 0..MAX_SPI_REPORTS_PER_POLL {
+                        let Some(transfer) = &mut context.spi else {
+                            break;
+                        };
+                        if let Some((command_id, data)) = transfer.update() {
+                            Self::send_subcommand(report_counter, device, command_id, &data);
+                        }
+                        let restored = transfer.take_restored();
+                        let done = transfer.is_done();
+                        // The restored user calibration replaces the current one.
+                        if restored {
+                            context.cache_state = CacheState::Refreshing;
+                            context.refresh =
+                                Some(Self::calibration_refresh(context.controller_type));
+                        }
+                        if done
+                            || Self::read_data_and_fill(device, &mut gamepad, context, input_buf, 0)
+                                .unwrap_or(0)
+                                == 0
+                        {
+                            break;
+                        }
+                    }
+                    if let Some(refresh) = &mut context.refresh {
+                        if let Some((command_id, data)) = refresh.update() {
+                            Self::send_subcommand(report_counter, device, command_id, &data);
+                        }
+                        if refresh.is_done() {
+                            match refresh.data() {
+                                Some(reads) => {
+                                    // Applied like during the initialization, factory data
+                                    // before the user calibration overriding it.
+                                    for (address, read) in reads {
+                                        let Ok(address) = SPIAddress::try_from(*address as u16)
+                                        else {
+                                            continue;
+                                        };
+                                        Self::apply_spi_read(
+                                            address,
+                                            read,
+                                            context.controller_type,
+                                            &mut context.cal_data,
+                                            &mut context.colors,
+                                        );
+                                    }
+                                }
+                                // Keep the calibration in use, and the cache as it is.
+                                None => context.cache_state = CacheState::Done,
+                            }
+                            context.refresh = None;
+                        }
+                    }
+                    if let Some(ring_con) = &mut context.ring_con {
+                        if let Some(request) = ring_con.update() {
+                            Self::send_mcu_request(report_counter, device, request);
+                        }
+                        if ring_con.is_done() {
+                            context.ring_con = None;
+                        }
+                    }
+                    // The flex sensor follows the stick axes.
+                    if let Some(flex) = context.ring_con.as_ref().and_then(|r| r.flex()) {
+                        gamepad.axes.push(flex);
+                    }
+                }
+                _ => {}
+            }
+
+            if context.controller_type.has_sticks() {
+                let drift = &mut context.drift;
+                let any_pressed = gamepad.buttons.iter().any(|button| button.pressed);
+                let stick_axes = if context.controller_type.has_right_stick() {
+                    4
+                } else {
+                    2
+                };
+                let sticks = &mut gamepad.axes[..stick_axes];
+                drift.update(sticks, any_pressed, context.motion.as_ref());
+                drift.compensate(sticks);
+                drift_deadzones.insert(*index, [drift.deadzone(0, 0.0), drift.deadzone(1, 0.0)]);
+
+                let cal_data = &context.cal_data;
+                let deadzone = |min: u16, max: u16| {
+                    let half_range = max.saturating_sub(min).max(2) as f32 / 2.0;
+                    response::Response::radial(cal_data.dead_zone as f32 / half_range)
+                };
+                factory_deadzones.insert(
+                    *index,
+                    [
+                        deadzone(cal_data.lx_min, cal_data.lx_max),
+                        deadzone(cal_data.rx_min, cal_data.rx_max),
+                    ],
+                );
+            }
+
+            // The type of controllers in the charging grip is only known once they answer.
+            if let Some(identity) = identities.get_mut(index) {
+                identity.controller_type = context.controller_type.profile_name();
+                identity.nintendo_labels = context.controller_type.has_nintendo_labels();
+            }
+
+            if context.controller_type != ControllerType::Unknown {
+                gamepads.push(gamepad);
+            }
+        }
+
+        device_map.retain(|sn, (index, _, context)| {
+            let live = live_sns.contains(sn);
+            if let (false, Some(session)) = (live, &mut context.nfc) {
+                session.complete(*index, None);
+            }
+            // Learned stick ranges are also saved on disconnect and when the API is dropped.
+            if let (false, Some(cache)) = (live, calibration_cache.as_mut()) {
+                Self::save_range(cache, context);
+            }
+            if !live {
+                identities.remove(index);
+            }
+            live
+        });
+
+        Self::poll_gamecube_adapters(hidapi, gamecube_map, &mut gamepads);
+        Self::poll_wiimotes(hidapi, wiimote_map, identities, &mut gamepads);
+        Self::poll_steam_devices(hidapi, steam_map, identities, &mut gamepads);
+        Self::poll_wheels(hidapi, wheel_map, identities, &mut gamepads);
+        Self::poll_generic_devices(hidapi, generic_map, mappings, identities, &mut gamepads);
+        if let Some(usb) = usb {
+            Self::poll_gip_devices(usb, gip_map, gip_rejected, identities, &mut gamepads);
+        }
+
+        // Gate correction, then deadzones and the response curve, or the factory deadzone.
+        for gamepad in &mut gamepads {
+            let index = gamepad.index;
+            // Axes 0 and 1 are the left stick, 2 and 3 the right one in the standard layout.
+            for (stick, axes) in gamepad.axes.chunks_exact_mut(2).take(2).enumerate() {
+                let [x, y] = axes else { unreachable!() };
+                if let Some(gate) = stick_gates
+                    .get_mut(&index)
+                    .and_then(|gates| gates[stick].as_mut())
+                {
+                    (*x, *y) = gate.apply(*x, *y);
+                }
+                let response = stick_responses
+                    .get(&index)
+                    .and_then(|responses| responses[stick].as_ref())
+                    .or_else(|| {
+                        factory_deadzones
+                            .get(&index)
+                            .map(|deadzones| &deadzones[stick])
+                    });
+                if let Some(response) = response {
+                    let drift_deadzone = drift_deadzones
+                        .get(&index)
+                        .map_or(0.0, |deadzones: &[f32; 2]| deadzones[stick]);
+                    (*x, *y) = response.with_inner_deadzone(drift_deadzone).apply(*x, *y);
+                }
+            }
+        }
+
+        // Profiles go last, on top of everything else.
+        for gamepad in &mut gamepads {
+            if let Some((profile, nintendo_labels)) = self.active_profile(gamepad.index) {
+                profile.apply(gamepad, nintendo_labels);
+            }
+        }
+
+        gamepads
+    }
+
+    fn poll_gamecube_adapters(
+        hidapi: &HidApi,
+        gamecube_map: &mut HashMap<String, gamecube::GameCubeAdapter>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let mut live_paths = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if !gamecube::GameCubeAdapter::is_supported(device_info) {
+                continue;
+            }
+            let path = device_info.path().to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = gamecube_map.entry(path.clone()) {
+                let Some(adapter) = gamecube::GameCubeAdapter::open(hidapi, device_info) else {
+                    continue;
+                };
+                entry.insert(adapter);
+            }
+            live_paths.insert(path);
+        }
+
+        gamecube_map.retain(|path, adapter| {
+            if !live_paths.contains(path) || !adapter.poll() {
+                return false;
+            }
+            adapter.fill_gamepads(gamepads);
+            true
+        });
+    }
+
+    fn poll_wiimotes(
+        hidapi: &HidApi,
+        wiimote_map: &mut HashMap<String, (usize, wiimote::WiimoteDevice)>,
+        identities: &mut HashMap<usize, DeviceIdentity>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let mut live_paths = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if !wiimote::WiimoteDevice::is_supported(device_info) {
+                continue;
+            }
+            let path = device_info.path().to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = wiimote_map.entry(path.clone()) {
+                let Some(wiimote) = wiimote::WiimoteDevice::open(hidapi, device_info) else {
+                    continue;
+                };
+                let index = util::generate_id();
+                let serial = util::serial_number(device_info);
+                identities.insert(index, DeviceIdentity::new("wiimote", serial));
+                entry.insert((index, wiimote));
+            }
+            live_paths.insert(path);
+        }
+
+        wiimote_map.retain(|path, (index, wiimote)| {
+            if !live_paths.contains(path) || !wiimote.poll() {
+                identities.remove(index);
+                return false;
+            }
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+            wiimote.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+            true
+        });
+    }
+
+    fn poll_steam_devices(
+        hidapi: &HidApi,
+        steam_map: &mut HashMap<String, (usize, steam::SteamDevice)>,
+        identities: &mut HashMap<usize, DeviceIdentity>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let mut live_paths = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if !steam::SteamDevice::is_supported(device_info) {
+                continue;
+            }
+            let path = device_info.path().to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = steam_map.entry(path.clone()) {
+                let Some(device) = steam::SteamDevice::open(hidapi, device_info) else {
+                    continue;
+                };
+                let index = util::generate_id();
+                let serial = util::serial_number(device_info);
+                identities.insert(index, DeviceIdentity::new("steam", serial));
+                entry.insert((index, device));
+            }
+            live_paths.insert(path);
+        }
+
+        steam_map.retain(|path, (index, device)| {
+            if !live_paths.contains(path) || !device.poll() {
+                identities.remove(index);
+                return false;
+            }
+            // An empty dongle slot.
+            if !device.is_connected() {
+                return true;
+            }
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+            device.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+            true
+        });
+    }
+
+    fn poll_wheels(
+        hidapi: &HidApi,
+        wheel_map: &mut HashMap<String, (usize, wheel::WheelDevice)>,
+        identities: &mut HashMap<usize, DeviceIdentity>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let mut live_paths = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if !wheel::WheelDevice::is_supported(device_info) {
+                continue;
+            }
+            let path = device_info.path().to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = wheel_map.entry(path.clone()) {
+                let Some(wheel) = wheel::WheelDevice::open(hidapi, device_info) else {
+                    continue;
+                };
+                let index = util::generate_id();
+                let serial = util::serial_number(device_info);
+                identities.insert(index, DeviceIdentity::new("wheel", serial));
+                entry.insert((index, wheel));
+            }
+            live_paths.insert(path);
+        }
+
+        wheel_map.retain(|path, (index, wheel)| {
+            if !live_paths.contains(path) || !wheel.poll() {
+                identities.remove(index);
+                return false;
+            }
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+            wheel.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+            true
+        });
+    }
+
+    /// Devices handled by a driver other than the generic HID one.
+    fn has_dedicated_driver(device_info: &DeviceInfo) -> bool {
+        DEVICE_TUPLES.contains(&(device_info.vendor_id(), device_info.product_id()))
+            || gamecube::GameCubeAdapter::is_supported(device_info)
+            || wiimote::WiimoteDevice::is_supported(device_info)
+            || steam::SteamDevice::is_supported(device_info)
+            || wheel::WheelDevice::is_supported(device_info)
+    }
+
+    fn poll_generic_devices(
+        hidapi: &HidApi,
+        generic_map: &mut HashMap<String, (usize, generic::GenericDevice)>,
+        mappings: &sdl_mapping::MappingDatabase,
+        identities: &mut HashMap<usize, DeviceIdentity>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let mut live_paths = HashSet::new();
+
+        for device_info in hidapi.device_list() {
+            if Self::has_dedicated_driver(device_info)
+                || !generic::GenericDevice::is_supported(device_info)
+            {
+                continue;
+            }
+            let path = device_info.path().to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = generic_map.entry(path.clone()) {
+                let Some(mut device) = generic::GenericDevice::open(hidapi, device_info) else {
+                    continue;
+                };
+                device.set_mapping(mappings.find(device.guid()).cloned());
+                let index = util::generate_id();
+                let serial = util::serial_number(device_info);
+                identities.insert(index, DeviceIdentity::new("generic", serial));
+                entry.insert((index, device));
+            }
+            live_paths.insert(path);
+        }
+
+        generic_map.retain(|path, (index, device)| {
+            if !live_paths.contains(path) || !device.poll() {
+                identities.remove(index);
+                return false;
+            }
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+            device.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+            true
+        });
+    }
+
+    fn poll_gip_devices(
+        usb: &rusb::Context,
+        gip_map: &mut HashMap<(u8, u8), (usize, gip::GipDevice)>,
+        gip_rejected: &mut HashSet<(u8, u8)>,
+        identities: &mut HashMap<usize, DeviceIdentity>,
+        gamepads: &mut Vec<Gamepad>,
+    ) {
+        let Ok(devices) = usb.devices() else {
+            return;
+        };
+
+        let mut live_keys = HashSet::new();
+
+        for device in devices.iter() {
+            let key = (device.bus_number(), device.address());
+            live_keys.insert(key);
+            if gip_rejected.contains(&key) {
+                continue;
+            }
+            if let Entry::Vacant(entry) = gip_map.entry(key) {
+                match gip::GipDevice::open(&device) {
+                    Some(gip) => {
+                        let index = util::generate_id();
+                        let serial = gip.serial().map(str::to_string);
+                        identities.insert(index, DeviceIdentity::new("xbox", serial));
+                        entry.insert((index, gip));
+                    }
+                    None => {
+                        gip_rejected.insert(key);
+                    }
+                }
+            }
+        }
+
+        // Addresses are reused once a device is unplugged.
+        gip_rejected.retain(|key| live_keys.contains(key));
+
+        gip_map.retain(|key, (index, gip)| {
+            if !live_keys.contains(key) || !gip.poll() {
+                identities.remove(index);
+                return false;
+            }
+            let mut gamepad = Gamepad {
+                index: *index,
+                ..Default::default()
+            };
+            gip.fill_gamepad(&mut gamepad);
+            gamepads.push(gamepad);
+            true
+        });
+    }
+
+    /// Adds SDL GameControllerDB mappings (one `GUID,name,mapping` entry per line) and
+    /// applies them to connected controllers. Returns the number of mappings added.
+    pub fn add_mappings(&mut self, text: &str) -> usize {
+        let count = self.mappings.add_mappings(text);
+        for (_, device) in self.generic_map.values_mut() {
+            device.set_mapping(self.mappings.find(device.guid()).cloned());
+        }
+        count
+    }
+
+    /// Replaces the input profiles with the ones in `text`, a TOML file assigning profiles to
+    /// controller types and serial numbers, see `profile`. Profiles remap buttons to other
+    /// buttons or axis directions, swap sticks, invert axes, disable inputs and choose whether
+    /// Nintendo face buttons keep their position or their label. Fails with the number, from 1,
+    /// of the first line that isn't understood, keeping the current profiles.
+    pub fn load_profiles(&mut self, text: &str) -> Result<(), usize> {
+        self.profiles = profile::ProfileSet::parse(text)?;
+        Ok(())
+    }
+
+    /// Switches a gamepad to another loaded profile, `None` goes back to the one assigned in
+    /// the file. Returns false if there's no such profile.
+    pub fn set_profile(&mut self, index: usize, name: Option<&str>) -> bool {
+        match name {
+            Some(name) if self.profiles.get(name).is_none() => false,
+            Some(name) => {
+                self.profile_overrides.insert(index, name.to_string());
+                true
+            }
+            None => {
+                self.profile_overrides.remove(&index);
+                true
+            }
+        }
+    }
+
+    // The profile of a gamepad and whether its face buttons are labeled like on a Switch.
+    fn active_profile(&self, index: usize) -> Option<(&profile::Profile, bool)> {
+        let (controller_type, serial, nintendo_labels) = match self.identities.get(&index) {
+            Some(identity) => (
+                identity.controller_type,
+                identity.serial.as_deref(),
+                identity.nintendo_labels,
+            ),
+            // Ports get their index when a controller is plugged in, not when it's opened.
+            None => {
+                let adapter = self
+                    .gamecube_map
+                    .values()
+                    .find(|adapter| adapter.has_gamepad(index))?;
+                ("gamecube", adapter.serial(), false)
+            }
+        };
+        let profile = match self.profile_overrides.get(&index) {
+            Some(name) => self.profiles.get(name),
+            None => self.profiles.assigned(controller_type, serial),
+        };
+        profile.map(|profile| (profile, nintendo_labels))
+    }
+
+    /// Sets the dual-rumble motors of a gamepad, magnitudes are in `0.0..=1.0`.
+    /// Returns false if the gamepad is gone or has no rumble support.
+    pub fn set_rumble(&mut self, index: usize, strong_magnitude: f32, weak_magnitude: f32) -> bool {
+        if let Some((_, gip)) = self.gip_map.values_mut().find(|(i, _)| *i == index) {
+            gip.set_rumble(strong_magnitude, weak_magnitude);
+            return true;
+        }
+        if let Some(adapter) = self
+            .gamecube_map
+            .values_mut()
+            .find(|adapter| adapter.has_gamepad(index))
+        {
+            return adapter.set_rumble(index, strong_magnitude, weak_magnitude);
+        }
+        if let Some((_, wiimote)) = self.wiimote_map.values_mut().find(|(i, _)| *i == index) {
+            return wiimote.set_rumble(strong_magnitude, weak_magnitude);
+        }
+        if let Some((_, device)) = self.steam_map.values().find(|(i, _)| *i == index) {
+            return device.set_rumble(strong_magnitude, weak_magnitude);
+        }
+        false
+    }
+
+    /// Starts reading an NFC tag held against a right Joy-Con or Pro Controller. The read
+    /// runs in the background of `get_gamepads` and `callback` is called from there once a
+    /// tag was read, or with `None` after an error or `cancel_nfc_read`. Returns false if the
+    /// gamepad has no NFC reader, isn't initialized yet or is already reading.
+    pub fn read_nfc_tag(&mut self, index: usize, callback: NfcCallback) -> bool {
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if !context.controller_type.has_mcu()
+            || !matches!(context.init_state, GamepadInitState::Initialized)
+            || context.nfc.is_some()
+            || context.ir.is_some()
+            || context.ring_con.is_some()
+        {
+            return false;
+        }
+        context.nfc = Some(NfcSession {
+            reader: Default::default(),
+            callback: Some(callback),
+        });
+        true
+    }
+
+    pub fn cancel_nfc_read(&mut self, index: usize) -> bool {
+        match self.device_map.values_mut().find(|(i, _, _)| *i == index) {
+            Some((_, _, context)) => match &mut context.nfc {
+                Some(session) => {
+                    session.reader.cancel();
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Starts the IR camera of a right Joy-Con. Images, moments or clusters are collected in
+    /// the background of `get_gamepads`. Returns false if the gamepad has no IR camera,
+    /// `config` is invalid or the MCU is busy.
+    pub fn start_ir_camera(&mut self, index: usize, config: &IrConfig) -> bool {
+        let (Ok(mode), Ok(resolution)) = (
+            ir::IrMode::try_from(config.mode),
+            ir::IrResolution::try_from(config.resolution),
+        ) else {
+            return false;
+        };
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if context.controller_type != ControllerType::JoyConRight
+            || !matches!(context.init_state, GamepadInitState::Initialized)
+            || context.nfc.is_some()
+            || context.ir.is_some()
+            || context.ring_con.is_some()
+        {
+            return false;
+        }
+        context.ir = Some(ir::IrCamera::new(mode, resolution, config));
+        true
+    }
+
+    pub fn stop_ir_camera(&mut self, index: usize) -> bool {
+        match self.find_ir_camera(index) {
+            Some(camera) => {
+                camera.stop();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find_ir_camera(&mut self, index: usize) -> Option<&mut ir::IrCamera> {
+        self.device_map
+            .values_mut()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.ir.as_mut())
+    }
+
+    /// The last complete IR image.
+    pub fn get_ir_image(&mut self, index: usize) -> Option<ir::IrFrame> {
+        self.find_ir_camera(index)?.frame().cloned()
+    }
+
+    pub fn get_ir_moments(&mut self, index: usize) -> Vec<IrMoment> {
+        self.find_ir_camera(index)
+            .map_or_else(Vec::new, |camera| camera.moments().to_vec())
+    }
+
+    pub fn get_ir_clusters(&mut self, index: usize) -> Vec<IrCluster> {
+        self.find_ir_camera(index)
+            .map_or_else(Vec::new, |camera| camera.clusters().to_vec())
+    }
+
+    /// Pulses the trackpad haptics of a Steam Controller or Steam Deck, `pad` 0 is the left
+    /// and 1 the right trackpad. Returns false if the gamepad is gone or has no trackpads.
+    pub fn trigger_haptic_pulse(
+        &self,
+        index: usize,
+        pad: u8,
+        duration_us: u16,
+        interval_us: u16,
+        count: u16,
+    ) -> bool {
+        match self.steam_map.values().find(|(i, _)| *i == index) {
+            Some((_, device)) => device.trigger_haptic_pulse(pad, duration_us, interval_us, count),
+            None => false,
+        }
+    }
+
+    fn find_wheel(&mut self, index: usize) -> Option<&mut wheel::WheelDevice> {
+        self.wheel_map
+            .values_mut()
+            .find(|(i, _)| *i == index)
+            .map(|(_, wheel)| wheel)
+    }
+
+    /// Returns the steering and pedal state of a racing wheel.
+    pub fn get_wheel_state(&self, index: usize) -> Option<WheelState> {
+        self.wheel_map
+            .values()
+            .find(|(i, _)| *i == index)
+            .map(|(_, wheel)| wheel.wheel_state())
+    }
+
+    /// Sets the rotation range of a racing wheel in degrees, lock to lock.
+    pub fn set_wheel_range(&mut self, index: usize, degrees: u16) -> bool {
+        self.find_wheel(index)
+            .is_some_and(|wheel| wheel.set_range(degrees))
+    }
+
+    /// Sets the strength of the built-in centering spring, 0.0 turns it off.
+    pub fn set_wheel_autocenter(&mut self, index: usize, strength: f32) -> bool {
+        self.find_wheel(index)
+            .is_some_and(|wheel| wheel.set_autocenter(strength))
+    }
+
+    /// Starts a force feedback effect, returns its slot or `None` if all slots are in use.
+    pub fn upload_wheel_effect(&mut self, index: usize, effect: ffb::Effect) -> Option<usize> {
+        self.find_wheel(index)?.upload_effect(effect)
+    }
+
+    pub fn update_wheel_effect(&mut self, index: usize, slot: usize, effect: ffb::Effect) -> bool {
+        self.find_wheel(index)
+            .is_some_and(|wheel| wheel.update_effect(slot, effect))
+    }
+
+    pub fn stop_wheel_effect(&mut self, index: usize, slot: usize) -> bool {
+        self.find_wheel(index)
+            .is_some_and(|wheel| wheel.stop_effect(slot))
+    }
+
+    /// Scales the force of all effects, `gain` is in `0.0..=1.0`.
+    pub fn set_wheel_gain(&mut self, index: usize, gain: f32) -> bool {
+        self.find_wheel(index)
+            .is_some_and(|wheel| wheel.set_gain(gain))
+    }
+
+    /// Returns the fingers currently on the touch surfaces of a gamepad.
+    pub fn get_touches(&self, index: usize) -> Vec<Touch> {
+        match self.steam_map.values().find(|(i, _)| *i == index) {
+            Some((_, device)) => device.touches(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts sampling a stick (0 left, 1 right) for a new calibration, see
+    /// `next_stick_calibration_step`. Replaces a previous calibration session.
+    pub fn start_stick_calibration(&mut self, index: usize, stick: u8) -> bool {
+        let Ok(stick) = calibration::Stick::try_from(stick) else {
+            return false;
+        };
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if !context.controller_type.has_sticks()
+            || !matches!(context.init_state, GamepadInitState::Initialized)
+            || context.spi_busy()
+        {
+            return false;
+        }
+        context.calibration = Some(calibration::CalibrationSession::new(stick));
+        true
+    }
+
+    fn find_calibration_session(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut calibration::CalibrationSession> {
+        self.device_map
+            .values_mut()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.calibration.as_mut())
+    }
+
+    /// Finishes center sampling, then range sampling. Returns false while the samples don't
+    /// cover enough of the stick yet.
+    pub fn next_stick_calibration_step(&mut self, index: usize) -> bool {
+        self.find_calibration_session(index)
+            .is_some_and(|session| session.next())
+    }
+
+    pub fn get_stick_calibration_status(&mut self, index: usize) -> Option<StickCalibrationStatus> {
+        self.find_calibration_session(index)
+            .map(|session| session.status())
+    }
+
+    /// Uses the sampled calibration from now on, and with `write` also stores it as user
+    /// calibration on the controller, where it survives reconnecting.
+    pub fn apply_stick_calibration(&mut self, index: usize, write: bool) -> bool {
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if write && context.spi_busy() {
+            return false;
+        }
+        let Some(session) = &mut context.calibration else {
+            return false;
+        };
+        let (CalibrationPhase::Sampled, Some(stick), Some(result)) =
+            (session.phase(), session.stick(), session.result())
+        else {
+            return false;
+        };
+        // Goes through the SPI flash layout, like user calibration read at initialization.
+        let spi = result.to_spi(stick);
+        let part = |i: usize| -> &[u8; 3] { spi[i * 3..i * 3 + 3].try_into().unwrap() };
+        let cal_data = &mut context.cal_data;
+        match stick {
+            calibration::Stick::Left => {
+                cal_data.sources.left_stick = Self::update_left_stick_calibration_data(
+                    part(0),
+                    part(1),
+                    part(2),
+                    CalibrationSource::User,
+                    cal_data,
+                );
+            }
+            calibration::Stick::Right => {
+                cal_data.sources.right_stick = Self::update_right_stick_calibration_data(
+                    part(0),
+                    part(1),
+                    part(2),
+                    CalibrationSource::User,
+                    cal_data,
+                );
+            }
+        }
+        if write {
+            session.write()
+        } else {
+            session.finish()
+        }
+    }
+
+    pub fn cancel_stick_calibration(&mut self, index: usize) -> bool {
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        match &context.calibration {
+            Some(session) if session.phase() != CalibrationPhase::Writing => {
+                context.calibration = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Erases the user calibration of both sticks and goes back to the factory calibration.
+    /// Progress is reported by `get_stick_calibration_status`.
+    pub fn erase_user_stick_calibration(&mut self, index: usize) -> bool {
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if !context.controller_type.has_sticks()
+            || !matches!(context.init_state, GamepadInitState::Initialized)
+            || context.spi_busy()
+        {
+            return false;
+        }
+        context.calibration = Some(calibration::CalibrationSession::erase());
+        true
+    }
+
+    /// Starts reading the whole 512 KB SPI flash of a Nintendo controller in the background of
+    /// `get_gamepads`, which takes a few minutes. See `get_spi_progress` and `get_spi_dump`.
+    pub fn start_spi_dump(&mut self, index: usize) -> bool {
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if !matches!(context.init_state, GamepadInitState::Initialized) || context.spi_busy() {
+            return false;
+        }
+        context.spi = Some(spi::SpiTransfer::Dump(Default::default()));
+        true
+    }
+
+    /// Writes the user calibration of a complete dump back to the controller it came from.
+    /// Nothing else is ever written. The serial number and factory stick calibration of the
+    /// controller are read first, the restore stops with `SpiProgress::state` 3 without writing
+    /// anything unless they match the dump.
+    pub fn start_spi_restore(&mut self, index: usize, dump: &[u8]) -> bool {
+        let Some(restore) = spi::SpiRestore::new(dump) else {
+            return false;
+        };
+        let Some((_, _, context)) = self.device_map.values_mut().find(|(i, _, _)| *i == index)
+        else {
+            return false;
+        };
+        if !matches!(context.init_state, GamepadInitState::Initialized) || context.spi_busy() {
+            return false;
+        }
+        context.spi = Some(spi::SpiTransfer::Restore(restore));
+        true
+    }
+
+    pub fn get_spi_progress(&self, index: usize) -> Option<SpiProgress> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.spi.as_ref())
+            .map(|transfer| transfer.progress())
+    }
+
+    /// The finished dump of `start_spi_dump`.
+    pub fn get_spi_dump(&self, index: usize) -> Option<&[u8]> {
+        match self
+            .device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.spi.as_ref())
+        {
+            Some(spi::SpiTransfer::Dump(dump)) => dump.data(),
+            _ => None,
+        }
+    }
+
+    /// Stops a dump or restore, chunks already written stay written.
+    pub fn cancel_spi_transfer(&mut self, index: usize) -> bool {
+        match self.device_map.values_mut().find(|(i, _, _)| *i == index) {
+            Some((_, _, context)) => context.spi.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns where the stick and IMU calibration of a Nintendo controller came from, or
+    /// `None` before initialization finished.
+    pub fn get_calibration_sources(&self, index: usize) -> Option<CalibrationSources> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .filter(|(_, _, context)| matches!(context.init_state, GamepadInitState::Initialized))
+            .map(|(_, _, context)| context.cal_data.sources)
+    }
+
+    /// Returns the firmware version, type and Bluetooth address a Nintendo controller reported,
+    /// or `None` before it did.
+    pub fn get_device_info(&self, index: usize) -> Option<ControllerInfo> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.device_info)
+    }
+
+    /// Returns the request a Nintendo controller stopped initializing at, `None` while it's
+    /// initializing or once it's done.
+    pub fn get_init_failure(&self, index: usize) -> Option<InitFailure> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.init_failure)
+    }
+
+    /// Keeps the calibration and colors of Nintendo controllers in the file at `path`, so a
+    /// returning controller is usable before its SPI flash was read again. It's still read
+    /// once the controller is initialized, to keep the file up to date.
+    pub fn enable_calibration_cache(&mut self, path: impl Into<PathBuf>) {
+        self.calibration_cache = Some(cache::CalibrationCache::open(path.into()));
+    }
+
+    /// Sets the deadzones and response curve of a stick (0 left, 1 right) of any gamepad, they
+    /// replace the factory deadzone of Nintendo controllers. `None` goes back to the default.
+    /// Returns false if the settings are out of range.
+    pub fn set_stick_response(
+        &mut self,
+        index: usize,
+        stick: u8,
+        response: Option<&StickResponse>,
+    ) -> bool {
+        let Ok(stick) = calibration::Stick::try_from(stick) else {
+            return false;
+        };
+        let response = match response.map(response::Response::new) {
+            Some(None) => return false,
+            response => response.flatten(),
+        };
+        self.stick_responses.entry(index).or_default()[stick as usize] = response;
+        true
+    }
+
+    /// Sets the gate correction of a stick (0 left, 1 right) of any gamepad, which brings its
+    /// diagonals to a full deflection before deadzones apply. `None` turns it off, a learned
+    /// gate starts over when set again. Returns false if the settings are out of range.
+    pub fn set_stick_gate(&mut self, index: usize, stick: u8, gate: Option<&StickGate>) -> bool {
+        let Ok(stick) = calibration::Stick::try_from(stick) else {
+            return false;
+        };
+        let gate = match gate.map(gate::GateCorrection::new) {
+            Some(None) => return false,
+            gate => gate.flatten(),
+        };
+        self.stick_gates.entry(index).or_default()[stick as usize] = gate;
+        true
+    }
+
+    /// Returns where a stick (0 left, 1 right) of a Nintendo controller rests, measured while
+    /// no button is pressed and the stick holds still near the center.
+    pub fn get_stick_drift(&self, index: usize, stick: u8) -> Option<StickDrift> {
+        let stick = calibration::Stick::try_from(stick).ok()?;
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .filter(|(_, _, context)| context.controller_type.has_sticks())
+            .map(|(_, _, context)| context.drift.status(stick as usize))
+    }
+
+    /// Recenters the sticks of a Nintendo controller on where they rest and grows the inner
+    /// deadzone, factory or set by `set_stick_response`, to cover their noise, until
+    /// disconnect. Returns false if the gamepad is gone.
+    pub fn set_drift_compensation(&mut self, index: usize, enabled: bool) -> bool {
+        match self.device_map.values_mut().find(|(i, _, _)| *i == index) {
+            Some((_, _, context)) => {
+                context.drift.set_compensation(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Learns how far the sticks of a Nintendo controller reach and uses that instead of their
+    /// calibrated range, for controllers with bogus or worn out calibration. New extremes have
+    /// to be held for a few reports before the range grows. With
+    /// `GamepadAPI::enable_calibration_cache`, learned ranges are saved per controller every 30
+    /// seconds, on disconnect and when the API is dropped, and picked up on the next
+    /// connection. Without it they only last as long as the connection, see
+    /// `GamepadAPI::get_adaptive_range`. Returns false if the gamepad is gone.
+    pub fn set_adaptive_range(&mut self, index: usize, enabled: bool) -> bool {
+        match self.device_map.values_mut().find(|(i, _, _)| *i == index) {
+            Some((_, _, context)) => {
+                context.range.set_enabled(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the learned stick ranges of a Nintendo controller.
+    pub fn get_adaptive_range(&self, index: usize) -> Option<AdaptiveRange> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .filter(|(_, _, context)| context.controller_type.has_sticks())
+            .map(|(_, _, context)| context.range.profile())
+    }
+
+    /// Returns the colors of a Nintendo controller, or `None` before they were read.
+    pub fn get_colors(&self, index: usize) -> Option<ControllerColors> {
+        self.device_map
+            .values()
+            .find(|(i, _, _)| *i == index)
+            .and_then(|(_, _, context)| context.colors)
+    }
+
+    /// Returns the latest motion sensor readings of a gamepad, or `None` if the gamepad is
+    /// gone or has no motion sensors.
+    pub fn get_motion(&self, index: usize) -> Option<Motion> {
+        if let Some((_, _, context)) = self.device_map.values().find(|(i, _, _)| *i == index) {
+            return context.motion;
+        }
+        if let Some((_, device)) = self.steam_map.values().find(|(i, _)| *i == index) {
+            return Some(device.motion());
+        }
+        self.wiimote_map
+            .values()
+            .find(|(i, _)| *i == index)
+            .map(|(_, wiimote)| wiimote.motion())
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn gamepad_api_new() -> *mut GamepadAPI {
+    Box::into_raw(Box::new(GamepadAPI::new()))
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn get_gamepads(api: *mut GamepadAPI) -> GamepadArray {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        let gamepads = api.get_gamepads();
+        let mut buf = Vec::new();
+        for gamepad in &gamepads {
+            gamepad.write_to(&mut buf);
+        }
+        // Backed by u64s so the headers are aligned.
+        let words: Box<[u64]> = buf
+            .chunks(GAMEPAD_ALIGN)
+            .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let size = words.len() * GAMEPAD_ALIGN;
+        let data = Box::into_raw(words) as *const GamepadHeader;
+        GamepadArray {
+            len: gamepads.len(),
+            data,
+            size,
+        }
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_rumble(
+    api: *mut GamepadAPI,
+    index: usize,
+    strong_magnitude: f32,
+    weak_magnitude: f32,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_rumble(index, strong_magnitude, weak_magnitude)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn start_stick_calibration(
+    api: *mut GamepadAPI,
+    index: usize,
+    stick: u8,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.start_stick_calibration(index, stick)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn next_stick_calibration_step(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.next_stick_calibration_step(index)
+    }
+}
+
+/// Writes the progress of a stick calibration to `status`, returns false if there is none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `status` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_stick_calibration_status(
+    api: *mut GamepadAPI,
+    index: usize,
+    status: *mut StickCalibrationStatus,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        match api.get_stick_calibration_status(index) {
+            Some(value) => {
+                *status = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn apply_stick_calibration(
+    api: *mut GamepadAPI,
+    index: usize,
+    write: bool,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.apply_stick_calibration(index, write)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn cancel_stick_calibration(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.cancel_stick_calibration(index)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn erase_user_stick_calibration(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.erase_user_stick_calibration(index)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn start_spi_dump(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.start_spi_dump(index)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `dump` valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn start_spi_restore(
+    api: *mut GamepadAPI,
+    index: usize,
+    dump: *const u8,
+    len: usize,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.start_spi_restore(index, std::slice::from_raw_parts(dump, len))
+    }
+}
+
+/// Writes the progress of a SPI dump or restore to `progress`, returns false if there is none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `progress` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_spi_progress(
+    api: *mut GamepadAPI,
+    index: usize,
+    progress: *mut SpiProgress,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_spi_progress(index) {
+            Some(value) => {
+                *progress = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Copies up to `capacity` bytes of a finished dump to `buf`, returns how many were copied.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `buf` valid for `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_spi_dump(
+    api: *mut GamepadAPI,
+    index: usize,
+    buf: *mut u8,
+    capacity: usize,
+) -> usize {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        let Some(dump) = api.get_spi_dump(index) else {
+            return 0;
+        };
+        let len = dump.len().min(capacity);
+        std::ptr::copy_nonoverlapping(dump.as_ptr(), buf, len);
+        len
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn cancel_spi_transfer(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.cancel_spi_transfer(index)
+    }
+}
+
+/// Summarizes a dump of `get_spi_dump` into `summary`, returns false unless it's complete.
+///
+/// # Safety
+///
+/// `dump` must be valid for `len` bytes, `summary` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn summarize_spi_dump(
+    dump: *const u8,
+    len: usize,
+    summary: *mut SpiSummary,
+) -> bool {
+    unsafe {
+        match SpiSummary::from_dump(std::slice::from_raw_parts(dump, len)) {
+            Some(value) => {
+                *summary = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
 
-        if len >= 12 {
-            match InputReportID::try_from(buf[0]) {
-                Ok(InputReportID::FullControllerState) => {
-                    let info = ControllerStateInfoPacket::read_from_prefix(&buf[1..]).unwrap();
-                    Self::update_gamepad(
-                        &info.controller_state.button_status,
-                        &info.controller_state.left_stick,
-                        &info.controller_state.right_stick,
-                        &cal_data,
-                        gamepad,
-                        false,
-                    );
-                }
-                Ok(InputReportID::SimpleControllerState) => {
-                    let state = SimpleControllerStatePacket::read_from_prefix(&buf[1..]).unwrap();
-                    Self::update_gamepad(
-                        &state.button_status,
-                        &state.left_stick,
-                        &state.right_stick,
-                        &cal_data,
-                        gamepad,
-                        true,
-                    );
-                }
-                Ok(InputReportID::SubcommandReply) => {
-                    let pack = SubcommandInputPacket::read_from_prefix(&buf[1..]).unwrap();
-                    Self::update_gamepad(
-                        &pack.controller_state_info.controller_state.button_status,
-                        &pack.controller_state_info.controller_state.left_stick,
-                        &pack.controller_state_info.controller_state.right_stick,
-                        &cal_data,
-                        gamepad,
-                        false,
-                    );
-                    match SubcommandID::try_from(pack.subcommand_id) {
-                        Ok(SubcommandID::SPIFlashRead) => {
-                            match SPIAddress::try_from(pack.address) {
-                                Ok(SPIAddress::AnalogStickCalibration) => {
-                                    let cal = AnalogStickCalibrationPacket::read_from_prefix(
-                                        &pack.subcommand_data,
-                                    )
-                                    .unwrap();
+/// Writes the calibration sources of a gamepad to `sources`, returns false if there are none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `sources` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_calibration_sources(
+    api: *mut GamepadAPI,
+    index: usize,
+    sources: *mut CalibrationSources,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_calibration_sources(index) {
+            Some(value) => {
+                *sources = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
 
-                                    Self::update_stick_calibration_data(&cal, cal_data);
+/// Writes the device info of a gamepad to `info`, returns false if there is none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `info` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_device_info(
+    api: *mut GamepadAPI,
+    index: usize,
+    info: *mut ControllerInfo,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_device_info(index) {
+            Some(value) => {
+                *info = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
 
-                                    *init_state = GamepadInitState::RequestedAnalogCalibrationOk;
-                                }
-                                Ok(SPIAddress::AnalogStickParameters) => {
-                                    let cal = AnalogStickParamsCalibrationPacket::read_from_prefix(
-                                        &pack.subcommand_data,
-                                    )
-                                    .unwrap();
-                                    Self::update_stick_params_calibration_data(&cal, cal_data);
-
-                                    *init_state = GamepadInitState::RequestedAnalogParamsOk;
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
+/// Writes why a gamepad stopped initializing to `failure`, returns false if it didn't.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `failure` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_init_failure(
+    api: *mut GamepadAPI,
+    index: usize,
+    failure: *mut InitFailure,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_init_failure(index) {
+            Some(value) => {
+                *failure = value;
+                true
             }
+            None => false,
         }
     }
 }
 
-impl GamepadAPI {
-    pub fn get_gamepads(&mut self) -> Vec<Gamepad> {
-        let GamepadAPI {
-            input_buf,
-            device_map,
-            hidapi,
-            report_counter,
-        } = self;
+/// Writes the colors of a gamepad to `colors`, returns false if there are none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `colors` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_gamepad_colors(
+    api: *mut GamepadAPI,
+    index: usize,
+    colors: *mut ControllerColors,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_colors(index) {
+            Some(value) => {
+                *colors = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Writes the motion sensor readings of a gamepad to `motion`, returns false if there are none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `motion` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_gamepad_motion(
+    api: *mut GamepadAPI,
+    index: usize,
+    motion: *mut Motion,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_motion(index) {
+            Some(value) => {
+                *motion = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Writes up to `capacity` touches of a gamepad to `touches`, returns how many were written.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `touches` must have room for
+/// `capacity` touches.
+#[no_mangle]
+pub unsafe extern "C" fn get_gamepad_touches(
+    api: *mut GamepadAPI,
+    index: usize,
+    touches: *mut Touch,
+    capacity: usize,
+) -> usize {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        let values = api.get_touches(index);
+        let len = values.len().min(capacity);
+        std::ptr::copy_nonoverlapping(values.as_ptr(), touches, len);
+        len
+    }
+}
 
-        hidapi.reset_devices().unwrap();
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn trigger_haptic_pulse(
+    api: *mut GamepadAPI,
+    index: usize,
+    pad: u8,
+    duration_us: u16,
+    interval_us: u16,
+    count: u16,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        api.trigger_haptic_pulse(index, pad, duration_us, interval_us, count)
+    }
+}
 
-        for (vid, pid) in DEVICE_TUPLES {
-            hidapi.add_devices(vid, pid).unwrap();
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `state` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_wheel_state(
+    api: *mut GamepadAPI,
+    index: usize,
+    state: *mut WheelState,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_wheel_state(index) {
+            Some(value) => {
+                *state = value;
+                true
+            }
+            None => false,
         }
+    }
+}
 
-        let mut gamepads = Vec::new();
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_wheel_range(api: *mut GamepadAPI, index: usize, degrees: u16) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_wheel_range(index, degrees)
+    }
+}
 
-        let mut live_sns: HashSet<&str> = HashSet::new();
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_wheel_autocenter(
+    api: *mut GamepadAPI,
+    index: usize,
+    strength: f32,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_wheel_autocenter(index, strength)
+    }
+}
 
-        for device_info in hidapi.device_list() {
-            let sn = device_info.serial_number().unwrap();
+/// Returns the slot of the started effect, or -1.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `effect` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn upload_wheel_effect(
+    api: *mut GamepadAPI,
+    index: usize,
+    effect: *const WheelEffect,
+) -> i32 {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        (*effect)
+            .to_effect()
+            .and_then(|effect| api.upload_wheel_effect(index, effect))
+            .map_or(-1, |slot| slot as i32)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `effect` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn update_wheel_effect(
+    api: *mut GamepadAPI,
+    index: usize,
+    slot: usize,
+    effect: *const WheelEffect,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        (*effect)
+            .to_effect()
+            .is_some_and(|effect| api.update_wheel_effect(index, slot, effect))
+    }
+}
 
-            live_sns.insert(sn);
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn stop_wheel_effect(
+    api: *mut GamepadAPI,
+    index: usize,
+    slot: usize,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.stop_wheel_effect(index, slot)
+    }
+}
 
-            let (index, device, context) = device_map.entry(sn.to_string()).or_insert_with(|| {
-                let device = hidapi
-                    .open_serial(device_info.vendor_id(), device_info.product_id(), sn)
-                    .unwrap();
-                (util::generate_id(), device, GamepadContext::default())
-            });
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_wheel_gain(api: *mut GamepadAPI, index: usize, gain: f32) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_wheel_gain(index, gain)
+    }
+}
 
-            let GamepadContext {
-                cal_data,
-                init_state,
-            } = context;
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `config` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn start_ir_camera(
+    api: *mut GamepadAPI,
+    index: usize,
+    config: *const IrConfig,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.start_ir_camera(index, &*config)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn stop_ir_camera(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.stop_ir_camera(index)
+    }
+}
+
+/// Writes the last complete IR image to `image` and up to `capacity` of its pixels to
+/// `pixels`, returns false if there is none yet.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `image` a valid pointer and
+/// `pixels` valid for `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_ir_image(
+    api: *mut GamepadAPI,
+    index: usize,
+    image: *mut IrImage,
+    pixels: *mut u8,
+    capacity: usize,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        let Some(frame) = api.get_ir_image(index) else {
+            return false;
+        };
+        *image = IrImage {
+            frame_id: frame.id,
+            width: frame.width,
+            height: frame.height,
+        };
+        let len = frame.pixels.len().min(capacity);
+        std::ptr::copy_nonoverlapping(frame.pixels.as_ptr(), pixels, len);
+        true
+    }
+}
 
-            let mut gamepad = Gamepad::default();
+/// Writes up to `capacity` moment blocks, returns how many were written.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `moments` valid for `capacity`
+/// elements.
+#[no_mangle]
+pub unsafe extern "C" fn get_ir_moments(
+    api: *mut GamepadAPI,
+    index: usize,
+    moments: *mut IrMoment,
+    capacity: usize,
+) -> usize {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        let values = api.get_ir_moments(index);
+        let len = values.len().min(capacity);
+        std::ptr::copy_nonoverlapping(values.as_ptr(), moments, len);
+        len
+    }
+}
 
-            gamepad.index = *index;
+/// Writes up to `capacity` clusters, returns how many were written.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `clusters` valid for `capacity`
+/// elements.
+#[no_mangle]
+pub unsafe extern "C" fn get_ir_clusters(
+    api: *mut GamepadAPI,
+    index: usize,
+    clusters: *mut IrCluster,
+    capacity: usize,
+) -> usize {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        let values = api.get_ir_clusters(index);
+        let len = values.len().min(capacity);
+        std::ptr::copy_nonoverlapping(values.as_ptr(), clusters, len);
+        len
+    }
+}
 
-            Self::read_data_and_fill(&device, &mut gamepad, cal_data, init_state, input_buf);
+/// Called with the gamepad index, the tag or null, and the `user_data` passed to `read_nfc_tag`.
+pub type NfcTagCallback = extern "C" fn(usize, *const NfcTag, *mut std::ffi::c_void);
 
-            match GamepadInitState::try_from(*init_state) {
-                Ok(GamepadInitState::Uninitialized) => {
-                    Self::request_analog_calibration(report_counter, &device);
-                    context.init_state = GamepadInitState::RequestedAnalogCalibration;
-                }
-                Ok(GamepadInitState::RequestedAnalogCalibrationOk) => {
-                    Self::request_analog_params(report_counter, &device);
-                    context.init_state = GamepadInitState::RequestedAnalogParams;
-                }
-                Ok(GamepadInitState::RequestedAnalogParamsOk) => {
-                    context.init_state = GamepadInitState::Initialized;
-                }
-                _ => {}
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `user_data` must stay valid until
+/// `callback` was called.
+#[no_mangle]
+pub unsafe extern "C" fn read_nfc_tag(
+    api: *mut GamepadAPI,
+    index: usize,
+    callback: NfcTagCallback,
+    user_data: *mut std::ffi::c_void,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.read_nfc_tag(
+            index,
+            Box::new(move |index, tag| {
+                callback(index, tag.map_or(std::ptr::null(), |tag| tag), user_data)
+            }),
+        )
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn cancel_nfc_read(api: *mut GamepadAPI, index: usize) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.cancel_nfc_read(index)
+    }
+}
+
+/// Sets the deadzones and response curve of a stick, null goes back to the default. Returns
+/// false if the settings are out of range.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `response` a valid pointer or null.
+#[no_mangle]
+pub unsafe extern "C" fn set_stick_response(
+    api: *mut GamepadAPI,
+    index: usize,
+    stick: u8,
+    response: *const StickResponse,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_stick_response(index, stick, response.as_ref())
+    }
+}
+
+/// Sets the gate correction of a stick, null turns it off. Returns false if the settings are
+/// out of range.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `gate` a valid pointer or null.
+#[no_mangle]
+pub unsafe extern "C" fn set_stick_gate(
+    api: *mut GamepadAPI,
+    index: usize,
+    stick: u8,
+    gate: *const StickGate,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_stick_gate(index, stick, gate.as_ref())
+    }
+}
+
+/// Writes where a stick of a gamepad rests to `drift`, returns false if it's not measured.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `drift` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_stick_drift(
+    api: *mut GamepadAPI,
+    index: usize,
+    stick: u8,
+    drift: *mut StickDrift,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_stick_drift(index, stick) {
+            Some(value) => {
+                *drift = value;
+                true
             }
+            None => false,
+        }
+    }
+}
 
-            gamepads.push(gamepad);
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_drift_compensation(
+    api: *mut GamepadAPI,
+    index: usize,
+    enabled: bool,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_drift_compensation(index, enabled)
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`.
+#[no_mangle]
+pub unsafe extern "C" fn set_adaptive_range(
+    api: *mut GamepadAPI,
+    index: usize,
+    enabled: bool,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        api.set_adaptive_range(index, enabled)
+    }
+}
+
+/// Writes the learned stick ranges of a gamepad to `range`, returns false if it has none.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `range` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn get_adaptive_range(
+    api: *mut GamepadAPI,
+    index: usize,
+    range: *mut AdaptiveRange,
+) -> bool {
+    unsafe {
+        let api = api.as_ref().unwrap();
+        match api.get_adaptive_range(index) {
+            Some(value) => {
+                *range = value;
+                true
+            }
+            None => false,
         }
+    }
+}
 
-        self.device_map
-            .retain(|sn, _| live_sns.contains(sn.as_str()));
+/// Returns false if `path` isn't UTF-8.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `path` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn enable_calibration_cache(
+    api: *mut GamepadAPI,
+    path: *const std::ffi::c_char,
+) -> bool {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        match std::ffi::CStr::from_ptr(path).to_str() {
+            Ok(path) => {
+                api.enable_calibration_cache(path);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
 
-        gamepads
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `mappings` a valid
+/// nul-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn add_gamepad_mappings(
+    api: *mut GamepadAPI,
+    mappings: *const std::ffi::c_char,
+) -> usize {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        match std::ffi::CStr::from_ptr(mappings).to_str() {
+            Ok(text) => api.add_mappings(text),
+            Err(_) => 0,
+        }
     }
 }
 
+/// Returns 0 once loaded, else the number, from 1, of the first line that isn't UTF-8 or isn't
+/// understood.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `profiles` a valid nul-terminated
+/// string.
 #[no_mangle]
-pub extern "C" fn gamepad_api_new() -> *mut GamepadAPI {
-    Box::into_raw(Box::new(GamepadAPI::new()))
+pub unsafe extern "C" fn load_profiles(
+    api: *mut GamepadAPI,
+    profiles: *const std::ffi::c_char,
+) -> usize {
+    unsafe {
+        let api = api.as_mut().unwrap();
+        let bytes = std::ffi::CStr::from_ptr(profiles).to_bytes();
+        let result = match std::str::from_utf8(bytes) {
+            Ok(text) => api.load_profiles(text),
+            Err(error) => {
+                let valid = &bytes[..error.valid_up_to()];
+                Err(valid.iter().filter(|&&byte| byte == b'\n').count() + 1)
+            }
+        };
+        result.err().unwrap_or(0)
+    }
 }
 
+/// A null `name` goes back to the profile assigned in the file.
+///
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, `name` null or a valid
+/// nul-terminated string.
 #[no_mangle]
-pub extern "C" fn get_gamepads(api: *mut GamepadAPI) -> GamepadArray {
+pub unsafe extern "C" fn set_profile(
+    api: *mut GamepadAPI,
+    index: usize,
+    name: *const std::ffi::c_char,
+) -> bool {
     unsafe {
         let api = api.as_mut().unwrap();
-        let gamepads = api.get_gamepads();
-        let len = gamepads.len();
-        let data = Box::into_raw(gamepads.into_boxed_slice()) as *const Gamepad;
-        GamepadArray { data, len }
+        if name.is_null() {
+            return api.set_profile(index, None);
+        }
+        match std::ffi::CStr::from_ptr(name).to_str() {
+            Ok(name) => api.set_profile(index, Some(name)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// # Safety
+///
+/// `api` must be a pointer returned by `gamepad_api_new`, it can't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn free_gamepad_api(api: *mut GamepadAPI) {
+    if !api.is_null() {
+        unsafe {
+            drop(Box::from_raw(api));
+        }
     }
 }
 
@@ -644,7 +4496,77 @@ pub extern "C" fn get_gamepads(api: *mut GamepadAPI) -> GamepadArray {
 pub extern "C" fn free_gamepad_array(array: GamepadArray) {
     if !array.data.is_null() {
         unsafe {
-            let _ = Vec::from_raw_parts(array.data as *mut Gamepad, array.len, array.len);
+            let words = array.size / GAMEPAD_ALIGN;
+            let _ = Vec::from_raw_parts(array.data as *mut u64, words, words);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Left stick calibration as stored in the SPI flash: max distances, center, min distances.
+    fn left_stick(center: u16, min: u16, max: u16) -> [[u8; 3]; 3] {
+        [
+            util::pack_shorts(max, max),
+            util::pack_shorts(center, center),
+            util::pack_shorts(min, min),
+        ]
+    }
+
+    fn apply_left_stick(
+        [xy_max, xy_center, xy_min]: [[u8; 3]; 3],
+        source: CalibrationSource,
+        cal_data: &mut CalibrationData,
+    ) {
+        cal_data.sources.left_stick = GamepadAPI::update_left_stick_calibration_data(
+            &xy_max, &xy_center, &xy_min, source, cal_data,
+        );
+    }
+
+    fn left_x(cal_data: &CalibrationData) -> (u16, u16, u16) {
+        (cal_data.lx_center, cal_data.lx_min, cal_data.lx_max)
+    }
+
+    #[test]
+    fn reads_stick_calibration_distances() {
+        let mut cal_data = CalibrationData::default();
+        apply_left_stick(
+            left_stick(2000, 1500, 1400),
+            CalibrationSource::Factory,
+            &mut cal_data,
+        );
+        assert_eq!(left_x(&cal_data), (2000, 500, 3400));
+        assert_eq!((cal_data.ly_min, cal_data.ly_max), (500, 3400));
+        assert_eq!(cal_data.sources.left_stick, CalibrationSource::Factory);
+    }
+
+    #[test]
+    fn keeps_factory_calibration_over_invalid_user_calibration() {
+        let mut cal_data = CalibrationData::default();
+        apply_left_stick(
+            left_stick(2000, 1500, 1400),
+            CalibrationSource::Factory,
+            &mut cal_data,
+        );
+        // A min distance past the center, and a max past 12 bits.
+        for user in [left_stick(1000, 1500, 1400), left_stick(3000, 1500, 1400)] {
+            apply_left_stick(user, CalibrationSource::User, &mut cal_data);
+            assert_eq!(left_x(&cal_data), (2000, 500, 3400));
+            assert_eq!(cal_data.sources.left_stick, CalibrationSource::Factory);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_defaults_for_invalid_factory_calibration() {
+        let defaults = (CAL_DEFAULT_CENTER, CAL_DEFAULT_MIN, CAL_DEFAULT_MAX);
+        // Erased, then corrupt.
+        for factory in [[[0xff; 3]; 3], left_stick(1000, 1500, 1400)] {
+            let mut cal_data = CalibrationData::default();
+            apply_left_stick(factory, CalibrationSource::Factory, &mut cal_data);
+            assert_eq!(left_x(&cal_data), defaults);
+            assert_eq!(cal_data.sources.left_stick, CalibrationSource::Default);
         }
     }
 }
@@ -0,0 +1,151 @@
+/**
+ * The NFC/IR microcontroller of the right Joy-Con and the Pro Controller, reached through
+ * `SubcommandID::SetMcuConfig`/`SetMcuState`, output report 0x11 and the MCU data of
+ * `InputReportID::FullControllerAndMcuState` reports.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+ *  https://github.com/CTCaer/jc_toolkit/blob/master/jctool/jctool.cpp
+ */
+use crate::SubcommandID;
+use num_enum::TryFromPrimitive;
+use std::time::Duration;
+
+// MCU data in a `FullControllerAndMcuState` input report.
+pub const MCU_DATA_OFFSET: usize = 49;
+pub const MCU_DATA_SIZE: usize = 313;
+
+// Data after the command byte of an MCU output report, the last byte is its CRC.
+pub const MCU_REQUEST_SIZE: usize = 37;
+// Data of a `SubcommandID::SetMcuConfig` subcommand, the last byte is the CRC of all but the
+// first one.
+const MCU_CONFIG_SIZE: usize = 38;
+const MCU_CONFIG_SET_MODE: u8 = 0x21;
+pub const MCU_CONFIG_IR: u8 = 0x23;
+
+// How long MCU clients wait for a reply before sending a request again, and how often.
+pub const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+pub const MAX_RETRIES: u8 = 10;
+
+// Offset of the MCU mode in a `McuReportType::Status` report.
+const STATUS_MODE_OFFSET: usize = 7;
+
+/// First byte of the data of an MCU output report.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum McuCommand {
+    Status = 0x01,
+    Nfc = 0x02,
+    Ir = 0x03,
+}
+
+/// First byte of the MCU data of an input report.
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive)]
+pub enum McuReportType {
+    Status = 0x01,
+    IrData = 0x03,
+    NfcState = 0x2a,
+    NfcReadData = 0x3a,
+}
+
+/// Argument of `SubcommandID::SetMcuState`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum McuState {
+    Suspend = 0x00,
+    Resume = 0x01,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+pub enum McuMode {
+    Standby = 0x01,
+    // Devices on the rail, such as the Ring-Con.
+    External = 0x03,
+    Nfc = 0x04,
+    Ir = 0x05,
+}
+
+/// A report to send to the controller on behalf of an MCU client.
+#[derive(Debug)]
+pub enum McuRequest {
+    Subcommand(SubcommandID, Vec<u8>),
+    Mcu(McuCommand, [u8; MCU_REQUEST_SIZE]),
+}
+
+/// CRC-8 with polynomial 0x07, used by all MCU packets.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// An MCU output report with `data` after the command byte.
+pub fn request(command: McuCommand, data: &[u8]) -> McuRequest {
+    let mut packet = [0; MCU_REQUEST_SIZE];
+    packet[..data.len()].copy_from_slice(data);
+    packet[MCU_REQUEST_SIZE - 1] = crc8(&packet[..MCU_REQUEST_SIZE - 1]);
+    McuRequest::Mcu(command, packet)
+}
+
+pub fn status_request() -> McuRequest {
+    request(McuCommand::Status, &[])
+}
+
+pub fn set_state_request(state: McuState) -> McuRequest {
+    McuRequest::Subcommand(SubcommandID::SetMcuState, vec![state as u8])
+}
+
+/// A `SubcommandID::SetMcuConfig` subcommand with `data` before the CRC.
+pub fn config_request(data: &[u8]) -> McuRequest {
+    let mut config = vec![0; MCU_CONFIG_SIZE];
+    config[..data.len()].copy_from_slice(data);
+    config[MCU_CONFIG_SIZE - 1] = crc8(&config[1..MCU_CONFIG_SIZE - 1]);
+    McuRequest::Subcommand(SubcommandID::SetMcuConfig, config)
+}
+
+pub fn set_mode_request(mode: McuMode) -> McuRequest {
+    config_request(&[MCU_CONFIG_SET_MODE, 0x00, mode as u8])
+}
+
+/// The MCU mode of a `McuReportType::Status` report.
+pub fn status_mode(data: &[u8]) -> Option<McuMode> {
+    McuMode::try_from(*data.get(STATUS_MODE_OFFSET)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subcommand_data(request: McuRequest) -> Vec<u8> {
+        match request {
+            McuRequest::Subcommand(SubcommandID::SetMcuConfig, data) => data,
+            request => panic!("unexpected {request:?}"),
+        }
+    }
+
+    #[test]
+    fn computes_crc8() {
+        // The check value of CRC-8 with polynomial 0x07.
+        assert_eq!(crc8(b"123456789"), 0xf4);
+    }
+
+    #[test]
+    fn builds_set_mode_config() {
+        // Subcommand data of jc_toolkit's IR mode switch, the CRC skips the first byte.
+        let mut expected = [0; 38];
+        expected[..3].copy_from_slice(&[0x21, 0x00, 0x05]);
+        expected[37] = 0x09;
+        assert_eq!(subcommand_data(set_mode_request(McuMode::Ir)), expected);
+
+        let nfc = subcommand_data(set_mode_request(McuMode::Nfc));
+        assert_eq!((nfc[2], nfc[37]), (0x04, 0xa2));
+    }
+}
@@ -0,0 +1,417 @@
+/**
+ * NFC reader of the right Joy-Con and the Pro Controller: switches the MCU into NFC mode,
+ * polls for a tag and reads the pages of an NTAG215 (amiibo), then puts the MCU back to sleep.
+ *
+ * ref:
+ *  https://github.com/CTCaer/jc_toolkit/blob/master/jctool/jctool.cpp
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+ */
+use crate::mcu::{
+    self, McuCommand, McuMode, McuReportType, McuRequest, McuState, MAX_RETRIES, RETRY_INTERVAL,
+};
+use crate::{InputReportID, NfcTag, SubcommandID};
+use num_enum::TryFromPrimitive;
+use std::time::Instant;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+enum NfcCommand {
+    StartPolling = 0x01,
+    StopPolling = 0x02,
+    Status = 0x04,
+    ReadNtag = 0x06,
+}
+
+const REQUEST_FLAG_LAST_FRAGMENT: u8 = 0x08;
+
+const START_POLLING_ARGS: [u8; 5] = [0x01, 0x00, 0x00, 0x2c, 0x01];
+// Read arguments followed by a zero UID (any tag), the tag type and the page ranges.
+const READ_NTAG_ARGS: [u8; 3] = [0xd0, 0x07, 0x00];
+const NTAG215_TYPE: u8 = 0x00;
+const NTAG215_PAGE_RANGES: [(u8, u8); 3] = [(0x00, 0x3b), (0x3c, 0x77), (0x78, 0x86)];
+
+// Offsets in the MCU data of `McuReportType::NfcState` reports.
+const NFC_STATE_OFFSET: usize = 7;
+const TAG_UID_LEN_OFFSET: usize = 13;
+const TAG_UID_OFFSET: usize = 14;
+
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive)]
+enum NfcState {
+    Idle = 0x00,
+    Polling = 0x01,
+    TagFound = 0x09,
+}
+
+// Offset of the fragment number in `McuReportType::NfcReadData` reports.
+const READ_FRAGMENT_OFFSET: usize = 2;
+// (fragment number, data offset, data length) of the NTAG215 pages.
+const READ_FRAGMENTS: [(u8, usize, usize); 2] = [(1, 67, 245), (2, 7, 295)];
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Step {
+    SetMcuReportMode,
+    ResumeMcu,
+    WaitMcuStandby,
+    SetNfcMode,
+    WaitNfcMode,
+    StartPolling,
+    WaitTag,
+    ReadTag,
+    // Shutdown, also taken after errors and cancellation.
+    StopPolling,
+    SuspendMcu,
+    RestoreReportMode,
+    Done,
+}
+
+pub struct NfcReader {
+    step: Step,
+    sent_at: Option<Instant>,
+    retries: u8,
+    sequence: u8,
+    // Last fragment of the tag data received, 0 before the first one.
+    fragment: u8,
+    tag: NfcTag,
+    // Set once the read succeeded, failed or was cancelled.
+    result: Option<Option<NfcTag>>,
+}
+
+impl Default for NfcReader {
+    fn default() -> Self {
+        NfcReader {
+            step: Step::SetMcuReportMode,
+            sent_at: None,
+            retries: 0,
+            sequence: 0,
+            fragment: 0,
+            tag: NfcTag::default(),
+            result: None,
+        }
+    }
+}
+
+impl NfcReader {
+    /// Whether the MCU is back to sleep and the reader can be dropped.
+    pub fn is_done(&self) -> bool {
+        self.step == Step::Done
+    }
+
+    /// The tag, or `None` after an error or cancellation. Only returns once.
+    pub fn take_result(&mut self) -> Option<Option<NfcTag>> {
+        self.result.take()
+    }
+
+    pub fn cancel(&mut self) {
+        self.finish(None);
+    }
+
+    fn advance(&mut self, step: Step) {
+        self.step = step;
+        self.sent_at = None;
+        self.retries = 0;
+    }
+
+    fn finish(&mut self, tag: Option<NfcTag>) {
+        if self.step < Step::StopPolling {
+            self.result = Some(tag);
+            self.advance(Step::StopPolling);
+        }
+    }
+
+    /// Returns the report to send next, if any.
+    pub fn update(&mut self) -> Option<McuRequest> {
+        if self.step == Step::Done {
+            return None;
+        }
+        let retry = self.sent_at.is_some();
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() < RETRY_INTERVAL {
+                return None;
+            }
+            self.retries += 1;
+            // Polling for a tag goes on until one shows up or the read is cancelled.
+            if self.retries > MAX_RETRIES && self.step != Step::WaitTag {
+                match self.step {
+                    Step::StopPolling => self.advance(Step::SuspendMcu),
+                    Step::SuspendMcu => self.advance(Step::RestoreReportMode),
+                    Step::RestoreReportMode => {
+                        self.advance(Step::Done);
+                        return None;
+                    }
+                    _ => self.finish(None),
+                }
+            }
+        }
+        self.sent_at = Some(Instant::now());
+        Some(self.request(retry))
+    }
+
+    fn request(&mut self, retry: bool) -> McuRequest {
+        match self.step {
+            Step::SetMcuReportMode => McuRequest::Subcommand(
+                SubcommandID::SetInputReportMode,
+                vec![InputReportID::FullControllerAndMcuState as u8],
+            ),
+            Step::ResumeMcu => mcu::set_state_request(McuState::Resume),
+            Step::WaitMcuStandby | Step::WaitNfcMode => mcu::status_request(),
+            Step::SetNfcMode => mcu::set_mode_request(McuMode::Nfc),
+            Step::StartPolling => self.nfc_request(NfcCommand::StartPolling, &START_POLLING_ARGS),
+            Step::WaitTag => self.nfc_request(NfcCommand::Status, &[]),
+            Step::ReadTag if !retry && self.fragment == 0 => {
+                let mut args = READ_NTAG_ARGS.to_vec();
+                // Zero UID and no UID check, any tag.
+                args.extend_from_slice(&[0; 8]);
+                args.push(NTAG215_TYPE);
+                args.push(NTAG215_PAGE_RANGES.len() as u8);
+                for (first, last) in NTAG215_PAGE_RANGES {
+                    args.extend_from_slice(&[first, last]);
+                }
+                self.nfc_request(NfcCommand::ReadNtag, &args)
+            }
+            // Acknowledges the last fragment, which makes the MCU send the next one.
+            Step::ReadTag => self.nfc_request(NfcCommand::Status, &[]),
+            Step::StopPolling => self.nfc_request(NfcCommand::StopPolling, &[]),
+            Step::SuspendMcu => mcu::set_state_request(McuState::Suspend),
+            Step::RestoreReportMode => McuRequest::Subcommand(
+                SubcommandID::SetInputReportMode,
+                vec![InputReportID::FullControllerState as u8],
+            ),
+            Step::Done => unreachable!(),
+        }
+    }
+
+    fn nfc_request(&mut self, command: NfcCommand, args: &[u8]) -> McuRequest {
+        self.sequence = self.sequence.wrapping_add(1);
+        // Command, sequence number, acknowledged fragment, flags and argument length.
+        let mut data = vec![
+            command as u8,
+            self.sequence,
+            self.fragment,
+            REQUEST_FLAG_LAST_FRAGMENT,
+            args.len() as u8,
+        ];
+        data.extend_from_slice(args);
+        mcu::request(McuCommand::Nfc, &data)
+    }
+
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool) {
+        let Ok(id) = SubcommandID::try_from(id) else {
+            return;
+        };
+        match (self.step, id) {
+            (Step::SetMcuReportMode, SubcommandID::SetInputReportMode)
+            | (Step::ResumeMcu, SubcommandID::SetMcuState)
+            | (Step::SetNfcMode, SubcommandID::SetMcuConfig)
+                if !ack =>
+            {
+                self.finish(None)
+            }
+            (Step::SetMcuReportMode, SubcommandID::SetInputReportMode) => {
+                self.advance(Step::ResumeMcu)
+            }
+            (Step::ResumeMcu, SubcommandID::SetMcuState) => self.advance(Step::WaitMcuStandby),
+            (Step::SetNfcMode, SubcommandID::SetMcuConfig) => self.advance(Step::WaitNfcMode),
+            (Step::SuspendMcu, SubcommandID::SetMcuState) => self.advance(Step::RestoreReportMode),
+            (Step::RestoreReportMode, SubcommandID::SetInputReportMode) => self.advance(Step::Done),
+            _ => {}
+        }
+    }
+
+    /// Handles the MCU data of a `FullControllerAndMcuState` report.
+    pub fn on_mcu_report(&mut self, data: &[u8]) {
+        let Some(report_type) = data.first().and_then(|t| McuReportType::try_from(*t).ok()) else {
+            return;
+        };
+        match (report_type, self.step) {
+            (McuReportType::Status, Step::WaitMcuStandby) if mcu::status_mode(data).is_some() => {
+                self.advance(Step::SetNfcMode)
+            }
+            (McuReportType::Status, Step::WaitNfcMode)
+                if mcu::status_mode(data) == Some(McuMode::Nfc) =>
+            {
+                self.advance(Step::StartPolling)
+            }
+            (McuReportType::NfcState, Step::StartPolling) => self.advance(Step::WaitTag),
+            (McuReportType::NfcState, Step::WaitTag) => {
+                match data.get(NFC_STATE_OFFSET).map(|s| NfcState::try_from(*s)) {
+                    Some(Ok(NfcState::TagFound)) => {
+                        let len = data.get(TAG_UID_LEN_OFFSET).copied().unwrap_or(0) as usize;
+                        let len = len.min(self.tag.uid.len());
+                        let Some(uid) = data.get(TAG_UID_OFFSET..TAG_UID_OFFSET + len) else {
+                            return;
+                        };
+                        self.tag.uid[..len].copy_from_slice(uid);
+                        self.tag.uid_len = len as u8;
+                        self.fragment = 0;
+                        self.advance(Step::ReadTag);
+                    }
+                    // Polling timed out without a tag, start over.
+                    Some(Ok(NfcState::Idle)) => self.advance(Step::StartPolling),
+                    _ => {}
+                }
+            }
+            (McuReportType::NfcReadData, Step::ReadTag) => {
+                let Some(&fragment) = data.get(READ_FRAGMENT_OFFSET) else {
+                    return;
+                };
+                let mut start = 0;
+                for (number, offset, len) in READ_FRAGMENTS {
+                    if number == fragment && fragment == self.fragment + 1 {
+                        let Some(pages) = data.get(offset..offset + len) else {
+                            return;
+                        };
+                        self.tag.data[start..start + len].copy_from_slice(pages);
+                        self.tag.data_len = (start + len) as u16;
+                        self.fragment = fragment;
+                        self.sent_at = None;
+                        self.retries = 0;
+                    }
+                    start += len;
+                }
+                if self.fragment as usize == READ_FRAGMENTS.len() {
+                    self.finish(Some(self.tag));
+                }
+            }
+            (McuReportType::NfcState, Step::StopPolling) => self.advance(Step::SuspendMcu),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: [u8; 7] = [0x04, 0x8e, 0x1c, 0x5a, 0xa2, 0x4b, 0x80];
+
+    // MCU data of a `FullControllerAndMcuState` report, `bytes` at `offset` after the type.
+    fn mcu_data(report_type: McuReportType, offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; mcu::MCU_DATA_SIZE];
+        data[0] = report_type as u8;
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    fn mcu_status(mode: McuMode) -> Vec<u8> {
+        // Firmware version 5.24 in between.
+        mcu_data(
+            McuReportType::Status,
+            1,
+            &[0x00, 0x00, 0x05, 0x00, 0x18, 0x00, mode as u8],
+        )
+    }
+
+    fn nfc_state(state: u8) -> Vec<u8> {
+        let mut data = mcu_data(McuReportType::NfcState, NFC_STATE_OFFSET, &[state]);
+        data[TAG_UID_LEN_OFFSET] = UID.len() as u8;
+        data[TAG_UID_OFFSET..TAG_UID_OFFSET + UID.len()].copy_from_slice(&UID);
+        data
+    }
+
+    // Fragment `number` of a tag whose page bytes count up from 0.
+    fn read_data(number: u8) -> Vec<u8> {
+        let (_, offset, len) = READ_FRAGMENTS[number as usize - 1];
+        let start = READ_FRAGMENTS[..number as usize - 1]
+            .iter()
+            .map(|(_, _, len)| len)
+            .sum::<usize>();
+        let pages: Vec<u8> = (start..start + len).map(|i| i as u8).collect();
+        let mut data = mcu_data(McuReportType::NfcReadData, offset, &pages);
+        data[1] = 0x01;
+        data[READ_FRAGMENT_OFFSET] = number;
+        data
+    }
+
+    fn nfc_request(reader: &mut NfcReader) -> [u8; mcu::MCU_REQUEST_SIZE] {
+        match reader.update() {
+            Some(McuRequest::Mcu(McuCommand::Nfc, packet)) => packet,
+            request => panic!("expected an NFC request, got {request:?}"),
+        }
+    }
+
+    // A reader waiting for a tag.
+    fn polling_reader() -> NfcReader {
+        let mut reader = NfcReader::default();
+        let reply = |reader: &mut NfcReader, id: SubcommandID| {
+            reader.update();
+            reader.on_subcommand_reply(id as u8, true);
+        };
+        reply(&mut reader, SubcommandID::SetInputReportMode);
+        reply(&mut reader, SubcommandID::SetMcuState);
+        reader.on_mcu_report(&mcu_status(McuMode::Standby));
+        assert_eq!(reader.step, Step::SetNfcMode);
+        match reader.update() {
+            Some(McuRequest::Subcommand(SubcommandID::SetMcuConfig, config)) => {
+                assert_eq!(config[..3], [0x21, 0x00, McuMode::Nfc as u8]);
+            }
+            request => panic!("expected the MCU configuration, got {request:?}"),
+        }
+        reader.on_subcommand_reply(SubcommandID::SetMcuConfig as u8, true);
+        // Still switching.
+        reader.on_mcu_report(&mcu_status(McuMode::Standby));
+        assert_eq!(reader.step, Step::WaitNfcMode);
+        reader.on_mcu_report(&mcu_status(McuMode::Nfc));
+        assert_eq!(
+            nfc_request(&mut reader)[..10],
+            [0x01, 0x01, 0x00, 0x08, 0x05, 0x01, 0x00, 0x00, 0x2c, 0x01]
+        );
+        reader.on_mcu_report(&nfc_state(NfcState::Polling as u8));
+        assert_eq!(reader.step, Step::WaitTag);
+        reader
+    }
+
+    #[test]
+    fn configures_mcu_and_polls() {
+        let mut reader = polling_reader();
+        reader.on_mcu_report(&nfc_state(NfcState::Polling as u8));
+        assert_eq!(reader.step, Step::WaitTag);
+        // Polling timed out.
+        reader.on_mcu_report(&nfc_state(NfcState::Idle as u8));
+        assert_eq!(reader.step, Step::StartPolling);
+    }
+
+    #[test]
+    fn stops_when_mcu_configuration_fails() {
+        let mut reader = NfcReader::default();
+        reader.update();
+        reader.on_subcommand_reply(SubcommandID::SetInputReportMode as u8, false);
+        assert!(matches!(reader.take_result(), Some(None)));
+        assert_eq!(reader.step, Step::StopPolling);
+    }
+
+    #[test]
+    fn reads_tag() {
+        let mut reader = polling_reader();
+        reader.on_mcu_report(&nfc_state(NfcState::TagFound as u8));
+        assert_eq!(reader.step, Step::ReadTag);
+        assert_eq!(reader.tag.uid[..reader.tag.uid_len as usize], UID);
+        assert_eq!(
+            nfc_request(&mut reader)[..22],
+            [
+                0x06, 0x02, 0x00, 0x08, 0x13, 0xd0, 0x07, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x03,
+                0x00, 0x3b, 0x3c, 0x77
+            ]
+        );
+
+        // Fragment 2 before 1 is dropped.
+        reader.on_mcu_report(&read_data(2));
+        assert_eq!(reader.fragment, 0);
+        reader.on_mcu_report(&read_data(1));
+        assert_eq!(reader.fragment, 1);
+        // Acknowledges fragment 1.
+        assert_eq!(
+            nfc_request(&mut reader)[..5],
+            [0x04, 0x03, 0x01, 0x08, 0x00]
+        );
+        // A truncated fragment is dropped, the acknowledgment keeps asking for it.
+        reader.on_mcu_report(&read_data(2)[..100]);
+        assert_eq!(reader.fragment, 1);
+        reader.on_mcu_report(&read_data(2));
+
+        let tag = reader.take_result().unwrap().unwrap();
+        assert_eq!(tag.data_len as usize, crate::NFC_TAG_DATA_SIZE);
+        assert!(tag.data.iter().enumerate().all(|(i, b)| *b == i as u8));
+        assert_eq!(reader.step, Step::StopPolling);
+    }
+}
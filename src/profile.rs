@@ -0,0 +1,465 @@
+/**
+ * Input profiles: remapping applied on top of the final output of a gamepad, after deadzones.
+ * Profiles come from a TOML file and are assigned per controller type or serial number:
+ *
+ *  [types]
+ *  snes = "fighting"
+ *
+ *  [serials]
+ *  "98:b6:e9:12:34:56" = "southpaw"
+ *
+ *  [profile.fighting]
+ *  layout = "labels"
+ *  disable = ["guide"]
+ *
+ *  [profile.fighting.remap]
+ *  leftshoulder = "righttrigger"
+ *  dpup = "-lefty"
+ *
+ *  [profile.southpaw]
+ *  swap_sticks = true
+ *  invert = ["righty"]
+ *
+ * Inputs use the names of SDL mappings for the standard layout, or `b<n>` and `a<n>` for
+ * buttons and axes of device specific layouts. Remap targets are buttons, or axis directions
+ * like `-leftx`, a sign is only allowed on axes. Only this subset of TOML is understood: tables,
+ * strings, booleans and arrays of strings on a single line.
+ *
+ * ref:
+ *  https://toml.io/en/v1.0.0
+ *  https://w3c.github.io/gamepad/#remapping
+ */
+use crate::sdl_mapping::{self, HalfAxis, MappingOutput};
+use crate::{Button, Gamepad, GamepadMapping};
+use std::collections::HashMap;
+
+// Standard layout face buttons: bottom, right, left, top.
+const FACE_BUTTONS: [usize; 4] = [0, 1, 2, 3];
+// Standard layout stick buttons, swapped along with the sticks.
+const STICK_BUTTONS: [usize; 2] = [10, 11];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum FaceLayout {
+    // Buttons keep their position, the right one is A on Nintendo controllers.
+    #[default]
+    Positional,
+    // Nintendo controllers report the button labeled A as the bottom one, like Xbox ones.
+    Labels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Button(usize),
+    Axis(usize),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    layout: FaceLayout,
+    remaps: Vec<(usize, MappingOutput)>,
+    swap_sticks: bool,
+    invert: Vec<usize>,
+    disable: Vec<Input>,
+}
+
+impl Profile {
+    /// Applies the profile to the output of a gamepad, `nintendo_labels` if its face buttons
+    /// are labeled like on a Switch.
+    pub fn apply(&self, gamepad: &mut Gamepad, nintendo_labels: bool) {
+        let standard = gamepad.mapping == GamepadMapping::Standard;
+        if self.layout == FaceLayout::Labels && nintendo_labels && standard {
+            let [bottom, right, left, top] = FACE_BUTTONS;
+            if gamepad.buttons.len() > top {
+                gamepad.buttons.swap(bottom, right);
+                gamepad.buttons.swap(left, top);
+            }
+        }
+
+        // All remaps read the buttons as they were, so swapping two buttons works.
+        let buttons = gamepad.buttons.clone();
+        for (from, _) in &self.remaps {
+            if let Some(button) = gamepad.buttons.get_mut(*from) {
+                *button = Button::default();
+            }
+        }
+        for (from, to) in &self.remaps {
+            let Some(source) = buttons.get(*from) else {
+                continue;
+            };
+            match *to {
+                MappingOutput::Button(index) => {
+                    if let Some(button) = gamepad.buttons.get_mut(index) {
+                        button.pressed |= source.pressed;
+                        button.value = button.value.max(source.value);
+                    }
+                }
+                MappingOutput::Axis { index, half } => {
+                    let sign = match half {
+                        Some(HalfAxis::Negative) => -1.0,
+                        _ => 1.0,
+                    };
+                    match gamepad.axes.get_mut(index) {
+                        Some(axis) if source.pressed => *axis = sign * source.value,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if self.swap_sticks && gamepad.axes.len() >= 4 {
+            gamepad.axes.swap(0, 2);
+            gamepad.axes.swap(1, 3);
+            let [left, right] = STICK_BUTTONS;
+            if standard && gamepad.buttons.len() > right {
+                gamepad.buttons.swap(left, right);
+            }
+        }
+        for index in &self.invert {
+            if let Some(axis) = gamepad.axes.get_mut(*index) {
+                *axis = -*axis;
+            }
+        }
+        for input in &self.disable {
+            match *input {
+                Input::Button(index) => {
+                    if let Some(button) = gamepad.buttons.get_mut(index) {
+                        *button = Button::default();
+                    }
+                }
+                Input::Axis(index) => {
+                    if let Some(axis) = gamepad.axes.get_mut(index) {
+                        *axis = 0.0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProfileSet {
+    profiles: HashMap<String, Profile>,
+    // Controller type or serial number to profile name.
+    types: HashMap<String, String>,
+    serials: HashMap<String, String>,
+}
+
+impl ProfileSet {
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// The profile assigned to a serial number, else to a controller type.
+    pub fn assigned(&self, controller_type: &str, serial: Option<&str>) -> Option<&Profile> {
+        serial
+            .and_then(|serial| self.serials.get(serial))
+            .or_else(|| self.types.get(controller_type))
+            .and_then(|name| self.get(name))
+    }
+
+    /// Parses a profile file. Fails with the number, from 1, of the first line it doesn't
+    /// understand, or of the first assignment of an undefined profile.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut set = ProfileSet::default();
+        let mut table = Vec::new();
+        // Assigned profile names and their line, checked once all profiles are known.
+        let mut assignments = Vec::new();
+        for (number, line) in (1..).zip(text.lines()) {
+            let assigned = set.parse_line(line, &mut table).ok_or(number)?;
+            assignments.extend(assigned.map(|name| (number, name)));
+        }
+        match assignments
+            .iter()
+            .find(|(_, name)| !set.profiles.contains_key(*name))
+        {
+            Some((number, _)) => Err(*number),
+            None => Ok(set),
+        }
+    }
+
+    // Handles a line inside `table`, returns the profile name it assigns, if any.
+    fn parse_line<'a>(
+        &mut self,
+        line: &'a str,
+        table: &mut Vec<&'a str>,
+    ) -> Option<Option<&'a str>> {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            return Some(None);
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            *table = header
+                .strip_suffix(']')?
+                .split('.')
+                .map(|key| parse_key(key.trim()))
+                .collect::<Option<_>>()?;
+            if let ["profile", name, ..] = table.as_slice() {
+                self.profiles.entry(name.to_string()).or_default();
+            }
+            return Some(None);
+        }
+        let (key, value) = line.split_once('=')?;
+        let key = parse_key(key.trim())?;
+        let value = parse_value(value.trim())?;
+        match (table.as_slice(), value) {
+            (["types"], Value::String(name)) => {
+                self.types.insert(key.to_string(), name.to_string());
+                return Some(Some(name));
+            }
+            (["serials"], Value::String(name)) => {
+                self.serials.insert(key.to_string(), name.to_string());
+                return Some(Some(name));
+            }
+            (["profile", name], value) => {
+                let profile = self.profiles.get_mut(*name)?;
+                match (key, value) {
+                    ("layout", Value::String("positional")) => {
+                        profile.layout = FaceLayout::Positional;
+                    }
+                    ("layout", Value::String("labels")) => profile.layout = FaceLayout::Labels,
+                    ("swap_sticks", Value::Bool(swap)) => profile.swap_sticks = swap,
+                    ("invert", Value::Array(names)) => {
+                        for name in names {
+                            match parse_input(name)? {
+                                Input::Axis(index) => profile.invert.push(index),
+                                Input::Button(_) => return None,
+                            }
+                        }
+                    }
+                    ("disable", Value::Array(names)) => {
+                        for name in names {
+                            profile.disable.push(parse_input(name)?);
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            (["profile", name, "remap"], Value::String(target)) => {
+                let Input::Button(from) = parse_input(key)? else {
+                    return None;
+                };
+                let to = parse_target(target)?;
+                self.profiles.get_mut(*name)?.remaps.push((from, to));
+            }
+            _ => return None,
+        }
+        Some(None)
+    }
+}
+
+enum Value<'a> {
+    String(&'a str),
+    Bool(bool),
+    Array(Vec<&'a str>),
+}
+
+// Strings here never contain `#`, anything after one is a comment.
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(line, _)| line)
+}
+
+// Bare keys, or quoted ones for serial numbers with colons.
+fn parse_key(key: &str) -> Option<&str> {
+    if let Some(key) = parse_string(key) {
+        return Some(key);
+    }
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    (!key.is_empty() && key.chars().all(bare)).then_some(key)
+}
+
+fn parse_string(text: &str) -> Option<&str> {
+    let text = text.strip_prefix('"')?.strip_suffix('"')?;
+    (!text.contains(['"', '\\'])).then_some(text)
+}
+
+fn parse_value(text: &str) -> Option<Value<'_>> {
+    match text {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items.strip_suffix(']')?.trim();
+        let items = items.strip_suffix(',').unwrap_or(items);
+        if items.trim().is_empty() {
+            return Some(Value::Array(Vec::new()));
+        }
+        return items
+            .split(',')
+            .map(|item| parse_string(item.trim()))
+            .collect::<Option<_>>()
+            .map(Value::Array);
+    }
+    parse_string(text).map(Value::String)
+}
+
+fn parse_input(name: &str) -> Option<Input> {
+    // SDL mappings take signed names, here they're only remap targets.
+    if name.starts_with(['+', '-']) {
+        return None;
+    }
+    if let Some(index) = name.strip_prefix('b').and_then(|i| i.parse().ok()) {
+        return Some(Input::Button(index));
+    }
+    if let Some(index) = name.strip_prefix('a').and_then(|i| i.parse().ok()) {
+        return Some(Input::Axis(index));
+    }
+    match sdl_mapping::parse_output(name)? {
+        MappingOutput::Button(index) => Some(Input::Button(index)),
+        MappingOutput::Axis { index, half: None } => Some(Input::Axis(index)),
+        MappingOutput::Axis { .. } => None,
+    }
+}
+
+fn parse_target(name: &str) -> Option<MappingOutput> {
+    match parse_input(name) {
+        Some(Input::Button(index)) => Some(MappingOutput::Button(index)),
+        // Whole axes can't be driven by a button, only their directions.
+        Some(Input::Axis(_)) => None,
+        None => {
+            let (half, axis) = match name.as_bytes().first()? {
+                b'+' => (HalfAxis::Positive, &name[1..]),
+                b'-' => (HalfAxis::Negative, &name[1..]),
+                _ => return None,
+            };
+            let Input::Axis(index) = parse_input(axis)? else {
+                return None;
+            };
+            Some(MappingOutput::Axis {
+                index,
+                half: Some(half),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example at the top of the file.
+    const EXAMPLE: &str = r#"
+        [types]
+        snes = "fighting"
+
+        [serials]
+        "98:b6:e9:12:34:56" = "southpaw"
+
+        [profile.fighting]
+        layout = "labels"
+        disable = ["guide"]
+
+        [profile.fighting.remap]
+        leftshoulder = "righttrigger"
+        dpup = "-lefty"
+
+        [profile.southpaw]
+        swap_sticks = true
+        invert = ["righty"]
+    "#;
+
+    fn standard() -> Gamepad {
+        Gamepad {
+            mapping: GamepadMapping::Standard,
+            ..Default::default()
+        }
+    }
+
+    fn pressed(gamepad: &Gamepad) -> Vec<usize> {
+        (0..gamepad.buttons.len())
+            .filter(|&index| gamepad.buttons[index].pressed)
+            .collect()
+    }
+
+    fn apply(text: &str, gamepad: &mut Gamepad, nintendo_labels: bool) {
+        let set = ProfileSet::parse(text).unwrap();
+        set.get("test").unwrap().apply(gamepad, nintendo_labels);
+    }
+
+    #[test]
+    fn parses_the_example() {
+        let set = ProfileSet::parse(EXAMPLE).unwrap();
+        let serial = Some("98:b6:e9:12:34:56");
+        assert!(set.assigned("snes", serial).unwrap().swap_sticks);
+        assert_eq!(
+            set.assigned("snes", None).unwrap().layout,
+            FaceLayout::Labels
+        );
+        assert!(set.assigned("xbox", None).is_none());
+
+        let mut gamepad = standard();
+        gamepad.buttons[4] = Button::digital(true);
+        gamepad.buttons[12] = Button::digital(true);
+        gamepad.buttons[16] = Button::digital(true);
+        set.get("fighting").unwrap().apply(&mut gamepad, false);
+        assert_eq!(pressed(&gamepad), [7]);
+        assert_eq!(gamepad.axes, [0.0, -1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn swaps_buttons() {
+        let text = "[profile.test.remap]\na = \"b\"\nb = \"a\"";
+        let mut gamepad = standard();
+        gamepad.buttons[0] = Button::digital(true);
+        apply(text, &mut gamepad, false);
+        assert_eq!(pressed(&gamepad), [1]);
+    }
+
+    #[test]
+    fn swaps_sticks() {
+        let text = "[profile.test]\nswap_sticks = true";
+        let mut gamepad = standard();
+        gamepad.axes = vec![0.1, 0.2, 0.3, 0.4];
+        gamepad.buttons[10] = Button::digital(true);
+        apply(text, &mut gamepad, false);
+        assert_eq!(gamepad.axes, [0.3, 0.4, 0.1, 0.2]);
+        assert_eq!(pressed(&gamepad), [11]);
+    }
+
+    #[test]
+    fn swaps_face_buttons_by_label() {
+        let text = "[profile.test]\nlayout = \"labels\"";
+        let mut gamepad = standard();
+        gamepad.buttons[1] = Button::digital(true);
+        gamepad.buttons[2] = Button::digital(true);
+        apply(text, &mut gamepad, true);
+        assert_eq!(pressed(&gamepad), [0, 3]);
+        // Other controllers are already labeled like Xbox ones.
+        apply(text, &mut gamepad, false);
+        assert_eq!(pressed(&gamepad), [0, 3]);
+    }
+
+    #[test]
+    fn inverts_and_disables() {
+        let text = "[profile.test]\ninvert = [\"righty\", \"a4\"]\ndisable = [\"a\", \"leftx\"]";
+        let mut gamepad = Gamepad {
+            axes: vec![0.5, 0.5, 0.5, 0.5, 0.5],
+            ..standard()
+        };
+        gamepad.buttons[0] = Button::digital(true);
+        gamepad.buttons[1] = Button::digital(true);
+        apply(text, &mut gamepad, false);
+        assert_eq!(gamepad.axes, [0.0, 0.5, 0.5, -0.5, -0.5]);
+        assert_eq!(pressed(&gamepad), [1]);
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        let parse = |text: &str| ProfileSet::parse(text).err();
+        // Signs pick axis directions, buttons have none.
+        assert_eq!(
+            parse("[profile.test.remap]\na = \"+righttrigger\""),
+            Some(2)
+        );
+        assert_eq!(parse("[profile.test]\ndisable = [\"-a\"]"), Some(2));
+        // Whole axes can't be driven by a button.
+        assert_eq!(parse("[profile.test.remap]\na = \"leftx\""), Some(2));
+        assert_eq!(parse("[profile.test]\n\ninvert = [\"a\"]"), Some(3));
+        assert_eq!(parse("# comment\n[profile.test\n"), Some(2));
+        assert_eq!(parse("[profile.test]\nunknown = true"), Some(2));
+        // Assignments of undefined profiles fail at the assignment.
+        let text = "[types]\nsnes = \"test\"\nxbox = \"missing\"\n[profile.test]";
+        assert_eq!(parse(text), Some(3));
+        assert_eq!(parse(EXAMPLE), None);
+    }
+}
@@ -0,0 +1,278 @@
+/**
+ * Adaptive stick range: learns how far each stick axis really reaches from the raw positions
+ * of full reports, for third-party and worn controllers whose SPI flash holds bogus or stale
+ * calibration. A new extreme has to be held for a few reports before a bound moves, so noise
+ * and single glitched reports don't stretch the range. Learned bounds replace the calibrated
+ * ones once both sides of an axis are known.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+ */
+use crate::{util, AdaptiveRange, CalibrationData};
+use std::borrow::Cow;
+
+// Reports an extreme has to be held before it moves a bound.
+const HOLD_REPORTS: u8 = 3;
+// Raw distance a position has to pass a bound by, so noise at the edge doesn't count.
+const NOISE: u16 = 8;
+// Smallest raw distance from the center a bound can have.
+const MIN_SPAN: u16 = 400;
+// Kept inside learned bounds, so a full deflection reliably reaches 1.0.
+const EDGE_MARGIN: u16 = 20;
+// Largest 12 bit stick position.
+const POSITION_MAX: u16 = 0xfff;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bound {
+    // 0 while unknown.
+    value: u16,
+    // Least extreme position of the current run past the bound, and the run's length.
+    candidate: u16,
+    run: u8,
+}
+
+impl Bound {
+    // `beyond` tells whether `a` is more extreme than `b` on this side.
+    fn observe(&mut self, position: u16, threshold: u16, beyond: fn(u16, u16) -> bool) {
+        if !beyond(position, threshold) {
+            self.run = 0;
+            return;
+        }
+        self.candidate = if self.run == 0 || beyond(self.candidate, position) {
+            position
+        } else {
+            self.candidate
+        };
+        self.run += 1;
+        if self.run >= HOLD_REPORTS {
+            // 0 is taken by unknown bounds.
+            self.value = self.candidate.max(1);
+            self.run = 0;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct AxisRange {
+    min: Bound,
+    max: Bound,
+}
+
+impl AxisRange {
+    fn observe(&mut self, position: u16, center: u16) {
+        let max_threshold = match self.max.value {
+            0 => center.saturating_add(MIN_SPAN),
+            max => max.saturating_add(NOISE),
+        };
+        self.max.observe(position, max_threshold, |a, b| a > b);
+        let min_threshold = match self.min.value {
+            0 => center.saturating_sub(MIN_SPAN),
+            min => min.saturating_sub(NOISE),
+        };
+        self.min.observe(position, min_threshold, |a, b| a < b);
+    }
+
+    // Saved bounds, unknown if they can't have been learned, e.g. from a corrupt cache.
+    fn from_saved(min: u16, max: u16) -> Self {
+        let mut axis = AxisRange::default();
+        axis.min.value = min;
+        axis.max.value = max;
+        let valid_min = min <= POSITION_MAX - MIN_SPAN;
+        let valid_max = max == 0 || (MIN_SPAN..=POSITION_MAX).contains(&max);
+        let valid_pair = min == 0 || max == 0 || axis.bounds().is_some();
+        if valid_min && valid_max && valid_pair {
+            axis
+        } else {
+            AxisRange::default()
+        }
+    }
+
+    // Learned (min, max) once both sides are known.
+    fn bounds(&self) -> Option<(u16, u16)> {
+        let (min, max) = (self.min.value, self.max.value);
+        if min == 0 || max == 0 {
+            return None;
+        }
+        let (min, max) = (min.checked_add(EDGE_MARGIN)?, max.checked_sub(EDGE_MARGIN)?);
+        (min < max).then_some((min, max))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RangeLearner {
+    enabled: bool,
+    // Left x, left y, right x, right y.
+    axes: [AxisRange; 4],
+    // Whether bounds moved since `take_changed`.
+    changed: bool,
+}
+
+impl RangeLearner {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Handles the raw sticks of a full report.
+    pub fn on_sticks(&mut self, left: &[u8; 3], right: &[u8; 3], cal_data: &CalibrationData) {
+        let mut positions = [0; 4];
+        let [lx, ly, rx, ry] = &mut positions;
+        util::unpack_shorts(left, lx, ly);
+        util::unpack_shorts(right, rx, ry);
+        let centers = [
+            cal_data.lx_center,
+            cal_data.ly_center,
+            cal_data.rx_center,
+            cal_data.ry_center,
+        ];
+        for ((axis, position), center) in self.axes.iter_mut().zip(positions).zip(centers) {
+            let before = (axis.min.value, axis.max.value);
+            axis.observe(position, center);
+            self.changed |= before != (axis.min.value, axis.max.value);
+        }
+    }
+
+    /// The calibration with learned bounds, if enabled.
+    pub fn apply<'a>(&self, cal_data: &'a CalibrationData) -> Cow<'a, CalibrationData> {
+        if !self.enabled || self.axes.iter().all(|axis| axis.bounds().is_none()) {
+            return Cow::Borrowed(cal_data);
+        }
+        let mut cal_data = cal_data.clone();
+        let bounds = [
+            (&mut cal_data.lx_min, &mut cal_data.lx_max),
+            (&mut cal_data.ly_min, &mut cal_data.ly_max),
+            (&mut cal_data.rx_min, &mut cal_data.rx_max),
+            (&mut cal_data.ry_min, &mut cal_data.ry_max),
+        ];
+        for (axis, (min, max)) in self.axes.iter().zip(bounds) {
+            if let Some(bounds) = axis.bounds() {
+                (*min, *max) = bounds;
+            }
+        }
+        Cow::Owned(cal_data)
+    }
+
+    /// True once after bounds moved, when the profile is worth saving.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn profile(&self) -> AdaptiveRange {
+        AdaptiveRange {
+            min: self.axes.map(|axis| axis.min.value),
+            max: self.axes.map(|axis| axis.max.value),
+            enabled: self.enabled,
+            ..Default::default()
+        }
+    }
+
+    /// Starts from a saved profile, keeps whether it's enabled. Axes with bounds that can't
+    /// have been learned start over.
+    pub fn load(&mut self, profile: &AdaptiveRange) {
+        for ((axis, min), max) in self.axes.iter_mut().zip(profile.min).zip(profile.max) {
+            *axis = AxisRange::from_saved(min, max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cal_data() -> CalibrationData {
+        CalibrationData {
+            lx_center: 2000,
+            ly_center: 2000,
+            rx_center: 2000,
+            ry_center: 2000,
+            lx_min: 600,
+            lx_max: 3400,
+            ..Default::default()
+        }
+    }
+
+    // Raw sticks with the left x at `lx`, everything else centered.
+    fn sticks(lx: u16) -> ([u8; 3], [u8; 3]) {
+        let pack = |x: u16, y: u16| [x as u8, (x >> 8) as u8 | (y << 4) as u8, (y >> 4) as u8];
+        (pack(lx, 2000), pack(2000, 2000))
+    }
+
+    fn hold(learner: &mut RangeLearner, lx: u16, reports: u8) {
+        let (left, right) = sticks(lx);
+        for _ in 0..reports {
+            learner.on_sticks(&left, &right, &cal_data());
+        }
+    }
+
+    #[test]
+    fn learns_held_extremes() {
+        let mut learner = RangeLearner::default();
+        learner.set_enabled(true);
+        // A glitch isn't held long enough.
+        hold(&mut learner, 3900, HOLD_REPORTS - 1);
+        hold(&mut learner, 2000, 1);
+        hold(&mut learner, 200, HOLD_REPORTS);
+        assert_eq!(learner.apply(&cal_data()).lx_min, 600);
+
+        hold(&mut learner, 3000, HOLD_REPORTS);
+        assert!(learner.take_changed());
+        assert!(!learner.take_changed());
+        let cal_data = cal_data();
+        let applied = learner.apply(&cal_data);
+        assert_eq!((applied.lx_min, applied.lx_max), (220, 2980));
+        // Other axes keep their calibration.
+        assert_eq!(applied.rx_max, cal_data.rx_max);
+    }
+
+    #[test]
+    fn ignores_noise_at_the_edge() {
+        let mut learner = RangeLearner::default();
+        hold(&mut learner, 3000, HOLD_REPORTS);
+        hold(&mut learner, 3000 + NOISE, HOLD_REPORTS);
+        assert_eq!(learner.profile().max[0], 3000);
+        hold(&mut learner, 3100, HOLD_REPORTS);
+        assert_eq!(learner.profile().max[0], 3100);
+    }
+
+    #[test]
+    fn loads_saved_bounds() {
+        let profile = AdaptiveRange {
+            min: [200, 0, 300, 0],
+            max: [3000, 3500, 3800, 0],
+            enabled: true,
+            ..Default::default()
+        };
+        let mut learner = RangeLearner::default();
+        learner.load(&profile);
+        assert_eq!(learner.profile().min, profile.min);
+        assert_eq!(learner.profile().max, profile.max);
+        // Loading keeps whether it's enabled.
+        assert!(!learner.profile().enabled);
+    }
+
+    #[test]
+    fn drops_corrupt_saved_bounds() {
+        let profile = AdaptiveRange {
+            // Inverted, too close for the margins, out of range, and a max too small.
+            min: [3000, 1000, 0xffff, 0],
+            max: [1000, 1030, 3000, 10],
+            ..Default::default()
+        };
+        let mut learner = RangeLearner::default();
+        learner.set_enabled(true);
+        learner.load(&profile);
+        assert_eq!(learner.profile().min, [0; 4]);
+        assert_eq!(learner.profile().max, [0; 4]);
+        assert!(matches!(learner.apply(&cal_data()), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn bounds_never_overflow() {
+        let mut axis = AxisRange::default();
+        axis.min.value = u16::MAX;
+        axis.max.value = 1;
+        assert_eq!(axis.bounds(), None);
+        axis.min.value = 1;
+        axis.max.value = u16::MAX;
+        assert_eq!(axis.bounds(), Some((21, u16::MAX - 20)));
+    }
+}
@@ -0,0 +1,795 @@
+/**
+ * HID report descriptor parser and input report decoder for generic gamepads,
+ * joysticks and other controllers that don't have a dedicated driver.
+ *
+ * ref:
+ *  https://www.usb.org/sites/default/files/hid1_11.pdf
+ *  https://www.usb.org/sites/default/files/hut1_5.pdf
+ */
+use crate::{Button, Gamepad, DEFAULT_AXIS_COUNT, DEFAULT_BUTTON_COUNT};
+use std::collections::HashMap;
+
+pub const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const USAGE_PAGE_SIMULATION: u16 = 0x02;
+const USAGE_PAGE_BUTTON: u16 = 0x09;
+
+pub const USAGE_JOYSTICK: u16 = 0x04;
+pub const USAGE_GAMEPAD: u16 = 0x05;
+pub const USAGE_MULTI_AXIS_CONTROLLER: u16 = 0x08;
+
+const USAGE_X: u16 = 0x30;
+const USAGE_WHEEL: u16 = 0x38;
+const USAGE_HAT_SWITCH: u16 = 0x39;
+const USAGE_DPAD_UP: u16 = 0x90;
+const USAGE_DPAD_LEFT: u16 = 0x93;
+
+// Simulation controls that are reported as plain axes.
+const USAGE_RUDDER: u16 = 0xba;
+const USAGE_THROTTLE: u16 = 0xbb;
+const USAGE_ACCELERATOR: u16 = 0xc4;
+const USAGE_BRAKE: u16 = 0xc5;
+const USAGE_CLUTCH: u16 = 0xc6;
+const USAGE_STEERING: u16 = 0xc8;
+
+const ITEM_TYPE_MAIN: u8 = 0;
+const ITEM_TYPE_GLOBAL: u8 = 1;
+const ITEM_TYPE_LOCAL: u8 = 2;
+
+const MAIN_INPUT: u8 = 0x8;
+const MAIN_COLLECTION: u8 = 0xa;
+const MAIN_END_COLLECTION: u8 = 0xc;
+
+const GLOBAL_USAGE_PAGE: u8 = 0x0;
+const GLOBAL_LOGICAL_MIN: u8 = 0x1;
+const GLOBAL_LOGICAL_MAX: u8 = 0x2;
+const GLOBAL_REPORT_SIZE: u8 = 0x7;
+const GLOBAL_REPORT_ID: u8 = 0x8;
+const GLOBAL_REPORT_COUNT: u8 = 0x9;
+const GLOBAL_PUSH: u8 = 0xa;
+const GLOBAL_POP: u8 = 0xb;
+
+const LOCAL_USAGE: u8 = 0x0;
+const LOCAL_USAGE_MIN: u8 = 0x1;
+const LOCAL_USAGE_MAX: u8 = 0x2;
+
+const INPUT_CONSTANT: u32 = 1 << 0;
+const INPUT_VARIABLE: u32 = 1 << 1;
+
+const COLLECTION_APPLICATION: u32 = 0x01;
+
+const LONG_ITEM_PREFIX: u8 = 0xfe;
+
+/// Longest input report that's decoded, in bytes without the report ID. Fields past it are
+/// dropped, so a corrupt report count can't make parsing spin.
+pub const MAX_REPORT_SIZE: usize = 256;
+
+// SDL style hat bits.
+pub const HAT_CENTERED: u8 = 0x00;
+pub const HAT_UP: u8 = 0x01;
+pub const HAT_RIGHT: u8 = 0x02;
+pub const HAT_DOWN: u8 = 0x04;
+pub const HAT_LEFT: u8 = 0x08;
+
+// Hat switch positions, clockwise starting from up.
+const HAT_DIRECTIONS: [u8; 8] = [
+    HAT_UP,
+    HAT_UP | HAT_RIGHT,
+    HAT_RIGHT,
+    HAT_RIGHT | HAT_DOWN,
+    HAT_DOWN,
+    HAT_DOWN | HAT_LEFT,
+    HAT_LEFT,
+    HAT_LEFT | HAT_UP,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldKind {
+    Button(usize),
+    Axis(usize),
+    Hat(usize),
+    /// A separate d-pad direction bit, reported through hat 0.
+    Dpad(u8),
+    /// An array field reporting the usage of pressed buttons, `usage_min` is button 0.
+    ButtonArray {
+        usage_min: u32,
+        usage_max: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub report_id: u8,
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    pub kind: FieldKind,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct GlobalState {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: u32,
+    report_id: u8,
+    report_count: u32,
+}
+
+#[derive(Debug, Default)]
+struct LocalState {
+    // Extended usages, with the usage page in the high 16 bits.
+    usages: Vec<u32>,
+    usage_min: Option<u32>,
+    usage_max: Option<u32>,
+}
+
+impl LocalState {
+    fn usage(&self, i: u32) -> Option<u32> {
+        if let (Some(min), Some(max)) = (self.usage_min, self.usage_max) {
+            return Some((min + i).min(max));
+        }
+        self.usages.get(i as usize).or(self.usages.last()).copied()
+    }
+}
+
+/// An input field found while parsing, before axis indices are assigned.
+struct RawField {
+    report_id: u8,
+    bit_offset: usize,
+    bit_size: usize,
+    logical_min: i32,
+    logical_max: i32,
+    usage: u32,
+    array: Option<(u32, u32)>,
+}
+
+fn extended_usage(page: u16, usage: u16) -> u32 {
+    ((page as u32) << 16) | usage as u32
+}
+
+fn is_axis_usage(usage: u32) -> bool {
+    let page = (usage >> 16) as u16;
+    let id = usage as u16;
+    match page {
+        USAGE_PAGE_GENERIC_DESKTOP => (USAGE_X..=USAGE_WHEEL).contains(&id),
+        USAGE_PAGE_SIMULATION => matches!(
+            id,
+            USAGE_RUDDER
+                | USAGE_THROTTLE
+                | USAGE_ACCELERATOR
+                | USAGE_BRAKE
+                | USAGE_CLUTCH
+                | USAGE_STEERING
+        ),
+        _ => false,
+    }
+}
+
+fn item_data(data: &[u8]) -> u32 {
+    data.iter()
+        .enumerate()
+        .fold(0, |acc, (i, b)| acc | (*b as u32) << (8 * i))
+}
+
+fn sign_extend(value: u32, bits: usize) -> i32 {
+    if bits == 0 || bits >= 32 {
+        return value as i32;
+    }
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+/// Decoder for the input reports of one device, built from its report descriptor.
+#[derive(Debug, Clone, Default)]
+pub struct ReportDecoder {
+    pub fields: Vec<Field>,
+    pub has_report_ids: bool,
+    pub axis_count: usize,
+    pub button_count: usize,
+    pub hat_count: usize,
+    /// Extended usage (page in the high 16 bits) of every axis, in axis index order.
+    pub axis_usages: Vec<u32>,
+}
+
+/// Raw state of a generic device: axes in `-1.0..=1.0`, buttons, and hats as `HAT_*` bits.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenericState {
+    pub axes: Vec<f32>,
+    pub buttons: Vec<bool>,
+    pub hats: Vec<u8>,
+}
+
+impl ReportDecoder {
+    /// Parses a report descriptor, only keeping the input fields that belong to a
+    /// joystick, gamepad or multi-axis controller application collection.
+    /// Returns `None` if there is no such collection.
+    pub fn parse(descriptor: &[u8]) -> Option<Self> {
+        let mut global = GlobalState::default();
+        let mut global_stack: Vec<GlobalState> = Vec::new();
+        let mut local = LocalState::default();
+        // For each open collection, whether it is (inside) a gamepad application.
+        let mut collections: Vec<bool> = Vec::new();
+        let mut bit_offsets: HashMap<u8, usize> = HashMap::new();
+        let mut raw_fields: Vec<RawField> = Vec::new();
+        let mut has_report_ids = false;
+        let mut found_application = false;
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            let prefix = descriptor[i];
+            if prefix == LONG_ITEM_PREFIX {
+                let size = *descriptor.get(i + 1)? as usize;
+                i += 3 + size;
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => size as usize,
+            };
+            let item_type = (prefix >> 2) & 0x03;
+            let tag = prefix >> 4;
+            let data = descriptor.get(i + 1..i + 1 + size)?;
+            let value = item_data(data);
+            i += 1 + size;
+
+            match item_type {
+                ITEM_TYPE_MAIN => {
+                    match tag {
+                        MAIN_COLLECTION => {
+                            let usage = local.usage(0).unwrap_or(0);
+                            let parent = collections.last().copied().unwrap_or(false);
+                            let is_gamepad = value == COLLECTION_APPLICATION
+                                && [USAGE_JOYSTICK, USAGE_GAMEPAD, USAGE_MULTI_AXIS_CONTROLLER]
+                                    .iter()
+                                    .any(|u| {
+                                        usage == extended_usage(USAGE_PAGE_GENERIC_DESKTOP, *u)
+                                    });
+                            found_application |= is_gamepad;
+                            collections.push(parent || is_gamepad);
+                        }
+                        MAIN_END_COLLECTION => {
+                            collections.pop();
+                        }
+                        MAIN_INPUT => {
+                            let report_id = global.report_id;
+                            let offset = bit_offsets.entry(report_id).or_insert(0);
+                            let bit_size = global.report_size as usize;
+                            let in_gamepad = collections.last().copied().unwrap_or(false);
+                            let report_count = global.report_count as usize;
+                            let count = match (MAX_REPORT_SIZE * 8).checked_sub(*offset) {
+                                Some(bits) if bit_size > 0 => report_count.min(bits / bit_size),
+                                _ => 0,
+                            };
+
+                            if in_gamepad && value & INPUT_CONSTANT == 0 {
+                                if value & INPUT_VARIABLE != 0 {
+                                    for n in 0..count {
+                                        if let Some(usage) = local.usage(n as u32) {
+                                            raw_fields.push(RawField {
+                                                report_id,
+                                                bit_offset: *offset + n * bit_size,
+                                                bit_size,
+                                                logical_min: global.logical_min,
+                                                logical_max: global.logical_max,
+                                                usage,
+                                                array: None,
+                                            });
+                                        }
+                                    }
+                                } else if let (Some(min), Some(max)) =
+                                    (local.usage_min, local.usage_max)
+                                {
+                                    for n in 0..count {
+                                        raw_fields.push(RawField {
+                                            report_id,
+                                            bit_offset: *offset + n * bit_size,
+                                            bit_size,
+                                            logical_min: global.logical_min,
+                                            logical_max: global.logical_max,
+                                            usage: min,
+                                            array: Some((min, max)),
+                                        });
+                                    }
+                                }
+                            }
+
+                            *offset = offset.saturating_add(report_count.saturating_mul(bit_size));
+                        }
+                        _ => {}
+                    }
+                    local = LocalState::default();
+                }
+                ITEM_TYPE_GLOBAL => match tag {
+                    GLOBAL_USAGE_PAGE => global.usage_page = value as u16,
+                    GLOBAL_LOGICAL_MIN => global.logical_min = sign_extend(value, size * 8),
+                    GLOBAL_LOGICAL_MAX => {
+                        // Devices commonly declare e.g. 0..255 with a one byte maximum,
+                        // so the maximum is only signed if the minimum is.
+                        global.logical_max = if global.logical_min < 0 {
+                            sign_extend(value, size * 8)
+                        } else {
+                            value as i32
+                        };
+                    }
+                    GLOBAL_REPORT_SIZE => global.report_size = value,
+                    GLOBAL_REPORT_ID => {
+                        global.report_id = value as u8;
+                        has_report_ids = true;
+                    }
+                    GLOBAL_REPORT_COUNT => global.report_count = value,
+                    GLOBAL_PUSH => global_stack.push(global),
+                    GLOBAL_POP => global = global_stack.pop().unwrap_or(global),
+                    _ => {}
+                },
+                ITEM_TYPE_LOCAL => {
+                    let usage = if size == 4 {
+                        value
+                    } else {
+                        extended_usage(global.usage_page, value as u16)
+                    };
+                    match tag {
+                        LOCAL_USAGE => local.usages.push(usage),
+                        LOCAL_USAGE_MIN => local.usage_min = Some(usage),
+                        LOCAL_USAGE_MAX => local.usage_max = Some(usage),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !found_application {
+            return None;
+        }
+
+        Some(Self::from_raw_fields(raw_fields, has_report_ids))
+    }
+
+    fn from_raw_fields(raw_fields: Vec<RawField>, has_report_ids: bool) -> Self {
+        // Axes are numbered by usage, the same order the evdev and DirectInput
+        // backends expose them in, so indices stay stable across report layouts.
+        let mut axis_usages: Vec<u32> = raw_fields
+            .iter()
+            .filter(|f| f.array.is_none() && is_axis_usage(f.usage))
+            .map(|f| f.usage)
+            .collect();
+        axis_usages.sort();
+        axis_usages.dedup();
+
+        let mut decoder = ReportDecoder {
+            has_report_ids,
+            axis_count: axis_usages.len(),
+            axis_usages: axis_usages.clone(),
+            ..Default::default()
+        };
+
+        let dpad_up = extended_usage(USAGE_PAGE_GENERIC_DESKTOP, USAGE_DPAD_UP);
+        let dpad_left = extended_usage(USAGE_PAGE_GENERIC_DESKTOP, USAGE_DPAD_LEFT);
+        let hat_switch = extended_usage(USAGE_PAGE_GENERIC_DESKTOP, USAGE_HAT_SWITCH);
+        let button_page = (USAGE_PAGE_BUTTON as u32) << 16;
+        let mut has_dpad = false;
+
+        for raw in raw_fields {
+            let kind = if let Some((min, max)) = raw.array {
+                if min >> 16 != USAGE_PAGE_BUTTON as u32 {
+                    continue;
+                }
+                decoder.button_count = decoder.button_count.max((max - button_page) as usize);
+                FieldKind::ButtonArray {
+                    usage_min: min,
+                    usage_max: max,
+                }
+            } else if raw.usage >> 16 == USAGE_PAGE_BUTTON as u32 {
+                // Button 1 is the first button, usage 0 means no button.
+                let Some(index) = ((raw.usage & 0xffff) as usize).checked_sub(1) else {
+                    continue;
+                };
+                decoder.button_count = decoder.button_count.max(index + 1);
+                FieldKind::Button(index)
+            } else if raw.usage == hat_switch {
+                decoder.hat_count += 1;
+                FieldKind::Hat(decoder.hat_count - 1)
+            } else if (dpad_up..=dpad_left).contains(&raw.usage) {
+                // Up, down, right, left.
+                has_dpad = true;
+                FieldKind::Dpad(
+                    [HAT_UP, HAT_DOWN, HAT_RIGHT, HAT_LEFT][(raw.usage - dpad_up) as usize],
+                )
+            } else if is_axis_usage(raw.usage) {
+                FieldKind::Axis(axis_usages.binary_search(&raw.usage).unwrap())
+            } else {
+                continue;
+            };
+
+            decoder.fields.push(Field {
+                report_id: raw.report_id,
+                bit_offset: raw.bit_offset,
+                bit_size: raw.bit_size,
+                logical_min: raw.logical_min,
+                logical_max: raw.logical_max,
+                kind,
+            });
+        }
+
+        if has_dpad && decoder.hat_count == 0 {
+            decoder.hat_count = 1;
+        }
+
+        decoder
+    }
+
+    /// Index of the axis with the given usage.
+    pub fn axis_index(&self, usage_page: u16, usage: u16) -> Option<usize> {
+        self.axis_usages
+            .binary_search(&extended_usage(usage_page, usage))
+            .ok()
+    }
+
+    pub fn new_state(&self) -> GenericState {
+        GenericState {
+            axes: vec![0.0; self.axis_count],
+            buttons: vec![false; self.button_count],
+            hats: vec![HAT_CENTERED; self.hat_count],
+        }
+    }
+
+    /// Applies one input report (including the report ID byte if the device uses them).
+    pub fn decode(&self, report: &[u8], state: &mut GenericState) {
+        let (report_id, data) = if self.has_report_ids {
+            match report.split_first() {
+                Some((id, data)) => (*id, data),
+                None => return,
+            }
+        } else {
+            (0, report)
+        };
+
+        let mut array_fields_seen = false;
+        let mut dpad_bits = None;
+
+        for field in &self.fields {
+            if field.report_id != report_id {
+                continue;
+            }
+            let Some(raw) = extract_field(data, field.bit_offset, field.bit_size) else {
+                continue;
+            };
+            let value = if field.logical_min < 0 {
+                sign_extend(raw, field.bit_size)
+            } else {
+                raw as i32
+            };
+
+            match field.kind {
+                FieldKind::Dpad(bit) => {
+                    let bits = dpad_bits.get_or_insert(HAT_CENTERED);
+                    if value != 0 {
+                        *bits |= bit;
+                    }
+                }
+                FieldKind::Button(index) => {
+                    if let Some(button) = state.buttons.get_mut(index) {
+                        *button = value != 0;
+                    }
+                }
+                FieldKind::ButtonArray {
+                    usage_min,
+                    usage_max,
+                } => {
+                    if !array_fields_seen {
+                        state.buttons.iter_mut().for_each(|b| *b = false);
+                        array_fields_seen = true;
+                    }
+                    let usage = (usage_min & 0xffff) as i32 + value - field.logical_min;
+                    if usage >= 1 && usage <= (usage_max & 0xffff) as i32 {
+                        if let Some(button) = state.buttons.get_mut(usage as usize - 1) {
+                            *button = true;
+                        }
+                    }
+                }
+                FieldKind::Axis(index) => {
+                    if let Some(axis) = state.axes.get_mut(index) {
+                        *axis = normalize_axis(value, field.logical_min, field.logical_max);
+                    }
+                }
+                FieldKind::Hat(index) => {
+                    if let Some(hat) = state.hats.get_mut(index) {
+                        *hat = hat_value(value, field.logical_min, field.logical_max);
+                    }
+                }
+            }
+        }
+
+        if let (Some(bits), Some(hat)) = (dpad_bits, state.hats.first_mut()) {
+            *hat = bits;
+        }
+    }
+}
+
+fn extract_field(data: &[u8], bit_offset: usize, bit_size: usize) -> Option<u32> {
+    if bit_size == 0 || bit_size > 32 || (bit_offset + bit_size).div_ceil(8) > data.len() {
+        return None;
+    }
+    let mut value: u64 = 0;
+    let first = bit_offset / 8;
+    let last = (bit_offset + bit_size - 1) / 8;
+    for (n, byte) in data[first..=last].iter().enumerate() {
+        value |= (*byte as u64) << (8 * n);
+    }
+    value >>= bit_offset % 8;
+    Some((value & ((1u64 << bit_size) - 1)) as u32)
+}
+
+pub fn normalize_axis(value: i32, min: i32, max: i32) -> f32 {
+    if max <= min {
+        return 0.0;
+    }
+    let value = value.clamp(min, max);
+    (2.0 * (value - min) as f64 / (max - min) as f64 - 1.0) as f32
+}
+
+/// Converts a hat switch value into `HAT_*` bits, out of range values mean centered.
+pub fn hat_value(value: i32, min: i32, max: i32) -> u8 {
+    if value < min || value > max {
+        return HAT_CENTERED;
+    }
+    let positions = (max - min + 1) as usize;
+    let position = (value - min) as usize;
+    match positions {
+        // Four way hats only report the cardinal directions.
+        4 => HAT_DIRECTIONS[position * 2],
+        8 => HAT_DIRECTIONS[position],
+        _ => HAT_CENTERED,
+    }
+}
+
+impl GenericState {
+    /// Fills `gamepad` with the raw layout: all axes, then the buttons in report order,
+    /// followed by the first hat as up/down/left/right buttons if `hat_buttons` is set,
+    /// and all hats. Short layouts are padded to the default axis and button counts.
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad, hat_buttons: bool) {
+        gamepad.axes = self.axes.clone();
+        gamepad
+            .axes
+            .resize(self.axes.len().max(DEFAULT_AXIS_COUNT), 0.0);
+
+        gamepad.buttons = self
+            .buttons
+            .iter()
+            .map(|pressed| Button::digital(*pressed))
+            .collect();
+        if let (true, Some(&hat)) = (hat_buttons, self.hats.first()) {
+            for mask in [HAT_UP, HAT_DOWN, HAT_LEFT, HAT_RIGHT] {
+                gamepad.buttons.push(Button::digital(hat & mask != 0));
+            }
+        }
+        let button_count = gamepad.buttons.len().max(DEFAULT_BUTTON_COUNT);
+        gamepad.buttons.resize(button_count, Button::default());
+
+        gamepad.hats = self.hats.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // DragonRise 0079:0006 gamepad, as fixed up by the Linux hid-dr driver: five 8-bit axes
+    // (X, Rx, Z, Y, Ry), a hat, 12 buttons and a vendor byte, no report IDs.
+    const DRAGONRISE_DESCRIPTOR: [u8; 101] = [
+        0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0xa1, 0x02, 0x75, 0x08, 0x95, 0x05, 0x15, 0x00, 0x26,
+        0xff, 0x00, 0x35, 0x00, 0x46, 0xff, 0x00, 0x09, 0x30, 0x09, 0x33, 0x09, 0x32, 0x09, 0x31,
+        0x09, 0x34, 0x81, 0x02, 0x75, 0x04, 0x95, 0x01, 0x25, 0x07, 0x46, 0x3b, 0x01, 0x65, 0x14,
+        0x09, 0x39, 0x81, 0x42, 0x65, 0x00, 0x75, 0x01, 0x95, 0x0c, 0x25, 0x01, 0x45, 0x01, 0x05,
+        0x09, 0x19, 0x01, 0x29, 0x0c, 0x81, 0x02, 0x06, 0x00, 0xff, 0x75, 0x01, 0x95, 0x08, 0x25,
+        0x01, 0x45, 0x01, 0x09, 0x01, 0x81, 0x02, 0xc0, 0xa1, 0x02, 0x75, 0x08, 0x95, 0x07, 0x46,
+        0xff, 0x00, 0x26, 0xff, 0x00, 0x09, 0x02, 0x91, 0x02, 0xc0, 0xc0,
+    ];
+
+    // Sony DualShock 3 (054c:0268) over USB, as in the Linux hid-sony driver: report ID 1 with
+    // 19 buttons, X, Y, Z and Rz, then pressure and motion data under other usages, and
+    // feature reports 2, 0xee and 0xef.
+    const DUALSHOCK3_DESCRIPTOR: [u8; 148] = [
+        0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0xa1, 0x02, 0x85, 0x01, 0x75, 0x08, 0x95, 0x01, 0x15,
+        0x00, 0x26, 0xff, 0x00, 0x81, 0x03, 0x75, 0x01, 0x95, 0x13, 0x15, 0x00, 0x25, 0x01, 0x35,
+        0x00, 0x45, 0x01, 0x05, 0x09, 0x19, 0x01, 0x29, 0x13, 0x81, 0x02, 0x75, 0x01, 0x95, 0x0d,
+        0x06, 0x00, 0xff, 0x81, 0x03, 0x15, 0x00, 0x26, 0xff, 0x00, 0x05, 0x01, 0x09, 0x01, 0xa1,
+        0x00, 0x75, 0x08, 0x95, 0x04, 0x35, 0x00, 0x46, 0xff, 0x00, 0x09, 0x30, 0x09, 0x31, 0x09,
+        0x32, 0x09, 0x35, 0x81, 0x02, 0xc0, 0x05, 0x01, 0x95, 0x13, 0x09, 0x01, 0x81, 0x02, 0x95,
+        0x0c, 0x81, 0x01, 0x75, 0x10, 0x95, 0x04, 0x26, 0xff, 0x03, 0x46, 0xff, 0x03, 0x09, 0x01,
+        0x81, 0x02, 0xc0, 0xa1, 0x02, 0x85, 0x02, 0x75, 0x08, 0x95, 0x30, 0x09, 0x01, 0xb1, 0x02,
+        0xc0, 0xa1, 0x02, 0x85, 0xee, 0x75, 0x08, 0x95, 0x30, 0x09, 0x01, 0xb1, 0x02, 0xc0, 0xa1,
+        0x02, 0x85, 0xef, 0x75, 0x08, 0x95, 0x30, 0x09, 0x01, 0xb1, 0x02, 0xc0, 0xc0,
+    ];
+
+    // HORI Fighting Stick 3 (0f0d:0010), the PS3 layout most arcade sticks copy: 13 buttons,
+    // a hat, X, Y, Z and Rz, then vendor defined pressure bytes, no report IDs.
+    const HORI_FIGHTING_STICK_DESCRIPTOR: [u8; 137] = [
+        0x05, 0x01, 0x09, 0x05, 0xa1, 0x01, 0x15, 0x00, 0x25, 0x01, 0x35, 0x00, 0x45, 0x01, 0x75,
+        0x01, 0x95, 0x0d, 0x05, 0x09, 0x19, 0x01, 0x29, 0x0d, 0x81, 0x02, 0x95, 0x03, 0x81, 0x01,
+        0x05, 0x01, 0x25, 0x07, 0x46, 0x3b, 0x01, 0x75, 0x04, 0x95, 0x01, 0x65, 0x14, 0x09, 0x39,
+        0x81, 0x42, 0x65, 0x00, 0x95, 0x01, 0x81, 0x01, 0x26, 0xff, 0x00, 0x46, 0xff, 0x00, 0x09,
+        0x30, 0x09, 0x31, 0x09, 0x32, 0x09, 0x35, 0x75, 0x08, 0x95, 0x04, 0x81, 0x02, 0x06, 0x00,
+        0xff, 0x09, 0x20, 0x09, 0x21, 0x09, 0x22, 0x09, 0x23, 0x09, 0x24, 0x09, 0x25, 0x09, 0x26,
+        0x09, 0x27, 0x09, 0x28, 0x09, 0x29, 0x09, 0x2a, 0x09, 0x2b, 0x95, 0x0c, 0x81, 0x02, 0x0a,
+        0x21, 0x26, 0x95, 0x08, 0xb1, 0x02, 0x0a, 0x21, 0x26, 0x91, 0x02, 0x26, 0xff, 0x03, 0x46,
+        0xff, 0x03, 0x09, 0x2c, 0x09, 0x2d, 0x09, 0x2e, 0x09, 0x2f, 0x75, 0x10, 0x95, 0x04, 0x81,
+        0x02, 0xc0,
+    ];
+
+    // Logitech Extreme 3D Pro (046d:c215), written out from its 7 byte report: 10-bit X and Y,
+    // a hat, twist, 8 buttons, the throttle slider and 4 more buttons, no report IDs.
+    const EXTREME_3D_PRO_DESCRIPTOR: [u8; 133] = [
+        0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0xa1, 0x02, 0x75, 0x0a, 0x95, 0x02, 0x15, 0x00, 0x26,
+        0xff, 0x03, 0x35, 0x00, 0x46, 0xff, 0x03, 0x09, 0x30, 0x09, 0x31, 0x81, 0x02, 0x75, 0x04,
+        0x95, 0x01, 0x25, 0x07, 0x46, 0x3b, 0x01, 0x65, 0x14, 0x09, 0x39, 0x81, 0x42, 0x65, 0x00,
+        0x75, 0x08, 0x95, 0x01, 0x26, 0xff, 0x00, 0x46, 0xff, 0x00, 0x09, 0x35, 0x81, 0x02, 0x75,
+        0x01, 0x95, 0x08, 0x25, 0x01, 0x45, 0x01, 0x05, 0x09, 0x19, 0x01, 0x29, 0x08, 0x81, 0x02,
+        0x05, 0x01, 0x75, 0x08, 0x95, 0x01, 0x26, 0xff, 0x00, 0x46, 0xff, 0x00, 0x09, 0x36, 0x81,
+        0x02, 0x75, 0x01, 0x95, 0x04, 0x25, 0x01, 0x45, 0x01, 0x05, 0x09, 0x19, 0x09, 0x29, 0x0c,
+        0x81, 0x02, 0x95, 0x04, 0x81, 0x01, 0xc0, 0xa1, 0x02, 0x26, 0xff, 0x00, 0x46, 0xff, 0x00,
+        0x75, 0x08, 0x95, 0x04, 0x06, 0x00, 0xff, 0x09, 0x01, 0x91, 0x02, 0xc0, 0xc0,
+    ];
+
+    // Thrustmaster T.16000M (044f:b10a), written out from its 9 byte report: 16 buttons, a
+    // hat, 14-bit X and Y in 16 bits each, twist and the throttle slider, no report IDs.
+    const T16000M_DESCRIPTOR: [u8; 82] = [
+        0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0x05, 0x09, 0x19, 0x01, 0x29, 0x10, 0x15, 0x00, 0x25,
+        0x01, 0x35, 0x00, 0x45, 0x01, 0x75, 0x01, 0x95, 0x10, 0x81, 0x02, 0x05, 0x01, 0x09, 0x39,
+        0x25, 0x07, 0x46, 0x3b, 0x01, 0x65, 0x14, 0x75, 0x04, 0x95, 0x01, 0x81, 0x42, 0x65, 0x00,
+        0x81, 0x01, 0x09, 0x30, 0x09, 0x31, 0x26, 0xff, 0x3f, 0x46, 0xff, 0x3f, 0x75, 0x10, 0x95,
+        0x02, 0x81, 0x02, 0x09, 0x35, 0x26, 0xff, 0x00, 0x46, 0xff, 0x00, 0x75, 0x08, 0x95, 0x01,
+        0x81, 0x02, 0x09, 0x36, 0x81, 0x02, 0xc0,
+    ];
+
+    #[test]
+    fn parses_dragonrise_gamepad() {
+        let decoder = ReportDecoder::parse(&DRAGONRISE_DESCRIPTOR).unwrap();
+        assert!(!decoder.has_report_ids);
+        assert_eq!(decoder.axis_count, 5);
+        assert_eq!(decoder.button_count, 12);
+        assert_eq!(decoder.hat_count, 1);
+        // Axes are numbered by usage, not by report order.
+        assert_eq!(
+            decoder.axis_index(USAGE_PAGE_GENERIC_DESKTOP, 0x30),
+            Some(0)
+        );
+        assert_eq!(
+            decoder.axis_index(USAGE_PAGE_GENERIC_DESKTOP, 0x31),
+            Some(1)
+        );
+        assert_eq!(
+            decoder.axis_index(USAGE_PAGE_GENERIC_DESKTOP, 0x34),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn decodes_dragonrise_report() {
+        let decoder = ReportDecoder::parse(&DRAGONRISE_DESCRIPTOR).unwrap();
+        let mut state = decoder.new_state();
+        // X left, Y down, hat up-right, buttons 1 and 12.
+        decoder.decode(
+            &[0x00, 0x80, 0x80, 0xff, 0x80, 0x11, 0x80, 0x00],
+            &mut state,
+        );
+        assert_eq!(state.axes[0], -1.0);
+        assert_eq!(state.axes[1], 1.0);
+        assert_eq!(state.hats[0], HAT_UP | HAT_RIGHT);
+        assert!(state.buttons[0]);
+        assert!(!state.buttons[1]);
+        assert!(state.buttons[11]);
+
+        // The hat's null state is centered.
+        decoder.decode(
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x0f, 0x00, 0x00],
+            &mut state,
+        );
+        assert_eq!(state.hats[0], HAT_CENTERED);
+        assert!(!state.buttons[0]);
+    }
+
+    #[test]
+    fn decodes_reports_by_id() {
+        let decoder = ReportDecoder::parse(&DUALSHOCK3_DESCRIPTOR).unwrap();
+        assert!(decoder.has_report_ids);
+        assert_eq!(
+            (decoder.axis_count, decoder.button_count, decoder.hat_count),
+            (4, 19, 0)
+        );
+        let mut state = decoder.new_state();
+        // Select, PS, left stick left and up, right stick centered.
+        let mut report = [0; 49];
+        report[..10].copy_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x80, 0x80]);
+        decoder.decode(&report, &mut state);
+        assert!(state.buttons[0]);
+        assert!(state.buttons[16]);
+        assert_eq!(state.buttons.iter().filter(|b| **b).count(), 2);
+        assert_eq!(state.axes[..2], [-1.0, -1.0]);
+        assert!(state.axes[2..].iter().all(|axis| axis.abs() < 0.01));
+        // Reports with another ID are ignored.
+        report[0] = 0x02;
+        report[2] = 0x00;
+        decoder.decode(&report, &mut state);
+        assert!(state.buttons[0]);
+    }
+
+    #[test]
+    fn decodes_arcade_stick_report() {
+        let decoder = ReportDecoder::parse(&HORI_FIGHTING_STICK_DESCRIPTOR).unwrap();
+        assert!(!decoder.has_report_ids);
+        assert_eq!(
+            (decoder.axis_count, decoder.button_count, decoder.hat_count),
+            (4, 13, 1)
+        );
+        let mut state = decoder.new_state();
+        // Buttons 1 and 13, hat left, stick down.
+        let mut report = [0; 27];
+        report[..7].copy_from_slice(&[0x01, 0x10, 0x06, 0x80, 0xff, 0x80, 0x80]);
+        decoder.decode(&report, &mut state);
+        assert!(state.buttons[0]);
+        assert!(state.buttons[12]);
+        assert_eq!(state.buttons.iter().filter(|b| **b).count(), 2);
+        assert_eq!(state.hats[0], HAT_LEFT);
+        assert_eq!(state.axes[1], 1.0);
+    }
+
+    #[test]
+    fn decodes_flight_stick_reports() {
+        let decoder = ReportDecoder::parse(&EXTREME_3D_PRO_DESCRIPTOR).unwrap();
+        assert_eq!(
+            (decoder.axis_count, decoder.button_count, decoder.hat_count),
+            (4, 12, 1)
+        );
+        assert_eq!(
+            decoder.axis_index(USAGE_PAGE_GENERIC_DESKTOP, 0x36),
+            Some(3)
+        );
+        let mut state = decoder.new_state();
+        // Full right and forward, hat down, twist and throttle at their minimum, the trigger
+        // and button 12.
+        decoder.decode(&[0xff, 0x03, 0x40, 0x00, 0x01, 0x00, 0x08], &mut state);
+        assert_eq!(state.axes, [1.0, -1.0, -1.0, -1.0]);
+        assert_eq!(state.hats[0], HAT_DOWN);
+        assert!(state.buttons[0]);
+        assert!(state.buttons[11]);
+        assert_eq!(state.buttons.iter().filter(|b| **b).count(), 2);
+
+        let decoder = ReportDecoder::parse(&T16000M_DESCRIPTOR).unwrap();
+        assert_eq!(
+            (decoder.axis_count, decoder.button_count, decoder.hat_count),
+            (4, 16, 1)
+        );
+        let mut state = decoder.new_state();
+        // Buttons 1 and 16, hat right, full left and back, throttle at its maximum.
+        decoder.decode(
+            &[0x01, 0x80, 0x02, 0x00, 0x00, 0xff, 0x3f, 0x80, 0xff],
+            &mut state,
+        );
+        assert_eq!(state.axes[..2], [-1.0, 1.0]);
+        assert_eq!(state.axes[3], 1.0);
+        assert_eq!(state.hats[0], HAT_RIGHT);
+        assert!(state.buttons[0]);
+        assert!(state.buttons[15]);
+    }
+
+    #[test]
+    fn bounds_huge_report_counts() {
+        // A gamepad declaring 0xffffffff one bit buttons.
+        let descriptor = [
+            0x05, 0x01, 0x09, 0x05, 0xa1, 0x01, 0x05, 0x09, 0x19, 0x01, 0x29, 0x08, 0x15, 0x00,
+            0x25, 0x01, 0x75, 0x01, 0x97, 0xff, 0xff, 0xff, 0xff, 0x81, 0x02, 0xc0,
+        ];
+        let decoder = ReportDecoder::parse(&descriptor).unwrap();
+        assert_eq!(decoder.fields.len(), MAX_REPORT_SIZE * 8);
+    }
+
+    #[test]
+    fn rejects_truncated_and_non_gamepad_descriptors() {
+        assert!(ReportDecoder::parse(&DRAGONRISE_DESCRIPTOR[..21]).is_none());
+        // A mouse.
+        assert!(ReportDecoder::parse(&[0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0xc0]).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+/**
+ * Deadzones and response curves of sticks, applied to calibrated axes in `-1.0..=1.0`: first
+ * the inner and outer deadzone in the configured shape, then the curve, then the
+ * anti-deadzone, which skips the deadzone a game applies on its own.
+ *
+ * ref:
+ *  https://www.gamedeveloper.com/business/doing-thumbstick-dead-zones-right
+ *  https://github.com/Minimuino/thumbstick-deadzones
+ */
+use crate::{DeadzoneShape, ResponseCurve, StickResponse};
+use std::borrow::Cow;
+
+// Travel left between a grown inner deadzone and the outer one.
+const MIN_ACTIVE_RANGE: f32 = 0.05;
+
+#[derive(Debug, Clone)]
+enum Curve {
+    Linear,
+    Power(f32),
+    Sigmoid(f32),
+    // Outputs for evenly spaced inputs from 0 to 1.
+    Lookup(Vec<f32>),
+}
+
+impl Curve {
+    fn apply(&self, value: f32) -> f32 {
+        match self {
+            Curve::Linear => value,
+            Curve::Power(exponent) => value.powf(*exponent),
+            Curve::Sigmoid(exponent) if value < 0.5 => 0.5 * (2.0 * value).powf(*exponent),
+            Curve::Sigmoid(exponent) => 1.0 - 0.5 * (2.0 - 2.0 * value).powf(*exponent),
+            Curve::Lookup(points) => {
+                let position = value * (points.len() - 1) as f32;
+                let i = (position as usize).min(points.len() - 2);
+                let t = position - i as f32;
+                points[i] + (points[i + 1] - points[i]) * t
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    shape: DeadzoneShape,
+    inner: f32,
+    outer: f32,
+    anti: f32,
+    curve: Curve,
+}
+
+impl Response {
+    /// `None` if the settings are out of range.
+    pub fn new(settings: &StickResponse) -> Option<Self> {
+        let shape = DeadzoneShape::try_from(settings.deadzone_shape).ok()?;
+        let StickResponse {
+            inner_deadzone: inner,
+            outer_deadzone: outer,
+            anti_deadzone: anti,
+            exponent,
+            ..
+        } = *settings;
+        if !(0.0 <= inner && inner < outer && outer <= 1.0 && (0.0..1.0).contains(&anti)) {
+            return None;
+        }
+        let curve = match ResponseCurve::try_from(settings.curve).ok()? {
+            ResponseCurve::Linear => Curve::Linear,
+            ResponseCurve::Power if exponent > 0.0 && exponent.is_finite() => {
+                Curve::Power(exponent)
+            }
+            ResponseCurve::SCurve if exponent > 0.0 && exponent.is_finite() => {
+                Curve::Sigmoid(exponent)
+            }
+            ResponseCurve::Lookup => {
+                let len = settings.lookup_len as usize;
+                let points = settings.lookup.get(..len)?;
+                if len < 2 || points.iter().any(|p| !(0.0..=1.0).contains(p)) {
+                    return None;
+                }
+                Curve::Lookup(points.to_vec())
+            }
+            _ => return None,
+        };
+        Some(Response {
+            shape,
+            inner,
+            outer,
+            anti,
+            curve,
+        })
+    }
+
+    /// Zero inside `inner`, everything else unchanged, like the factory deadzone of Nintendo
+    /// controllers.
+    pub fn radial(inner: f32) -> Self {
+        Response {
+            shape: DeadzoneShape::Radial,
+            inner,
+            outer: 1.0,
+            anti: 0.0,
+            curve: Curve::Linear,
+        }
+    }
+
+    /// With the inner deadzone grown to `inner` if that's larger, short of the outer one.
+    pub fn with_inner_deadzone(&self, inner: f32) -> Cow<'_, Self> {
+        let inner = inner.min(self.outer - MIN_ACTIVE_RANGE);
+        if inner <= self.inner {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Response {
+            inner,
+            ..self.clone()
+        })
+    }
+
+    // From between the deadzones to `0.0..=1.0`.
+    fn rescale(&self, value: f32) -> f32 {
+        ((value - self.inner) / (self.outer - self.inner)).clamp(0.0, 1.0)
+    }
+
+    fn output(&self, value: f32) -> f32 {
+        self.anti + (1.0 - self.anti) * self.curve.apply(value.clamp(0.0, 1.0))
+    }
+
+    fn axis(&self, value: f32) -> f32 {
+        let distance = value.abs();
+        if distance < self.inner {
+            return 0.0;
+        }
+        value.signum() * self.output(self.rescale(distance))
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let magnitude = x.hypot(y);
+        if magnitude == 0.0 || magnitude < self.inner {
+            return (0.0, 0.0);
+        }
+        let (x, y) = match self.shape {
+            // Past the outer deadzone both axes keep growing until they're clamped, which keeps
+            // the corners of square gates.
+            DeadzoneShape::Radial => {
+                let distance = (magnitude / self.outer).min(1.0);
+                let factor = self.output(distance) / magnitude.min(self.outer);
+                (x * factor, y * factor)
+            }
+            DeadzoneShape::ScaledRadial => {
+                let factor = self.output(self.rescale(magnitude)) / magnitude;
+                (x * factor, y * factor)
+            }
+            DeadzoneShape::Axial => (self.axis(x), self.axis(y)),
+            DeadzoneShape::Hybrid => {
+                let factor = self.output(self.rescale(magnitude)) / magnitude;
+                let (x, y) = (x * factor, y * factor);
+                // Sloped axial deadzone: snaps to an axis when close to it, wider further out.
+                (
+                    if x.abs() < self.inner * y.abs() {
+                        0.0
+                    } else {
+                        x
+                    },
+                    if y.abs() < self.inner * x.abs() {
+                        0.0
+                    } else {
+                        y
+                    },
+                )
+            }
+        };
+        (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0))
+    }
+}
@@ -0,0 +1,219 @@
+/**
+ * Ring-Con of Ring Fit Adventure, attached to the rail of a right Joy-Con: switches the MCU
+ * into external device mode, identifies the Ring-Con, reads its flex sensor calibration from
+ * its EEPROM and enables polling, which puts the flex sensor into the standard full reports.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+ *  https://github.com/CTCaer/jc_toolkit/blob/master/jctool/jctool.cpp
+ */
+use crate::mcu::{self, McuMode, McuRequest, McuState, MAX_RETRIES, RETRY_INTERVAL};
+use crate::SubcommandID;
+use std::time::Instant;
+
+// Reply data of `SubcommandID::GetExternalDeviceInfo`.
+const DEVICE_ID_OFFSET: usize = 1;
+const RING_CON_ID: u8 = 0x20;
+
+// Polling setup of the Ring-Con, sent with `SubcommandID::SetExternalDeviceConfig`.
+const RING_CON_CONFIG: [u8; 38] = [
+    0x06, 0x03, 0x25, 0x06, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x16, 0xed, 0x34, 0x36, 0x00, 0x00, 0x00,
+    0x0a, 0x64, 0x0b, 0xe6, 0xa9, 0x22, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x90, 0xa8, 0xe1, 0x34, 0x36, 0x00,
+];
+const POLLING_ARGS: [u8; 4] = [0x04, 0x01, 0x01, 0x02];
+
+// EEPROM read of the flex calibration: command, address and length.
+const READ_CALIBRATION_ARGS: [u8; 4] = [0x04, 0x1a, 0x00, 0x03];
+// Neutral, fully pulled and fully squeezed flex values in the reply data.
+const CALIBRATION_OFFSET: usize = 4;
+
+// Flex sensor value in a full report, it replaces part of the last IMU sample.
+const FLEX_OFFSET: usize = 40;
+
+#[derive(Debug, Clone, Copy)]
+struct FlexCalibration {
+    neutral: u8,
+    pull: u8,
+    push: u8,
+}
+
+// Used when the EEPROM can't be read or holds nonsense.
+const DEFAULT_CALIBRATION: FlexCalibration = FlexCalibration {
+    neutral: 10,
+    pull: 0,
+    push: 20,
+};
+
+impl FlexCalibration {
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        let &[neutral, pull, push] = data.get(CALIBRATION_OFFSET..CALIBRATION_OFFSET + 3)? else {
+            return None;
+        };
+        (pull < neutral && neutral < push).then_some(FlexCalibration {
+            neutral,
+            pull,
+            push,
+        })
+    }
+
+    // `-1.0..=1.0`, squeezing is positive.
+    fn normalize(&self, value: u8) -> f32 {
+        let value = value as f32;
+        let neutral = self.neutral as f32;
+        let flex = if value >= neutral {
+            (value - neutral) / (self.push as f32 - neutral)
+        } else {
+            (value - neutral) / (neutral - self.pull as f32)
+        };
+        flex.clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Step {
+    ResumeMcu,
+    SetExternalMode,
+    Identify,
+    Configure,
+    ReadCalibration,
+    EnablePolling,
+    Polling,
+    // Shutdown when no Ring-Con is attached or the setup failed.
+    SuspendMcu,
+    Done,
+}
+
+pub struct RingCon {
+    step: Step,
+    sent_at: Option<Instant>,
+    retries: u8,
+    calibration: FlexCalibration,
+    flex: f32,
+}
+
+impl Default for RingCon {
+    fn default() -> Self {
+        RingCon {
+            step: Step::ResumeMcu,
+            sent_at: None,
+            retries: 0,
+            calibration: DEFAULT_CALIBRATION,
+            flex: 0.0,
+        }
+    }
+}
+
+impl RingCon {
+    /// Whether detection gave up and the MCU is back to sleep.
+    pub fn is_done(&self) -> bool {
+        self.step == Step::Done
+    }
+
+    /// The normalized flex strength once a Ring-Con is polled.
+    pub fn flex(&self) -> Option<f32> {
+        (self.step == Step::Polling).then_some(self.flex)
+    }
+
+    fn advance(&mut self, step: Step) {
+        self.step = step;
+        self.sent_at = None;
+        self.retries = 0;
+    }
+
+    /// Returns the report to send next, if any.
+    pub fn update(&mut self) -> Option<McuRequest> {
+        if matches!(self.step, Step::Polling | Step::Done) {
+            return None;
+        }
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() < RETRY_INTERVAL {
+                return None;
+            }
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                match self.step {
+                    // The calibration is optional.
+                    Step::ReadCalibration => self.advance(Step::EnablePolling),
+                    Step::SuspendMcu => {
+                        self.advance(Step::Done);
+                        return None;
+                    }
+                    _ => self.advance(Step::SuspendMcu),
+                }
+            }
+        }
+        self.sent_at = Some(Instant::now());
+        Some(self.request())
+    }
+
+    fn request(&self) -> McuRequest {
+        match self.step {
+            Step::ResumeMcu => mcu::set_state_request(McuState::Resume),
+            Step::SetExternalMode => mcu::set_mode_request(McuMode::External),
+            Step::Identify => McuRequest::Subcommand(SubcommandID::GetExternalDeviceInfo, vec![]),
+            Step::Configure => McuRequest::Subcommand(
+                SubcommandID::SetExternalDeviceConfig,
+                RING_CON_CONFIG.to_vec(),
+            ),
+            Step::ReadCalibration => McuRequest::Subcommand(
+                SubcommandID::ReadExternalDevice,
+                READ_CALIBRATION_ARGS.to_vec(),
+            ),
+            Step::EnablePolling => {
+                McuRequest::Subcommand(SubcommandID::EnableExternalPolling, POLLING_ARGS.to_vec())
+            }
+            Step::SuspendMcu => mcu::set_state_request(McuState::Suspend),
+            Step::Polling | Step::Done => unreachable!(),
+        }
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        let Ok(id) = SubcommandID::try_from(id) else {
+            return;
+        };
+        match (self.step, id) {
+            // Unanswered steps are sent again until `MAX_RETRIES`.
+            (_, _) if !ack => {}
+            (Step::ResumeMcu, SubcommandID::SetMcuState) => self.advance(Step::SetExternalMode),
+            // The reply carries the MCU status, which takes a moment to switch modes.
+            (Step::SetExternalMode, SubcommandID::SetMcuConfig)
+                if mcu::status_mode(data) == Some(McuMode::External) =>
+            {
+                self.advance(Step::Identify)
+            }
+            (Step::Identify, SubcommandID::GetExternalDeviceInfo) => {
+                if data.get(DEVICE_ID_OFFSET) == Some(&RING_CON_ID) {
+                    self.advance(Step::Configure);
+                } else {
+                    self.advance(Step::SuspendMcu);
+                }
+            }
+            (Step::Configure, SubcommandID::SetExternalDeviceConfig) => {
+                self.advance(Step::ReadCalibration)
+            }
+            (Step::ReadCalibration, SubcommandID::ReadExternalDevice) => {
+                if let Some(calibration) = FlexCalibration::from_bytes(data) {
+                    self.calibration = calibration;
+                }
+                self.advance(Step::EnablePolling);
+            }
+            (Step::EnablePolling, SubcommandID::EnableExternalPolling) => {
+                self.advance(Step::Polling)
+            }
+            (Step::SuspendMcu, SubcommandID::SetMcuState) => self.advance(Step::Done),
+            _ => {}
+        }
+    }
+
+    /// Handles a `FullControllerState` report.
+    pub fn on_input_report(&mut self, report: &[u8]) {
+        if self.step != Step::Polling {
+            return;
+        }
+        if let Some(&value) = report.get(FLEX_OFFSET) {
+            self.flex = self.calibration.normalize(value);
+        }
+    }
+}
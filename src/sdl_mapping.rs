@@ -0,0 +1,493 @@
+/**
+ * SDL GameControllerDB mapping support for generic controllers.
+ *
+ * ref:
+ *  https://github.com/mdqinc/SDL_GameControllerDB
+ *  https://github.com/libsdl-org/SDL/blob/SDL2/src/joystick/SDL_gamecontroller.c
+ */
+use crate::report_descriptor::GenericState;
+use crate::{Button, Gamepad};
+use std::collections::HashMap;
+
+const SDL_HARDWARE_BUS_UNKNOWN: u16 = 0x00;
+const SDL_HARDWARE_BUS_USB: u16 = 0x03;
+const SDL_HARDWARE_BUS_BLUETOOTH: u16 = 0x05;
+
+#[cfg(target_os = "windows")]
+const PLATFORM: &str = "Windows";
+#[cfg(target_os = "macos")]
+const PLATFORM: &str = "Mac OS X";
+#[cfg(target_os = "linux")]
+const PLATFORM: &str = "Linux";
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+const PLATFORM: &str = "";
+
+// Standard gamepad layout, see https://w3c.github.io/gamepad/#remapping
+const BUTTON_NAMES: [(&str, usize); 23] = [
+    ("a", 0),
+    ("b", 1),
+    ("x", 2),
+    ("y", 3),
+    ("leftshoulder", 4),
+    ("rightshoulder", 5),
+    ("lefttrigger", 6),
+    ("righttrigger", 7),
+    ("back", 8),
+    ("start", 9),
+    ("leftstick", 10),
+    ("rightstick", 11),
+    ("dpup", 12),
+    ("dpdown", 13),
+    ("dpleft", 14),
+    ("dpright", 15),
+    ("guide", 16),
+    ("paddle1", 17),
+    ("paddle2", 18),
+    ("paddle3", 19),
+    ("paddle4", 20),
+    ("misc1", 21),
+    ("touchpad", 22),
+];
+
+const AXIS_NAMES: [(&str, usize); 4] = [("leftx", 0), ("lefty", 1), ("rightx", 2), ("righty", 3)];
+
+// Trigger buttons keep their analog value.
+const ANALOG_BUTTONS: [usize; 2] = [6, 7];
+
+const BUTTON_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HalfAxis {
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingInput {
+    Button(usize),
+    Axis {
+        index: usize,
+        half: Option<HalfAxis>,
+        invert: bool,
+    },
+    Hat {
+        index: usize,
+        mask: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingOutput {
+    Button(usize),
+    Axis {
+        index: usize,
+        half: Option<HalfAxis>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub guid: String,
+    pub name: String,
+    pub bindings: Vec<(MappingOutput, MappingInput)>,
+}
+
+/// Builds the SDL2 joystick GUID string of a device. The name CRC is left zero,
+/// matching how entries are looked up in `MappingDatabase`.
+pub fn create_guid(bus: hidapi::BusType, vendor_id: u16, product_id: u16, version: u16) -> String {
+    let bus = match bus {
+        hidapi::BusType::Usb => SDL_HARDWARE_BUS_USB,
+        hidapi::BusType::Bluetooth => SDL_HARDWARE_BUS_BLUETOOTH,
+        _ => SDL_HARDWARE_BUS_UNKNOWN,
+    };
+    let words = [bus, 0, vendor_id, 0, product_id, 0, version, 0];
+    words
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Clears the name CRC (bytes 2..4) of a GUID string, and the version (bytes 12..14)
+/// if `version` is false.
+fn normalize_guid(guid: &str, version: bool) -> Option<String> {
+    if guid.len() != 32 || !guid.is_ascii() {
+        return None;
+    }
+    let mut guid = guid.to_ascii_lowercase();
+    guid.replace_range(4..8, "0000");
+    if !version {
+        guid.replace_range(24..28, "0000");
+    }
+    Some(guid)
+}
+
+fn parse_input(value: &str) -> Option<MappingInput> {
+    let (half, value) = match value.as_bytes().first()? {
+        b'+' => (Some(HalfAxis::Positive), &value[1..]),
+        b'-' => (Some(HalfAxis::Negative), &value[1..]),
+        _ => (None, value),
+    };
+    let (value, invert) = match value.strip_suffix('~') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+
+    match value.as_bytes().first()? {
+        b'b' => Some(MappingInput::Button(value[1..].parse().ok()?)),
+        b'a' => Some(MappingInput::Axis {
+            index: value[1..].parse().ok()?,
+            half,
+            invert,
+        }),
+        b'h' => {
+            let (index, mask) = value[1..].split_once('.')?;
+            Some(MappingInput::Hat {
+                index: index.parse().ok()?,
+                mask: mask.parse().ok()?,
+            })
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_output(key: &str) -> Option<MappingOutput> {
+    let (half, key) = match key.as_bytes().first()? {
+        b'+' => (Some(HalfAxis::Positive), &key[1..]),
+        b'-' => (Some(HalfAxis::Negative), &key[1..]),
+        _ => (None, key),
+    };
+    if let Some((_, index)) = AXIS_NAMES.iter().find(|(name, _)| *name == key) {
+        return Some(MappingOutput::Axis {
+            index: *index,
+            half,
+        });
+    }
+    BUTTON_NAMES
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, index)| MappingOutput::Button(*index))
+}
+
+impl Mapping {
+    /// Parses one `GUID,name,key:value,...` mapping line. Returns `None` for malformed
+    /// lines and for mappings meant for another platform.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.trim().split(',');
+        let guid = parts.next()?.trim().to_string();
+        let name = parts.next()?.trim().to_string();
+        normalize_guid(&guid, true)?;
+
+        let mut bindings = Vec::new();
+        for part in parts {
+            let Some((key, value)) = part.trim().split_once(':') else {
+                continue;
+            };
+            if key == "platform" {
+                if value != PLATFORM {
+                    return None;
+                }
+                continue;
+            }
+            // Unknown keys such as `crc` or `hint` are ignored.
+            if let (Some(output), Some(input)) = (parse_output(key), parse_input(value)) {
+                bindings.push((output, input));
+            }
+        }
+
+        Some(Mapping {
+            guid,
+            name,
+            bindings,
+        })
+    }
+
+    /// Maps the raw state of a generic device onto the standard gamepad layout.
+    pub fn apply(&self, state: &GenericState, gamepad: &mut Gamepad) {
+        *gamepad = Gamepad {
+            index: gamepad.index,
+            ..Default::default()
+        };
+
+        for (output, input) in &self.bindings {
+            // `value` is in -1.0..=1.0 for full axes and 0.0..=1.0 otherwise.
+            let (value, full_axis) = match *input {
+                MappingInput::Button(index) => {
+                    let pressed = state.buttons.get(index).copied().unwrap_or(false);
+                    (if pressed { 1.0 } else { 0.0 }, false)
+                }
+                MappingInput::Hat { index, mask } => {
+                    let hat = state.hats.get(index).copied().unwrap_or(0);
+                    (if hat & mask != 0 { 1.0 } else { 0.0 }, false)
+                }
+                MappingInput::Axis {
+                    index,
+                    half,
+                    invert,
+                } => {
+                    let mut value = state.axes.get(index).copied().unwrap_or(0.0);
+                    if invert {
+                        value = -value;
+                    }
+                    match half {
+                        None => (value, true),
+                        Some(HalfAxis::Positive) => (value.max(0.0), false),
+                        Some(HalfAxis::Negative) => ((-value).max(0.0), false),
+                    }
+                }
+            };
+            let normalized = if full_axis {
+                (value + 1.0) / 2.0
+            } else {
+                value
+            };
+
+            match *output {
+                MappingOutput::Button(index) => {
+                    gamepad.buttons[index] = if ANALOG_BUTTONS.contains(&index) {
+                        Button::analog(normalized)
+                    } else {
+                        Button::digital(normalized > BUTTON_THRESHOLD)
+                    };
+                }
+                MappingOutput::Axis { index, half: None } => {
+                    gamepad.axes[index] = if full_axis {
+                        value
+                    } else {
+                        normalized * 2.0 - 1.0
+                    };
+                }
+                MappingOutput::Axis {
+                    index,
+                    half: Some(half),
+                } => {
+                    let value = if half == HalfAxis::Positive {
+                        normalized
+                    } else {
+                        -normalized
+                    };
+                    gamepad.axes[index] = (gamepad.axes[index] + value).clamp(-1.0, 1.0);
+                }
+            }
+        }
+    }
+}
+
+/// Mappings keyed by their normalized GUID.
+#[derive(Debug, Default)]
+pub struct MappingDatabase {
+    mappings: HashMap<String, Mapping>,
+}
+
+impl MappingDatabase {
+    /// Adds every valid mapping in `text` (in `gamecontrollerdb.txt` format), replacing
+    /// existing mappings for the same GUID. Returns the number of mappings added.
+    pub fn add_mappings(&mut self, text: &str) -> usize {
+        let mut count = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(mapping) = Mapping::parse(line) {
+                let guid = normalize_guid(&mapping.guid, true).unwrap();
+                self.mappings.insert(guid, mapping);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Looks up a mapping by GUID, falling back to an entry without the version.
+    pub fn find(&self, guid: &str) -> Option<&Mapping> {
+        let exact = normalize_guid(guid, true)?;
+        let without_version = normalize_guid(guid, false)?;
+        self.mappings
+            .get(&exact)
+            .or_else(|| self.mappings.get(&without_version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The Linux entries of the wired Xbox 360 controller and the DualShock 4 in
+    // gamecontrollerdb.txt.
+    const XBOX_360: &str = "030000005e0400008e02000010010000,Xbox 360 Controller,a:b0,b:b1,\
+        back:b6,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,guide:b8,leftshoulder:b4,\
+        leftstick:b9,lefttrigger:a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b10,\
+        righttrigger:a5,rightx:a3,righty:a4,start:b7,x:b2,y:b3,platform:Linux,";
+    const PS4: &str = "030000004c050000c405000011810000,PS4 Controller,a:b0,b:b1,back:b8,\
+        dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,guide:b10,leftshoulder:b4,\
+        leftstick:b11,lefttrigger:a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b12,\
+        righttrigger:a5,rightx:a3,righty:a4,start:b9,x:b3,y:b2,platform:Linux,";
+    // Like the entries of DragonRise encoders, whose d-pad is a pair of axes, with the
+    // y axis upside down.
+    const DRAGONRISE: &str = "03000000790000000600000010010000,DragonRise Inc. Generic USB \
+        Joystick,a:b2,b:b1,back:b8,dpdown:+a4,dpleft:-a3,dpright:+a3,dpup:-a4,\
+        leftshoulder:b4,lefttrigger:b6,leftx:a0,lefty:a1~,rightshoulder:b5,righttrigger:b7,\
+        start:b9,x:b3,y:b0,";
+
+    fn for_this_platform(line: &str) -> String {
+        line.replace("platform:Linux,", "") + &format!("platform:{PLATFORM},")
+    }
+
+    fn binding(mapping: &Mapping, output: MappingOutput) -> Option<MappingInput> {
+        mapping
+            .bindings
+            .iter()
+            .find_map(|(o, input)| (*o == output).then_some(*input))
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let mapping = Mapping::parse(&for_this_platform(XBOX_360)).unwrap();
+        assert_eq!(mapping.name, "Xbox 360 Controller");
+        assert_eq!(mapping.bindings.len(), 21);
+        assert_eq!(
+            binding(&mapping, MappingOutput::Button(0)),
+            Some(MappingInput::Button(0))
+        );
+        assert_eq!(
+            binding(
+                &mapping,
+                MappingOutput::Axis {
+                    index: 0,
+                    half: None
+                }
+            ),
+            Some(MappingInput::Axis {
+                index: 0,
+                half: None,
+                invert: false
+            })
+        );
+        assert_eq!(
+            binding(&mapping, MappingOutput::Button(12)),
+            Some(MappingInput::Hat { index: 0, mask: 1 })
+        );
+
+        let mapping = Mapping::parse(DRAGONRISE).unwrap();
+        assert_eq!(
+            binding(&mapping, MappingOutput::Button(13)),
+            Some(MappingInput::Axis {
+                index: 4,
+                half: Some(HalfAxis::Positive),
+                invert: false
+            })
+        );
+        assert_eq!(
+            binding(&mapping, MappingOutput::Button(14)),
+            Some(MappingInput::Axis {
+                index: 3,
+                half: Some(HalfAxis::Negative),
+                invert: false
+            })
+        );
+        assert_eq!(
+            binding(
+                &mapping,
+                MappingOutput::Axis {
+                    index: 1,
+                    half: None
+                }
+            ),
+            Some(MappingInput::Axis {
+                index: 1,
+                half: None,
+                invert: true
+            })
+        );
+    }
+
+    #[test]
+    fn parses_half_axis_outputs() {
+        assert_eq!(
+            parse_output("+rightx"),
+            Some(MappingOutput::Axis {
+                index: 2,
+                half: Some(HalfAxis::Positive)
+            })
+        );
+        assert_eq!(parse_output("touchpad"), Some(MappingOutput::Button(22)));
+        assert_eq!(parse_output("crc"), None);
+        assert_eq!(parse_input("h0"), None);
+        assert_eq!(parse_input("x1"), None);
+    }
+
+    #[test]
+    fn skips_other_platforms() {
+        let windows = XBOX_360.replace("platform:Linux", "platform:Windows");
+        let android = XBOX_360.replace("platform:Linux", "platform:Android");
+        assert!(Mapping::parse(&for_this_platform(XBOX_360)).is_some());
+        assert!(PLATFORM == "Windows" || Mapping::parse(&windows).is_none());
+        assert!(Mapping::parse(&android).is_none());
+        assert!(Mapping::parse("030000005e04,Too Short,a:b0").is_none());
+    }
+
+    #[test]
+    fn applies_bindings() {
+        let mut gamepad = Gamepad::default();
+        let mapping = Mapping::parse(&for_this_platform(XBOX_360)).unwrap();
+        let state = GenericState {
+            axes: vec![0.5, -0.25, 0.0, 0.0, 0.0, 1.0],
+            buttons: vec![true, false, false, false, false, false, false, true],
+            hats: vec![0x1],
+        };
+        mapping.apply(&state, &mut gamepad);
+        assert!(gamepad.buttons[0].pressed);
+        assert!(gamepad.buttons[9].pressed);
+        assert!(gamepad.buttons[12].pressed);
+        assert!(!gamepad.buttons[13].pressed);
+        assert_eq!(gamepad.axes[..2], [0.5, -0.25]);
+        assert_eq!(gamepad.buttons[6].value, 0.5);
+        assert_eq!(gamepad.buttons[7].value, 1.0);
+
+        let mapping = Mapping::parse(DRAGONRISE).unwrap();
+        let state = GenericState {
+            axes: vec![0.5, -0.25, 0.0, 1.0, -1.0],
+            buttons: vec![false, false, true],
+            hats: Vec::new(),
+        };
+        mapping.apply(&state, &mut gamepad);
+        assert!(gamepad.buttons[0].pressed);
+        assert!(gamepad.buttons[12].pressed);
+        assert!(gamepad.buttons[15].pressed);
+        assert!(!gamepad.buttons[13].pressed);
+        assert!(!gamepad.buttons[14].pressed);
+        assert_eq!(gamepad.axes[..2], [0.5, 0.25]);
+    }
+
+    #[test]
+    fn creates_guids() {
+        // Wired Xbox 360 controller, firmware 1.10.
+        let guid = create_guid(hidapi::BusType::Usb, 0x045e, 0x028e, 0x0110);
+        assert_eq!(guid, &XBOX_360[..32]);
+        let guid = create_guid(hidapi::BusType::Bluetooth, 0x054c, 0x05c4, 0x8111);
+        assert_eq!(guid, "050000004c050000c405000011810000");
+    }
+
+    #[test]
+    fn finds_mappings_without_version() {
+        let mut database = MappingDatabase::default();
+        let without_version = for_this_platform(PS4).replacen("11810000", "00000000", 1);
+        assert_eq!(database.add_mappings(&without_version), 1);
+        let guid = create_guid(hidapi::BusType::Usb, 0x054c, 0x05c4, 0x8111);
+        assert_eq!(database.find(&guid).unwrap().guid, &without_version[..32]);
+
+        // An exact match wins over the entry without version.
+        let text = format!(
+            "# PS4\n\n{}\n",
+            for_this_platform(PS4).replace("PS4", "DS4")
+        );
+        assert_eq!(database.add_mappings(&text), 1);
+        assert_eq!(database.find(&guid).unwrap().name, "DS4 Controller");
+        let other = create_guid(hidapi::BusType::Usb, 0x054c, 0x05c4, 0x0100);
+        assert_eq!(database.find(&other).unwrap().name, "PS4 Controller");
+        // The name CRC of SDL 2.0.16 GUIDs is ignored.
+        let with_crc = format!("0300a3f1{}", &guid[8..]);
+        assert!(database.find(&with_crc).is_some());
+        assert!(database.find("030000005e0400008e02").is_none());
+    }
+}
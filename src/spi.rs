@@ -0,0 +1,580 @@
+/**
+ * SPI flash of Nintendo controllers: verified writes through `SubcommandID::SPIFlashWrite`,
+ * dumps of the whole flash in `SubcommandID::SPIFlashRead` sized chunks, and restores of the
+ * regions a Switch writes to itself.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+ */
+use crate::{ReadSpiPacket, SPIAddress, SpiProgress, SubcommandID};
+use std::time::{Duration, Instant};
+use zerocopy::{AsBytes, FromBytes};
+
+pub const FLASH_SIZE: usize = 0x80000;
+// Data of a single `SubcommandID::SPIFlashWrite` or `SubcommandID::SPIFlashRead`.
+pub const MAX_WRITE_SIZE: usize = 0x1d;
+const MAX_READ_SIZE: usize = 0x1d;
+
+// Address and length before the data of write requests and read replies.
+const HEADER_SIZE: usize = std::mem::size_of::<ReadSpiPacket>();
+
+const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_RETRIES: u8 = 5;
+
+// (address, length) of what `SpiRestore` writes: the user stick and IMU calibration. Factory
+// data, serial number and firmware are never written.
+const RESTORE_REGIONS: [(u32, usize); 1] = [(SPIAddress::UserStickCalibration as u32, 0x30)];
+// (address, length) a dump has to share with the controller it's restored to: the serial number
+// and the factory stick calibration.
+const IDENTITY_REGIONS: [(u32, usize); 2] = [
+    (SPIAddress::SerialNumber as u32, 16),
+    (SPIAddress::AnalogStickCalibration as u32, 18),
+];
+
+// `SpiProgress::state`.
+const PROGRESS_RUNNING: u8 = 0;
+const PROGRESS_DONE: u8 = 1;
+const PROGRESS_FAILED: u8 = 2;
+const PROGRESS_MISMATCH: u8 = 3;
+
+fn read_request(address: u32, length: usize) -> (SubcommandID, Vec<u8>) {
+    let packet = ReadSpiPacket {
+        address,
+        length: length as u8,
+    };
+    (SubcommandID::SPIFlashRead, packet.as_bytes().to_vec())
+}
+
+// The address and data of a `SubcommandID::SPIFlashRead` reply.
+fn read_reply(data: &[u8]) -> Option<(u32, &[u8])> {
+    let packet = ReadSpiPacket::read_from_prefix(data)?;
+    let read = data.get(HEADER_SIZE..HEADER_SIZE + packet.length as usize)?;
+    Some((packet.address, read))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Write,
+    Verify,
+    Done,
+}
+
+pub struct SpiWrite {
+    address: u32,
+    data: Vec<u8>,
+    step: Step,
+    sent_at: Option<Instant>,
+    retries: u8,
+    // Set once the data was verified or the write failed.
+    result: Option<bool>,
+}
+
+impl SpiWrite {
+    pub fn new(address: u32, data: Vec<u8>) -> Self {
+        debug_assert!(data.len() <= MAX_WRITE_SIZE);
+        SpiWrite {
+            address,
+            data,
+            step: Step::Write,
+            sent_at: None,
+            retries: 0,
+            result: None,
+        }
+    }
+
+    /// Whether the data was written and read back, `None` while in progress.
+    pub fn result(&self) -> Option<bool> {
+        self.result
+    }
+
+    fn advance(&mut self, step: Step) {
+        self.step = step;
+        self.sent_at = None;
+        self.retries = 0;
+    }
+
+    fn finish(&mut self, ok: bool) {
+        self.result = Some(ok);
+        self.advance(Step::Done);
+    }
+
+    /// Returns the subcommand to send next, if any.
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        if self.step == Step::Done {
+            return None;
+        }
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() < REPLY_TIMEOUT {
+                return None;
+            }
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                self.finish(false);
+                return None;
+            }
+        }
+        self.sent_at = Some(Instant::now());
+        match self.step {
+            Step::Write => {
+                let mut args = self.address.to_le_bytes().to_vec();
+                args.push(self.data.len() as u8);
+                args.extend_from_slice(&self.data);
+                Some((SubcommandID::SPIFlashWrite, args))
+            }
+            Step::Verify => Some(read_request(self.address, self.data.len())),
+            Step::Done => unreachable!(),
+        }
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        let Ok(id) = SubcommandID::try_from(id) else {
+            return;
+        };
+        match (self.step, id) {
+            // 0x00 is success, 0x01 write protected.
+            (Step::Write, SubcommandID::SPIFlashWrite) if ack && data.first() == Some(&0x00) => {
+                self.advance(Step::Verify)
+            }
+            (Step::Write, SubcommandID::SPIFlashWrite) => self.finish(false),
+            (Step::Verify, SubcommandID::SPIFlashRead) => match read_reply(data) {
+                Some((address, read)) if address == self.address => {
+                    self.finish(read == &self.data[..]);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Reads the whole SPI flash, one chunk at a time.
+pub struct SpiDump {
+    data: Vec<u8>,
+    sent_at: Option<Instant>,
+    retries: u8,
+    failed: bool,
+}
+
+impl Default for SpiDump {
+    fn default() -> Self {
+        SpiDump {
+            data: Vec::with_capacity(FLASH_SIZE),
+            sent_at: None,
+            retries: 0,
+            failed: false,
+        }
+    }
+}
+
+impl SpiDump {
+    pub fn is_done(&self) -> bool {
+        self.failed || self.data.len() == FLASH_SIZE
+    }
+
+    pub fn progress(&self) -> SpiProgress {
+        SpiProgress {
+            state: match (self.failed, self.is_done()) {
+                (true, _) => PROGRESS_FAILED,
+                (false, true) => PROGRESS_DONE,
+                (false, false) => PROGRESS_RUNNING,
+            },
+            done: self.data.len() as u32,
+            total: FLASH_SIZE as u32,
+            ..Default::default()
+        }
+    }
+
+    /// The complete dump, `None` while reading or after a failure.
+    pub fn data(&self) -> Option<&[u8]> {
+        (!self.failed && self.is_done()).then_some(&self.data[..])
+    }
+
+    /// Returns the subcommand to send next, if any.
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        if self.is_done() {
+            return None;
+        }
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() < REPLY_TIMEOUT {
+                return None;
+            }
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                self.failed = true;
+                return None;
+            }
+        }
+        self.sent_at = Some(Instant::now());
+        let length = MAX_READ_SIZE.min(FLASH_SIZE - self.data.len());
+        Some(read_request(self.data.len() as u32, length))
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        if !ack || id != SubcommandID::SPIFlashRead as u8 || self.is_done() {
+            return;
+        }
+        let Some((address, read)) = read_reply(data) else {
+            return;
+        };
+        let length = MAX_READ_SIZE.min(FLASH_SIZE - self.data.len());
+        if address as usize == self.data.len() && read.len() == length {
+            self.data.extend_from_slice(read);
+            self.sent_at = None;
+            self.retries = 0;
+        }
+    }
+}
+
+/// Reads a list of regions, one after another, without holding up other clients.
+pub struct SpiRead {
+    // (address, length) still to read, in reverse order.
+    regions: Vec<(u32, usize)>,
+    // (address, data) of the regions read so far.
+    read: Vec<(u32, Vec<u8>)>,
+    sent_at: Option<Instant>,
+    retries: u8,
+    failed: bool,
+}
+
+impl SpiRead {
+    pub fn new(regions: &[(u32, usize)]) -> Self {
+        debug_assert!(regions.iter().all(|(_, length)| *length <= MAX_READ_SIZE));
+        SpiRead {
+            regions: regions.iter().rev().copied().collect(),
+            read: Vec::with_capacity(regions.len()),
+            sent_at: None,
+            retries: 0,
+            failed: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.failed || self.regions.is_empty()
+    }
+
+    /// The (address, data) of all regions in order, `None` while reading or after a failure.
+    pub fn data(&self) -> Option<&[(u32, Vec<u8>)]> {
+        (!self.failed && self.is_done()).then_some(&self.read[..])
+    }
+
+    /// Returns the subcommand to send next, if any.
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        if self.is_done() {
+            return None;
+        }
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() < REPLY_TIMEOUT {
+                return None;
+            }
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                self.failed = true;
+                return None;
+            }
+        }
+        self.sent_at = Some(Instant::now());
+        let (address, length) = *self.regions.last()?;
+        Some(read_request(address, length))
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        if !ack || id != SubcommandID::SPIFlashRead as u8 {
+            return;
+        }
+        let (Some(&(address, length)), Some((read_address, read))) =
+            (self.regions.last(), read_reply(data))
+        else {
+            return;
+        };
+        if read_address == address && read.len() == length {
+            self.read.push((address, read.to_vec()));
+            self.regions.pop();
+            self.sent_at = None;
+            self.retries = 0;
+        }
+    }
+}
+
+/// Writes the `RESTORE_REGIONS` of a dump back, one verified chunk at a time, once the
+/// `IDENTITY_REGIONS` of the controller were read and match the dump.
+pub struct SpiRestore {
+    // Reads the `IDENTITY_REGIONS` of the controller, until they matched.
+    check: Option<SpiRead>,
+    // (address, data) of the `IDENTITY_REGIONS` of the dump.
+    identity: Vec<(u32, Vec<u8>)>,
+    // (address, data) chunks still to write, in reverse order.
+    chunks: Vec<(u32, Vec<u8>)>,
+    total: usize,
+    write: Option<SpiWrite>,
+    failed: bool,
+    // The dump is of another controller, nothing was written.
+    mismatch: bool,
+    restored: bool,
+}
+
+impl SpiRestore {
+    /// `None` unless `dump` is a complete dump.
+    pub fn new(dump: &[u8]) -> Option<Self> {
+        if dump.len() != FLASH_SIZE {
+            return None;
+        }
+        let mut chunks = Vec::new();
+        for (address, length) in RESTORE_REGIONS {
+            let region = &dump[address as usize..address as usize + length];
+            for (i, chunk) in region.chunks(MAX_WRITE_SIZE).enumerate() {
+                chunks.push((address + (i * MAX_WRITE_SIZE) as u32, chunk.to_vec()));
+            }
+        }
+        chunks.reverse();
+        let identity = IDENTITY_REGIONS
+            .iter()
+            .map(|&(address, length)| {
+                let start = address as usize;
+                (address, dump[start..start + length].to_vec())
+            })
+            .collect();
+        Some(SpiRestore {
+            check: Some(SpiRead::new(&IDENTITY_REGIONS)),
+            identity,
+            total: chunks.len(),
+            chunks,
+            write: None,
+            failed: false,
+            mismatch: false,
+            restored: false,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.failed
+            || self.mismatch
+            || (self.check.is_none() && self.chunks.is_empty() && self.write.is_none())
+    }
+
+    /// True once after all chunks were written, when the calibration needs reading again.
+    pub fn take_restored(&mut self) -> bool {
+        std::mem::take(&mut self.restored)
+    }
+
+    pub fn progress(&self) -> SpiProgress {
+        let pending = self.chunks.len() + self.write.is_some() as usize;
+        SpiProgress {
+            state: match (self.failed, self.mismatch, self.is_done()) {
+                (true, _, _) => PROGRESS_FAILED,
+                (false, true, _) => PROGRESS_MISMATCH,
+                (false, false, true) => PROGRESS_DONE,
+                (false, false, false) => PROGRESS_RUNNING,
+            },
+            done: (self.total - pending) as u32,
+            total: self.total as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the subcommand to send next, if any.
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        if let Some(check) = &mut self.check {
+            if !check.is_done() {
+                return check.update();
+            }
+            match check.data() {
+                Some(read) if read == self.identity => self.check = None,
+                Some(_) => self.mismatch = true,
+                None => self.failed = true,
+            }
+        }
+        if self.is_done() {
+            return None;
+        }
+        if self.write.is_none() {
+            let (address, data) = self.chunks.pop()?;
+            self.write = Some(SpiWrite::new(address, data));
+        }
+        let write = self.write.as_mut()?;
+        let request = write.update();
+        match write.result() {
+            Some(true) => {
+                self.write = None;
+                self.restored = self.chunks.is_empty();
+            }
+            Some(false) => self.failed = true,
+            None => {}
+        }
+        request
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        if let Some(check) = &mut self.check {
+            check.on_subcommand_reply(id, ack, data);
+        } else if let Some(write) = &mut self.write {
+            write.on_subcommand_reply(id, ack, data);
+        }
+    }
+}
+
+/// The SPI flash client of a controller, one at a time.
+pub enum SpiTransfer {
+    Dump(SpiDump),
+    Restore(SpiRestore),
+}
+
+impl SpiTransfer {
+    pub fn is_done(&self) -> bool {
+        match self {
+            SpiTransfer::Dump(dump) => dump.is_done(),
+            SpiTransfer::Restore(restore) => restore.is_done(),
+        }
+    }
+
+    pub fn progress(&self) -> SpiProgress {
+        match self {
+            SpiTransfer::Dump(dump) => dump.progress(),
+            SpiTransfer::Restore(restore) => restore.progress(),
+        }
+    }
+
+    pub fn take_restored(&mut self) -> bool {
+        match self {
+            SpiTransfer::Dump(_) => false,
+            SpiTransfer::Restore(restore) => restore.take_restored(),
+        }
+    }
+
+    pub fn update(&mut self) -> Option<(SubcommandID, Vec<u8>)> {
+        match self {
+            SpiTransfer::Dump(dump) => dump.update(),
+            SpiTransfer::Restore(restore) => restore.update(),
+        }
+    }
+
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) {
+        match self {
+            SpiTransfer::Dump(dump) => dump.on_subcommand_reply(id, ack, data),
+            SpiTransfer::Restore(restore) => restore.on_subcommand_reply(id, ack, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Data of a `SubcommandID::SPIFlashRead` reply after the subcommand ID.
+    fn reply(address: u32, read: &[u8]) -> Vec<u8> {
+        let mut data = address.to_le_bytes().to_vec();
+        data.push(read.len() as u8);
+        data.extend_from_slice(read);
+        data
+    }
+
+    // Arguments of the read `spi_read` sends next.
+    fn sent(spi_read: &mut SpiRead) -> Option<Vec<u8>> {
+        let (id, args) = spi_read.update()?;
+        assert!(matches!(id, SubcommandID::SPIFlashRead));
+        Some(args)
+    }
+
+    #[test]
+    fn reads_regions_in_order() {
+        let mut spi_read = SpiRead::new(&[(0x6050, 3), (0x603d, 2)]);
+        let id = SubcommandID::SPIFlashRead as u8;
+        assert_eq!(sent(&mut spi_read), Some(vec![0x50, 0x60, 0, 0, 3]));
+        // Nothing new until the reply or the timeout.
+        assert_eq!(sent(&mut spi_read), None);
+
+        // Replies to other reads, NACKs and short ones don't count.
+        spi_read.on_subcommand_reply(id, true, &reply(0x603d, &[1, 2]));
+        spi_read.on_subcommand_reply(id, false, &reply(0x6050, &[1, 2, 3]));
+        spi_read.on_subcommand_reply(id, true, &reply(0x6050, &[1, 2]));
+        assert!(spi_read.data().is_none());
+
+        spi_read.on_subcommand_reply(id, true, &reply(0x6050, &[1, 2, 3]));
+        assert_eq!(sent(&mut spi_read), Some(vec![0x3d, 0x60, 0, 0, 2]));
+        spi_read.on_subcommand_reply(id, true, &reply(0x603d, &[4, 5]));
+        assert!(spi_read.is_done());
+        assert_eq!(
+            spi_read.data().unwrap(),
+            [(0x6050, vec![1, 2, 3]), (0x603d, vec![4, 5])]
+        );
+        assert_eq!(sent(&mut spi_read), None);
+    }
+
+    #[test]
+    fn fails_after_retries() {
+        let mut spi_read = SpiRead::new(&[(0x6050, 3)]);
+        for _ in 0..=MAX_RETRIES {
+            assert!(spi_read.update().is_some());
+            spi_read.sent_at = Some(Instant::now() - REPLY_TIMEOUT);
+        }
+        assert_eq!(sent(&mut spi_read), None);
+        assert!(spi_read.is_done());
+        assert!(spi_read.data().is_none());
+    }
+
+    // A complete dump with `serial`, factory stick calibration 1, 2, 3, ... and user stick
+    // calibration 0xb2, 0xa1 followed by 0x40.
+    fn dump(serial: &[u8]) -> Vec<u8> {
+        let mut dump = vec![0xff; FLASH_SIZE];
+        let at = |address: SPIAddress| address as usize;
+        dump[at(SPIAddress::SerialNumber)..][..serial.len()].copy_from_slice(serial);
+        for (i, byte) in dump[at(SPIAddress::AnalogStickCalibration)..][..18]
+            .iter_mut()
+            .enumerate()
+        {
+            *byte = i as u8 + 1;
+        }
+        dump[at(SPIAddress::UserStickCalibration)..][..0x30].fill(0x40);
+        dump[at(SPIAddress::UserStickCalibration)..][..2].copy_from_slice(&[0xb2, 0xa1]);
+        dump
+    }
+
+    // Answers the identity reads of `restore` from `controller`, a dump of the controller.
+    fn check_identity(restore: &mut SpiRestore, controller: &[u8]) {
+        let id = SubcommandID::SPIFlashRead as u8;
+        for (address, length) in IDENTITY_REGIONS {
+            let (_, args) = restore.update().unwrap();
+            assert_eq!(args[..4], address.to_le_bytes());
+            let start = address as usize;
+            restore.on_subcommand_reply(
+                id,
+                true,
+                &reply(address, &controller[start..start + length]),
+            );
+        }
+    }
+
+    #[test]
+    fn restores_to_the_same_controller() {
+        let dump = dump(b"XBW12345678901");
+        let mut restore = SpiRestore::new(&dump).unwrap();
+        check_identity(&mut restore, &dump);
+        let (id, args) = restore.update().unwrap();
+        assert!(matches!(id, SubcommandID::SPIFlashWrite));
+        assert_eq!(args[..6], [0x10, 0x80, 0, 0, MAX_WRITE_SIZE as u8, 0xb2]);
+        assert_eq!(restore.progress().state, PROGRESS_RUNNING);
+    }
+
+    #[test]
+    fn refuses_dumps_of_other_controllers() {
+        let dump = dump(b"XBW12345678901");
+        let mut restore = SpiRestore::new(&dump).unwrap();
+        check_identity(&mut restore, &self::dump(b"XBW10987654321"));
+        assert!(restore.update().is_none());
+        assert!(restore.is_done());
+        assert!(!restore.take_restored());
+        assert_eq!(restore.progress().state, PROGRESS_MISMATCH);
+
+        // Same serial number, other factory calibration.
+        let mut controller = dump.clone();
+        controller[SPIAddress::AnalogStickCalibration as usize] = 0x00;
+        let mut restore = SpiRestore::new(&dump).unwrap();
+        check_identity(&mut restore, &controller);
+        assert!(restore.update().is_none());
+        assert_eq!(restore.progress().state, PROGRESS_MISMATCH);
+    }
+}
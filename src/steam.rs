@@ -0,0 +1,493 @@
+/**
+ * Steam Controller (wired and wireless dongle) and Steam Deck built-in controls.
+ *
+ * port form:
+ *  https://github.com/libsdl-org/SDL/blob/main/src/joystick/hidapi/SDL_hidapi_steam.c
+ *  https://github.com/libsdl-org/SDL/blob/main/src/joystick/hidapi/SDL_hidapi_steamdeck.c
+ *  https://github.com/libsdl-org/SDL/blob/main/src/joystick/hidapi/steam/controller_constants.h
+ */
+use crate::{Button, Gamepad, GamepadMapping, Motion, Touch};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use num_enum::TryFromPrimitive;
+use std::time::{Duration, Instant};
+use zerocopy::*;
+
+const VENDOR_ID_VALVE: u16 = 0x28de;
+const PRODUCT_ID_STEAM_CONTROLLER: u16 = 0x1102;
+const PRODUCT_ID_STEAM_CONTROLLER_DONGLE: u16 = 0x1142;
+const PRODUCT_ID_STEAM_DECK: u16 = 0x1205;
+
+// Feature reports are 64 bytes, plus the report number hidapi expects in front.
+const FEATURE_REPORT_SIZE: usize = 64;
+const INPUT_REPORT_SIZE: usize = 64;
+
+// The firmware turns lizard mode (keyboard and mouse emulation) back on after a while.
+const LIZARD_MODE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[repr(u8)]
+enum FeatureReportID {
+    ClearDigitalMappings = 0x81,
+    SetSettingsValues = 0x87,
+    TriggerHapticPulse = 0x8f,
+    TriggerRumble = 0xeb,
+}
+
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive)]
+enum InputReportID {
+    ControllerState = 0x01,
+    Wireless = 0x03,
+    DeckState = 0x09,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Setting {
+    LeftTrackpadMode = 7,
+    RightTrackpadMode = 8,
+    SmoothAbsoluteMouse = 24,
+    ImuMode = 48,
+    LeftTrackpadClickPressure = 52,
+    RightTrackpadClickPressure = 53,
+}
+
+const TRACKPAD_NONE: u16 = 7;
+const IMU_MODE_SEND_RAW_ACCEL: u16 = 0x08;
+const IMU_MODE_SEND_RAW_GYRO: u16 = 0x10;
+// Pad clicks need more pressure than the pad can report.
+const TRACKPAD_CLICK_PRESSURE_DISABLED: u16 = 0xffff;
+
+const WIRELESS_EVENT_DISCONNECT: u8 = 1;
+const WIRELESS_EVENT_CONNECT: u8 = 2;
+
+// Button bits shared by the Steam Controller and the Steam Deck.
+const BUTTON_RIGHT_TRIGGER: u64 = 1 << 0;
+const BUTTON_LEFT_TRIGGER: u64 = 1 << 1;
+const BUTTON_LEFT_PAD_CLICK: u64 = 1 << 17;
+const BUTTON_RIGHT_PAD_CLICK: u64 = 1 << 18;
+const BUTTON_LEFT_PAD_TOUCH: u64 = 1 << 19;
+const BUTTON_RIGHT_PAD_TOUCH: u64 = 1 << 20;
+
+// (bit, standard gamepad button)
+const COMMON_BUTTON_LAYOUT: [(u64, usize); 15] = [
+    (1 << 7, 0),   // A
+    (1 << 5, 1),   // B
+    (1 << 6, 2),   // X
+    (1 << 4, 3),   // Y
+    (1 << 3, 4),   // Left bumper
+    (1 << 2, 5),   // Right bumper
+    (1 << 12, 8),  // View
+    (1 << 14, 9),  // Menu
+    (1 << 8, 12),  // D-pad up
+    (1 << 11, 13), // D-pad down
+    (1 << 10, 14), // D-pad left
+    (1 << 9, 15),  // D-pad right
+    (1 << 13, 16), // Steam
+    (BUTTON_RIGHT_PAD_CLICK, 22),
+    // Extra button after the standard ones.
+    (BUTTON_LEFT_PAD_CLICK, 23),
+];
+
+const CONTROLLER_BUTTON_LAYOUT: [(u64, usize); 3] = [
+    (1 << 16, 17), // Right grip
+    (1 << 15, 18), // Left grip
+    (1 << 22, 10), // Stick click
+];
+
+const DECK_BUTTON_LAYOUT: [(u64, usize); 7] = [
+    (1 << 22, 10), // L3
+    (1 << 26, 11), // R3
+    (1 << 42, 17), // R4
+    (1 << 41, 18), // L4
+    (1 << 16, 19), // R5
+    (1 << 15, 20), // L5
+    (1 << 50, 21), // Quick access
+];
+
+const TRIGGER_MAX_CONTROLLER: f32 = 255.0;
+const TRIGGER_MAX_DECK: f32 = 32767.0;
+
+// IMU full scale, ±2G and ±2000dps.
+const ACCEL_RANGE: f32 = 2.0;
+const GYRO_RANGE: f32 = 2000.0;
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct ReportHeader {
+    version: u16,
+    report_type: u8,
+    length: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct ControllerStatePacket {
+    packet_num: u32,
+    // Button bits, with the trigger positions in bytes 3 and 4.
+    buttons: [u8; 8],
+    // Shared with the stick, see `BUTTON_LEFT_PAD_TOUCH`.
+    left_pad: [i16; 2],
+    right_pad: [i16; 2],
+    raw_triggers: [u16; 2],
+    accel: [i16; 3],
+    gyro: [i16; 3],
+    quaternion: [i16; 4],
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, Default, Debug)]
+struct DeckStatePacket {
+    packet_num: u32,
+    buttons: [u8; 8],
+    left_pad: [i16; 2],
+    right_pad: [i16; 2],
+    accel: [i16; 3],
+    gyro: [i16; 3],
+    quaternion: [i16; 4],
+    triggers: [u16; 2],
+    left_stick: [i16; 2],
+    right_stick: [i16; 2],
+    pad_pressure: [u16; 2],
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, AsBytes, Default, Debug)]
+struct HapticPulsePacket {
+    pad: u8,
+    duration_us: [u8; 2],
+    interval_us: [u8; 2],
+    count: [u8; 2],
+    gain_db: [u8; 2],
+    priority: u8,
+}
+
+#[repr(C)]
+#[derive(FromBytes, FromZeroes, AsBytes, Default, Debug)]
+struct RumblePacket {
+    rumble_type: u8,
+    intensity: [u8; 2],
+    left_speed: [u8; 2],
+    right_speed: [u8; 2],
+    left_gain: i8,
+    right_gain: i8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Model {
+    Controller,
+    Deck,
+}
+
+#[derive(Debug, Default)]
+struct SteamState {
+    buttons: u64,
+    left_stick: [i16; 2],
+    right_stick: [i16; 2],
+    triggers: [f32; 2],
+    // Pad positions while a finger is on the pad.
+    pads: [Option<[i16; 2]>; 2],
+    accel: [i16; 3],
+    gyro: [i16; 3],
+}
+
+pub struct SteamDevice {
+    device: HidDevice,
+    model: Model,
+    // Wireless controllers are only there while the dongle says so.
+    connected: bool,
+    lizard_mode_sent: Option<Instant>,
+    state: SteamState,
+    // Touch ids of the fingers currently on the left and right pad.
+    touch_ids: [Option<u32>; 2],
+    next_touch_id: u32,
+}
+
+impl SteamDevice {
+    pub fn is_supported(device_info: &DeviceInfo) -> bool {
+        if device_info.vendor_id() != VENDOR_ID_VALVE {
+            return false;
+        }
+        // Only the vendor interface, the others are keyboard and mouse.
+        match device_info.product_id() {
+            PRODUCT_ID_STEAM_CONTROLLER | PRODUCT_ID_STEAM_DECK => {
+                device_info.interface_number() == 2
+            }
+            PRODUCT_ID_STEAM_CONTROLLER_DONGLE => (1..=4).contains(&device_info.interface_number()),
+            _ => false,
+        }
+    }
+
+    pub fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Option<Self> {
+        let device = device_info.open_device(hidapi).ok()?;
+        let model = if device_info.product_id() == PRODUCT_ID_STEAM_DECK {
+            Model::Deck
+        } else {
+            Model::Controller
+        };
+        Some(SteamDevice {
+            device,
+            model,
+            connected: device_info.product_id() != PRODUCT_ID_STEAM_CONTROLLER_DONGLE,
+            lizard_mode_sent: None,
+            state: Default::default(),
+            touch_ids: [None; 2],
+            next_touch_id: 0,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send_feature_report(&self, report_id: FeatureReportID, payload: &[u8]) -> bool {
+        let mut buf = [0; FEATURE_REPORT_SIZE + 1];
+        buf[1] = report_id as u8;
+        buf[2] = payload.len() as u8;
+        buf[3..3 + payload.len()].copy_from_slice(payload);
+        self.device.send_feature_report(&buf).is_ok()
+    }
+
+    fn set_settings(&self, settings: &[(Setting, u16)]) -> bool {
+        let payload: Vec<u8> = settings
+            .iter()
+            .flat_map(|(setting, value)| {
+                let [low, high] = value.to_le_bytes();
+                [*setting as u8, low, high]
+            })
+            .collect();
+        self.send_feature_report(FeatureReportID::SetSettingsValues, &payload)
+    }
+
+    fn disable_lizard_mode(&self) {
+        self.send_feature_report(FeatureReportID::ClearDigitalMappings, &[]);
+        let mut settings = vec![
+            (Setting::SmoothAbsoluteMouse, 0),
+            (Setting::LeftTrackpadMode, TRACKPAD_NONE),
+            (Setting::RightTrackpadMode, TRACKPAD_NONE),
+        ];
+        match self.model {
+            Model::Controller => settings.push((
+                Setting::ImuMode,
+                IMU_MODE_SEND_RAW_ACCEL | IMU_MODE_SEND_RAW_GYRO,
+            )),
+            Model::Deck => settings.extend([
+                (
+                    Setting::LeftTrackpadClickPressure,
+                    TRACKPAD_CLICK_PRESSURE_DISABLED,
+                ),
+                (
+                    Setting::RightTrackpadClickPressure,
+                    TRACKPAD_CLICK_PRESSURE_DISABLED,
+                ),
+            ]),
+        }
+        self.set_settings(&settings);
+    }
+
+    /// Applies all pending input reports, returns false once the device is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; INPUT_REPORT_SIZE];
+        loop {
+            match self.device.read_timeout(&mut buf, 0) {
+                Ok(0) => break,
+                Ok(len) => self.handle_report(&buf[..len]),
+                Err(_) => return false,
+            }
+        }
+
+        if self.connected
+            && self
+                .lizard_mode_sent
+                .is_none_or(|sent| sent.elapsed() > LIZARD_MODE_INTERVAL)
+        {
+            self.disable_lizard_mode();
+            self.lizard_mode_sent = Some(Instant::now());
+        }
+        true
+    }
+
+    fn handle_report(&mut self, report: &[u8]) {
+        let Some(header) = ReportHeader::read_from_prefix(report) else {
+            return;
+        };
+        let payload = &report[4..];
+        match InputReportID::try_from(header.report_type) {
+            Ok(InputReportID::ControllerState) => {
+                if let Some(packet) = ControllerStatePacket::read_from_prefix(payload) {
+                    self.connected = true;
+                    self.update_controller(&packet);
+                }
+            }
+            Ok(InputReportID::DeckState) => {
+                if let Some(packet) = DeckStatePacket::read_from_prefix(payload) {
+                    self.update_deck(&packet);
+                }
+            }
+            Ok(InputReportID::Wireless) => match payload.first() {
+                Some(&WIRELESS_EVENT_CONNECT) => {
+                    self.connected = true;
+                    // Settings don't survive a reconnect.
+                    self.lizard_mode_sent = None;
+                }
+                Some(&WIRELESS_EVENT_DISCONNECT) => {
+                    self.connected = false;
+                    self.state = Default::default();
+                    self.touch_ids = [None; 2];
+                }
+                _ => {}
+            },
+            Err(_) => {}
+        }
+    }
+
+    fn update_controller(&mut self, packet: &ControllerStatePacket) {
+        let buttons = u64::from_le_bytes(packet.buttons);
+        let state = &mut self.state;
+        state.buttons = buttons;
+        state.triggers = [
+            packet.buttons[3] as f32 / TRIGGER_MAX_CONTROLLER,
+            packet.buttons[4] as f32 / TRIGGER_MAX_CONTROLLER,
+        ];
+        // The left pad and the stick share their fields, the touch bit tells which one it is.
+        if buttons & BUTTON_LEFT_PAD_TOUCH != 0 {
+            state.pads[0] = Some(packet.left_pad);
+        } else {
+            state.pads[0] = None;
+            state.left_stick = packet.left_pad;
+        }
+        state.pads[1] = (buttons & BUTTON_RIGHT_PAD_TOUCH != 0).then_some(packet.right_pad);
+        // Without a right stick the right pad takes its place.
+        state.right_stick = state.pads[1].unwrap_or_default();
+        state.accel = packet.accel;
+        state.gyro = packet.gyro;
+        self.update_touch_ids();
+    }
+
+    fn update_deck(&mut self, packet: &DeckStatePacket) {
+        let buttons = u64::from_le_bytes(packet.buttons);
+        let state = &mut self.state;
+        state.buttons = buttons;
+        state.triggers = [
+            packet.triggers[0] as f32 / TRIGGER_MAX_DECK,
+            packet.triggers[1] as f32 / TRIGGER_MAX_DECK,
+        ];
+        state.left_stick = packet.left_stick;
+        state.right_stick = packet.right_stick;
+        state.pads = [
+            (buttons & BUTTON_LEFT_PAD_TOUCH != 0).then_some(packet.left_pad),
+            (buttons & BUTTON_RIGHT_PAD_TOUCH != 0).then_some(packet.right_pad),
+        ];
+        state.accel = packet.accel;
+        state.gyro = packet.gyro;
+        self.update_touch_ids();
+    }
+
+    /// Every new finger on a pad gets a new touch id, like `GamepadTouch.touchId`.
+    fn update_touch_ids(&mut self) {
+        for (pad, touch_id) in self.state.pads.iter().zip(self.touch_ids.iter_mut()) {
+            match (pad, *touch_id) {
+                (Some(_), None) => {
+                    *touch_id = Some(self.next_touch_id);
+                    self.next_touch_id = self.next_touch_id.wrapping_add(1);
+                }
+                (None, Some(_)) => *touch_id = None,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        let state = &self.state;
+        let layout: &[(u64, usize)] = match self.model {
+            Model::Controller => &CONTROLLER_BUTTON_LAYOUT,
+            Model::Deck => &DECK_BUTTON_LAYOUT,
+        };
+        for (mask, index) in COMMON_BUTTON_LAYOUT.iter().chain(layout) {
+            gamepad.buttons[*index] = Button::digital(state.buttons & mask != 0);
+        }
+
+        let clicks = [BUTTON_LEFT_TRIGGER, BUTTON_RIGHT_TRIGGER];
+        for ((trigger, click), button) in state
+            .triggers
+            .iter()
+            .zip(clicks)
+            .zip(gamepad.buttons[6..8].iter_mut())
+        {
+            *button = Button::analog(*trigger);
+            button.pressed |= state.buttons & click != 0;
+        }
+
+        let axis = |value: i16| (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0);
+        // Standard layout, up is -1.0.
+        gamepad.axes = vec![
+            axis(state.left_stick[0]),
+            -axis(state.left_stick[1]),
+            axis(state.right_stick[0]),
+            -axis(state.right_stick[1]),
+        ];
+        gamepad.mapping = GamepadMapping::Standard;
+    }
+
+    pub fn motion(&self) -> Motion {
+        let scale = |value: i16, range: f32| value as f32 / 32768.0 * range;
+        Motion {
+            accel: self.state.accel.map(|value| scale(value, ACCEL_RANGE)),
+            gyro: self.state.gyro.map(|value| scale(value, GYRO_RANGE)),
+            has_accel: true,
+            has_gyro: true,
+        }
+    }
+
+    /// Fingers on the trackpads, surface 0 is the left and surface 1 the right pad.
+    pub fn touches(&self) -> Vec<Touch> {
+        let mut touches = Vec::new();
+        for (surface_id, (pad, touch_id)) in self.state.pads.iter().zip(self.touch_ids).enumerate()
+        {
+            if let (Some([x, y]), Some(touch_id)) = (pad, touch_id) {
+                let axis = |value: i16| (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0);
+                // -1.0 is the left and top edge.
+                touches.push(Touch::new(
+                    touch_id,
+                    surface_id as u8,
+                    [axis(*x), -axis(*y)],
+                ));
+            }
+        }
+        touches
+    }
+
+    /// Pulses the haptic actuator under a trackpad, `pad` 0 is the left and 1 the right one.
+    pub fn trigger_haptic_pulse(
+        &self,
+        pad: u8,
+        duration_us: u16,
+        interval_us: u16,
+        count: u16,
+    ) -> bool {
+        let packet = HapticPulsePacket {
+            // The firmware numbers the pads right to left.
+            pad: if pad == 0 { 1 } else { 0 },
+            duration_us: duration_us.to_le_bytes(),
+            interval_us: interval_us.to_le_bytes(),
+            count: count.to_le_bytes(),
+            ..Default::default()
+        };
+        self.send_feature_report(FeatureReportID::TriggerHapticPulse, packet.as_bytes())
+    }
+
+    /// Only the Steam Deck has rumble motors.
+    pub fn set_rumble(&self, strong: f32, weak: f32) -> bool {
+        if self.model != Model::Deck {
+            return false;
+        }
+        let speed = |value: f32| ((value.clamp(0.0, 1.0) * u16::MAX as f32) as u16).to_le_bytes();
+        let packet = RumblePacket {
+            intensity: u16::MAX.to_le_bytes(),
+            left_speed: speed(strong),
+            right_speed: speed(weak),
+            left_gain: 2,
+            ..Default::default()
+        };
+        self.send_feature_report(FeatureReportID::TriggerRumble, packet.as_bytes())
+    }
+}
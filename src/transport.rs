@@ -0,0 +1,249 @@
+/**
+ * Requests of the initialization of Nintendo controllers: keeps the one subcommand or, over
+ * USB, proprietary command a controller hasn't answered yet, matches replies to it by ID and
+ * SPI flash address, and sends it again with backoff when the reply is lost or a NACK.
+ *
+ * ref:
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+ *  https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/USB-HID-Notes.md
+ */
+use crate::{InitFailure, ProprietaryCommandID, SubcommandID};
+use std::time::{Duration, Instant};
+
+// Doubled after every unanswered attempt.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_RETRIES: u8 = 4;
+
+// Address at the start of `SubcommandID::SPIFlashRead` requests and replies.
+const SPI_ADDRESS_SIZE: usize = 4;
+
+#[derive(Debug, Clone)]
+pub enum Request {
+    Subcommand(SubcommandID, Vec<u8>),
+    Proprietary(ProprietaryCommandID),
+}
+
+impl Request {
+    // SPI flash reads share an ID, their replies repeat the address.
+    fn is_answered_by(&self, id: u8, data: &[u8]) -> bool {
+        match self {
+            Request::Subcommand(SubcommandID::SPIFlashRead, args) => {
+                id == SubcommandID::SPIFlashRead as u8
+                    && data.get(..SPI_ADDRESS_SIZE) == args.get(..SPI_ADDRESS_SIZE)
+            }
+            Request::Subcommand(command_id, _) => id == *command_id as u8,
+            Request::Proprietary(_) => false,
+        }
+    }
+
+    fn failure(&self, nacked: bool) -> InitFailure {
+        match self {
+            Request::Subcommand(command_id, args) => InitFailure {
+                command_id: *command_id as u8,
+                nacked,
+                spi_address: match command_id {
+                    SubcommandID::SPIFlashRead => args
+                        .get(..SPI_ADDRESS_SIZE)
+                        .map_or(0, |address| u32::from_le_bytes(address.try_into().unwrap())),
+                    _ => 0,
+                },
+                ..Default::default()
+            },
+            Request::Proprietary(command_id) => InitFailure {
+                command_id: *command_id as u8,
+                is_proprietary: true,
+                nacked,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+struct Pending {
+    request: Request,
+    sent_at: Instant,
+    retries: u8,
+    // Set by a NACK, reported if the retries run out.
+    nacked: bool,
+}
+
+#[derive(Default)]
+pub struct Transport {
+    pending: Option<Pending>,
+}
+
+impl Transport {
+    /// Starts waiting for the reply to `request`, returns it for sending.
+    pub fn send(&mut self, request: Request) -> &Request {
+        &self
+            .pending
+            .insert(Pending {
+                request,
+                sent_at: Instant::now(),
+                retries: 0,
+                nacked: false,
+            })
+            .request
+    }
+
+    /// Returns the request to send again once its reply is overdue, or why it was given up.
+    pub fn update(&mut self) -> Result<Option<&Request>, InitFailure> {
+        let Some(pending) = &self.pending else {
+            return Ok(None);
+        };
+        let timeout = REPLY_TIMEOUT * 2u32.pow(pending.retries as u32);
+        if pending.sent_at.elapsed() < timeout {
+            return Ok(None);
+        }
+        if pending.retries == MAX_RETRIES {
+            let failure = pending.request.failure(pending.nacked);
+            self.pending = None;
+            return Err(failure);
+        }
+        let Some(pending) = &mut self.pending else {
+            unreachable!()
+        };
+        pending.retries += 1;
+        pending.sent_at = Instant::now();
+        pending.nacked = false;
+        Ok(Some(&pending.request))
+    }
+
+    /// Handles a subcommand reply, `data` starts after the subcommand ID. Returns true if it
+    /// acknowledges the pending request.
+    pub fn on_subcommand_reply(&mut self, id: u8, ack: bool, data: &[u8]) -> bool {
+        let Some(pending) = &mut self.pending else {
+            return false;
+        };
+        if !pending.request.is_answered_by(id, data) {
+            return false;
+        }
+        if !ack {
+            pending.nacked = true;
+            return false;
+        }
+        self.pending = None;
+        true
+    }
+
+    /// Handles the reply to a proprietary command, returns true if it's the pending request.
+    pub fn on_command_ack(&mut self, command_id: u8) -> bool {
+        match &self.pending {
+            Some(Pending {
+                request: Request::Proprietary(pending_id),
+                ..
+            }) if *pending_id as u8 == command_id => {
+                self.pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Makes the pending request look sent `elapsed` ago.
+    fn age(transport: &mut Transport, elapsed: Duration) {
+        transport.pending.as_mut().unwrap().sent_at = Instant::now() - elapsed;
+    }
+
+    #[test]
+    fn matches_replies_by_id() {
+        let mut transport = Transport::default();
+        transport.send(Request::Subcommand(
+            SubcommandID::SetInputReportMode,
+            vec![0x30],
+        ));
+        let id = SubcommandID::SetInputReportMode as u8;
+        assert!(!transport.on_subcommand_reply(SubcommandID::EnableIMU as u8, true, &[]));
+        assert!(!transport.on_command_ack(id));
+        assert!(transport.on_subcommand_reply(id, true, &[]));
+        assert!(!transport.on_subcommand_reply(id, true, &[]));
+
+        transport.send(Request::Proprietary(ProprietaryCommandID::Handshake));
+        assert!(!transport.on_subcommand_reply(ProprietaryCommandID::Handshake as u8, true, &[]));
+        assert!(!transport.on_command_ack(ProprietaryCommandID::HighSpeed as u8));
+        assert!(transport.on_command_ack(ProprietaryCommandID::Handshake as u8));
+        assert!(transport.pending.is_none());
+    }
+
+    #[test]
+    fn matches_spi_reads_by_address() {
+        let mut transport = Transport::default();
+        let id = SubcommandID::SPIFlashRead as u8;
+        transport.send(Request::Subcommand(
+            SubcommandID::SPIFlashRead,
+            vec![0x3d, 0x60, 0x00, 0x00, 0x12],
+        ));
+        assert!(!transport.on_subcommand_reply(id, true, &[0x50, 0x60, 0x00, 0x00, 0x0d]));
+        assert!(!transport.on_subcommand_reply(id, true, &[0x3d, 0x60]));
+        assert!(transport.on_subcommand_reply(id, true, &[0x3d, 0x60, 0x00, 0x00, 0x12]));
+    }
+
+    #[test]
+    fn resends_with_backoff() {
+        let mut transport = Transport::default();
+        transport.send(Request::Subcommand(SubcommandID::EnableIMU, vec![0x01]));
+        assert!(matches!(transport.update(), Ok(None)));
+        age(&mut transport, REPLY_TIMEOUT);
+        assert!(matches!(
+            transport.update(),
+            Ok(Some(Request::Subcommand(SubcommandID::EnableIMU, _)))
+        ));
+        age(&mut transport, REPLY_TIMEOUT);
+        assert!(matches!(transport.update(), Ok(None)));
+        age(&mut transport, REPLY_TIMEOUT * 2);
+        assert!(matches!(transport.update(), Ok(Some(_))));
+    }
+
+    #[test]
+    fn resends_nacks_with_backoff() {
+        let mut transport = Transport::default();
+        let id = SubcommandID::EnableIMU as u8;
+        transport.send(Request::Subcommand(SubcommandID::EnableIMU, vec![0x01]));
+        assert!(!transport.on_subcommand_reply(id, false, &[]));
+        assert!(matches!(transport.update(), Ok(None)));
+        age(&mut transport, REPLY_TIMEOUT);
+        assert!(matches!(transport.update(), Ok(Some(_))));
+        assert!(!transport.on_subcommand_reply(id, false, &[]));
+        age(&mut transport, REPLY_TIMEOUT);
+        assert!(matches!(transport.update(), Ok(None)));
+        assert!(transport.on_subcommand_reply(id, true, &[]));
+        assert!(matches!(transport.update(), Ok(None)));
+    }
+
+    #[test]
+    fn fails_after_retries() {
+        let mut transport = Transport::default();
+        transport.send(Request::Subcommand(
+            SubcommandID::SPIFlashRead,
+            vec![0x50, 0x60, 0x00, 0x00, 0x0d],
+        ));
+        for retries in 0..MAX_RETRIES {
+            age(&mut transport, REPLY_TIMEOUT * 2u32.pow(retries as u32));
+            assert!(matches!(transport.update(), Ok(Some(_))));
+        }
+        transport.on_subcommand_reply(SubcommandID::SPIFlashRead as u8, false, &[0x50, 0x60, 0, 0]);
+        age(&mut transport, REPLY_TIMEOUT * 2u32.pow(MAX_RETRIES as u32));
+        let failure = transport.update().unwrap_err();
+        assert_eq!(failure.command_id, SubcommandID::SPIFlashRead as u8);
+        assert!(!failure.is_proprietary);
+        assert!(failure.nacked);
+        assert_eq!(failure.spi_address, 0x6050);
+        assert!(matches!(transport.update(), Ok(None)));
+
+        transport.send(Request::Proprietary(ProprietaryCommandID::Status));
+        for retries in 0..MAX_RETRIES {
+            age(&mut transport, REPLY_TIMEOUT * 2u32.pow(retries as u32));
+            assert!(matches!(transport.update(), Ok(Some(_))));
+        }
+        age(&mut transport, REPLY_TIMEOUT * 2u32.pow(MAX_RETRIES as u32));
+        let failure = transport.update().unwrap_err();
+        assert_eq!(failure.command_id, ProprietaryCommandID::Status as u8);
+        assert!(failure.is_proprietary);
+        assert!(!failure.nacked);
+    }
+}
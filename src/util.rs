@@ -9,11 +9,11 @@ pub fn generate_id() -> usize {
 pub fn extract_bits(value: &[u8], bytes: usize) -> Vec<u8> {
     let mut bits = vec![];
 
-    for j in 0..bytes {
+    for byte in value.iter().take(bytes) {
         for i in 0..8 {
             let mask = 1 << i;
             // Check if the bit is set and store 1 or 0 accordingly
-            let bit = if (value[j] & mask) != 0 { 1 } else { 0 };
+            let bit = if (byte & mask) != 0 { 1 } else { 0 };
 
             bits.push(bit);
         }
@@ -27,35 +27,26 @@ pub fn unpack_shorts(buf: &[u8], v1: &mut u16, v2: &mut u16) {
     *v2 = ((buf[2] as u16) << 4) | ((buf[1] as u16) >> 4);
 }
 
-pub fn is_dead_zone(x: u16, y: u16, x_center: u16, y_center: u16, dead_zone: u16) -> bool {
-    let dx = if x > x_center {
-        x - x_center
-    } else {
-        x_center - x
-    } as i32;
-    let dy = if y > y_center {
-        y - y_center
-    } else {
-        y_center - y
-    } as i32;
-
-    if dx * dx + dy * dy < (dead_zone * dead_zone) as i32 {
-        return true;
-    }
-    false
+pub fn pack_shorts(v1: u16, v2: u16) -> [u8; 3] {
+    [
+        v1 as u8,
+        ((v1 >> 8) & 0x0f) as u8 | (v2 << 4) as u8,
+        (v2 >> 4) as u8,
+    ]
 }
 
 pub fn clamp_axis(value: u16, min: u16, max: u16) -> f32 {
     // Clamp the value between min and max
     if value <= min {
-        return -1.0;
+        -1.0
     } else if value >= max {
-        return 1.0;
+        1.0
     } else {
-        return 2.0 * (value - min) as f32 / (max - min) as f32 - 1.0;
+        2.0 * (value - min) as f32 / (max - min) as f32 - 1.0
     }
 }
 
+#[allow(dead_code)]
 pub fn to_hex_string(data: &[u8]) -> String {
     let mut hex_string = String::with_capacity(data.len() * 2);
     for byte in data {
@@ -64,3 +55,11 @@ pub fn to_hex_string(data: &[u8]) -> String {
     }
     hex_string
 }
+
+// Some devices and Bluetooth stacks report an empty serial number.
+pub fn serial_number(device_info: &hidapi::DeviceInfo) -> Option<String> {
+    device_info
+        .serial_number()
+        .filter(|serial| !serial.is_empty())
+        .map(str::to_string)
+}
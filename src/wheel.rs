@@ -0,0 +1,391 @@
+/**
+ * Logitech racing wheels in native mode: the ones speaking the classic force feedback
+ * protocol (G25, G27, G29, Driving Force GT), and the G920 speaking HID++.
+ *
+ * ref:
+ *  https://github.com/torvalds/linux/blob/master/drivers/hid/hid-lg4ff.c
+ *  https://github.com/torvalds/linux/blob/master/drivers/hid/hid-logitech-hidpp.c
+ */
+use crate::ffb::{self, Command, Effect, EffectSlots, SLOT_COUNT};
+use crate::hidpp::{self, USAGE_HIDPP_LONG, USAGE_HIDPP_VERY_LONG, USAGE_PAGE_HIDPP};
+use crate::report_descriptor::{GenericState, ReportDecoder, USAGE_PAGE_GENERIC_DESKTOP};
+use crate::{Gamepad, WheelState};
+use hidapi::{DeviceInfo, HidApi, HidDevice, MAX_REPORT_DESCRIPTOR_SIZE};
+use std::time::{Duration, Instant};
+
+const VENDOR_ID_LOGITECH: u16 = 0x046d;
+const PRODUCT_ID_LOGITECH_DFGT: u16 = 0xc29a;
+const PRODUCT_ID_LOGITECH_G25: u16 = 0xc299;
+const PRODUCT_ID_LOGITECH_G27: u16 = 0xc29b;
+const PRODUCT_ID_LOGITECH_G29: u16 = 0xc24f;
+const PRODUCT_ID_LOGITECH_G920: u16 = 0xc262;
+
+const USAGE_X: u16 = 0x30;
+const USAGE_Y: u16 = 0x31;
+const USAGE_Z: u16 = 0x32;
+const USAGE_RZ: u16 = 0x35;
+
+const WHEEL_REPORT_BUF_SIZE: usize = 64;
+const DEFAULT_RANGE: u16 = 900;
+// How long a HID++ call waits for its reply.
+const HIDPP_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy)]
+struct WheelModel {
+    product_id: u16,
+    // First of the six gear buttons of the H-shifter, reverse follows them.
+    shifter_first_button: Option<usize>,
+    hidpp: bool,
+}
+
+const WHEEL_MODELS: [WheelModel; 5] = [
+    WheelModel {
+        product_id: PRODUCT_ID_LOGITECH_DFGT,
+        shifter_first_button: None,
+        hidpp: false,
+    },
+    WheelModel {
+        product_id: PRODUCT_ID_LOGITECH_G25,
+        shifter_first_button: Some(8),
+        hidpp: false,
+    },
+    WheelModel {
+        product_id: PRODUCT_ID_LOGITECH_G27,
+        shifter_first_button: Some(8),
+        hidpp: false,
+    },
+    WheelModel {
+        product_id: PRODUCT_ID_LOGITECH_G29,
+        shifter_first_button: Some(12),
+        hidpp: false,
+    },
+    WheelModel {
+        product_id: PRODUCT_ID_LOGITECH_G920,
+        shifter_first_button: Some(12),
+        hidpp: true,
+    },
+];
+
+enum Ffb {
+    Classic(EffectSlots),
+    Hidpp {
+        feature: hidpp::ForceFeedback,
+        // The wheel's slots of the effects, by slot.
+        slots: [Option<u8>; SLOT_COUNT],
+        autocenter: Option<u8>,
+    },
+}
+
+// Where HID++ reports go. Windows and macOS list each collection as a device of its own,
+// elsewhere they share the wheel's handle and these are `None`.
+#[derive(Default)]
+struct HidppCollections {
+    long: Option<HidDevice>,
+    very_long: Option<HidDevice>,
+}
+
+impl HidppCollections {
+    fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Self {
+        let open = |usage| {
+            hidapi
+                .device_list()
+                .find(|info| {
+                    info.vendor_id() == device_info.vendor_id()
+                        && info.product_id() == device_info.product_id()
+                        && info.serial_number() == device_info.serial_number()
+                        && info.usage_page() == USAGE_PAGE_HIDPP
+                        && info.usage() == usage
+                        && info.path() != device_info.path()
+                })?
+                .open_device(hidapi)
+                .ok()
+        };
+        HidppCollections {
+            long: open(USAGE_HIDPP_LONG),
+            very_long: open(USAGE_HIDPP_VERY_LONG),
+        }
+    }
+
+    /// Makes a HID++ call and waits for its reply, passing the input reports that arrive
+    /// meanwhile to `on_input`. Returns the reply's parameters.
+    fn call(
+        &self,
+        wheel: &HidDevice,
+        call: &hidpp::Call,
+        mut on_input: impl FnMut(&[u8]),
+    ) -> Option<Vec<u8>> {
+        let collection = match call.is_very_long() {
+            true => &self.very_long,
+            false => &self.long,
+        };
+        let device = collection.as_ref().unwrap_or(wheel);
+        device.write(&call.report()).ok()?;
+
+        let deadline = Instant::now() + HIDPP_TIMEOUT;
+        let mut buf = [0; WHEEL_REPORT_BUF_SIZE];
+        loop {
+            let timeout = deadline.checked_duration_since(Instant::now())?;
+            let len = device
+                .read_timeout(&mut buf, timeout.as_millis() as i32)
+                .ok()?;
+            match call.parse_reply(&buf[..len]) {
+                Some(reply) => return reply.ok().map(<[u8]>::to_vec),
+                None if len > 0 => on_input(&buf[..len]),
+                None => {}
+            }
+        }
+    }
+}
+
+pub struct WheelDevice {
+    device: HidDevice,
+    model: WheelModel,
+    decoder: ReportDecoder,
+    state: GenericState,
+    // Axis indices of the steering, throttle, brake and clutch.
+    axes: [Option<usize>; 4],
+    ffb: Ffb,
+    hidpp: HidppCollections,
+    range: u16,
+}
+
+impl WheelDevice {
+    pub fn is_supported(device_info: &DeviceInfo) -> bool {
+        // The G920's HID++ collections are opened along with the wheel.
+        device_info.vendor_id() == VENDOR_ID_LOGITECH
+            && device_info.usage_page() != USAGE_PAGE_HIDPP
+            && WHEEL_MODELS
+                .iter()
+                .any(|model| model.product_id == device_info.product_id())
+    }
+
+    pub fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Option<Self> {
+        let model = *WHEEL_MODELS
+            .iter()
+            .find(|model| model.product_id == device_info.product_id())?;
+        let device = device_info.open_device(hidapi).ok()?;
+        let mut descriptor = [0; MAX_REPORT_DESCRIPTOR_SIZE];
+        let len = device.get_report_descriptor(&mut descriptor).ok()?;
+        let decoder = ReportDecoder::parse(&descriptor[..len])?;
+        let state = decoder.new_state();
+        // The pedals are reported as separate axes, the wheel is X.
+        let axes = [USAGE_X, USAGE_Z, USAGE_RZ, USAGE_Y]
+            .map(|usage| decoder.axis_index(USAGE_PAGE_GENERIC_DESKTOP, usage));
+
+        let (ffb, hidpp) = if model.hidpp {
+            let hidpp = HidppCollections::open(hidapi, device_info);
+            let call = hidpp::get_feature(hidpp::FEATURE_FORCE_FEEDBACK);
+            let feature_index = hidpp.call(&device, &call, |_| {})?[0];
+            if feature_index == 0 {
+                return None;
+            }
+            let ffb = Ffb::Hidpp {
+                feature: hidpp::ForceFeedback { feature_index },
+                slots: [None; SLOT_COUNT],
+                autocenter: None,
+            };
+            (ffb, hidpp)
+        } else {
+            (Ffb::Classic(Default::default()), Default::default())
+        };
+
+        let mut wheel = WheelDevice {
+            device,
+            model,
+            decoder,
+            state,
+            axes,
+            ffb,
+            hidpp,
+            range: DEFAULT_RANGE,
+        };
+        wheel.stop_all();
+        wheel.set_range(DEFAULT_RANGE);
+        Some(wheel)
+    }
+
+    fn send(&self, command: &Command) -> bool {
+        let mut report = [0; 8];
+        report[1..].copy_from_slice(command);
+        self.device.write(&report).is_ok()
+    }
+
+    // Input reports arriving while waiting for the reply aren't lost.
+    fn call(&mut self, call: &hidpp::Call) -> Option<Vec<u8>> {
+        let (decoder, state) = (&self.decoder, &mut self.state);
+        self.hidpp
+            .call(&self.device, call, |report| decoder.decode(report, state))
+    }
+
+    fn stop_all(&mut self) -> bool {
+        match &self.ffb {
+            Ffb::Classic(_) => self.send(&ffb::encode_stop_all()),
+            Ffb::Hidpp { feature, .. } => {
+                let call = feature.reset_all();
+                self.call(&call).is_some()
+            }
+        }
+    }
+
+    /// Applies all pending input reports, returns false once the wheel is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; WHEEL_REPORT_BUF_SIZE];
+        loop {
+            match self.device.read_timeout(&mut buf, 0) {
+                Ok(0) => return true,
+                Ok(len) => self.decoder.decode(&buf[..len], &mut self.state),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    pub fn wheel_state(&self) -> WheelState {
+        let axis = |i: Option<usize>| i.and_then(|i| self.state.axes.get(i).copied());
+        // Pedals read 1.0 when released.
+        let pedal = |i: Option<usize>| axis(i).map_or(0.0, |value| (1.0 - value) / 2.0);
+        let [steering, throttle, brake, clutch] = self.axes;
+
+        let gear = self.model.shifter_first_button.map_or(0, |first| {
+            let pressed = |i: usize| self.state.buttons.get(first + i).copied().unwrap_or(false);
+            match (0..7).find(|i| pressed(*i)) {
+                Some(6) => -1,
+                Some(i) => i as i8 + 1,
+                None => 0,
+            }
+        });
+
+        WheelState {
+            steering: axis(steering).unwrap_or(0.0),
+            throttle: pedal(throttle),
+            brake: pedal(brake),
+            clutch: pedal(clutch),
+            range: self.range,
+            gear,
+        }
+    }
+
+    /// Steering, throttle, brake and clutch as the four axes, buttons in report order.
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        self.state.fill_gamepad(gamepad, true);
+        let state = self.wheel_state();
+        gamepad.axes = vec![state.steering, state.throttle, state.brake, state.clutch];
+    }
+
+    pub fn set_range(&mut self, degrees: u16) -> bool {
+        self.range = degrees.clamp(ffb::RANGE_MIN, ffb::RANGE_MAX);
+        match &self.ffb {
+            Ffb::Classic(_) => self.send(&ffb::encode_range(self.range)),
+            Ffb::Hidpp { feature, .. } => {
+                let call = feature.set_aperture(self.range);
+                self.call(&call).is_some()
+            }
+        }
+    }
+
+    pub fn set_autocenter(&mut self, strength: f32) -> bool {
+        let (feature, slot) = match &self.ffb {
+            Ffb::Classic(_) => {
+                return ffb::encode_autocenter(strength)
+                    .iter()
+                    .all(|command| self.send(command));
+            }
+            Ffb::Hidpp {
+                feature,
+                autocenter,
+                ..
+            } => (*feature, *autocenter),
+        };
+        let reply = match (slot, strength > 0.0) {
+            (None, false) => return true,
+            (Some(slot), false) => self.call(&feature.destroy(slot)).map(|_| None),
+            (slot, true) => {
+                let call = feature.autocenter(slot.unwrap_or(hidpp::NEW_SLOT), strength);
+                self.call(&call).map(|params| params.first().copied())
+            }
+        };
+        let Some(slot) = reply else {
+            return false;
+        };
+        if let Ffb::Hidpp { autocenter, .. } = &mut self.ffb {
+            *autocenter = slot;
+        }
+        true
+    }
+
+    // Downloads `effect` into the wheel's slot of `slot`, or a new one.
+    fn download_effect(&mut self, slot: usize, effect: &Effect) -> bool {
+        let Ffb::Hidpp { feature, slots, .. } = &self.ffb else {
+            return false;
+        };
+        let call = feature.download(slots[slot].unwrap_or(hidpp::NEW_SLOT), effect);
+        let Some(&device_slot) = self.call(&call).as_ref().and_then(|params| params.first()) else {
+            return false;
+        };
+        if let Ffb::Hidpp { slots, .. } = &mut self.ffb {
+            slots[slot] = Some(device_slot);
+        }
+        true
+    }
+
+    pub fn upload_effect(&mut self, effect: Effect) -> Option<usize> {
+        match &mut self.ffb {
+            Ffb::Classic(effects) => {
+                let (slot, command) = effects.upload(effect)?;
+                self.send(&command);
+                Some(slot)
+            }
+            Ffb::Hidpp { slots, .. } => {
+                let slot = slots.iter().position(Option::is_none)?;
+                self.download_effect(slot, &effect).then_some(slot)
+            }
+        }
+    }
+
+    pub fn update_effect(&mut self, slot: usize, effect: Effect) -> bool {
+        match &mut self.ffb {
+            Ffb::Classic(effects) => match effects.update(slot, effect) {
+                Some(command) => self.send(&command),
+                None => false,
+            },
+            Ffb::Hidpp { slots, .. } => {
+                slots.get(slot).is_some_and(Option::is_some) && self.download_effect(slot, &effect)
+            }
+        }
+    }
+
+    pub fn stop_effect(&mut self, slot: usize) -> bool {
+        match &mut self.ffb {
+            Ffb::Classic(effects) => match effects.stop(slot) {
+                Some(command) => self.send(&command),
+                None => false,
+            },
+            Ffb::Hidpp { feature, slots, .. } => {
+                let Some(device_slot) = slots.get_mut(slot).and_then(Option::take) else {
+                    return false;
+                };
+                let call = feature.destroy(device_slot);
+                self.call(&call).is_some()
+            }
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) -> bool {
+        match &mut self.ffb {
+            Ffb::Classic(effects) => {
+                let commands = effects.set_gain(gain);
+                commands.iter().all(|command| self.send(command))
+            }
+            Ffb::Hidpp { feature, .. } => {
+                let call = feature.set_gain(gain);
+                self.call(&call).is_some()
+            }
+        }
+    }
+}
+
+impl Drop for WheelDevice {
+    fn drop(&mut self) {
+        // Don't leave the wheel pulling after the program is gone.
+        self.stop_all();
+    }
+}
@@ -0,0 +1,689 @@
+/**
+ * Wii Remote driver, with Nunchuk, Classic Controller and Motion Plus extensions.
+ *
+ * ref:
+ *  https://wiibrew.org/wiki/Wiimote
+ *  https://wiibrew.org/wiki/Wiimote/Extension_Controllers
+ *  https://github.com/dolphin-emu/dolphin/tree/master/Source/Core/Core/HW/WiimoteEmu
+ */
+use crate::{Button, Gamepad, GamepadMapping, Motion};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use num_enum::TryFromPrimitive;
+use std::time::{Duration, Instant};
+
+const VENDOR_ID_NINTENDO: u16 = 0x057e;
+const PRODUCT_ID_NINTENDO_WIIMOTE: u16 = 0x0306;
+const PRODUCT_ID_NINTENDO_WIIMOTE_PLUS: u16 = 0x0330;
+
+#[repr(u8)]
+enum OutputReportID {
+    Rumble = 0x10,
+    PlayerLights = 0x11,
+    ReportMode = 0x12,
+    RequestStatus = 0x15,
+    WriteMemory = 0x16,
+    ReadMemory = 0x17,
+}
+
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive)]
+enum InputReportID {
+    Status = 0x20,
+    ReadMemoryData = 0x21,
+    Ack = 0x22,
+    ButtonsAccelExtension = 0x35,
+}
+
+// Flag byte of memory reads and writes, selects the control registers instead of the EEPROM.
+const ADDRESS_SPACE_EEPROM: u8 = 0x00;
+const ADDRESS_SPACE_REGISTER: u8 = 0x04;
+const REPORT_MODE_CONTINUOUS: u8 = 0x04;
+const PLAYER_LIGHT_1: u8 = 0x10;
+const STATUS_EXTENSION_CONNECTED: u8 = 0x02;
+
+// EEPROM and extension register addresses.
+const ADDRESS_ACCEL_CALIBRATION: u32 = 0x0016;
+const ADDRESS_EXTENSION_INIT_1: u32 = 0xa400f0;
+const ADDRESS_EXTENSION_INIT_2: u32 = 0xa400fb;
+// Legacy initialization, leaves the extension data encrypted with an all zero key.
+const ADDRESS_EXTENSION_ENCRYPTION_KEY: u32 = 0xa40040;
+const ADDRESS_EXTENSION_CALIBRATION: u32 = 0xa40020;
+const ADDRESS_EXTENSION_ID: u32 = 0xa400fa;
+const ADDRESS_MOTION_PLUS_ID: u32 = 0xa600fa;
+const ADDRESS_MOTION_PLUS_MODE: u32 = 0xa600fe;
+
+const EXTENSION_ID_NUNCHUK: u16 = 0x0000;
+const EXTENSION_ID_CLASSIC: u16 = 0x0101;
+const MOTION_PLUS_ID: [u8; 2] = [0xa6, 0x20];
+
+const MOTION_PLUS_MODE_STANDALONE: u8 = 0x04;
+const MOTION_PLUS_MODE_NUNCHUK: u8 = 0x05;
+const MOTION_PLUS_MODE_CLASSIC: u8 = 0x07;
+
+// A reply that takes longer than this is requested again.
+const SETUP_TIMEOUT: Duration = Duration::from_millis(200);
+const SETUP_MAX_RETRIES: u8 = 3;
+
+const INPUT_REPORT_SIZE: usize = 22;
+const EXTENSION_DATA_OFFSET: usize = 6;
+const EXTENSION_DATA_SIZE: usize = 6;
+
+// Motion Plus rates, 8192/595 units per degree per second in slow mode and 2000/440 times
+// coarser in fast mode.
+const MOTION_PLUS_CENTER: f32 = 8192.0;
+const MOTION_PLUS_SLOW_SCALE: f32 = 595.0 / 8192.0;
+const MOTION_PLUS_FAST_SCALE: f32 = MOTION_PLUS_SLOW_SCALE * 2000.0 / 440.0;
+
+// Nominal accelerometer calibration, 10 bit samples.
+const ACCEL_DEFAULT_ZERO: u16 = 512;
+const ACCEL_DEFAULT_ONE: u16 = 616;
+
+// (byte, mask, standard gamepad button)
+const WIIMOTE_BUTTON_LAYOUT: [(usize, u8, usize); 10] = [
+    (1, 0x08, 0),  // A
+    (1, 0x02, 2),  // 1
+    (1, 0x01, 3),  // 2
+    (1, 0x10, 8),  // Minus
+    (0, 0x10, 9),  // Plus
+    (0, 0x08, 12), // D-pad up
+    (0, 0x04, 13), // D-pad down
+    (0, 0x01, 14), // D-pad left
+    (0, 0x02, 15), // D-pad right
+    (1, 0x80, 16), // Home
+];
+const WIIMOTE_BUTTON_B: (usize, u8) = (1, 0x04);
+
+// (bit, standard gamepad button), bits of `ClassicState::buttons`.
+const CLASSIC_BUTTON_LAYOUT: [(u16, usize); 15] = [
+    (6, 0),   // b
+    (4, 1),   // a
+    (5, 2),   // y
+    (3, 3),   // x
+    (7, 4),   // ZL
+    (2, 5),   // ZR
+    (12, 8),  // Minus
+    (10, 9),  // Plus
+    (0, 12),  // D-pad up
+    (14, 13), // D-pad down
+    (1, 14),  // D-pad left
+    (15, 15), // D-pad right
+    (11, 16), // Home
+    (13, 6),  // L (digital click)
+    (9, 7),   // R (digital click)
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Extension {
+    Nunchuk,
+    Classic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SetupStep {
+    ReadAccelCalibration,
+    InitExtension1,
+    InitExtension2,
+    InitEncryptedExtension,
+    ReadExtensionId,
+    ReadExtensionCalibration,
+    ReadMotionPlusId,
+    ActivateMotionPlus,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StickCalibration {
+    min: u8,
+    center: u8,
+    max: u8,
+}
+
+impl Default for StickCalibration {
+    fn default() -> Self {
+        StickCalibration {
+            min: 32,
+            center: 128,
+            max: 224,
+        }
+    }
+}
+
+impl StickCalibration {
+    fn from_bytes(max: u8, min: u8, center: u8) -> Self {
+        // Erased or unset calibration.
+        if min >= center || center >= max {
+            return Self::default();
+        }
+        StickCalibration { min, center, max }
+    }
+
+    fn normalize(&self, value: u8) -> f32 {
+        let value = value as f32 - self.center as f32;
+        let range = if value >= 0.0 {
+            self.max - self.center
+        } else {
+            self.center - self.min
+        };
+        (value / range as f32).clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct NunchukState {
+    stick: [u8; 2],
+    c: bool,
+    z: bool,
+}
+
+#[derive(Debug, Default)]
+struct ClassicState {
+    // Scaled to 8 bits.
+    left_stick: [u8; 2],
+    right_stick: [u8; 2],
+    // 5 bit trigger positions.
+    triggers: [u8; 2],
+    // Pressed buttons, byte 4 in the high and byte 5 in the low byte.
+    buttons: u16,
+}
+
+/// The extension data of the legacy initialization is encrypted with an all zero key.
+fn decrypt(data: &mut [u8]) {
+    for byte in data {
+        *byte = (*byte ^ 0x17).wrapping_add(0x17);
+    }
+}
+
+fn extract_accel(data: &[u8], lsb: u8) -> [u16; 3] {
+    [
+        (data[0] as u16) << 2 | (lsb >> 4 & 0x3) as u16,
+        (data[1] as u16) << 2 | (lsb >> 2 & 0x3) as u16,
+        (data[2] as u16) << 2 | (lsb & 0x3) as u16,
+    ]
+}
+
+pub struct WiimoteDevice {
+    device: HidDevice,
+    rumble: bool,
+    step: Option<SetupStep>,
+    step_sent: Instant,
+    retries: u8,
+    // Extension bit of the last status report, and whether an extension sits behind the
+    // Motion Plus.
+    extension_connected: Option<bool>,
+    passthrough_connected: Option<bool>,
+    extension: Option<Extension>,
+    encrypted: bool,
+    motion_plus: bool,
+
+    accel_zero: [u16; 3],
+    accel_one: [u16; 3],
+    stick_calibration: [StickCalibration; 4],
+
+    buttons: [u8; 2],
+    accel: [u16; 3],
+    gyro: [f32; 3],
+    nunchuk: NunchukState,
+    classic: ClassicState,
+}
+
+impl WiimoteDevice {
+    pub fn is_supported(device_info: &DeviceInfo) -> bool {
+        device_info.vendor_id() == VENDOR_ID_NINTENDO
+            && [
+                PRODUCT_ID_NINTENDO_WIIMOTE,
+                PRODUCT_ID_NINTENDO_WIIMOTE_PLUS,
+            ]
+            .contains(&device_info.product_id())
+    }
+
+    pub fn open(hidapi: &HidApi, device_info: &DeviceInfo) -> Option<Self> {
+        let device = device_info.open_device(hidapi).ok()?;
+        let mut wiimote = WiimoteDevice {
+            device,
+            rumble: false,
+            step: None,
+            step_sent: Instant::now(),
+            retries: 0,
+            extension_connected: None,
+            passthrough_connected: None,
+            extension: None,
+            encrypted: false,
+            motion_plus: false,
+            accel_zero: [ACCEL_DEFAULT_ZERO; 3],
+            accel_one: [ACCEL_DEFAULT_ONE; 3],
+            stick_calibration: Default::default(),
+            buttons: [0; 2],
+            accel: [ACCEL_DEFAULT_ZERO; 3],
+            gyro: [0.0; 3],
+            nunchuk: Default::default(),
+            classic: Default::default(),
+        };
+        // Stops the player lights from blinking.
+        wiimote.write(&[OutputReportID::PlayerLights as u8, PLAYER_LIGHT_1]);
+        wiimote.set_report_mode();
+        wiimote.start_step(SetupStep::ReadAccelCalibration);
+        Some(wiimote)
+    }
+
+    /// Sends an output report, every report carries the rumble bit in its first data byte.
+    fn write(&self, report: &[u8]) -> bool {
+        let mut report = report.to_vec();
+        if self.rumble {
+            report[1] |= 0x01;
+        }
+        self.device.write(&report).is_ok()
+    }
+
+    fn set_report_mode(&self) {
+        self.write(&[
+            OutputReportID::ReportMode as u8,
+            REPORT_MODE_CONTINUOUS,
+            InputReportID::ButtonsAccelExtension as u8,
+        ]);
+    }
+
+    fn read_memory(&self, space: u8, address: u32, size: u16) {
+        let [_, a2, a1, a0] = address.to_be_bytes();
+        let [s1, s0] = size.to_be_bytes();
+        self.write(&[OutputReportID::ReadMemory as u8, space, a2, a1, a0, s1, s0]);
+    }
+
+    fn write_register(&self, address: u32, value: u8) {
+        let [_, a2, a1, a0] = address.to_be_bytes();
+        let mut report = [0; 22];
+        report[..7].copy_from_slice(&[
+            OutputReportID::WriteMemory as u8,
+            ADDRESS_SPACE_REGISTER,
+            a2,
+            a1,
+            a0,
+            1,
+            value,
+        ]);
+        self.write(&report);
+    }
+
+    fn start_step(&mut self, step: SetupStep) {
+        self.step = Some(step);
+        self.step_sent = Instant::now();
+        self.retries = 0;
+        self.send_step(step);
+    }
+
+    fn send_step(&self, step: SetupStep) {
+        match step {
+            SetupStep::ReadAccelCalibration => {
+                self.read_memory(ADDRESS_SPACE_EEPROM, ADDRESS_ACCEL_CALIBRATION, 8)
+            }
+            SetupStep::InitExtension1 => self.write_register(ADDRESS_EXTENSION_INIT_1, 0x55),
+            SetupStep::InitExtension2 => self.write_register(ADDRESS_EXTENSION_INIT_2, 0x00),
+            SetupStep::InitEncryptedExtension => {
+                self.write_register(ADDRESS_EXTENSION_ENCRYPTION_KEY, 0x00)
+            }
+            SetupStep::ReadExtensionId => {
+                self.read_memory(ADDRESS_SPACE_REGISTER, ADDRESS_EXTENSION_ID, 6)
+            }
+            SetupStep::ReadExtensionCalibration => {
+                self.read_memory(ADDRESS_SPACE_REGISTER, ADDRESS_EXTENSION_CALIBRATION, 16)
+            }
+            SetupStep::ReadMotionPlusId => {
+                self.read_memory(ADDRESS_SPACE_REGISTER, ADDRESS_MOTION_PLUS_ID, 6)
+            }
+            SetupStep::ActivateMotionPlus => {
+                let mode = match self.extension {
+                    Some(Extension::Nunchuk) => MOTION_PLUS_MODE_NUNCHUK,
+                    Some(Extension::Classic) => MOTION_PLUS_MODE_CLASSIC,
+                    None => MOTION_PLUS_MODE_STANDALONE,
+                };
+                self.write_register(ADDRESS_MOTION_PLUS_MODE, mode);
+            }
+        }
+    }
+
+    /// Identifies the extension from scratch, also deactivates an active Motion Plus.
+    fn detect_extension(&mut self) {
+        self.extension = None;
+        self.encrypted = false;
+        self.motion_plus = false;
+        self.passthrough_connected = None;
+        self.nunchuk = Default::default();
+        self.classic = Default::default();
+        self.gyro = [0.0; 3];
+        self.start_step(SetupStep::InitExtension1);
+    }
+
+    fn finish_setup(&mut self) {
+        self.step = None;
+        // Writing to the extension registers resets the report mode.
+        self.set_report_mode();
+    }
+
+    fn handle_ack(&mut self, error: bool) {
+        let extension_connected = self.extension_connected == Some(true);
+        match self.step {
+            Some(SetupStep::InitExtension1) if !error => self.start_step(SetupStep::InitExtension2),
+            // Some third party extensions only accept the legacy initialization.
+            Some(SetupStep::InitExtension1) if extension_connected => {
+                self.start_step(SetupStep::InitEncryptedExtension)
+            }
+            Some(SetupStep::InitExtension1) => self.start_step(SetupStep::ReadMotionPlusId),
+            Some(SetupStep::InitExtension2) => self.start_step(SetupStep::ReadExtensionId),
+            Some(SetupStep::InitEncryptedExtension) if !error => {
+                self.encrypted = true;
+                self.start_step(SetupStep::ReadExtensionId);
+            }
+            Some(SetupStep::InitEncryptedExtension) => self.start_step(SetupStep::ReadMotionPlusId),
+            Some(SetupStep::ActivateMotionPlus) => {
+                self.motion_plus = !error;
+                // The active Motion Plus shows up as the extension.
+                self.extension_connected = Some(self.motion_plus || extension_connected);
+                self.finish_setup();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_read(&mut self, error: bool, data: &mut [u8]) {
+        match self.step {
+            Some(SetupStep::ReadAccelCalibration) => {
+                if !error && data.len() >= 8 {
+                    self.accel_zero = extract_accel(&data[0..3], data[3]);
+                    self.accel_one = extract_accel(&data[4..7], data[7]);
+                }
+                self.step = None;
+                // The status reply starts the extension detection.
+                self.write(&[OutputReportID::RequestStatus as u8, 0]);
+            }
+            Some(SetupStep::ReadExtensionId) => {
+                if self.encrypted {
+                    decrypt(data);
+                }
+                self.extension = match (error, data) {
+                    (false, [.., 0xa4, 0x20, id1, id0]) => match u16::from_be_bytes([*id1, *id0]) {
+                        EXTENSION_ID_NUNCHUK => Some(Extension::Nunchuk),
+                        EXTENSION_ID_CLASSIC => Some(Extension::Classic),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if self.extension.is_some() {
+                    self.start_step(SetupStep::ReadExtensionCalibration);
+                } else {
+                    self.start_step(SetupStep::ReadMotionPlusId);
+                }
+            }
+            Some(SetupStep::ReadExtensionCalibration) => {
+                if self.encrypted {
+                    decrypt(data);
+                }
+                if !error && data.len() >= 16 {
+                    self.update_stick_calibration(data);
+                }
+                self.start_step(SetupStep::ReadMotionPlusId);
+            }
+            Some(SetupStep::ReadMotionPlusId) => {
+                // The passthrough modes don't work with encrypted extensions.
+                let found = !error && data.len() >= 6 && data[2..4] == MOTION_PLUS_ID;
+                if found && !self.encrypted {
+                    self.start_step(SetupStep::ActivateMotionPlus);
+                } else {
+                    self.finish_setup();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_stick_calibration(&mut self, data: &[u8]) {
+        match self.extension {
+            Some(Extension::Nunchuk) => {
+                self.stick_calibration[0] =
+                    StickCalibration::from_bytes(data[8], data[9], data[10]);
+                self.stick_calibration[1] =
+                    StickCalibration::from_bytes(data[11], data[12], data[13]);
+            }
+            Some(Extension::Classic) => {
+                for (i, cal) in self.stick_calibration.iter_mut().enumerate() {
+                    let offset = i * 3;
+                    *cal = StickCalibration::from_bytes(
+                        data[offset],
+                        data[offset + 1],
+                        data[offset + 2],
+                    );
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn handle_status(&mut self, flags: u8) {
+        let connected = flags & STATUS_EXTENSION_CONNECTED != 0;
+        if self.step.is_none() && self.extension_connected != Some(connected) {
+            self.extension_connected = Some(connected);
+            self.detect_extension();
+        }
+        // The Wii Remote falls back to buttons only reports after every status report.
+        self.set_report_mode();
+    }
+
+    fn handle_extension_data(&mut self, data: &mut [u8]) {
+        if self.encrypted {
+            decrypt(data);
+        }
+
+        if !self.motion_plus {
+            match self.extension {
+                Some(Extension::Nunchuk) => self.update_nunchuk(data, false),
+                Some(Extension::Classic) => self.update_classic(data, false),
+                None => {}
+            }
+            return;
+        }
+
+        // In the passthrough modes reports alternate between Motion Plus and extension data.
+        if data[5] & 0x02 == 0 {
+            match self.extension {
+                Some(Extension::Nunchuk) => self.update_nunchuk(data, true),
+                Some(Extension::Classic) => self.update_classic(data, true),
+                None => {}
+            }
+            return;
+        }
+
+        let rate = |low: u8, high: u8, slow: bool| {
+            let raw = ((high as u16 >> 2) << 8 | low as u16) as f32 - MOTION_PLUS_CENTER;
+            raw * if slow {
+                MOTION_PLUS_SLOW_SCALE
+            } else {
+                MOTION_PLUS_FAST_SCALE
+            }
+        };
+        // (pitch, roll, yaw), around the Wii Remote's x, y and z axes.
+        self.gyro = [
+            rate(data[2], data[5], data[3] & 0x01 != 0),
+            rate(data[1], data[4], data[4] & 0x02 != 0),
+            rate(data[0], data[3], data[3] & 0x02 != 0),
+        ];
+
+        // An extension was plugged into or pulled from the Motion Plus.
+        let passthrough_connected = data[4] & 0x01 != 0;
+        if self.step.is_none()
+            && self
+                .passthrough_connected
+                .is_some_and(|connected| connected != passthrough_connected)
+        {
+            self.detect_extension();
+            return;
+        }
+        self.passthrough_connected = Some(passthrough_connected);
+    }
+
+    fn update_nunchuk(&mut self, data: &[u8], passthrough: bool) {
+        // Buttons are active low.
+        let (c, z) = if passthrough {
+            (data[5] & 0x08, data[5] & 0x04)
+        } else {
+            (data[5] & 0x02, data[5] & 0x01)
+        };
+        self.nunchuk = NunchukState {
+            stick: [data[0], data[1]],
+            c: c == 0,
+            z: z == 0,
+        };
+    }
+
+    fn update_classic(&mut self, data: &[u8], passthrough: bool) {
+        let (lx, ly, low) = if passthrough {
+            // D-pad up and left move to the lowest bits of the stick bytes.
+            let low = (data[5] & 0xfc) | (data[1] & 0x01) << 1 | (data[0] & 0x01);
+            (data[0] & 0x3e, data[1] & 0x3e, low)
+        } else {
+            (data[0] & 0x3f, data[1] & 0x3f, data[5])
+        };
+        let rx = (data[0] >> 6) << 3 | (data[1] >> 6) << 1 | data[2] >> 7;
+        let ry = data[2] & 0x1f;
+        let lt = (data[2] >> 5 & 0x03) << 3 | data[3] >> 5;
+        let rt = data[3] & 0x1f;
+        self.classic = ClassicState {
+            left_stick: [lx << 2, ly << 2],
+            right_stick: [rx << 3, ry << 3],
+            triggers: [lt, rt],
+            // Buttons are active low.
+            buttons: !u16::from_be_bytes([data[4], low]),
+        };
+    }
+
+    fn handle_report(&mut self, report: &mut [u8]) {
+        let Ok(report_id) = InputReportID::try_from(report[0]) else {
+            return;
+        };
+        // Every input report handled here starts with the core buttons.
+        if report.len() >= 3 {
+            self.buttons = [report[1], report[2]];
+        }
+        match report_id {
+            InputReportID::Status if report.len() >= 4 => self.handle_status(report[3]),
+            InputReportID::ReadMemoryData if report.len() >= 6 => {
+                let error = report[3] & 0x0f != 0;
+                let size = (report[3] >> 4) as usize + 1;
+                let end = (6 + size).min(report.len());
+                self.handle_read(error, &mut report[6..end]);
+            }
+            InputReportID::Ack
+                if report.len() >= 5 && report[3] == OutputReportID::WriteMemory as u8 =>
+            {
+                self.handle_ack(report[4] != 0)
+            }
+            InputReportID::ButtonsAccelExtension
+                if report.len() >= EXTENSION_DATA_OFFSET + EXTENSION_DATA_SIZE =>
+            {
+                self.accel = [
+                    (report[3] as u16) << 2 | (report[1] >> 5 & 0x03) as u16,
+                    (report[4] as u16) << 2 | (report[2] >> 4 & 0x02) as u16,
+                    (report[5] as u16) << 2 | (report[2] >> 5 & 0x02) as u16,
+                ];
+                self.handle_extension_data(
+                    &mut report[EXTENSION_DATA_OFFSET..EXTENSION_DATA_OFFSET + EXTENSION_DATA_SIZE],
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies all pending input reports, returns false once the Wii Remote is gone.
+    pub fn poll(&mut self) -> bool {
+        let mut buf = [0; INPUT_REPORT_SIZE];
+        loop {
+            match self.device.read_timeout(&mut buf, 0) {
+                Ok(0) => break,
+                Ok(len) => self.handle_report(&mut buf[..len]),
+                Err(_) => return false,
+            }
+        }
+
+        if let Some(step) = self.step {
+            if self.step_sent.elapsed() > SETUP_TIMEOUT {
+                if self.retries < SETUP_MAX_RETRIES {
+                    self.retries += 1;
+                    self.step_sent = Instant::now();
+                    self.send_step(step);
+                } else {
+                    // Give up on the extension, the Wii Remote itself keeps working.
+                    self.extension = None;
+                    self.finish_setup();
+                }
+            }
+        }
+        true
+    }
+
+    pub fn fill_gamepad(&self, gamepad: &mut Gamepad) {
+        match self.extension {
+            Some(Extension::Classic) => self.fill_classic(gamepad),
+            _ => self.fill_wiimote(gamepad),
+        }
+        gamepad.mapping = GamepadMapping::Standard;
+    }
+
+    fn fill_wiimote(&self, gamepad: &mut Gamepad) {
+        for (byte, mask, index) in WIIMOTE_BUTTON_LAYOUT {
+            gamepad.buttons[index] = Button::digital(self.buttons[byte] & mask != 0);
+        }
+        let (byte, mask) = WIIMOTE_BUTTON_B;
+        let b = Button::digital(self.buttons[byte] & mask != 0);
+
+        if self.extension == Some(Extension::Nunchuk) {
+            // Held like a pair of sticks, B and Z are the triggers.
+            gamepad.buttons[7] = b;
+            gamepad.buttons[4] = Button::digital(self.nunchuk.c);
+            gamepad.buttons[6] = Button::digital(self.nunchuk.z);
+            let [x, y] = self.nunchuk.stick;
+            gamepad.axes[0] = self.stick_calibration[0].normalize(x);
+            // Standard layout, up is -1.0.
+            gamepad.axes[1] = -self.stick_calibration[1].normalize(y);
+        } else {
+            gamepad.buttons[1] = b;
+        }
+    }
+
+    fn fill_classic(&self, gamepad: &mut Gamepad) {
+        let classic = &self.classic;
+        for (bit, index) in CLASSIC_BUTTON_LAYOUT {
+            gamepad.buttons[index] = Button::digital(classic.buttons & (1 << bit) != 0);
+        }
+        for (i, trigger) in classic.triggers.iter().enumerate() {
+            let click = gamepad.buttons[6 + i].pressed;
+            gamepad.buttons[6 + i] = Button::analog(*trigger as f32 / 31.0);
+            gamepad.buttons[6 + i].pressed |= click;
+        }
+        let [lx, ly] = classic.left_stick;
+        let [rx, ry] = classic.right_stick;
+        // Standard layout, up is -1.0.
+        gamepad.axes[0] = self.stick_calibration[0].normalize(lx);
+        gamepad.axes[1] = -self.stick_calibration[1].normalize(ly);
+        gamepad.axes[2] = self.stick_calibration[2].normalize(rx);
+        gamepad.axes[3] = -self.stick_calibration[3].normalize(ry);
+    }
+
+    /// Accelerometer data of the Wii Remote, and angular velocity while a Motion Plus is active.
+    pub fn motion(&self) -> Motion {
+        let mut accel = [0.0; 3];
+        for (i, value) in accel.iter_mut().enumerate() {
+            let range = self.accel_one[i] as f32 - self.accel_zero[i] as f32;
+            if range != 0.0 {
+                *value = (self.accel[i] as f32 - self.accel_zero[i] as f32) / range;
+            }
+        }
+        Motion {
+            accel,
+            gyro: self.gyro,
+            has_accel: true,
+            has_gyro: self.motion_plus,
+        }
+    }
+
+    /// The Wii Remote only supports switching the motor on and off.
+    pub fn set_rumble(&mut self, strong: f32, weak: f32) -> bool {
+        self.rumble = strong > 0.0 || weak > 0.0;
+        self.write(&[OutputReportID::Rumble as u8, 0])
+    }
+}